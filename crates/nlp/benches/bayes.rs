@@ -0,0 +1,101 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use nlp::{
+    bayes::{tokenize::BayesTokenizer, BayesClassifier, BayesModel, TokenHash, OSB_WINDOW_SIZE},
+    tokenizers::osb::{OsbToken, OsbTokenizer},
+};
+use utils::suffixlist::PublicSuffix;
+
+const FIXTURES: &[(&str, bool)] = &[
+    (include_str!("fixtures/ham_meeting.txt"), false),
+    (include_str!("fixtures/ham_invoice.txt"), false),
+    (include_str!("fixtures/spam_lottery.txt"), true),
+    (include_str!("fixtures/spam_pharmacy.txt"), true),
+];
+
+fn tokenize(c: &mut Criterion) {
+    let suffixes = PublicSuffix::default();
+
+    c.bench_function("tokenize", |b| {
+        b.iter(|| {
+            for (text, _) in FIXTURES {
+                black_box(
+                    OsbTokenizer::<_, TokenHash>::new(
+                        BayesTokenizer::deterministic(text, &suffixes),
+                        OSB_WINDOW_SIZE,
+                    )
+                    .count(),
+                );
+            }
+        })
+    });
+}
+
+fn classify(c: &mut Criterion) {
+    let suffixes = PublicSuffix::default();
+    let mut model = BayesModel::default();
+    for (text, is_spam) in FIXTURES {
+        model.train(
+            OsbTokenizer::new(
+                BayesTokenizer::deterministic(text, &suffixes),
+                OSB_WINDOW_SIZE,
+            ),
+            *is_spam,
+        );
+    }
+    let classifier = BayesClassifier {
+        min_learns: 0,
+        min_tokens: 0,
+        min_token_hits: 1,
+        ..Default::default()
+    };
+
+    c.bench_function("classify", |b| {
+        b.iter(|| {
+            for (text, _) in FIXTURES {
+                black_box(
+                    classifier.classify(
+                        OsbTokenizer::<_, TokenHash>::new(
+                            BayesTokenizer::deterministic(text, &suffixes),
+                            OSB_WINDOW_SIZE,
+                        )
+                        .filter_map(|t| {
+                            OsbToken {
+                                inner: *model.weights.get(&t.inner)?,
+                                idx: t.idx,
+                            }
+                            .into()
+                        }),
+                        model.ham_learns,
+                        model.spam_learns,
+                    ),
+                );
+            }
+        })
+    });
+}
+
+criterion_group!(benches, tokenize, classify);
+criterion_main!(benches);
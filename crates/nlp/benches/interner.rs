@@ -0,0 +1,119 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use nlp::{
+    bayes::{interner::StringInterner, tokenize::BayesTokenizer, TokenHash, OSB_WINDOW_SIZE},
+    tokenizers::osb::OsbTokenizer,
+};
+use utils::suffixlist::PublicSuffix;
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const FIXTURES: &[&str] = &[
+    include_str!("fixtures/ham_meeting.txt"),
+    include_str!("fixtures/ham_invoice.txt"),
+    include_str!("fixtures/spam_lottery.txt"),
+    include_str!("fixtures/spam_pharmacy.txt"),
+];
+
+fn corpus() -> Vec<String> {
+    // Repetitive vocabulary, as seen in list traffic and resends
+    (0..50)
+        .map(|idx| FIXTURES[idx % FIXTURES.len()].repeat(3))
+        .collect()
+}
+
+fn plain(corpus: &[String], suffixes: &PublicSuffix) -> usize {
+    corpus
+        .iter()
+        .map(|text| {
+            OsbTokenizer::<_, TokenHash>::new(
+                BayesTokenizer::deterministic(text, suffixes),
+                OSB_WINDOW_SIZE,
+            )
+            .count()
+        })
+        .sum()
+}
+
+fn interned(corpus: &[String], suffixes: &PublicSuffix, interner: &mut StringInterner) -> usize {
+    corpus
+        .iter()
+        .map(|text| {
+            let ids = interner.tokenize(&mut BayesTokenizer::deterministic(text, suffixes));
+            OsbTokenizer::<_, TokenHash>::new(interner.tokens(&ids), OSB_WINDOW_SIZE).count()
+        })
+        .sum()
+}
+
+fn allocations(f: impl FnOnce() -> usize) -> usize {
+    let start = ALLOCATIONS.load(Ordering::Relaxed);
+    black_box(f());
+    ALLOCATIONS.load(Ordering::Relaxed) - start
+}
+
+fn interner(c: &mut Criterion) {
+    let suffixes = PublicSuffix::default();
+    let corpus = corpus();
+    let mut interner = StringInterner::new();
+
+    // Warm up the interner so only steady-state allocations are counted
+    interned(&corpus, &suffixes, &mut interner);
+    println!(
+        "allocations per corpus pass: plain {}, interned {}",
+        allocations(|| plain(&corpus, &suffixes)),
+        allocations(|| interned(&corpus, &suffixes, &mut interner))
+    );
+
+    let mut group = c.benchmark_group("tokenize_corpus");
+    group.bench_function("plain", |b| b.iter(|| plain(&corpus, &suffixes)));
+    group.bench_function("interned", |b| {
+        b.iter(|| interned(&corpus, &suffixes, &mut interner))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, interner);
+criterion_main!(benches);
@@ -0,0 +1,176 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{borrow::Cow, net::Ipv6Addr};
+
+use serde::{Deserialize, Serialize};
+use utils::suffixlist::PublicSuffix;
+
+use crate::tokenizers::osb::OsbTokenizer;
+
+use super::{pipeline::PipelineError, tokenize::BayesTokenizer, BayesClassifier, BayesModel};
+
+/// Thresholds that the separate scores of the words and of the links of a
+/// message must both reach for it to be classified as spam.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LinkAgreement {
+    pub word_threshold: f64,
+    pub link_threshold: f64,
+}
+
+impl Default for LinkAgreement {
+    fn default() -> Self {
+        LinkAgreement {
+            word_threshold: 0.9,
+            link_threshold: 0.9,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AgreementResult {
+    // Score of all the tokens of the message
+    pub score: Option<f64>,
+    // Scores of the word tokens and of the host, IP address and email tokens
+    pub words: Option<f64>,
+    pub links: Option<f64>,
+    pub is_spam: bool,
+}
+
+impl BayesModel {
+    /// Classifies a text scoring its words and its links separately. When
+    /// `BayesClassifier::link_agreement` is set, the text is only spam if
+    /// both scores reach their thresholds, so texts without links are never
+    /// spam. Otherwise the verdict is that of `classify_text`.
+    pub fn classify_text_agreement(
+        &self,
+        classifier: &BayesClassifier,
+        text: &str,
+        suffixes: &PublicSuffix,
+    ) -> Result<AgreementResult, PipelineError> {
+        let score = self.classify_text(classifier, text, suffixes)?;
+        let (links, words): (Vec<_>, Vec<_>) =
+            BayesTokenizer::new(text, suffixes).partition(|token| is_link_token(token));
+        let classify = |tokens: Vec<Cow<'_, str>>| {
+            if tokens.is_empty() {
+                None
+            } else {
+                self.classify_token_hashes(
+                    classifier,
+                    OsbTokenizer::with_hasher(
+                        tokens.into_iter(),
+                        self.window_size,
+                        self.token_hasher(),
+                    ),
+                )
+            }
+        };
+        let (words, links) = (classify(words), classify(links));
+
+        let is_spam = if let Some(agreement) = &classifier.link_agreement {
+            words.is_some_and(|score| score >= agreement.word_threshold)
+                && links.is_some_and(|score| score >= agreement.link_threshold)
+        } else {
+            score.is_some_and(|score| classifier.is_spam(score))
+        };
+
+        Ok(AgreementResult {
+            score,
+            words,
+            links,
+            is_spam,
+        })
+    }
+}
+
+// Hosts, IP addresses and email addresses are the only tokens with dots, as
+// numbers are replaced by tags
+fn is_link_token(token: &str) -> bool {
+    token.contains('.') || token.parse::<Ipv6Addr>().is_ok()
+}
+
+#[cfg(test)]
+mod test {
+    use utils::suffixlist::PublicSuffix;
+
+    use crate::bayes::{BayesClassifier, BayesModel};
+
+    use super::LinkAgreement;
+
+    #[test]
+    fn link_agreement() {
+        let suffixes = PublicSuffix::default();
+        let mut model = BayesModel::default();
+        for _ in 0..5 {
+            model
+                .train_text(
+                    "win a cash prize today at https://prizes.example.net/claim",
+                    true,
+                    &suffixes,
+                )
+                .unwrap();
+            model
+                .train_text(
+                    "agenda for the meeting at https://docs.example.org/agenda",
+                    false,
+                    &suffixes,
+                )
+                .unwrap();
+        }
+
+        let mut classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 1,
+            min_token_hits: 1,
+            spam_threshold: 0.6,
+            ..Default::default()
+        };
+        let text = "win a cash prize today, see https://docs.example.org/agenda";
+        let result = model
+            .classify_text_agreement(&classifier, text, &suffixes)
+            .unwrap();
+        assert!(result.is_spam, "{result:?}");
+
+        // Spammy words with clean links are not spam when both must agree
+        classifier.link_agreement = Some(LinkAgreement::default());
+        let result = model
+            .classify_text_agreement(&classifier, text, &suffixes)
+            .unwrap();
+        assert!(!result.is_spam);
+        assert!(result.words.unwrap() > 0.9, "{result:?}");
+        assert!(result.links.unwrap() < 0.1, "{result:?}");
+
+        let result = model
+            .classify_text_agreement(
+                &classifier,
+                "win a cash prize at https://prizes.example.net/claim",
+                &suffixes,
+            )
+            .unwrap();
+        assert!(result.is_spam, "{result:?}");
+        let result = model
+            .classify_text_agreement(&classifier, "win a cash prize today", &suffixes)
+            .unwrap();
+        assert!(!result.is_spam && result.links.is_none());
+    }
+}
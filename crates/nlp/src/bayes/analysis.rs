@@ -0,0 +1,737 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use crate::tokenizers::osb::OsbToken;
+
+use super::{
+    classify::TokenInput, BayesClassifier, BayesModel, TokenHash, Weights, FEATURE_POSITIONS,
+};
+
+/// Histogram of the probability strength `|bayes_spam_prob - 0.5|` of the tokens in
+/// a corpus, split into equal width buckets covering `[0, 0.5]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StrengthDistribution {
+    pub buckets: Vec<u64>,
+    pub total: u64,
+}
+
+impl BayesClassifier {
+    /// Computes the strength distribution of all tokens with enough hits in a
+    /// corpus of tokenized messages, in order to help choose `min_prob_strength`.
+    pub fn prob_strength_distribution<M, T>(
+        &self,
+        messages: M,
+        ham_learns: u32,
+        spam_learns: u32,
+        num_buckets: usize,
+    ) -> StrengthDistribution
+    where
+        M: IntoIterator<Item = T>,
+        T: IntoIterator<Item = OsbToken<Weights>>,
+    {
+        let num_buckets = num_buckets.max(1);
+        let mut distribution = StrengthDistribution {
+            buckets: vec![0; num_buckets],
+            total: 0,
+        };
+
+        for token in messages.into_iter().flatten() {
+            if let Some((bayes_spam_prob, _)) = self.combined_token_probs(
+                &TokenInput::from(token),
+                ham_learns,
+                spam_learns,
+                self.min_token_hits,
+            ) {
+                let strength = (bayes_spam_prob - 0.5).abs();
+                let bucket = ((strength / 0.5 * num_buckets as f64) as usize).min(num_buckets - 1);
+                distribution.buckets[bucket] += 1;
+                distribution.total += 1;
+            }
+        }
+
+        distribution
+    }
+}
+
+/// Discriminative power of the tokens at one position of the OSB window,
+/// measured by scoring each message of a labeled corpus with the tokens of
+/// that position alone.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PositionEffectiveness {
+    // Tokens at this position with enough hits
+    pub tokens: u64,
+    // Messages with at least one such token
+    pub messages: u64,
+    // Mean probability strength `|bayes_spam_prob - 0.5|` of the tokens
+    pub mean_strength: f64,
+    // Fraction of the messages scored on the side of their label
+    pub accuracy: f64,
+    // Pearson correlation between the message scores and their labels (1.0
+    // for spam), 0.0 when either of them does not vary
+    pub correlation: f64,
+}
+
+impl BayesClassifier {
+    /// Measures how well the tokens of each position tell spam from ham over
+    /// a corpus of tokenized messages labeled as spam or ham, as evidence for
+    /// the feature weight of each position. Tokens are scored as during
+    /// classification, so the feature weights in use are part of the result.
+    pub fn position_effectiveness<M, T>(
+        &self,
+        messages: M,
+        ham_learns: u32,
+        spam_learns: u32,
+    ) -> [PositionEffectiveness; FEATURE_POSITIONS]
+    where
+        M: IntoIterator<Item = (T, bool)>,
+        T: IntoIterator<Item = OsbToken<Weights>>,
+    {
+        // Sums of the token strengths, correct messages, and of the scores
+        // and labels for their correlation
+        #[derive(Clone, Copy, Default)]
+        struct Sums {
+            strength: f64,
+            correct: u64,
+            x: f64,
+            y: f64,
+            xy: f64,
+            xx: f64,
+            yy: f64,
+        }
+        let mut report = [PositionEffectiveness::default(); FEATURE_POSITIONS];
+        let mut sums = [Sums::default(); FEATURE_POSITIONS];
+
+        for (tokens, is_spam) in messages {
+            // Spam and ham log probabilities and token count of each position
+            let mut positions = [(0.0, 0.0, 0); FEATURE_POSITIONS];
+            for token in tokens {
+                let token = TokenInput::from(token);
+                if token.idx >= FEATURE_POSITIONS {
+                    continue;
+                }
+                if let Some((spam_prob, ham_prob)) =
+                    self.token_probs(&token, ham_learns, spam_learns, self.min_token_hits)
+                {
+                    let position = &mut positions[token.idx];
+                    position.0 += spam_prob.ln();
+                    position.1 += ham_prob.ln();
+                    position.2 += 1;
+                    report[token.idx].tokens += 1;
+                    sums[token.idx].strength += (spam_prob - 0.5).abs();
+                }
+            }
+
+            let label = if is_spam { 1.0 } else { 0.0 };
+            for (idx, &(spam_prob, ham_prob, count)) in positions.iter().enumerate() {
+                if count == 0 {
+                    continue;
+                }
+                let score = self.combine(spam_prob, ham_prob, count);
+                let sums = &mut sums[idx];
+                report[idx].messages += 1;
+                sums.correct += (score != 0.5 && (score > 0.5) == is_spam) as u64;
+                sums.x += score;
+                sums.y += label;
+                sums.xy += score * label;
+                sums.xx += score * score;
+                sums.yy += label * label;
+            }
+        }
+
+        for (position, sums) in report.iter_mut().zip(sums) {
+            if position.tokens > 0 {
+                position.mean_strength = sums.strength / position.tokens as f64;
+            }
+            if position.messages > 0 {
+                let n = position.messages as f64;
+                position.accuracy = sums.correct as f64 / n;
+                let cov = sums.xy / n - (sums.x / n) * (sums.y / n);
+                let var_x = sums.xx / n - (sums.x / n).powi(2);
+                let var_y = sums.yy / n - (sums.y / n).powi(2);
+                if var_x > f64::EPSILON && var_y > f64::EPSILON {
+                    position.correlation = cov / (var_x * var_y).sqrt();
+                }
+            }
+        }
+
+        report
+    }
+}
+
+/// A token ranked by how strongly it indicates a class.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TopToken {
+    pub hash: TokenHash,
+    pub weights: Weights,
+    // Probability of the token appearing in spam, normalized by the learns of each class
+    pub spam_prob: f64,
+}
+
+impl BayesModel {
+    /// Returns the `n` tokens seen at least `min_count` times with the most
+    /// extreme probability of belonging to the requested class. Ties are broken
+    /// by the number of times the token was seen, then by hash.
+    pub fn top_tokens(&self, is_spam: bool, n: usize, min_count: u32) -> Vec<TopToken> {
+        let spam_learns = f64::max(1.0, self.spam_learns as f64);
+        let ham_learns = f64::max(1.0, self.ham_learns as f64);
+        let mut tokens = self
+            .weights
+            .iter()
+            .filter(|(_, weights)| weights.spam.saturating_add(weights.ham) >= min_count.max(1))
+            .map(|(hash, weights)| {
+                let spam_freq = weights.spam as f64 / spam_learns;
+                let ham_freq = weights.ham as f64 / ham_learns;
+                TopToken {
+                    hash: *hash,
+                    weights: *weights,
+                    spam_prob: spam_freq / (spam_freq + ham_freq),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        tokens.sort_unstable_by(|a, b| {
+            let (a_prob, b_prob) = if is_spam {
+                (a.spam_prob, b.spam_prob)
+            } else {
+                (1.0 - a.spam_prob, 1.0 - b.spam_prob)
+            };
+            b_prob
+                .total_cmp(&a_prob)
+                .then_with(|| {
+                    b.weights
+                        .spam
+                        .saturating_add(b.weights.ham)
+                        .cmp(&a.weights.spam.saturating_add(a.weights.ham))
+                })
+                .then_with(|| a.hash.cmp(&b.hash))
+        });
+        tokens.truncate(n);
+        tokens
+    }
+}
+
+/// A token ranked by the mutual information of its presence with the label.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InformativeToken {
+    pub hash: TokenHash,
+    pub weights: Weights,
+    // In bits, from 0.0 for tokens independent of the label up to the
+    // entropy of the class priors
+    pub mutual_information: f64,
+}
+
+/// Returns the mutual information, in bits, between the presence of a token
+/// seen `weights` times and the spam label, given the learns of each class.
+/// Counts are taken as the number of messages of each class containing the
+/// token, capped at the learns of the class.
+pub fn mutual_information(weights: Weights, spam_learns: u32, ham_learns: u32) -> f64 {
+    let total = spam_learns as f64 + ham_learns as f64;
+    if total == 0.0 {
+        return 0.0;
+    }
+    let spam = weights.spam.min(spam_learns) as f64;
+    let ham = weights.ham.min(ham_learns) as f64;
+    let present = spam + ham;
+    // Messages with the token and class, with the totals of their row and column
+    [
+        (spam, present, spam_learns as f64),
+        (ham, present, ham_learns as f64),
+        (
+            spam_learns as f64 - spam,
+            total - present,
+            spam_learns as f64,
+        ),
+        (ham_learns as f64 - ham, total - present, ham_learns as f64),
+    ]
+    .into_iter()
+    .filter(|(count, _, _)| *count > 0.0)
+    .map(|(count, row, column)| count / total * (count * total / (row * column)).log2())
+    .sum::<f64>()
+    .max(0.0)
+}
+
+impl BayesModel {
+    /// Returns the `n` tokens seen at least `min_count` times with the highest
+    /// mutual information with the spam label, e.g. to select the features of
+    /// a compact model. Ties are broken by the number of times the token was
+    /// seen, then by hash.
+    pub fn informative_tokens(&self, n: usize, min_count: u32) -> Vec<InformativeToken> {
+        let mut tokens = self
+            .weights
+            .iter()
+            .filter(|(_, weights)| weights.spam.saturating_add(weights.ham) >= min_count.max(1))
+            .map(|(hash, weights)| InformativeToken {
+                hash: *hash,
+                weights: *weights,
+                mutual_information: mutual_information(*weights, self.spam_learns, self.ham_learns),
+            })
+            .collect::<Vec<_>>();
+
+        tokens.sort_unstable_by(|a, b| {
+            b.mutual_information
+                .total_cmp(&a.mutual_information)
+                .then_with(|| {
+                    b.weights
+                        .spam
+                        .saturating_add(b.weights.ham)
+                        .cmp(&a.weights.spam.saturating_add(a.weights.ham))
+                })
+                .then_with(|| a.hash.cmp(&b.hash))
+        });
+        tokens.truncate(n);
+        tokens
+    }
+}
+
+/// Agreement between the verdicts of two models over the same corpus.
+/// `confusion[a][b]` counts the messages given verdict `a` by the first model
+/// and `b` by the second, indexed by `AGREEMENT_HAM`, `AGREEMENT_SPAM` and
+/// `AGREEMENT_UNDECIDED`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModelAgreement {
+    pub confusion: [[u64; 3]; 3],
+    pub total: u64,
+}
+
+pub const AGREEMENT_HAM: usize = 0;
+pub const AGREEMENT_SPAM: usize = 1;
+pub const AGREEMENT_UNDECIDED: usize = 2;
+
+impl BayesModel {
+    /// Classifies each message of a corpus with this model and with `other`,
+    /// e.g. a candidate replacing it, and counts how their verdicts agree.
+    pub fn agreement<'x, M>(
+        &self,
+        classifier: &BayesClassifier,
+        other: &BayesModel,
+        other_classifier: &BayesClassifier,
+        messages: M,
+    ) -> ModelAgreement
+    where
+        M: IntoIterator<Item = &'x [OsbToken<TokenHash>]>,
+    {
+        let verdict = |score: Option<f64>| match score {
+            Some(score) if score > 0.5 => AGREEMENT_SPAM,
+            Some(_) => AGREEMENT_HAM,
+            None => AGREEMENT_UNDECIDED,
+        };
+        let mut agreement = ModelAgreement {
+            confusion: [[0; 3]; 3],
+            total: 0,
+        };
+
+        for tokens in messages {
+            let a = self.classify_token_hashes(classifier, tokens.iter().cloned());
+            let b = other.classify_token_hashes(other_classifier, tokens.iter().cloned());
+            agreement.confusion[verdict(a)][verdict(b)] += 1;
+            agreement.total += 1;
+        }
+
+        agreement
+    }
+}
+
+impl ModelAgreement {
+    /// Fraction of the messages given the same verdict by both models.
+    pub fn rate(&self) -> f64 {
+        if self.total > 0 {
+            (0..3).map(|i| self.confusion[i][i]).sum::<u64>() as f64 / self.total as f64
+        } else {
+            1.0
+        }
+    }
+
+    /// Cohen's kappa, the agreement corrected for the agreement expected by
+    /// chance given how often each model issues each verdict. Models that
+    /// always issue the same single verdict have a kappa of 1.0.
+    pub fn kappa(&self) -> f64 {
+        let total = self.total as f64;
+        let expected = (0..3)
+            .map(|i| {
+                let a = self.confusion[i].iter().sum::<u64>() as f64;
+                let b = self.confusion.iter().map(|row| row[i]).sum::<u64>() as f64;
+                a * b
+            })
+            .sum::<f64>()
+            / (total * total).max(1.0);
+
+        if expected < 1.0 {
+            (self.rate() - expected) / (1.0 - expected)
+        } else {
+            1.0
+        }
+    }
+}
+
+impl StrengthDistribution {
+    /// Upper strength limit of a bucket
+    pub fn bucket_limit(&self, bucket: usize) -> f64 {
+        0.5 * (bucket + 1) as f64 / self.buckets.len() as f64
+    }
+}
+
+/// Number of distinct tokens learned into each class.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClassDiversity {
+    pub spam_tokens: usize,
+    pub ham_tokens: usize,
+}
+
+impl BayesModel {
+    pub fn diversity(&self) -> ClassDiversity {
+        self.class_index.diversity(&self.weights)
+    }
+
+    /// Whether either class has fewer than `min_class_tokens` distinct tokens,
+    /// in which case no verdicts are returned.
+    pub(crate) fn lacks_diversity(&self, classifier: &BayesClassifier) -> bool {
+        classifier.min_class_tokens > 0 && {
+            let diversity = self.diversity();
+            diversity.spam_tokens < classifier.min_class_tokens
+                || diversity.ham_tokens < classifier.min_class_tokens
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        bayes::{
+            synthetic::SyntheticCorpus, train::MemoryBudget, BayesClassifier, BayesModel,
+            BayesVerdict, TokenHash, Weights,
+        },
+        tokenizers::osb::OsbToken,
+    };
+    use utils::suffixlist::PublicSuffix;
+
+    use super::{mutual_information, AGREEMENT_HAM, AGREEMENT_SPAM, AGREEMENT_UNDECIDED};
+
+    #[test]
+    fn strength_distribution() {
+        let token = |spam, ham| OsbToken {
+            inner: Weights { spam, ham },
+            idx: 0,
+        };
+        let classifier = BayesClassifier::default();
+
+        // With equal learns and a feature weight of 1.0:
+        // (1, 1) -> 0.5       strength 0.0
+        // (3, 1) -> 0.70833   strength 0.20833
+        // (2, 0) -> 0.875     strength 0.375
+        // (0, 4) -> 0.08333   strength 0.41667
+        // (1, 0) -> below min_token_hits
+        let distribution = classifier.prob_strength_distribution(
+            [
+                vec![token(1, 1), token(3, 1)],
+                vec![token(2, 0), token(0, 4), token(1, 0)],
+            ],
+            10,
+            10,
+            5,
+        );
+
+        assert_eq!(distribution.buckets, [1, 0, 1, 1, 1]);
+        assert_eq!(distribution.total, 4);
+        assert_eq!(distribution.bucket_limit(1), 0.2);
+    }
+
+    #[test]
+    fn position_effectiveness() {
+        let classifier = BayesClassifier::default();
+        let token = |idx, spam_heavy| OsbToken {
+            inner: if spam_heavy {
+                Weights { spam: 8, ham: 1 }
+            } else {
+                Weights { spam: 1, ham: 8 }
+            },
+            idx,
+        };
+
+        // Tokens at position 1 follow the label while tokens at position 2
+        // lean towards a class at random
+        let mut seed = 7u64;
+        let messages = (0..400)
+            .map(|i| {
+                seed = seed
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                let is_spam = i % 2 == 0;
+                (vec![token(1, is_spam), token(2, seed >> 63 == 1)], is_spam)
+            })
+            .collect::<Vec<_>>();
+        let report = classifier.position_effectiveness(messages, 100, 100);
+
+        assert_eq!(report[0], Default::default());
+        assert_eq!((report[1].messages, report[2].messages), (400, 400));
+        assert_eq!(report[1].accuracy, 1.0);
+        assert!(report[1].correlation > 0.99, "{:?}", report[1]);
+        assert!((0.4..0.6).contains(&report[2].accuracy), "{:?}", report[2]);
+        assert!(report[2].correlation.abs() < 0.2, "{:?}", report[2]);
+        // Both positions have strong tokens, yet only one is informative
+        assert!(report[1].mean_strength > 0.3 && report[2].mean_strength > 0.3);
+    }
+
+    #[test]
+    fn top_tokens() {
+        let mut model = BayesModel {
+            spam_learns: 10,
+            ham_learns: 20,
+            ..Default::default()
+        };
+        for (h1, spam, ham) in [
+            (1, 9, 2),
+            // Spammiest, but below the minimum count
+            (2, 2, 0),
+            (3, 10, 0),
+            (4, 5, 10),
+            (5, 1, 30),
+            (6, 20, 0),
+        ] {
+            model
+                .weights
+                .insert(TokenHash { h1, h2: 0 }, Weights { spam, ham });
+        }
+
+        let ranked = |is_spam| {
+            model
+                .top_tokens(is_spam, 3, 5)
+                .into_iter()
+                .map(|token| token.hash.h1)
+                .collect::<Vec<_>>()
+        };
+        // Equally spammy tokens are ranked by count
+        assert_eq!(ranked(true), [6, 3, 1]);
+        assert_eq!(ranked(false), [5, 4, 1]);
+        assert_eq!(model.top_tokens(true, 1, 5)[0].spam_prob, 1.0);
+
+        // Counts near the limit do not overflow
+        for h1 in [7, 8] {
+            model.weights.insert(
+                TokenHash { h1, h2: 0 },
+                Weights {
+                    spam: u32::MAX,
+                    ham: 1,
+                },
+            );
+        }
+        let ranked = model
+            .top_tokens(true, 3, u32::MAX)
+            .into_iter()
+            .map(|token| token.hash.h1)
+            .collect::<Vec<_>>();
+        assert_eq!(ranked, [7, 8]);
+    }
+
+    #[test]
+    fn informative_tokens() {
+        let mut model = BayesModel {
+            spam_learns: 100,
+            ham_learns: 100,
+            ..Default::default()
+        };
+        for (hash, spam, ham) in [(1, 50, 50), (2, 100, 0), (3, 0, 60), (4, 5, 5), (5, 70, 30)] {
+            model
+                .weights
+                .insert(TokenHash { h1: hash, h2: 0 }, Weights { spam, ham });
+        }
+
+        // A token in every spam and no ham determines the label
+        assert_eq!(
+            mutual_information(Weights { spam: 100, ham: 0 }, 100, 100),
+            1.0
+        );
+        assert_eq!(
+            mutual_information(Weights { spam: 50, ham: 50 }, 100, 100),
+            0.0
+        );
+        assert_eq!(mutual_information(Weights { spam: 5, ham: 0 }, 0, 0), 0.0);
+
+        let ranked = model
+            .informative_tokens(10, 1)
+            .into_iter()
+            .map(|token| token.hash.h1)
+            .collect::<Vec<_>>();
+        assert_eq!(ranked, [2, 3, 5, 1, 4]);
+        assert_eq!(model.informative_tokens(2, 20).len(), 2);
+
+        // Counts near the limit do not overflow
+        for h1 in [6, 7] {
+            model.weights.insert(
+                TokenHash { h1, h2: 0 },
+                Weights {
+                    spam: u32::MAX,
+                    ham: u32::MAX,
+                },
+            );
+        }
+        assert_eq!(model.informative_tokens(10, u32::MAX).len(), 2);
+    }
+
+    #[test]
+    fn model_agreement() {
+        let mut corpus = SyntheticCorpus::new(3);
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            ..Default::default()
+        };
+        let mut model = BayesModel::default();
+        let mut flipped = BayesModel::default();
+        for message in corpus.messages(100) {
+            model.train(message.tokens(), message.is_spam);
+            flipped.train(message.tokens(), !message.is_spam);
+        }
+        let test = corpus
+            .messages(50)
+            .iter()
+            .map(|message| message.tokens())
+            .collect::<Vec<_>>();
+        let messages = || test.iter().map(|tokens| tokens.as_slice());
+
+        let same = model.agreement(&classifier, &model, &classifier, messages());
+        assert_eq!(same.total, 100);
+        assert_eq!(same.rate(), 1.0);
+        assert_eq!(same.kappa(), 1.0);
+
+        // Opposite verdicts on every decided message, both models only agree
+        // on the messages neither can decide
+        let divergent = model.agreement(&classifier, &flipped, &classifier, messages());
+        assert_eq!(
+            divergent.rate(),
+            divergent.confusion[AGREEMENT_UNDECIDED][AGREEMENT_UNDECIDED] as f64 / 100.0
+        );
+        assert!(divergent.rate() < 0.5, "{divergent:?}");
+        assert!(divergent.kappa() < 0.0, "{divergent:?}");
+        assert_eq!(divergent.confusion[AGREEMENT_SPAM][AGREEMENT_SPAM], 0);
+        assert_eq!(divergent.confusion[AGREEMENT_HAM][AGREEMENT_HAM], 0);
+        assert_eq!(
+            divergent.confusion[AGREEMENT_SPAM][AGREEMENT_HAM],
+            same.confusion[AGREEMENT_SPAM][AGREEMENT_SPAM]
+        );
+    }
+
+    #[test]
+    fn diversity_counters() {
+        let tokens = |hashes: &[u64]| {
+            hashes
+                .iter()
+                .map(|&h1| OsbToken {
+                    inner: TokenHash { h1, h2: 0 },
+                    idx: 0,
+                })
+                .collect::<Vec<_>>()
+        };
+        let scanned = |model: &BayesModel| {
+            let count = |is_spam: bool| {
+                model
+                    .weights
+                    .values()
+                    .filter(|weights| if is_spam { weights.spam } else { weights.ham } > 0)
+                    .count()
+            };
+            (count(true), count(false))
+        };
+        let counted = |model: &BayesModel| {
+            let diversity = model.diversity();
+            (diversity.spam_tokens, diversity.ham_tokens)
+        };
+
+        let mut model = BayesModel::default();
+        model.train(tokens(&[1, 2, 3]), true);
+        assert_eq!(counted(&model), (3, 0));
+        model.train(tokens(&[3, 4]), false);
+        model.train(tokens(&[5, 6]), true);
+        assert_eq!(counted(&model), (5, 2));
+        model.untrain(tokens(&[5, 6]), true);
+        assert_eq!(counted(&model), scanned(&model));
+        model.relearn(tokens(&[1]), true, false);
+        assert_eq!(counted(&model), (2, 3));
+
+        model.class_token_limit = Some(3);
+        model.train(tokens(&[7, 8, 9]), true);
+        assert_eq!(counted(&model), scanned(&model));
+        model.prune_to_budget(MemoryBudget {
+            bytes: model.memory_usage() / 2,
+            strategy: Default::default(),
+        });
+        assert_eq!(counted(&model), scanned(&model));
+        model.decay(0.5);
+        assert_eq!(counted(&model), scanned(&model));
+    }
+
+    #[test]
+    fn class_diversity() {
+        let suffixes = PublicSuffix::default();
+        let classifier = BayesClassifier {
+            min_learns: 10,
+            min_tokens: 1,
+            min_class_tokens: 20,
+            ..Default::default()
+        };
+        let mut model = BayesModel::default();
+        for _ in 0..10 {
+            model
+                .train_text("claim your prize now", true, &suffixes)
+                .unwrap();
+            model
+                .train_text("lunch with the team", false, &suffixes)
+                .unwrap();
+        }
+        let text = "claim your prize now";
+        let diversity = model.diversity();
+        assert!(diversity.spam_tokens < 20, "{diversity:?}");
+        assert_eq!(
+            model.classify_text(&classifier, text, &suffixes).unwrap(),
+            None
+        );
+        assert_eq!(
+            model
+                .classify_text_verdict(&classifier, text, &suffixes)
+                .unwrap(),
+            BayesVerdict::Warming
+        );
+
+        for text in [
+            "limited offer on cheap watches and pills",
+            "your account won a free cruise vacation today",
+        ] {
+            model.train_text(text, true, &suffixes).unwrap();
+        }
+        for text in [
+            "the quarterly report is attached for review",
+            "please send the slides before the board meeting",
+        ] {
+            model.train_text(text, false, &suffixes).unwrap();
+        }
+        let diversity = model.diversity();
+        assert!(
+            diversity.spam_tokens >= 20 && diversity.ham_tokens >= 20,
+            "{diversity:?}"
+        );
+        assert!(matches!(
+            model
+                .classify_text_verdict(&classifier, text, &suffixes)
+                .unwrap(),
+            BayesVerdict::Score(score) if score > 0.5
+        ));
+    }
+}
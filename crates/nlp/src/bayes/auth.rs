@@ -0,0 +1,147 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::borrow::Cow;
+
+/// Emits a `<method>:<result>` token for each result of the
+/// `Authentication-Results` headers of a message, such as `dkim:pass` or
+/// `spf:softfail`. Other headers are ignored.
+///
+/// Whether a missing signature is suspicious depends on the history of the
+/// sender, so callers that know which methods a sender normally passes can
+/// supply them as expectations, and `<method>:missing_expected` is emitted
+/// for each expected method without a `pass` result.
+pub struct AuthResultsTokenizer<'x> {
+    tokens: std::vec::IntoIter<Cow<'x, str>>,
+}
+
+impl<'x> AuthResultsTokenizer<'x> {
+    /// Creates a tokenizer from `(name, value)` header pairs.
+    pub fn new(headers: &[(&str, &str)]) -> Self {
+        Self::with_expected(headers, &[])
+    }
+
+    /// Creates a tokenizer from `(name, value)` header pairs and the
+    /// authentication methods expected to pass, e.g. `["dkim"]`.
+    pub fn with_expected(headers: &[(&str, &str)], expected: &[&str]) -> Self {
+        let mut results = Vec::new();
+
+        for (name, value) in headers {
+            if !name.eq_ignore_ascii_case("Authentication-Results") {
+                continue;
+            }
+            // The first field is the identifier of the server that verified the message
+            for field in value.split(';').skip(1) {
+                let Some((method, result)) = field
+                    .split_whitespace()
+                    .next()
+                    .and_then(|result| result.split_once('='))
+                else {
+                    continue;
+                };
+                let method = method.trim().to_lowercase();
+                let result = result
+                    .trim()
+                    .trim_end_matches(|ch: char| !ch.is_ascii_alphanumeric())
+                    .to_lowercase();
+                if !method.is_empty() && !result.is_empty() {
+                    results.push((method, result));
+                }
+            }
+        }
+        results.sort_unstable();
+        results.dedup();
+
+        let mut tokens = results
+            .iter()
+            .map(|(method, result)| Cow::from(format!("{method}:{result}")))
+            .collect::<Vec<_>>();
+        for method in expected {
+            let method = method.trim().to_lowercase();
+            if !method.is_empty()
+                && !results
+                    .iter()
+                    .any(|(found, result)| *found == method && result == "pass")
+            {
+                tokens.push(Cow::from(format!("{method}:missing_expected")));
+            }
+        }
+
+        Self {
+            tokens: tokens.into_iter(),
+        }
+    }
+}
+
+impl<'x> Iterator for AuthResultsTokenizer<'x> {
+    type Item = Cow<'x, str>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.tokens.next()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::AuthResultsTokenizer;
+
+    #[test]
+    fn auth_results() {
+        let signed = [
+            ("From", "Example <news@example.com>"),
+            (
+                "Authentication-Results",
+                "mx.example.org; dkim=pass header.d=example.com; \
+                 spf=pass (sender allowed) smtp.mailfrom=example.com; DMARC=pass",
+            ),
+        ];
+        let unsigned = [(
+            "authentication-results",
+            "mx.example.org; dkim=none; spf=softfail smtp.mailfrom=example.com",
+        )];
+
+        assert_eq!(
+            AuthResultsTokenizer::new(&signed).collect::<Vec<_>>(),
+            ["dkim:pass", "dmarc:pass", "spf:pass"]
+        );
+        assert_eq!(
+            AuthResultsTokenizer::new(&unsigned).collect::<Vec<_>>(),
+            ["dkim:none", "spf:softfail"]
+        );
+
+        // The sender normally signs with DKIM
+        assert_eq!(
+            AuthResultsTokenizer::with_expected(&signed, &["dkim"]).collect::<Vec<_>>(),
+            ["dkim:pass", "dmarc:pass", "spf:pass"]
+        );
+        assert_eq!(
+            AuthResultsTokenizer::with_expected(&unsigned, &["DKIM"]).collect::<Vec<_>>(),
+            ["dkim:none", "spf:softfail", "dkim:missing_expected"]
+        );
+        assert_eq!(
+            AuthResultsTokenizer::with_expected(&[("Subject", "Hi")], &["dkim", "spf"])
+                .collect::<Vec<_>>(),
+            ["dkim:missing_expected", "spf:missing_expected"]
+        );
+    }
+}
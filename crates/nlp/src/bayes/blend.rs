@@ -0,0 +1,135 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use crate::tokenizers::osb::OsbToken;
+
+use super::{BayesClassifier, BayesModel, BayesResult, TokenHash};
+
+/// Score of a message blended from a global and a personal model.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlendedResult {
+    pub score: f64,
+    // Weight of the personal model in `score`, between 0.0 and 1.0
+    pub personal_weight: f64,
+    pub global: Option<BayesResult>,
+    pub personal: Option<BayesResult>,
+}
+
+impl BayesClassifier {
+    /// Classifies a message with a global and a personal model, blending their
+    /// scores by the share of the tokens each one processed. A message made of
+    /// tokens the user has trained leans on the personal model, while one
+    /// made of tokens only seen globally leans on the global model. Returns
+    /// None when neither model reaches a verdict.
+    pub fn classify_blended(
+        &self,
+        global: &BayesModel,
+        personal: &BayesModel,
+        tokens: &[OsbToken<TokenHash>],
+    ) -> Option<BlendedResult> {
+        let global_result = global.classify_token_hashes_detailed(self, tokens.iter().cloned());
+        let personal_result = personal.classify_token_hashes_detailed(self, tokens.iter().cloned());
+
+        let (score, personal_weight) = match (&global_result, &personal_result) {
+            (Some(global), Some(personal)) => {
+                let weight = personal.processed_tokens as f64
+                    / (personal.processed_tokens + global.processed_tokens) as f64;
+                (
+                    weight * personal.score + (1.0 - weight) * global.score,
+                    weight,
+                )
+            }
+            (Some(global), None) => (global.score, 0.0),
+            (None, Some(personal)) => (personal.score, 1.0),
+            (None, None) => return None,
+        };
+
+        Some(BlendedResult {
+            score,
+            personal_weight,
+            global: global_result,
+            personal: personal_result,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::borrow::Cow;
+
+    use crate::{
+        bayes::{BayesClassifier, BayesModel, TokenHash, OSB_WINDOW_SIZE},
+        tokenizers::osb::{OsbToken, OsbTokenizer},
+    };
+
+    fn tokens(text: &str) -> Vec<OsbToken<TokenHash>> {
+        OsbTokenizer::new(text.split(' ').map(Cow::from), OSB_WINDOW_SIZE).collect()
+    }
+
+    fn train(model: &mut BayesModel, text: &str, is_spam: bool) {
+        for _ in 0..5 {
+            model.train(tokens(text), is_spam);
+        }
+    }
+
+    #[test]
+    fn blended_classification() {
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 1,
+            ..Default::default()
+        };
+        let mut global = BayesModel::default();
+        train(&mut global, "claim your cash prize winner today", true);
+        train(&mut global, "the quarterly report for the meeting", false);
+        train(&mut global, "crypto airdrop", true);
+        let mut personal = BayesModel::default();
+        // This user subscribes to crypto newsletters
+        train(&mut personal, "crypto token airdrop wallet bonus", false);
+        train(&mut personal, "claim your cash prize", true);
+
+        let personal_message = classifier
+            .classify_blended(
+                &global,
+                &personal,
+                &tokens("crypto token airdrop wallet bonus"),
+            )
+            .unwrap();
+        let global_message = classifier
+            .classify_blended(
+                &global,
+                &personal,
+                &tokens("the quarterly report for the meeting today"),
+            )
+            .unwrap();
+
+        assert!(
+            personal_message.personal_weight > 0.5,
+            "{personal_message:?}"
+        );
+        assert!(personal_message.score < 0.5, "{personal_message:?}");
+        assert!(global_message.personal_weight < 0.5, "{global_message:?}");
+        assert!(global_message.score < 0.5, "{global_message:?}");
+        assert!(global_message.personal_weight < personal_message.personal_weight);
+    }
+}
@@ -0,0 +1,120 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::f64::consts::LN_2;
+
+use super::{BayesModel, TokenHash};
+
+/// Bloom filter of the tokens learned by a model, used to skip the store
+/// lookups of tokens that were never learned. It has no false negatives, so
+/// tokens learned after the filter was built must be inserted into it too.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenBloom {
+    bits: Vec<u64>,
+    hashes: u32,
+}
+
+impl TokenBloom {
+    /// Creates an empty filter sized for `capacity` tokens at the given rate
+    /// of false positives.
+    pub fn new(capacity: usize, false_positive_rate: f64) -> Self {
+        let capacity = capacity.max(1) as f64;
+        let false_positive_rate = false_positive_rate.clamp(f64::MIN_POSITIVE, 0.5);
+        let bits = (-capacity * false_positive_rate.ln() / (LN_2 * LN_2))
+            .ceil()
+            .max(64.0) as usize;
+        let words = bits.div_ceil(64);
+        TokenBloom {
+            bits: vec![0; words],
+            hashes: ((words * 64) as f64 / capacity * LN_2)
+                .round()
+                .clamp(1.0, 16.0) as u32,
+        }
+    }
+
+    /// Creates a filter containing all the tokens of a model.
+    pub fn from_model(model: &BayesModel, false_positive_rate: f64) -> Self {
+        let mut bloom = TokenBloom::new(model.weights.len(), false_positive_rate);
+        for hash in model.weights.keys() {
+            bloom.insert(hash);
+        }
+        bloom
+    }
+
+    pub fn insert(&mut self, hash: &TokenHash) {
+        for bit in self.bits(hash) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+
+    /// Whether a token may have been inserted. False positives happen at about
+    /// the rate the filter was sized for, as long as it is not overfilled.
+    pub fn contains(&self, hash: &TokenHash) -> bool {
+        self.bits(hash)
+            .all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    // Double hashing of the two halves of the token hash, mixed so that
+    // tokens differing in a few bits of either half set unrelated bits
+    fn bits(&self, hash: &TokenHash) -> impl Iterator<Item = usize> {
+        let len = (self.bits.len() * 64) as u64;
+        let h1 = hash.h1.wrapping_mul(0xff51_afd7_ed55_8ccd) ^ hash.h2;
+        let h2 = (hash.h2 ^ hash.h1.rotate_left(32)).wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+        (0..self.hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len) as usize)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        bayes::{BayesModel, TokenHash},
+        tokenizers::osb::OsbToken,
+    };
+
+    use super::TokenBloom;
+
+    #[test]
+    fn token_bloom() {
+        let hash = |h1| TokenHash { h1, h2: 0 };
+        let mut model = BayesModel::default();
+        model.train(
+            (0..1000).map(|h1| OsbToken {
+                inner: hash(h1),
+                idx: 0,
+            }),
+            true,
+        );
+
+        let bloom = TokenBloom::from_model(&model, 0.01);
+        assert!((0..1000).all(|h1| bloom.contains(&hash(h1))));
+        let false_positives = (1000..11000)
+            .filter(|h1| bloom.contains(&hash(*h1)))
+            .count();
+        assert!(false_positives < 300, "{false_positives}");
+
+        let mut bloom = TokenBloom::new(10, 0.01);
+        assert!(!bloom.contains(&hash(1)));
+        bloom.insert(&hash(1));
+        assert!(bloom.contains(&hash(1)));
+    }
+}
@@ -30,7 +30,20 @@ use lru_cache::LruCache;
 use nohash::NoHashHasher;
 use parking_lot::Mutex;
 
-use super::{TokenHash, Weights};
+#[cfg(feature = "store")]
+use super::{namespace::Namespace, store::TokenStore};
+use super::{BayesClassifier, TokenHash, Weights};
+
+#[derive(Debug)]
+pub struct BayesVerdictCache {
+    verdicts: Mutex<LruCache<u64, CachedVerdict, BuildHasherDefault<NoHashHasher<u64>>>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct CachedVerdict {
+    version: u64,
+    score: Option<f64>,
+}
 
 #[derive(Debug)]
 pub struct BayesTokenCache {
@@ -99,6 +112,16 @@ impl BayesTokenCache {
             .insert(hash, Instant::now() + self.ttl_negative);
     }
 
+    /// Caches the weights fetched from a store, as a negative entry when the
+    /// token was never learned.
+    pub fn insert_weights(&self, hash: TokenHash, weights: Weights) {
+        if weights == Weights::default() {
+            self.insert_negative(hash);
+        } else {
+            self.insert_positive(hash, weights);
+        }
+    }
+
     pub fn invalidate(&self, hash: &TokenHash) {
         if self.positive.lock().remove(hash).is_none() {
             self.negative.lock().remove(hash);
@@ -106,6 +129,87 @@ impl BayesTokenCache {
     }
 }
 
+#[cfg(feature = "store")]
+impl BayesTokenCache {
+    /// Loads the `n` most frequently seen tokens of a store into the cache,
+    /// so that the first classifications after startup do not have to query
+    /// the store for common tokens. Returns the number of tokens loaded.
+    pub async fn warm_cache<S: TokenStore>(&self, store: &S, n: usize) -> Result<usize, S::Error> {
+        let tokens = store.hottest_tokens(n).await?;
+        let count = tokens.len();
+        for (hash, weights) in tokens {
+            self.insert_positive(hash, weights);
+        }
+        Ok(count)
+    }
+}
+
+/// Token store that answers lookups from a `BayesTokenCache` when possible,
+/// caching the weights fetched from the underlying store.
+#[cfg(feature = "store")]
+pub struct CachedStore<'x, S> {
+    store: &'x S,
+    cache: &'x BayesTokenCache,
+}
+
+#[cfg(feature = "store")]
+impl<'x, S: TokenStore> CachedStore<'x, S> {
+    pub fn new(store: &'x S, cache: &'x BayesTokenCache) -> Self {
+        CachedStore { store, cache }
+    }
+}
+
+#[cfg(feature = "store")]
+impl<S: TokenStore> TokenStore for CachedStore<'_, S> {
+    type Error = S::Error;
+
+    async fn get_weights(&self, hash: TokenHash) -> Result<Weights, Self::Error> {
+        if let Some(weights) = self.cache.get(&hash) {
+            return Ok(weights.unwrap_or_default());
+        }
+        let weights = self.store.get_weights(hash).await?;
+        self.cache.insert_weights(hash, weights);
+        Ok(weights)
+    }
+
+    async fn lookup_many(&self, hashes: &[TokenHash]) -> Result<Vec<Weights>, Self::Error> {
+        let mut weights = Vec::with_capacity(hashes.len());
+        let mut missing = Vec::new();
+        for (pos, hash) in hashes.iter().enumerate() {
+            match self.cache.get(hash) {
+                Some(cached) => weights.push(cached.unwrap_or_default()),
+                None => {
+                    weights.push(Weights::default());
+                    missing.push(pos);
+                }
+            }
+        }
+
+        if !missing.is_empty() {
+            let missing_hashes = missing.iter().map(|pos| hashes[*pos]).collect::<Vec<_>>();
+            let fetched = self.store.lookup_many(&missing_hashes).await?;
+            for (pos, fetched) in missing.into_iter().zip(fetched) {
+                self.cache.insert_weights(hashes[pos], fetched);
+                weights[pos] = fetched;
+            }
+        }
+
+        Ok(weights)
+    }
+
+    async fn learns(&self) -> Result<(u32, u32), Self::Error> {
+        self.store.learns().await
+    }
+
+    async fn namespace_learns(&self, namespace: Namespace) -> Result<(u32, u32), Self::Error> {
+        self.store.namespace_learns(namespace).await
+    }
+
+    async fn hottest_tokens(&self, n: usize) -> Result<Vec<(TokenHash, Weights)>, Self::Error> {
+        self.store.hottest_tokens(n).await
+    }
+}
+
 impl Default for BayesTokenCache {
     fn default() -> Self {
         Self {
@@ -127,3 +231,162 @@ impl Clone for BayesTokenCache {
         }
     }
 }
+
+impl BayesVerdictCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            verdicts: Mutex::new(LruCache::with_hasher(capacity, Default::default())),
+        }
+    }
+
+    pub fn fingerprint(text: &str) -> u64 {
+        xxhash_rust::xxh3::xxh3_64(text.as_bytes())
+    }
+
+    /// Fingerprint of a text classified with a classifier, so that verdicts
+    /// are not shared between classifiers or across parameter changes.
+    pub fn classifier_fingerprint(text: &str, classifier: &BayesClassifier) -> u64 {
+        xxhash_rust::xxh3::xxh3_64_with_seed(text.as_bytes(), classifier.config_fingerprint())
+    }
+
+    /// Returns the cached score for a fingerprint, as long as it was
+    /// computed with the same model version.
+    pub fn get(&self, fingerprint: u64, version: u64) -> Option<Option<f64>> {
+        let mut verdicts = self.verdicts.lock();
+        match verdicts.get_mut(&fingerprint) {
+            Some(entry) if entry.version == version => Some(entry.score),
+            Some(_) => {
+                verdicts.remove(&fingerprint);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn insert(&self, fingerprint: u64, version: u64, score: Option<f64>) {
+        self.verdicts
+            .lock()
+            .insert(fingerprint, CachedVerdict { version, score });
+    }
+
+    pub fn len(&self) -> usize {
+        self.verdicts.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for BayesVerdictCache {
+    fn default() -> Self {
+        Self::new(1024)
+    }
+}
+
+impl Clone for BayesVerdictCache {
+    fn clone(&self) -> Self {
+        Self {
+            verdicts: Mutex::new(self.verdicts.lock().clone()),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "store"))]
+mod test {
+    use std::{
+        convert::Infallible,
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use crate::{
+        bayes::{store::TokenStore, BayesClassifier, BayesModel, TokenHash, Weights},
+        tokenizers::osb::OsbToken,
+    };
+
+    use super::{BayesTokenCache, CachedStore};
+
+    // Counts the token lookups issued to a model
+    #[derive(Default)]
+    struct CountingStore {
+        model: BayesModel,
+        lookups: AtomicUsize,
+    }
+
+    impl TokenStore for CountingStore {
+        type Error = Infallible;
+
+        async fn get_weights(&self, hash: TokenHash) -> Result<Weights, Self::Error> {
+            self.lookups.fetch_add(1, Ordering::Relaxed);
+            self.model.get_weights(hash).await
+        }
+
+        async fn learns(&self) -> Result<(u32, u32), Self::Error> {
+            self.model.learns().await
+        }
+
+        async fn hottest_tokens(&self, n: usize) -> Result<Vec<(TokenHash, Weights)>, Self::Error> {
+            self.model.hottest_tokens(n).await
+        }
+    }
+
+    fn tokens(hashes: &[u64]) -> Vec<OsbToken<TokenHash>> {
+        hashes
+            .iter()
+            .map(|&h1| OsbToken {
+                inner: TokenHash { h1, h2: 0 },
+                idx: 0,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn warm_cache() {
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 1,
+            min_token_hits: 1,
+            ..Default::default()
+        };
+        let mut store = CountingStore::default();
+        for _ in 0..3 {
+            store.model.train(tokens(&[1, 2, 3]), true);
+            store.model.train(tokens(&[4, 5, 6]), false);
+        }
+        store.model.train(tokens(&[7, 8]), true);
+
+        let cache = BayesTokenCache::new(16, Duration::from_secs(60), Duration::from_secs(60));
+        assert_eq!(cache.warm_cache(&store, 6).await.unwrap(), 6);
+        assert_eq!(cache.get(&TokenHash { h1: 7, h2: 0 }), None);
+        assert_eq!(
+            cache.get(&TokenHash { h1: 1, h2: 0 }),
+            Some(Some(Weights { spam: 3, ham: 0 }))
+        );
+
+        // Common tokens are answered by the cache alone
+        let cached = CachedStore::new(&store, &cache);
+        let message = tokens(&[1, 2, 4]);
+        let result = classifier
+            .classify_async(&cached, message.clone())
+            .await
+            .unwrap();
+        assert_eq!(store.lookups.load(Ordering::Relaxed), 0);
+        assert_eq!(
+            result,
+            classifier
+                .classify_async(&store.model, message)
+                .await
+                .unwrap()
+        );
+
+        // Other tokens are fetched once, then cached
+        for _ in 0..2 {
+            classifier
+                .classify_async(&cached, tokens(&[1, 7, 9]))
+                .await
+                .unwrap();
+            assert_eq!(store.lookups.load(Ordering::Relaxed), 2);
+        }
+    }
+}
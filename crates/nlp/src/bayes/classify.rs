@@ -23,10 +23,45 @@
 
 use crate::tokenizers::osb::OsbToken;
 
-use super::{BayesClassifier, Weights};
+use super::{
+    BayesClassifier, BayesResult, BayesVerdict, ClassifyOverrides, FisherCutoff, GapDecay,
+    SingleClassPolicy, Smoothing, Tier, TieredResult, TokenCombination, TokenHash, UncertainBand,
+    Weights, FEATURE_POSITIONS, OSB_WINDOW_SIZE,
+};
 
-// Position 0 represents Unigram weights
-const FEATURE_WEIGHT: [f64; 8] = [1.0, 3125.0, 256.0, 27.0, 1.0, 0.0, 0.0, 0.0];
+// Position 0 represents Unigram weights, position 5 character n-grams and
+// position 6 the features of external extractors
+const FEATURE_WEIGHT: [f64; FEATURE_POSITIONS] = [1.0, 3125.0, 256.0, 27.0, 1.0, 0.5, 0.0, 0.0];
+
+// Number of processed tokens at which the confidence reaches 0.5
+const CONFIDENCE_TOKENS: f64 = 20.0;
+
+// Probabilities are clamped to [e, 1 - e] before computing log-odds
+const LOG_ODDS_EPSILON: f64 = 1e-12;
+
+/// Error returned by `BayesClassifier::try_classify` for tokens that would
+/// otherwise make the classification panic or silently misbehave.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClassifyError {
+    // The token position is outside of `FEATURE_WEIGHT`, or its combined
+    // spam and ham counts overflow
+    MalformedToken { idx: usize, weights: Weights },
+}
+
+// Token as seen by the classification loop. The loop assumes that `idx` is
+// within `FEATURE_WEIGHT` and that `weights.spam + weights.ham` does not
+// overflow, which holds for tokens produced by `OsbTokenizer` (or the n-gram
+// and feature extractors) and counted by `BayesModel::train`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct TokenInput {
+    pub weights: Weights,
+    pub idx: usize,
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub hash: Option<TokenHash>,
+    pub doc_freq: Option<u32>,
+    // Spam probability known in advance, e.g. read from a quantized model
+    pub spam_prob: Option<f64>,
+}
 
 // Credits: ported from RSpamd
 impl BayesClassifier {
@@ -34,64 +69,511 @@ impl BayesClassifier {
     where
         T: Iterator<Item = OsbToken<Weights>>,
     {
-        if self.min_learns > 0 && (spam_learns < self.min_learns || ham_learns < self.min_learns) {
+        self.classify_detailed(tokens, ham_learns, spam_learns)
+            .map(|result| result.score)
+    }
+
+    /// Same as `classify_detailed` for tokens paired with their hash, which is
+    /// required for `token_allow` and `token_deny` to apply.
+    pub fn classify_hashed<T>(
+        &self,
+        tokens: T,
+        ham_learns: u32,
+        spam_learns: u32,
+    ) -> Option<BayesResult>
+    where
+        T: Iterator<Item = (TokenHash, OsbToken<Weights>)>,
+    {
+        self.classify_tokens(
+            tokens.map(|(hash, token)| TokenInput {
+                hash: Some(hash),
+                ..TokenInput::from(token)
+            }),
+            ham_learns,
+            spam_learns,
+        )
+    }
+
+    /// Same as `classify`, returning the log-odds `ln(p / (1 - p))` of the
+    /// spam probability instead.
+    pub fn classify_logodds<T>(&self, tokens: T, ham_learns: u32, spam_learns: u32) -> Option<f64>
+    where
+        T: Iterator<Item = OsbToken<Weights>>,
+    {
+        self.classify(tokens, ham_learns, spam_learns).map(log_odds)
+    }
+
+    /// Classifies features given as `(idx, spam_count, ham_count)` triples,
+    /// where `idx` is the position of the feature within the OSB window.
+    /// No tokenization or store is involved and the math is exactly that of
+    /// `classify`, so other implementations of the scorer can be checked
+    /// against it on identical inputs.
+    pub fn classify_features(
+        &self,
+        features: &[(usize, u32, u32)],
+        ham_learns: u32,
+        spam_learns: u32,
+    ) -> Option<f64> {
+        self.classify(
+            features.iter().map(|&(idx, spam, ham)| OsbToken {
+                inner: Weights { spam, ham },
+                idx,
+            }),
+            ham_learns,
+            spam_learns,
+        )
+    }
+
+    /// Same as `classify`, combining the score with an external prior such as
+    /// a sender reputation. The prior is given as log-odds, positive values
+    /// leaning spam and negative ones ham, and is added to the log-odds of the
+    /// token-derived score before converting it back into a probability:
+    /// `p = 1 / (1 + e^-(ln(score / (1 - score)) + prior))`. A prior of zero
+    /// leaves the score unchanged, and no verdict is returned when the tokens
+    /// alone do not produce one.
+    pub fn classify_with_prior<T>(
+        &self,
+        tokens: T,
+        ham_learns: u32,
+        spam_learns: u32,
+        prior_log_odds: f64,
+    ) -> Option<f64>
+    where
+        T: Iterator<Item = OsbToken<Weights>>,
+    {
+        self.classify(tokens, ham_learns, spam_learns)
+            .map(|score| apply_prior(score, prior_log_odds))
+    }
+
+    /// Same as `classify`, also returning the number of processed tokens and
+    /// a confidence derived from it.
+    pub fn classify_detailed<T>(
+        &self,
+        tokens: T,
+        ham_learns: u32,
+        spam_learns: u32,
+    ) -> Option<BayesResult>
+    where
+        T: Iterator<Item = OsbToken<Weights>>,
+    {
+        self.classify_tokens(tokens.map(TokenInput::from), ham_learns, spam_learns)
+    }
+
+    /// Classifies with this classifier first and, when it reaches no verdict
+    /// or a score within `band`, with `secondary`, which receives the result
+    /// of this classifier and is never called for clear cases. The uncertain
+    /// result is kept when `secondary` returns None as well.
+    pub fn classify_tiered<T, F>(
+        &self,
+        tokens: T,
+        ham_learns: u32,
+        spam_learns: u32,
+        band: &UncertainBand,
+        secondary: F,
+    ) -> TieredResult
+    where
+        T: Iterator<Item = OsbToken<Weights>>,
+        F: FnOnce(Option<BayesResult>) -> Option<f64>,
+    {
+        let primary = self.classify_detailed(tokens, ham_learns, spam_learns);
+        let score = primary.map(|result| result.score);
+        if score.is_some_and(|score| score < band.low || score > band.high) {
+            return TieredResult {
+                score,
+                tier: Tier::Primary,
+            };
+        }
+
+        match secondary(primary) {
+            Some(score) => TieredResult {
+                score: Some(score),
+                tier: Tier::Secondary,
+            },
+            None => TieredResult {
+                score,
+                tier: Tier::Primary,
+            },
+        }
+    }
+
+    /// Same as `classify_detailed`, validating every token first so that
+    /// tokens from untrusted sources, e.g. deserialized from another system,
+    /// return `ClassifyError::MalformedToken` instead of panicking. The
+    /// classification stops at the first malformed token.
+    pub fn try_classify<T>(
+        &self,
+        tokens: T,
+        ham_learns: u32,
+        spam_learns: u32,
+    ) -> Result<Option<BayesResult>, ClassifyError>
+    where
+        T: Iterator<Item = OsbToken<Weights>>,
+    {
+        let mut error = None;
+        let result = self.classify_tokens(
+            tokens.map_while(|token| {
+                if token.idx < FEATURE_WEIGHT.len()
+                    && token.inner.spam.checked_add(token.inner.ham).is_some()
+                {
+                    Some(TokenInput::from(token))
+                } else {
+                    error = Some(ClassifyError::MalformedToken {
+                        idx: token.idx,
+                        weights: token.inner,
+                    });
+                    None
+                }
+            }),
+            ham_learns,
+            spam_learns,
+        );
+
+        error.map_or(Ok(result), Err)
+    }
+
+    /// Same as `classify`, returning `BayesVerdict::Warming` while fewer than
+    /// `warmup_messages` messages were learned by the model, or while it has
+    /// only learned one class as configured by `single_class`.
+    pub fn classify_verdict<T>(
+        &self,
+        tokens: T,
+        ham_learns: u32,
+        spam_learns: u32,
+        learned_messages: u64,
+    ) -> BayesVerdict
+    where
+        T: Iterator<Item = OsbToken<Weights>>,
+    {
+        if self.is_warming(ham_learns, spam_learns, learned_messages) {
+            BayesVerdict::Warming
+        } else {
+            self.classify(tokens, ham_learns, spam_learns)
+                .map_or(BayesVerdict::Undecided, BayesVerdict::Score)
+        }
+    }
+
+    /// Whether a score is at or above `spam_threshold`.
+    pub fn is_spam(&self, score: f64) -> bool {
+        score >= self.spam_threshold
+    }
+
+    /// Whether a model is still in its warm-up period, see `classify_verdict`.
+    pub fn is_warming(&self, ham_learns: u32, spam_learns: u32, learned_messages: u64) -> bool {
+        learned_messages < self.warmup_messages || self.is_single_class(ham_learns, spam_learns)
+    }
+
+    fn is_single_class(&self, ham_learns: u32, spam_learns: u32) -> bool {
+        self.single_class == SingleClassPolicy::Warming && (ham_learns == 0 || spam_learns == 0)
+    }
+
+    /// Classifies tokens paired with the number of trained messages they appeared in.
+    /// When `idf_weighting` is enabled, tokens present in most messages are pulled
+    /// towards a neutral probability.
+    pub fn classify_with_doc_freq<T>(
+        &self,
+        tokens: T,
+        ham_learns: u32,
+        spam_learns: u32,
+    ) -> Option<f64>
+    where
+        T: Iterator<Item = (OsbToken<Weights>, u32)>,
+    {
+        let idf_weighting = self.idf_weighting;
+        self.classify_tokens(
+            tokens.map(|(token, doc_freq)| TokenInput {
+                doc_freq: Some(doc_freq).filter(|_| idf_weighting),
+                ..TokenInput::from(token)
+            }),
+            ham_learns,
+            spam_learns,
+        )
+        .map(|result| result.score)
+    }
+
+    /// Same as `classify`, replacing some of the classifier thresholds
+    /// for this call only.
+    pub fn classify_with_overrides<T>(
+        &self,
+        tokens: T,
+        ham_learns: u32,
+        spam_learns: u32,
+        overrides: &ClassifyOverrides,
+    ) -> Option<f64>
+    where
+        T: Iterator<Item = OsbToken<Weights>>,
+    {
+        self.classify_tokens_with(
+            tokens.map(TokenInput::from),
+            ham_learns,
+            spam_learns,
+            overrides,
+        )
+        .map(|result| result.score)
+    }
+
+    pub(crate) fn classify_tokens<T>(
+        &self,
+        tokens: T,
+        ham_learns: u32,
+        spam_learns: u32,
+    ) -> Option<BayesResult>
+    where
+        T: Iterator<Item = TokenInput>,
+    {
+        self.classify_tokens_with(
+            tokens,
+            ham_learns,
+            spam_learns,
+            &ClassifyOverrides::default(),
+        )
+    }
+
+    fn classify_tokens_with<T>(
+        &self,
+        tokens: T,
+        ham_learns: u32,
+        spam_learns: u32,
+        overrides: &ClassifyOverrides,
+    ) -> Option<BayesResult>
+    where
+        T: Iterator<Item = TokenInput>,
+    {
+        #[cfg(feature = "std")]
+        if !self.spam_floor.is_empty() {
+            return self.classify_with_floor(tokens, ham_learns, spam_learns, overrides);
+        }
+
+        self.classify_bayes(tokens, ham_learns, spam_learns, overrides)
+    }
+
+    /// Raises the score of messages containing a `spam_floor` token to at
+    /// least `spam_floor_score`, even when no verdict would be reached
+    /// otherwise. Floored scores are never auto-learned.
+    #[cfg(feature = "std")]
+    fn classify_with_floor<T>(
+        &self,
+        tokens: T,
+        ham_learns: u32,
+        spam_learns: u32,
+        overrides: &ClassifyOverrides,
+    ) -> Option<BayesResult>
+    where
+        T: Iterator<Item = TokenInput>,
+    {
+        let mut floor_token = None;
+        let mut tokens = tokens.inspect(|token| {
+            if floor_token.is_none() {
+                floor_token = token.hash.filter(|hash| self.spam_floor.contains(hash));
+            }
+        });
+        let result = self.classify_bayes(tokens.by_ref(), ham_learns, spam_learns, overrides);
+        // The classification can stop early, e.g. when the model has too few learns
+        tokens.for_each(drop);
+
+        let Some(hash) = floor_token else {
+            return result;
+        };
+        match result {
+            Some(result) if result.score >= self.spam_floor_score => Some(result),
+            Some(result) => Some(BayesResult {
+                score: self.spam_floor_score,
+                autolearn: None,
+                floor_token: Some(hash),
+                ..result
+            }),
+            None => Some(BayesResult {
+                score: self.spam_floor_score,
+                processed_tokens: 0,
+                confidence: 0.0,
+                autolearn: None,
+                floor_token: Some(hash),
+                goodword_attack: false,
+            }),
+        }
+    }
+
+    fn classify_bayes<T>(
+        &self,
+        tokens: T,
+        ham_learns: u32,
+        spam_learns: u32,
+        overrides: &ClassifyOverrides,
+    ) -> Option<BayesResult>
+    where
+        T: Iterator<Item = TokenInput>,
+    {
+        let min_token_hits = overrides.min_token_hits.unwrap_or(self.min_token_hits);
+        let min_tokens = overrides.min_tokens.unwrap_or(self.min_tokens);
+        let decision_margin = overrides.decision_margin.unwrap_or(self.decision_margin);
+
+        if self.min_learns > 0 && (spam_learns < self.min_learns || ham_learns < self.min_learns)
+            || self.is_single_class(ham_learns, spam_learns)
+        {
             return None;
         }
 
         let mut processed_tokens = 0;
         let mut total_spam_prob = 0.0;
         let mut total_ham_prob = 0.0;
+        let mut evidence = 0.0;
+        // Spam and ham log probabilities and token count of each position
+        let mut positions = [(0.0, 0.0, 0); FEATURE_POSITIONS];
+        // Earlier occurrences of each token, for `repeat_decay`
+        #[cfg(feature = "std")]
+        let mut occurrences = std::collections::HashMap::new();
+        // Token probabilities, summed in a canonical order once all tokens
+        // are known when `stable_summation` or `goodword_defense` is enabled
+        #[cfg(feature = "std")]
+        let mut contributions = Vec::new();
+        // Returns whether the evidence so far is decisive, see `early_exit`
+        let mut add = |bayes_spam_prob: f64, bayes_ham_prob: f64, idx: usize| {
+            total_spam_prob += ln(bayes_spam_prob);
+            total_ham_prob += ln(bayes_ham_prob);
+            evidence += (bayes_spam_prob - 0.5).abs();
+            processed_tokens += 1;
+
+            let position = &mut positions[idx];
+            position.0 += ln(bayes_spam_prob);
+            position.1 += ln(bayes_ham_prob);
+            position.2 += 1;
+
+            self.early_exit.is_some_and(|gap| {
+                processed_tokens >= min_tokens && (total_spam_prob - total_ham_prob).abs() >= gap
+            })
+        };
 
         for token in tokens {
-            let weights = token.inner;
-            let total_count = weights.spam + weights.ham;
-
-            if total_count >= self.min_token_hits {
-                let total_count = total_count as f64;
-                let spam_freq = weights.spam as f64 / f64::max(1.0, spam_learns as f64);
-                let ham_freq = weights.ham as f64 / f64::max(1.0, ham_learns as f64);
-                let spam_prob = spam_freq / (spam_freq + ham_freq);
-                let ham_prob = ham_freq / (spam_freq + ham_freq);
-
-                let fw = FEATURE_WEIGHT[token.idx];
-                let w = (fw * total_count) / (1.0 + fw * total_count);
-                let bayes_spam_prob = prob_combine(spam_prob, total_count, w, 0.5);
-
-                if !((bayes_spam_prob > 0.5 && bayes_spam_prob < 0.5 + self.min_prob_strength)
-                    || (bayes_spam_prob < 0.5 && bayes_spam_prob > 0.5 - self.min_prob_strength))
-                {
-                    let bayes_ham_prob = prob_combine(ham_prob, total_count, w, 0.5);
-                    total_spam_prob += bayes_spam_prob.ln();
-                    total_ham_prob += bayes_ham_prob.ln();
-                    processed_tokens += 1;
+            if let Some((bayes_spam_prob, bayes_ham_prob)) =
+                self.token_probs(&token, ham_learns, spam_learns, min_token_hits)
+            {
+                #[cfg(feature = "std")]
+                let (bayes_spam_prob, bayes_ham_prob) = match (self.repeat_decay, token.hash) {
+                    (Some(decay), Some(hash)) => {
+                        let repeats = occurrences.entry(hash).or_insert(0);
+                        let factor = decay.powi(*repeats);
+                        *repeats += 1;
+                        (
+                            0.5 + (bayes_spam_prob - 0.5) * factor,
+                            0.5 + (bayes_ham_prob - 0.5) * factor,
+                        )
+                    }
+                    _ => (bayes_spam_prob, bayes_ham_prob),
+                };
+                #[cfg(feature = "std")]
+                if self.stable_summation || self.goodword_defense.is_some() {
+                    contributions.push((bayes_spam_prob, bayes_ham_prob, token.idx));
+                    continue;
+                }
+                if add(bayes_spam_prob, bayes_ham_prob, token.idx) {
+                    break;
                 }
             }
         }
+        #[cfg(feature = "std")]
+        let goodword_attack = self
+            .goodword_defense
+            .is_some_and(|defense| defense.apply(&mut contributions, self.min_prob_strength));
+        #[cfg(not(feature = "std"))]
+        let goodword_attack = false;
+        #[cfg(feature = "std")]
+        {
+            if self.stable_summation {
+                contributions.sort_unstable_by(|a, b| {
+                    a.0.total_cmp(&b.0)
+                        .then_with(|| a.1.total_cmp(&b.1))
+                        .then_with(|| a.2.cmp(&b.2))
+                });
+            }
+            for (bayes_spam_prob, bayes_ham_prob, idx) in contributions {
+                add(bayes_spam_prob, bayes_ham_prob, idx);
+            }
+        }
 
-        if processed_tokens == 0 || self.min_tokens > 0 && processed_tokens < self.min_tokens {
+        if processed_tokens == 0
+            || min_tokens > 0 && processed_tokens < min_tokens
+            || evidence < self.min_evidence
+        {
             return None;
         }
 
-        let (h, s) = if total_spam_prob > -300.0 && total_ham_prob > -300.0 {
+        let final_prob = match &self.combination {
+            TokenCombination::Flat => {
+                self.combine(total_spam_prob, total_ham_prob, processed_tokens)
+            }
+            TokenCombination::Positional { weights } => {
+                let (mut total_spam_prob, mut total_ham_prob, mut combined) = (0.0, 0.0, 0);
+                for (idx, &(spam_prob, ham_prob, count)) in positions.iter().enumerate() {
+                    if count > 0 && weights[idx] > 0.0 {
+                        let score = self
+                            .combine(spam_prob, ham_prob, count)
+                            .clamp(LOG_ODDS_EPSILON, 1.0 - LOG_ODDS_EPSILON);
+                        total_spam_prob += weights[idx] * ln(score);
+                        total_ham_prob += weights[idx] * ln(1.0 - score);
+                        combined += 1;
+                    }
+                }
+                if combined == 0 {
+                    return None;
+                }
+                self.combine(total_spam_prob, total_ham_prob, combined)
+            }
+        };
+
+        if processed_tokens > 0 && (final_prob - 0.5).abs() > decision_margin {
+            Some(BayesResult {
+                score: final_prob,
+                processed_tokens,
+                confidence: confidence(processed_tokens),
+                autolearn: if processed_tokens < self.autolearn_min_tokens {
+                    None
+                } else if final_prob >= self.autolearn_spam {
+                    Some(true)
+                } else if final_prob <= self.autolearn_ham {
+                    Some(false)
+                } else {
+                    None
+                },
+                floor_token: None,
+                goodword_attack,
+            })
+        } else {
+            None
+        }
+    }
+
+    // Combines the spam and ham log probabilities of `processed_tokens`
+    // tokens into a score
+    pub(crate) fn combine(
+        &self,
+        total_spam_prob: f64,
+        total_ham_prob: f64,
+        processed_tokens: u32,
+    ) -> f64 {
+        let (h, s) = if self.use_chi_square(total_spam_prob, total_ham_prob, processed_tokens) {
             /* Fisher value is low enough to apply inv_chi_square */
+            let eps = self.chi_square_eps;
             (
-                1.0 - inv_chi_square(total_spam_prob, processed_tokens),
-                1.0 - inv_chi_square(total_ham_prob, processed_tokens),
+                1.0 - inv_chi_square(total_spam_prob, processed_tokens).clamp(eps, 1.0 - eps),
+                1.0 - inv_chi_square(total_ham_prob, processed_tokens).clamp(eps, 1.0 - eps),
             )
         } else {
-            /* Use naive method */
+            /*
+             * Use naive method, only the winning class gets a value so that
+             * the score is the logistic of the log-odds: balanced evidence
+             * scores 0.5 and swapping the classes mirrors the score
+             */
             if total_spam_prob < total_ham_prob {
-                let h = (1.0 - (total_spam_prob - total_ham_prob).exp())
-                    / (1.0 + (total_spam_prob - total_ham_prob).exp());
-                (h, 1.0 - h)
+                let h = (1.0 - exp(total_spam_prob - total_ham_prob))
+                    / (1.0 + exp(total_spam_prob - total_ham_prob));
+                (h, 0.0)
             } else {
-                let s = (1.0 - (total_ham_prob - total_spam_prob).exp())
-                    / (1.0 + (total_ham_prob - total_spam_prob).exp());
-                (1.0 - s, s)
+                let s = (1.0 - exp(total_ham_prob - total_spam_prob))
+                    / (1.0 + exp(total_ham_prob - total_spam_prob));
+                (0.0, s)
             }
         };
 
-        let final_prob = if h.is_finite() && s.is_finite() {
+        if h.is_finite() && s.is_finite() {
             (s + 1.0 - h) / 2.0
         } else {
             /*
@@ -106,14 +588,170 @@ impl BayesClassifier {
             } else {
                 0.5
             }
+        }
+    }
+
+    // Whether the Fisher values are low enough to apply inv_chi_square
+    fn use_chi_square(
+        &self,
+        total_spam_prob: f64,
+        total_ham_prob: f64,
+        processed_tokens: u32,
+    ) -> bool {
+        let (total_spam_prob, total_ham_prob, cutoff) = match self.fisher_cutoff {
+            FisherCutoff::Absolute(cutoff) => (total_spam_prob, total_ham_prob, cutoff),
+            FisherCutoff::PerToken(cutoff) => (
+                total_spam_prob / processed_tokens as f64,
+                total_ham_prob / processed_tokens as f64,
+                cutoff,
+            ),
         };
+        total_spam_prob > cutoff && total_ham_prob > cutoff
+    }
 
-        if processed_tokens > 0 && (final_prob - 0.5).abs() > 0.05 {
-            Some(final_prob)
+    /// Returns the spam and ham probabilities of a token, or None if it
+    /// lacks enough evidence or its probability is not strong enough.
+    pub(crate) fn token_probs(
+        &self,
+        token: &TokenInput,
+        ham_learns: u32,
+        spam_learns: u32,
+        min_token_hits: u32,
+    ) -> Option<(f64, f64)> {
+        let (bayes_spam_prob, bayes_ham_prob) =
+            self.combined_token_probs(token, ham_learns, spam_learns, min_token_hits)?;
+
+        if !((bayes_spam_prob > 0.5 && bayes_spam_prob < 0.5 + self.min_prob_strength)
+            || (bayes_spam_prob < 0.5 && bayes_spam_prob > 0.5 - self.min_prob_strength))
+        {
+            Some(if let Some(eps) = self.prob_clamp {
+                // Limit the influence of a single overconfident token
+                (
+                    bayes_spam_prob.clamp(eps, 1.0 - eps),
+                    bayes_ham_prob.clamp(eps, 1.0 - eps),
+                )
+            } else {
+                (bayes_spam_prob, bayes_ham_prob)
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Returns the spam and ham probabilities of a token before applying
+    /// `min_prob_strength`, or None if it lacks enough evidence.
+    pub(crate) fn combined_token_probs(
+        &self,
+        token: &TokenInput,
+        ham_learns: u32,
+        spam_learns: u32,
+        min_token_hits: u32,
+    ) -> Option<(f64, f64)> {
+        let weights = token.weights;
+        let total_count = weights.spam.saturating_add(weights.ham);
+
+        #[cfg(feature = "std")]
+        let is_allowed = match &token.hash {
+            Some(hash) if self.token_deny.contains(hash) => return None,
+            Some(hash) => total_count > 0 && self.token_allow.contains(hash),
+            None => false,
+        };
+        #[cfg(not(feature = "std"))]
+        let is_allowed = false;
+
+        if total_count >= min_token_hits || is_allowed {
+            let total_count = total_count as f64;
+            let (spam_prob, ham_prob) = if let Some(spam_prob) = token.spam_prob {
+                (spam_prob, 1.0 - spam_prob)
+            } else {
+                let (spam_freq, ham_freq) = match self.smoothing {
+                    Smoothing::None => (
+                        weights.spam as f64 / f64::max(1.0, spam_learns as f64),
+                        weights.ham as f64 / f64::max(1.0, ham_learns as f64),
+                    ),
+                    Smoothing::Laplace(alpha) => (
+                        (weights.spam as f64 + alpha)
+                            / f64::max(1.0, spam_learns as f64 + 2.0 * alpha),
+                        (weights.ham as f64 + alpha)
+                            / f64::max(1.0, ham_learns as f64 + 2.0 * alpha),
+                    ),
+                };
+                (
+                    spam_freq / (spam_freq + ham_freq),
+                    ham_freq / (spam_freq + ham_freq),
+                )
+            };
+
+            let fw = FEATURE_WEIGHT[token.idx];
+            let mut w = (fw * total_count) / (1.0 + fw * total_count);
+            if let Some(doc_freq) = token.doc_freq {
+                w /= inv_doc_freq(doc_freq, ham_learns as f64 + spam_learns as f64)
+                    .max(f64::EPSILON);
+            }
+
+            let spam_prob = prob_combine(spam_prob, total_count, w, 0.5);
+            let ham_prob = prob_combine(ham_prob, total_count, w, 0.5);
+            Some(match self.gap_decay {
+                Some(decay) if (1..OSB_WINDOW_SIZE).contains(&token.idx) => {
+                    let factor = decay.factor(token.idx - 1);
+                    (
+                        0.5 + (spam_prob - 0.5) * factor,
+                        0.5 + (ham_prob - 0.5) * factor,
+                    )
+                }
+                _ => (spam_prob, ham_prob),
+            })
         } else {
             None
         }
     }
+
+    /// Classifies a message that belongs to a thread, smoothing its score with the
+    /// scores of the earlier messages (oldest first) using an exponential moving
+    /// average weighted by `thread_alpha`.
+    pub fn classify_in_thread<T>(
+        &self,
+        tokens: T,
+        ham_learns: u32,
+        spam_learns: u32,
+        thread_scores: &[f64],
+    ) -> Option<f64>
+    where
+        T: Iterator<Item = OsbToken<Weights>>,
+    {
+        let score = self.classify(tokens, ham_learns, spam_learns)?;
+        let mut thread_scores = thread_scores.iter().copied();
+
+        if let Some(first_score) = thread_scores.next() {
+            let thread_score = thread_scores.fold(first_score, |avg, score| {
+                self.thread_alpha * score + (1.0 - self.thread_alpha) * avg
+            });
+            Some(self.thread_alpha * score + (1.0 - self.thread_alpha) * thread_score)
+        } else {
+            Some(score)
+        }
+    }
+}
+
+impl GapDecay {
+    // Scale of the evidence of a bigram skipping `gap` words
+    fn factor(&self, gap: usize) -> f64 {
+        (0..gap)
+            .fold(self.base, |factor, _| factor * self.ratio)
+            .min(1.0)
+    }
+}
+
+impl From<OsbToken<Weights>> for TokenInput {
+    fn from(token: OsbToken<Weights>) -> Self {
+        TokenInput {
+            weights: token.inner,
+            idx: token.idx,
+            hash: None,
+            doc_freq: None,
+            spam_prob: None,
+        }
+    }
 }
 
 /**
@@ -122,7 +760,7 @@ impl BayesClassifier {
  */
 #[inline(always)]
 fn inv_chi_square(value: f64, freedom_deg: u32) -> f64 {
-    let mut prob = value.exp();
+    let mut prob = exp(value);
 
     if prob.is_finite() {
         /*
@@ -160,7 +798,1039 @@ fn normalize_probability(x: f64, bias: f64) -> f64 {
     ((x - bias) * 2.0).powi(8)
 }*/
 
+/**
+ * Returns the inverse document frequency of a token normalized to [0, 1],
+ * where 0 means the token was seen in every trained message
+ */
+#[inline(always)]
+fn inv_doc_freq(doc_freq: u32, total_learns: f64) -> f64 {
+    if total_learns > 1.0 {
+        ln(total_learns / (doc_freq as f64).clamp(1.0, total_learns)) / ln(total_learns)
+    } else {
+        1.0
+    }
+}
+
+/**
+ * Confidence grows with the amount of evidence as n / (n + CONFIDENCE_TOKENS),
+ * approaching 1.0 as the number of processed tokens n increases
+ */
+#[inline(always)]
+fn confidence(processed_tokens: u32) -> f64 {
+    processed_tokens as f64 / (processed_tokens as f64 + CONFIDENCE_TOKENS)
+}
+
+#[inline(always)]
+pub fn log_odds(prob: f64) -> f64 {
+    let prob = prob.clamp(LOG_ODDS_EPSILON, 1.0 - LOG_ODDS_EPSILON);
+    ln(prob / (1.0 - prob))
+}
+
+/// Adds a prior given as log-odds to a probability.
+#[inline(always)]
+pub fn apply_prior(prob: f64, prior_log_odds: f64) -> f64 {
+    1.0 / (1.0 + exp(-(log_odds(prob) + prior_log_odds)))
+}
+
 #[inline(always)]
 fn prob_combine(prob: f64, cnt: f64, weight: f64, assumed: f64) -> f64 {
+    debug_assert!(!prob.is_infinite(), "infinite probability");
+    debug_assert!(
+        cnt.is_finite() && weight.is_finite() && assumed.is_finite(),
+        "non-finite inputs: cnt {cnt}, weight {weight}, assumed {assumed}"
+    );
+
+    // A NaN probability (i.e. a token without counts) would poison the sums
+    // and neutralize the whole classification, treat it as the assumed value
+    if prob.is_nan() || weight + cnt == 0.0 {
+        return assumed;
+    }
+
     ((weight) * (assumed) + (cnt) * (prob)) / ((weight) + (cnt))
 }
+
+// Without std, fall back to libm for the transcendental functions
+#[cfg(feature = "std")]
+#[inline(always)]
+fn ln(x: f64) -> f64 {
+    x.ln()
+}
+
+#[cfg(not(feature = "std"))]
+#[inline(always)]
+fn ln(x: f64) -> f64 {
+    libm::log(x)
+}
+
+#[cfg(feature = "std")]
+#[inline(always)]
+fn exp(x: f64) -> f64 {
+    x.exp()
+}
+
+#[cfg(not(feature = "std"))]
+#[inline(always)]
+fn exp(x: f64) -> f64 {
+    libm::exp(x)
+}
+
+impl core::fmt::Display for ClassifyError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ClassifyError::MalformedToken { idx, weights } => write!(
+                f,
+                "Malformed token at position {idx} with {} spam and {} ham hits",
+                weights.spam, weights.ham
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ClassifyError {}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        bayes::{
+            BayesClassifier, BayesVerdict, ClassifyOverrides, FisherCutoff, GapDecay,
+            SingleClassPolicy, Smoothing, Tier, TieredResult, TokenCombination, UncertainBand,
+            Weights, FEATURE_POSITIONS,
+        },
+        tokenizers::osb::OsbToken,
+    };
+
+    use core::cell::Cell;
+
+    use super::{ClassifyError, TokenInput, OSB_WINDOW_SIZE};
+
+    fn tokens(weights: &[(u32, u32)]) -> Vec<OsbToken<Weights>> {
+        weights
+            .iter()
+            .map(|&(spam, ham)| OsbToken {
+                inner: Weights { spam, ham },
+                idx: 0,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn clamp_extreme_token() {
+        let mut weights = vec![(3, 7); 12];
+        weights.push((1000, 0));
+        let tokens = tokens(&weights);
+
+        let mut classifier = BayesClassifier {
+            min_learns: 0,
+            ..Default::default()
+        };
+        let unclamped = classifier
+            .classify(tokens.iter().cloned(), 100, 100)
+            .unwrap();
+        classifier.prob_clamp = Some(0.01);
+        let clamped = classifier
+            .classify(tokens.iter().cloned(), 100, 100)
+            .unwrap();
+
+        assert!(
+            clamped < unclamped,
+            "clamped {clamped} should be below unclamped {unclamped}"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn label_symmetry() {
+        use crate::bayes::{synthetic::SyntheticCorpus, BayesModel};
+
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            decision_margin: 0.0,
+            ..Default::default()
+        };
+        // Short messages are scored by inverse chi-square and long ones by
+        // the naive method, over classes sharing few or most of their words
+        let (mut chi_square, mut naive) = (0, 0);
+        for overlap in [0.1, 0.5, 0.9] {
+            let mut corpus = SyntheticCorpus::new(5).separation(0.6).overlap(overlap);
+            let (mut model, mut swapped) = (BayesModel::default(), BayesModel::default());
+            for message in corpus.messages(60) {
+                model.train(message.tokens(), message.is_spam);
+                swapped.train(message.tokens(), !message.is_spam);
+            }
+
+            for words in [12, 40, 400] {
+                let mut corpus = SyntheticCorpus::new(words as u64)
+                    .words_per_message(words)
+                    .overlap(overlap);
+                for message in corpus.messages(50) {
+                    let tokens = message.tokens();
+                    let classify = |model: &BayesModel| {
+                        model.classify_token_hashes(&classifier, tokens.iter().cloned())
+                    };
+                    let (score, mirrored) = (classify(&model), classify(&swapped));
+                    assert_eq!(score.is_some(), mirrored.is_some());
+                    if let (Some(score), Some(mirrored)) = (score, mirrored) {
+                        assert!(
+                            (score - (1.0 - mirrored)).abs() < 1e-9,
+                            "{overlap} overlap, {words} words: {score} vs 1 - {mirrored}"
+                        );
+                        if words == 400 {
+                            naive += 1;
+                        } else {
+                            chi_square += 1;
+                        }
+                    }
+                }
+            }
+        }
+        assert!(chi_square > 0 && naive > 0);
+
+        // Balanced evidence beyond the Fisher cutoff is neutral rather than ham,
+        // and slightly unbalanced evidence leans towards its class
+        let classify = |extra: (u32, u32)| {
+            let mut weights = vec![(5, 5); 500];
+            weights.extend([extra; 10]);
+            classifier.classify(tokens(&weights).into_iter(), 100, 100)
+        };
+        assert_eq!(classify((5, 5)), None);
+        let ham = classify((4, 6)).unwrap();
+        let spam = classify((6, 4)).unwrap();
+        assert!(ham < 0.5 && spam > 0.5, "{ham} {spam}");
+        assert!((ham - (1.0 - spam)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn positional_combination() {
+        let positioned = |sets: &[(usize, (u32, u32), usize)]| {
+            sets.iter()
+                .flat_map(|&(idx, (spam, ham), count)| {
+                    (0..count).map(move |_| OsbToken {
+                        inner: Weights { spam, ham },
+                        idx,
+                    })
+                })
+                .collect::<Vec<_>>()
+        };
+        let flat = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 1,
+            decision_margin: 0.0,
+            ..Default::default()
+        };
+        let mut weights = [1.0; FEATURE_POSITIONS];
+        let positional = BayesClassifier {
+            combination: TokenCombination::Positional { weights },
+            ..flat.clone()
+        };
+        // Strong unigrams against many mildly ham bigrams repeating them
+        let mut set = vec![(0, (18, 2), 3)];
+        set.extend((1..OSB_WINDOW_SIZE).map(|idx| (idx, (8, 12), 6)));
+        let tokens = positioned(&set);
+        let classify = |classifier: &BayesClassifier, tokens: &[OsbToken<Weights>]| {
+            classifier.classify(tokens.iter().cloned(), 100, 100)
+        };
+
+        // Flat counts every bigram as independent evidence, positional
+        // counts each position once
+        let flat_score = classify(&flat, &tokens).unwrap();
+        let positional_score = classify(&positional, &tokens).unwrap();
+        assert!(flat_score < 0.5, "{flat_score}");
+        assert!(positional_score > 0.5, "{positional_score}");
+
+        // A single weighted position scores as its tokens alone
+        weights = [0.0; FEATURE_POSITIONS];
+        weights[0] = 1.0;
+        let unigrams = BayesClassifier {
+            combination: TokenCombination::Positional { weights },
+            ..flat.clone()
+        };
+        let score = classify(&unigrams, &tokens).unwrap();
+        let expected = classify(&flat, &positioned(&set[..1])).unwrap();
+        assert!((score - expected).abs() < 1e-9, "{score} {expected}");
+
+        // Down-weighting the bigrams moves the score towards the unigrams
+        weights = [0.5; FEATURE_POSITIONS];
+        weights[0] = 1.0;
+        let weighted = BayesClassifier {
+            combination: TokenCombination::Positional { weights },
+            ..flat.clone()
+        };
+        let score = classify(&weighted, &tokens).unwrap();
+        assert!(score > positional_score && score < expected, "{score}");
+
+        // No verdict without weighted positions
+        let none = BayesClassifier {
+            combination: TokenCombination::Positional {
+                weights: [0.0; FEATURE_POSITIONS],
+            },
+            ..flat
+        };
+        assert_eq!(classify(&none, &tokens), None);
+    }
+
+    #[test]
+    fn classify_tiered() {
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            decision_margin: 0.0,
+            ..Default::default()
+        };
+        let band = UncertainBand::default();
+        let calls = Cell::new(0);
+        let classify = |weights: &[(u32, u32)], secondary: Option<f64>| {
+            classifier.classify_tiered(tokens(weights).into_iter(), 100, 100, &band, |primary| {
+                calls.set(calls.get() + 1);
+                assert_eq!(
+                    primary,
+                    classifier.classify_detailed(tokens(weights).into_iter(), 100, 100)
+                );
+                secondary
+            })
+        };
+
+        // Clear cases skip the secondary classifier
+        let spam = classify(&[(9, 1); 12], Some(0.0));
+        assert_eq!(spam.tier, Tier::Primary);
+        assert!(spam.score.unwrap() > band.high);
+        let ham = classify(&[(1, 9); 12], Some(1.0));
+        assert_eq!(ham.tier, Tier::Primary);
+        assert!(ham.score.unwrap() < band.low);
+        assert_eq!(calls.get(), 0);
+
+        // Borderline scores and missing verdicts invoke it
+        let borderline = classify(&[(6, 4); 12], Some(0.95));
+        assert_eq!(
+            borderline,
+            TieredResult {
+                score: Some(0.95),
+                tier: Tier::Secondary
+            }
+        );
+        assert_eq!(classify(&[(9, 1); 2], Some(0.9)).tier, Tier::Secondary);
+        assert_eq!(calls.get(), 2);
+
+        // The primary result stands when the secondary has no verdict either
+        let undecided = classify(&[(6, 4); 12], None);
+        assert_eq!(undecided.tier, Tier::Primary);
+        assert!(band.low <= undecided.score.unwrap() && undecided.score.unwrap() <= band.high);
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[test]
+    fn thread_smoothing() {
+        let tokens = tokens(&[(6, 4); 12]);
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            ..Default::default()
+        };
+
+        let score = classifier
+            .classify(tokens.iter().cloned(), 100, 100)
+            .unwrap();
+        assert!(score > 0.5);
+        assert_eq!(
+            classifier.classify_in_thread(tokens.iter().cloned(), 100, 100, &[]),
+            Some(score)
+        );
+
+        let smoothed = classifier
+            .classify_in_thread(tokens.iter().cloned(), 100, 100, &[0.05, 0.1, 0.08])
+            .unwrap();
+        assert!(smoothed < 0.5, "smoothed score {smoothed} should lean ham");
+    }
+
+    #[test]
+    fn confidence() {
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 0,
+            ..Default::default()
+        };
+        let few = classifier
+            .classify_detailed(tokens(&[(100, 0); 3]).into_iter(), 100, 100)
+            .unwrap();
+        let many = classifier
+            .classify_detailed(tokens(&[(100, 0); 300]).into_iter(), 100, 100)
+            .unwrap();
+
+        assert!((few.score - many.score).abs() < 0.001, "{few:?} {many:?}");
+        assert_eq!((few.processed_tokens, many.processed_tokens), (3, 300));
+        assert!(
+            few.confidence < 0.2 && many.confidence > 0.9,
+            "{few:?} {many:?}"
+        );
+    }
+
+    #[test]
+    fn log_odds() {
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            ..Default::default()
+        };
+
+        for weights in [(9, 1), (7, 3), (3, 7), (1, 9), (1000, 0)] {
+            let tokens = tokens(&[weights; 12]);
+            let prob = classifier
+                .classify(tokens.iter().cloned(), 100, 100)
+                .unwrap();
+            let log_odds = classifier
+                .classify_logodds(tokens.iter().cloned(), 100, 100)
+                .unwrap();
+
+            assert!(log_odds.is_finite(), "{weights:?}");
+            if prob < 1.0 {
+                assert!(
+                    (log_odds - (prob / (1.0 - prob)).ln()).abs() < 1e-9,
+                    "{weights:?}: {log_odds} {prob}"
+                );
+            }
+            assert_eq!(log_odds > 0.0, prob > 0.5);
+        }
+
+        assert_eq!(super::log_odds(0.5), 0.0);
+        assert!(super::log_odds(1.0).is_finite());
+        assert!((super::log_odds(1.0) + super::log_odds(0.0)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn chi_square_eps() {
+        let tokens = tokens(&[(1000, 0); 12]);
+        let mut classifier = BayesClassifier {
+            min_learns: 0,
+            chi_square_eps: 0.0,
+            ..Default::default()
+        };
+        assert_eq!(
+            classifier.classify(tokens.iter().cloned(), 100, 100),
+            Some(1.0)
+        );
+
+        classifier.chi_square_eps = 1e-6;
+        let score = classifier
+            .classify(tokens.iter().cloned(), 100, 100)
+            .unwrap();
+        assert!((score - (1.0 - 1e-6)).abs() < 1e-9, "{score}");
+        assert!(super::log_odds(score) < 14.0);
+
+        let score = classifier
+            .classify(self::tokens(&[(0, 1000); 12]).into_iter(), 100, 100)
+            .unwrap();
+        assert!((score - 1e-6).abs() < 1e-9, "{score}");
+    }
+
+    #[test]
+    fn reputation_prior() {
+        let tokens = tokens(&[(6, 4); 12]);
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            ..Default::default()
+        };
+        let score = classifier
+            .classify(tokens.iter().cloned(), 100, 100)
+            .unwrap();
+        assert!(score > 0.5 && score < 0.9, "{score}");
+
+        let neutral = classifier
+            .classify_with_prior(tokens.iter().cloned(), 100, 100, 0.0)
+            .unwrap();
+        assert!((neutral - score).abs() < 1e-9, "{neutral} {score}");
+
+        // A sender with a good reputation pulls the borderline message to ham
+        let reputable = classifier
+            .classify_with_prior(tokens.iter().cloned(), 100, 100, -2.0)
+            .unwrap();
+        assert!(reputable < 0.5, "{reputable}");
+
+        let untrusted = classifier
+            .classify_with_prior(tokens.iter().cloned(), 100, 100, 2.0)
+            .unwrap();
+        assert!(untrusted > score, "{untrusted} {score}");
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn allow_deny_tokens() {
+        use crate::bayes::TokenHash;
+
+        let hashed = |weights: &[(u32, u32)]| {
+            tokens(weights)
+                .into_iter()
+                .enumerate()
+                .map(|(h1, token)| {
+                    (
+                        TokenHash {
+                            h1: h1 as u64,
+                            h2: 0,
+                        },
+                        token,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            ..Default::default()
+        };
+
+        // Denied tokens never contribute
+        let mut weights = vec![(6, 4); 12];
+        let baseline = classifier
+            .classify_hashed(hashed(&weights).into_iter(), 100, 100)
+            .unwrap();
+        weights.push((50, 0));
+        let with_spammy = classifier
+            .classify_hashed(hashed(&weights).into_iter(), 100, 100)
+            .unwrap();
+        classifier.token_deny.insert(TokenHash { h1: 12, h2: 0 });
+        let denied = classifier
+            .classify_hashed(hashed(&weights).into_iter(), 100, 100)
+            .unwrap();
+        assert_ne!(with_spammy, baseline);
+        assert_eq!(denied, baseline);
+
+        // Allowed tokens bypass min_token_hits
+        classifier.token_deny.clear();
+        weights.pop();
+        weights.push((1, 0));
+        let rare = classifier
+            .classify_hashed(hashed(&weights).into_iter(), 100, 100)
+            .unwrap();
+        classifier.token_allow.insert(TokenHash { h1: 12, h2: 0 });
+        let allowed = classifier
+            .classify_hashed(hashed(&weights).into_iter(), 100, 100)
+            .unwrap();
+        assert_eq!(rare.processed_tokens, 12);
+        assert_eq!(allowed.processed_tokens, 13);
+        assert!(allowed.score > rare.score);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn spam_floor() {
+        use crate::bayes::TokenHash;
+
+        let hashed = |weights: &[(u32, u32)]| {
+            weights
+                .iter()
+                .enumerate()
+                .map(|(i, &(spam, ham))| {
+                    (
+                        TokenHash {
+                            h1: i as u64,
+                            h2: 0,
+                        },
+                        OsbToken {
+                            inner: Weights { spam, ham },
+                            idx: 0,
+                        },
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            ..Default::default()
+        };
+        let known_bad = TokenHash { h1: 12, h2: 0 };
+
+        // A ham message with a token never seen by the model
+        let mut weights = vec![(1, 9); 12];
+        weights.push((0, 0));
+        let ham = classifier
+            .classify_hashed(hashed(&weights).into_iter(), 100, 100)
+            .unwrap();
+        assert!(ham.score < 0.1, "{ham:?}");
+        assert_eq!(ham.floor_token, None);
+
+        classifier.add_floor_token(known_bad);
+        let floored = classifier
+            .classify_hashed(hashed(&weights).into_iter(), 100, 100)
+            .unwrap();
+        assert_eq!(floored.score, classifier.spam_floor_score);
+        assert_eq!(floored.floor_token, Some(known_bad));
+        assert_eq!(floored.processed_tokens, ham.processed_tokens);
+        assert_eq!(floored.autolearn_verdict(), None);
+
+        // Models without enough learns still apply the floor
+        let classifier = BayesClassifier {
+            min_learns: 200,
+            ..classifier
+        };
+        let floored = classifier
+            .classify_hashed(hashed(&weights).into_iter(), 100, 100)
+            .unwrap();
+        assert_eq!(floored.score, classifier.spam_floor_score);
+
+        // Scores above the floor are left untouched
+        let spam = classifier
+            .classify_hashed(hashed(&[(9, 1); 13]).into_iter(), 200, 200)
+            .unwrap();
+        assert!(spam.score > classifier.spam_floor_score);
+        assert_eq!(spam.floor_token, None);
+    }
+
+    #[test]
+    fn fisher_cutoff() {
+        let mut classifier = BayesClassifier::default();
+
+        // Same evidence per token (ln 0.2 and ln 0.8) at growing token counts
+        let branches = |classifier: &BayesClassifier| {
+            [10, 100, 1000].map(|n| {
+                classifier.use_chi_square(0.2f64.ln() * n as f64, 0.8f64.ln() * n as f64, n)
+            })
+        };
+        assert_eq!(branches(&classifier), [true, true, false]);
+
+        classifier.fisher_cutoff = FisherCutoff::PerToken(-2.0);
+        assert_eq!(branches(&classifier), [true, true, true]);
+        classifier.fisher_cutoff = FisherCutoff::PerToken(-1.0);
+        assert_eq!(branches(&classifier), [false, false, false]);
+    }
+
+    #[test]
+    fn idf_weighting() {
+        // Tokens seen in 10 out of 200 messages, plus one seen in all of them
+        let tokens = tokens(&[(7, 3); 12])
+            .into_iter()
+            .map(|token| (token, 10))
+            .collect::<Vec<_>>();
+        let mut with_universal = tokens.clone();
+        with_universal.extend(self::tokens(&[(190, 10)]).into_iter().map(|t| (t, 200)));
+
+        let mut classifier = BayesClassifier {
+            min_learns: 0,
+            ..Default::default()
+        };
+        let influence = |classifier: &BayesClassifier| {
+            let without = classifier
+                .classify_with_doc_freq(tokens.iter().cloned(), 100, 100)
+                .unwrap();
+            let with = classifier
+                .classify_with_doc_freq(with_universal.iter().cloned(), 100, 100)
+                .unwrap();
+            (with - without).abs()
+        };
+        let plain = influence(&classifier);
+        classifier.idf_weighting = true;
+        let weighted = influence(&classifier);
+
+        assert!(
+            weighted < plain,
+            "universal token influence {weighted} should be below {plain}"
+        );
+    }
+
+    #[test]
+    fn autolearn_verdict() {
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 1,
+            autolearn_min_tokens: 20,
+            ..Default::default()
+        };
+        let autolearn = |weights: &[(u32, u32)]| {
+            let result = classifier
+                .classify_detailed(tokens(weights).into_iter(), 100, 100)
+                .unwrap();
+            (result.score, result.autolearn_verdict())
+        };
+
+        // Confident and well-evidenced
+        let (score, verdict) = autolearn(&[(50, 0); 30]);
+        assert_eq!(verdict, Some(true), "{score}");
+        let (score, verdict) = autolearn(&[(0, 50); 30]);
+        assert_eq!(verdict, Some(false), "{score}");
+
+        // Confident but with too few tokens
+        let (score, verdict) = autolearn(&[(50, 0); 5]);
+        assert!(score > 0.9, "{score}");
+        assert_eq!(verdict, None);
+
+        // Enough tokens but not confident enough
+        let (score, verdict) = autolearn(&[(6, 4); 30]);
+        assert!(score > 0.5 && score < classifier.autolearn_spam, "{score}");
+        assert_eq!(verdict, None);
+    }
+
+    #[test]
+    fn single_class_model() {
+        // Ham-looking tokens of a model that has only learned ham
+        let tokens = tokens(&[(0, 5), (0, 3), (0, 8), (1, 4)]);
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 1,
+            ..Default::default()
+        };
+
+        assert_eq!(classifier.classify(tokens.iter().cloned(), 10, 0), None);
+        assert_eq!(
+            classifier.classify_verdict(tokens.iter().cloned(), 10, 0, 10),
+            BayesVerdict::Warming
+        );
+        assert_eq!(
+            classifier.classify_verdict(tokens.iter().cloned(), 0, 10, 10),
+            BayesVerdict::Warming
+        );
+        assert!(matches!(
+            classifier.classify_verdict(tokens.iter().cloned(), 10, 1, 11),
+            BayesVerdict::Score(_)
+        ));
+
+        // Every message would be classified as ham otherwise
+        let classifier = BayesClassifier {
+            single_class: SingleClassPolicy::Classify,
+            ..classifier
+        };
+        let score = classifier.classify(tokens.iter().cloned(), 10, 0).unwrap();
+        assert!(score < 0.5, "{score}");
+    }
+
+    #[test]
+    fn laplace_smoothing() {
+        let smoothed = BayesClassifier {
+            smoothing: Smoothing::Laplace(1.0),
+            ..Default::default()
+        };
+        let unsmoothed = BayesClassifier::default();
+        let spam_prob = |classifier: &BayesClassifier, spam, ham| {
+            classifier
+                .combined_token_probs(
+                    &TokenInput::from(OsbToken {
+                        inner: Weights { spam, ham },
+                        idx: 0,
+                    }),
+                    100,
+                    100,
+                    2,
+                )
+                .unwrap()
+                .0
+        };
+
+        // Rare tokens are pulled towards 0.5
+        for (spam, ham) in [(2, 0), (0, 2), (3, 1)] {
+            let (smoothed, unsmoothed) = (
+                spam_prob(&smoothed, spam, ham),
+                spam_prob(&unsmoothed, spam, ham),
+            );
+            assert!(
+                (smoothed - 0.5).abs() < (unsmoothed - 0.5).abs(),
+                "{spam}/{ham}: {smoothed} vs {unsmoothed}"
+            );
+        }
+
+        // Frequent tokens barely change
+        let (smoothed, unsmoothed) = (spam_prob(&smoothed, 90, 10), spam_prob(&unsmoothed, 90, 10));
+        assert!(
+            (smoothed - unsmoothed).abs() < 0.01,
+            "{smoothed} vs {unsmoothed}"
+        );
+
+        // Disabled by default
+        assert_eq!(Smoothing::default(), Smoothing::None);
+    }
+
+    #[test]
+    fn classify_overrides() {
+        let tokens = tokens(&[(8, 2); 5]);
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            ..Default::default()
+        };
+        let classify = |overrides: ClassifyOverrides| {
+            classifier.classify_with_overrides(tokens.iter().cloned(), 100, 100, &overrides)
+        };
+
+        // Too few tokens for the default `min_tokens`
+        assert_eq!(classify(ClassifyOverrides::default()), None);
+        assert_eq!(classifier.classify(tokens.iter().cloned(), 100, 100), None);
+
+        let short = ClassifyOverrides {
+            min_tokens: Some(3),
+            ..Default::default()
+        };
+        let score = classify(short).unwrap();
+        assert!(score > 0.55, "{score}");
+
+        // Tokens seen fewer times than `min_token_hits` are ignored
+        assert_eq!(
+            classify(ClassifyOverrides {
+                min_token_hits: Some(20),
+                ..short
+            }),
+            None
+        );
+
+        // Scores closer to 0.5 than `decision_margin` yield no verdict
+        assert_eq!(
+            classify(ClassifyOverrides {
+                decision_margin: Some(score - 0.5),
+                ..short
+            }),
+            None
+        );
+
+        // The classifier itself is unchanged
+        assert_eq!(classifier.min_tokens, 11);
+    }
+
+    #[test]
+    fn min_evidence() {
+        // A long message of barely spammy tokens
+        let bland = tokens(&[(58, 42); 40]);
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            min_prob_strength: 0.0,
+            ..Default::default()
+        };
+        let score = classifier
+            .classify(bland.iter().cloned(), 100, 100)
+            .unwrap();
+        assert!(score > 0.5, "{score}");
+
+        let classifier = BayesClassifier {
+            min_evidence: 4.0,
+            ..classifier
+        };
+        assert_eq!(classifier.classify(bland.iter().cloned(), 100, 100), None);
+
+        // Fewer but strongly signalled tokens are still classified
+        let strong = tokens(&[(90, 10); 12]);
+        let score = classifier
+            .classify(strong.iter().cloned(), 100, 100)
+            .unwrap();
+        assert!(score > 0.9, "{score}");
+    }
+
+    #[test]
+    fn gap_decay() {
+        let classifier = BayesClassifier {
+            gap_decay: Some(GapDecay {
+                base: 1.0,
+                ratio: 0.5,
+            }),
+            ..Default::default()
+        };
+        let deviation = |classifier: &BayesClassifier, idx| {
+            let token = TokenInput {
+                idx,
+                ..TokenInput::from(OsbToken {
+                    inner: Weights { spam: 8, ham: 2 },
+                    idx,
+                })
+            };
+            let (spam_prob, ham_prob) = classifier.combined_token_probs(&token, 10, 10, 2).unwrap();
+            assert!((spam_prob + ham_prob - 1.0).abs() < 1e-9);
+            spam_prob - 0.5
+        };
+
+        // Each skipped word halves the evidence of a bigram
+        let adjacent = deviation(&classifier, 1);
+        let distant = deviation(&classifier, 4);
+        assert!(distant > 0.0 && distant < adjacent, "{distant} {adjacent}");
+        let plain = BayesClassifier::default();
+        assert_eq!(adjacent, deviation(&plain, 1));
+        assert!((distant - deviation(&plain, 4) / 8.0).abs() < 1e-9);
+
+        // Unigrams are not decayed
+        assert_eq!(deviation(&classifier, 0), deviation(&plain, 0));
+    }
+
+    #[test]
+    fn malformed_token() {
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 1,
+            ..Default::default()
+        };
+        let mut tokens = tokens(&[(8, 2); 12]);
+        assert_eq!(
+            classifier
+                .try_classify(tokens.iter().cloned(), 100, 100)
+                .unwrap(),
+            classifier.classify_detailed(tokens.iter().cloned(), 100, 100)
+        );
+
+        tokens[3].idx = 100;
+        assert_eq!(
+            classifier.try_classify(tokens.iter().cloned(), 100, 100),
+            Err(ClassifyError::MalformedToken {
+                idx: 100,
+                weights: Weights { spam: 8, ham: 2 }
+            })
+        );
+
+        tokens[3] = OsbToken {
+            inner: Weights {
+                spam: u32::MAX,
+                ham: 1,
+            },
+            idx: 0,
+        };
+        assert!(matches!(
+            classifier.try_classify(tokens.iter().cloned(), 100, 100),
+            Err(ClassifyError::MalformedToken { idx: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn nan_probability() {
+        assert_eq!(super::prob_combine(f64::NAN, 3.0, 0.75, 0.5), 0.5);
+        assert_eq!(super::prob_combine(1.0, 0.0, 0.75, 0.5), 0.5);
+
+        // A token without counts used to turn the whole result into NaN
+        let mut weights = vec![(8, 2); 12];
+        weights.push((0, 0));
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            min_token_hits: 0,
+            ..Default::default()
+        };
+        let score = classifier
+            .classify(tokens(&weights).into_iter(), 100, 100)
+            .unwrap();
+        assert!(score.is_finite() && score > 0.5, "{score}");
+    }
+
+    #[test]
+    fn classify_features() {
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 1,
+            decision_margin: 0.0,
+            ..Default::default()
+        };
+
+        // 3 spam and 1 ham hits over 10 learns each give a spam probability
+        // of 0.75, pulled towards 0.5 by the weight 4 / 5 of the four hits:
+        // (0.8 * 0.5 + 4 * 0.75) / 4.8 = 17 / 24, which is also the score of
+        // a single feature
+        let score = classifier.classify_features(&[(0, 3, 1)], 10, 10).unwrap();
+        assert!((score - 17.0 / 24.0).abs() < 1e-12, "{score}");
+
+        // A second feature with a spam probability of 1 / 12 gives the Fisher
+        // sums S = ln(17 / 24 * 1 / 12) and H = ln(7 / 24 * 11 / 12), which
+        // inv_chi_square with two degrees of freedom turns into
+        // (1 + e^S * (1 - S) - e^H * (1 - H)) / 2
+        let features = [(0, 3, 1), (0, 0, 4)];
+        let score = classifier.classify_features(&features, 10, 10).unwrap();
+        let (s, h) = ((17.0f64 / 288.0).ln(), (77.0f64 / 288.0).ln());
+        let expected = (1.0 + s.exp() * (1.0 - s) - h.exp() * (1.0 - h)) / 2.0;
+        assert!((score - expected).abs() < 1e-12, "{score}");
+        assert!((score - 0.303005).abs() < 1e-6, "{score}");
+        assert_eq!(
+            Some(score),
+            classifier.classify(tokens(&[(3, 1), (0, 4)]).into_iter(), 10, 10)
+        );
+
+        // Features below `min_token_hits` are ignored
+        let features = [(0, 3, 1), (0, 0, 4), (1, 1, 0)];
+        assert_eq!(classifier.classify_features(&features, 10, 10), Some(score));
+        assert_eq!(
+            BayesClassifier::default().classify_features(&features, 10, 10),
+            None
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn stable_summation() {
+        use crate::bayes::synthetic::SyntheticCorpus;
+
+        let mut message = (0..200u32)
+            .map(|i| OsbToken {
+                inner: Weights {
+                    spam: (i * 37) % 101 + 1,
+                    ham: (i * 59) % 103 + 1,
+                },
+                idx: i as usize % FEATURE_POSITIONS,
+            })
+            .collect::<Vec<_>>();
+        let mut classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 1,
+            decision_margin: 0.0,
+            stable_summation: true,
+            ..Default::default()
+        };
+
+        let mut corpus = SyntheticCorpus::new(7);
+        for combination in [
+            TokenCombination::Flat,
+            TokenCombination::Positional {
+                weights: [1.0, 0.5, 2.0, 1.0, 0.25, 1.0, 1.5, 1.0],
+            },
+        ] {
+            classifier.combination = combination;
+            let expected = classifier.classify_detailed(message.iter().cloned(), 150, 150);
+            assert!(expected.is_some());
+            for _ in 0..50 {
+                corpus.shuffle(&mut message);
+                assert_eq!(
+                    classifier.classify_detailed(message.iter().cloned(), 150, 150),
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn repeat_decay() {
+        use crate::bayes::TokenHash;
+
+        // A spammy word repeated ten times among six mildly ham words
+        let message = (0..16)
+            .map(|i| {
+                let (h1, weights) = if i < 10 { (0, (30, 1)) } else { (i, (4, 6)) };
+                (TokenHash { h1, h2: 0 }, weights)
+            })
+            .collect::<Vec<_>>();
+        let hashed = |message: &[(TokenHash, (u32, u32))]| {
+            let weights = message
+                .iter()
+                .map(|(_, weights)| *weights)
+                .collect::<Vec<_>>();
+            message
+                .iter()
+                .map(|(hash, _)| *hash)
+                .zip(tokens(&weights))
+                .collect::<Vec<_>>()
+        };
+        let mut classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 1,
+            decision_margin: 0.0,
+            ..Default::default()
+        };
+        let score = |classifier: &BayesClassifier, message| {
+            classifier
+                .classify_hashed(hashed(message).into_iter(), 100, 100)
+                .unwrap()
+        };
+
+        let flat = score(&classifier, &message);
+        classifier.repeat_decay = Some(0.5);
+        let decayed = score(&classifier, &message);
+        assert!(decayed.score < flat.score, "{decayed:?} {flat:?}");
+        assert_eq!(decayed.processed_tokens, flat.processed_tokens);
+
+        // The repeats still count for something
+        let once = score(&classifier, &message[9..]);
+        assert!(decayed.score > once.score, "{decayed:?} {once:?}");
+
+        // Messages without repeats and a decay of 1.0 are not affected
+        classifier.repeat_decay = None;
+        assert_eq!(score(&classifier, &message[9..]), once);
+        classifier.repeat_decay = Some(1.0);
+        assert_eq!(score(&classifier, &message), flat);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "infinite probability")]
+    fn infinite_probability() {
+        super::prob_combine(f64::INFINITY, 3.0, 0.75, 0.5);
+    }
+}
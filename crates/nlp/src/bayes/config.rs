@@ -0,0 +1,150 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use super::BayesClassifier;
+
+impl BayesClassifier {
+    /// Serializes the classifier parameters as pretty-printed JSON, without
+    /// any token data, so that tuning can be shared and kept under version
+    /// control separately from the model snapshots. Token filters are written
+    /// sorted, so the same parameters always produce the same text.
+    #[cfg(feature = "json")]
+    pub fn export_config(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    /// Reads parameters written by `export_config`. Parameters added after
+    /// the configuration was exported take their default values.
+    #[cfg(feature = "json")]
+    pub fn import_config(config: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(config)
+    }
+
+    /// Returns a hash of every parameter of the classifier, including the
+    /// current tokens of its filters, so that results computed with other
+    /// parameters can be told apart. It serializes the whole classifier, so
+    /// it costs about as much as sorting its filter tokens.
+    pub fn config_fingerprint(&self) -> u64 {
+        bincode::serialize(self).map_or(0, |config| xxhash_rust::xxh3::xxh3_64(&config))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(feature = "json")]
+    use utils::suffixlist::PublicSuffix;
+
+    use crate::bayes::{hasher::HashAlgorithm, BayesClassifier, BayesModel};
+    #[cfg(feature = "json")]
+    use crate::bayes::{TokenCombination, TokenHash};
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn config_roundtrip() {
+        let suffixes = PublicSuffix::default();
+        let mut model = BayesModel::default();
+        for _ in 0..3 {
+            model
+                .train_text("claim your free prize now", true, &suffixes)
+                .unwrap();
+            model
+                .train_text("see you at the team lunch", false, &suffixes)
+                .unwrap();
+        }
+        let snapshot = model.to_snapshot().unwrap();
+
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 1,
+            spam_threshold: 0.7,
+            combination: TokenCombination::Positional {
+                weights: [1.0, 2.0, 1.0, 1.0, 0.5, 0.5, 1.0, 0.0],
+            },
+            ..Default::default()
+        };
+        for h1 in [9, 3, 7] {
+            classifier.token_deny.insert(TokenHash { h1, h2: 0 });
+        }
+        let config = classifier.export_config().unwrap();
+        let imported = BayesClassifier::import_config(&config).unwrap();
+        assert_eq!(imported.export_config().unwrap(), config);
+        assert_eq!(imported.spam_threshold, 0.7);
+        assert_eq!(imported.combination, classifier.combination);
+        assert!(imported.token_deny.contains(&TokenHash { h1: 7, h2: 0 }));
+
+        // The model is neither part of the configuration nor changed by it
+        assert!(config.len() < snapshot.len() * 4, "{config}");
+        assert_eq!(
+            model
+                .classify_text(&imported, "claim your free prize", &suffixes)
+                .unwrap(),
+            model
+                .classify_text(&classifier, "claim your free prize", &suffixes)
+                .unwrap()
+        );
+        assert_eq!(model.to_snapshot().unwrap(), snapshot);
+        assert_eq!((model.spam_learns, model.ham_learns), (3, 3));
+
+        // Parameters missing from older configurations take their defaults
+        let mut value: serde_json::Value = serde_json::from_str(&config).unwrap();
+        value.as_object_mut().unwrap().remove("spam_threshold");
+        let older = BayesClassifier::import_config(&value.to_string()).unwrap();
+        assert_eq!(
+            older.spam_threshold,
+            BayesClassifier::default().spam_threshold
+        );
+        assert!(BayesClassifier::import_config("{\"min_tokens\": 1}").is_err());
+    }
+
+    #[test]
+    fn config_missing_fields() {
+        let classifier = BayesClassifier {
+            thread_alpha: 0.8,
+            idf_weighting: true,
+            ..Default::default()
+        };
+        let config = serde_json::to_value(&classifier).unwrap();
+        for field in ["thread_alpha", "idf_weighting"] {
+            let mut config = config.clone();
+            config.as_object_mut().unwrap().remove(field).unwrap();
+            let imported = serde_json::from_value::<BayesClassifier>(config).unwrap();
+            assert_eq!(
+                (imported.thread_alpha, imported.idf_weighting),
+                match field {
+                    "thread_alpha" => (0.5, true),
+                    _ => (0.8, false),
+                }
+            );
+        }
+
+        // Models serialized before the hasher, document frequencies and
+        // version were recorded
+        let model: BayesModel =
+            serde_json::from_str("{\"weights\": {}, \"spam_learns\": 1, \"ham_learns\": 2}")
+                .unwrap();
+        assert_eq!((model.spam_learns, model.ham_learns), (1, 2));
+        assert_eq!(model.hasher, HashAlgorithm::default());
+        assert!(model.doc_freq.is_empty());
+        assert_eq!(model.version, 0);
+    }
+}
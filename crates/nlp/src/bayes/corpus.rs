@@ -0,0 +1,450 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use mail_parser::{Message, MessageParser};
+use utils::suffixlist::PublicSuffix;
+
+use crate::tokenizers::osb::OsbTokenizer;
+
+use super::{pipeline::PipelineError, tokenize::BayesTokenizer, BayesClassifier, BayesModel};
+
+#[derive(Debug, Default)]
+pub struct TrainReport {
+    pub learned: usize,
+    pub errors: Vec<(PathBuf, io::Error)>,
+}
+
+/// Progress of a bulk training run, counting the messages learned so far and
+/// the files that failed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TrainProgress {
+    pub learned: usize,
+    pub errors: usize,
+}
+
+/// Outcome of training with labeled CSV rows, see `train_csv`.
+#[cfg(feature = "csv")]
+#[derive(Debug, Default)]
+pub struct CsvTrainReport {
+    pub spam: usize,
+    pub ham: usize,
+    // Line numbers of the rows skipped for being malformed, lacking either
+    // column, having an unknown label or a text without tokens
+    pub malformed: Vec<u64>,
+}
+
+/// Verdicts of the messages of a folder, see `scan_maildir`.
+#[derive(Debug, Default)]
+pub struct ScanReport {
+    // Messages scoring at or above `spam_threshold`
+    pub spam: usize,
+    pub ham: usize,
+    // Messages without tokens or a verdict
+    pub unclassified: usize,
+    pub errors: Vec<(PathBuf, io::Error)>,
+    // Highest scoring messages, highest first
+    pub top: Vec<(PathBuf, f64)>,
+}
+
+impl BayesModel {
+    /// Trains the model with every message in the `cur` and `new` directories
+    /// of a Maildir folder. Files that cannot be read or parsed, messages
+    /// without tokens and subdirectories that cannot be read are recorded in
+    /// the report and skipped, while missing subdirectories are ignored.
+    pub fn train_maildir(
+        &mut self,
+        dir: impl AsRef<Path>,
+        is_spam: bool,
+        suffixes: &PublicSuffix,
+    ) -> io::Result<TrainReport> {
+        self.train_maildir_with_progress(dir, is_spam, suffixes, 0, |_| {})
+    }
+
+    /// Same as `train_maildir`, calling `progress` each time another `every`
+    /// messages have been learned or failed. A zero `every` disables it.
+    pub fn train_maildir_with_progress(
+        &mut self,
+        dir: impl AsRef<Path>,
+        is_spam: bool,
+        suffixes: &PublicSuffix,
+        every: usize,
+        mut progress: impl FnMut(TrainProgress),
+    ) -> io::Result<TrainReport> {
+        let mut report = TrainReport::default();
+        for_each_message(dir.as_ref(), |path, text| {
+            match text {
+                Ok(text) => match self.train_text(&text, is_spam, suffixes) {
+                    Ok(()) => report.learned += 1,
+                    Err(PipelineError::EmptyInput) => report.errors.push((
+                        path,
+                        io::Error::new(io::ErrorKind::InvalidData, "Message has no tokens"),
+                    )),
+                },
+                Err(err) => {
+                    report.errors.push((path, err));
+                }
+            }
+
+            if every > 0 && (report.learned + report.errors.len()) % every == 0 {
+                progress(TrainProgress {
+                    learned: report.learned,
+                    errors: report.errors.len(),
+                });
+            }
+        })?;
+
+        Ok(report)
+    }
+
+    /// Classifies every message in the `cur` and `new` directories of a
+    /// Maildir folder without training the model, counting the messages of
+    /// each verdict and keeping the `top` highest scoring ones.
+    pub fn scan_maildir(
+        &self,
+        classifier: &BayesClassifier,
+        dir: impl AsRef<Path>,
+        suffixes: &PublicSuffix,
+        top: usize,
+    ) -> io::Result<ScanReport> {
+        let mut report = ScanReport::default();
+        for_each_message(dir.as_ref(), |path, text| {
+            let text = match text {
+                Ok(text) => text,
+                Err(err) => {
+                    report.errors.push((path, err));
+                    return;
+                }
+            };
+            let tokens = OsbTokenizer::with_hasher(
+                BayesTokenizer::new(&text, suffixes),
+                self.window_size,
+                self.token_hasher(),
+            );
+            match self.classify_token_hashes(classifier, tokens) {
+                Some(score) => {
+                    if classifier.is_spam(score) {
+                        report.spam += 1;
+                    } else {
+                        report.ham += 1;
+                    }
+                    report.top.push((path, score));
+                }
+                None => report.unclassified += 1,
+            }
+        })?;
+
+        report.top.sort_by(|a, b| b.1.total_cmp(&a.1));
+        report.top.truncate(top);
+        Ok(report)
+    }
+}
+
+#[cfg(feature = "csv")]
+impl BayesModel {
+    /// Trains the model with the rows of a CSV file with a header row, taking
+    /// the text and label of each message from the named columns. Labels are
+    /// `spam` or `1` for spam and `ham` or `0` for ham, in any case. Malformed
+    /// rows are recorded in the report and skipped, while a header without
+    /// the columns is an error.
+    pub fn train_csv(
+        &mut self,
+        reader: impl io::Read,
+        text_col: &str,
+        label_col: &str,
+        suffixes: &PublicSuffix,
+    ) -> io::Result<CsvTrainReport> {
+        let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(reader);
+        let headers = reader.headers()?;
+        let position = |name: &str| {
+            headers
+                .iter()
+                .position(|header| header == name)
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Column {name:?} not found"),
+                    )
+                })
+        };
+        let (text_pos, label_pos) = (position(text_col)?, position(label_col)?);
+
+        let mut report = CsvTrainReport::default();
+        let mut record = csv::StringRecord::new();
+        loop {
+            let line = reader.position().line();
+            match reader.read_record(&mut record) {
+                Ok(true) => (),
+                Ok(false) => break,
+                Err(err) if err.is_io_error() => return Err(err.into()),
+                Err(_) => {
+                    report.malformed.push(line);
+                    continue;
+                }
+            }
+
+            let is_spam = match record.get(label_pos).map(|label| label.trim()) {
+                Some(label) if label == "1" || label.eq_ignore_ascii_case("spam") => true,
+                Some(label) if label == "0" || label.eq_ignore_ascii_case("ham") => false,
+                _ => {
+                    report.malformed.push(line);
+                    continue;
+                }
+            };
+            let trained = record
+                .get(text_pos)
+                .is_some_and(|text| self.train_text(text, is_spam, suffixes).is_ok());
+            if !trained {
+                report.malformed.push(line);
+            } else if is_spam {
+                report.spam += 1;
+            } else {
+                report.ham += 1;
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+// Calls `visit` with the text of every message of a Maildir folder, or the
+// error that prevented reading or parsing it. Only failing to read the folder
+// itself is an error.
+fn for_each_message(
+    dir: &Path,
+    mut visit: impl FnMut(PathBuf, io::Result<String>),
+) -> io::Result<()> {
+    let parser = MessageParser::default();
+
+    fs::read_dir(dir)?;
+    for sub_dir in ["cur", "new"] {
+        let entries = match fs::read_dir(dir.join(sub_dir)) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => {
+                visit(dir.join(sub_dir), Err(err));
+                continue;
+            }
+        };
+        for entry in entries {
+            let path = match entry {
+                Ok(entry) => entry.path(),
+                Err(err) => {
+                    visit(dir.join(sub_dir), Err(err));
+                    continue;
+                }
+            };
+            if !path.is_file()
+                || path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .is_none_or(|name| name.starts_with('.'))
+            {
+                continue;
+            }
+
+            let text = fs::read(&path).and_then(|bytes| {
+                parser
+                    .parse(&bytes)
+                    .map(|message| message_text(&message))
+                    .ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::InvalidData, "Failed to parse message")
+                    })
+            });
+            visit(path, text);
+        }
+    }
+
+    Ok(())
+}
+
+pub fn message_text(message: &Message<'_>) -> String {
+    let mut text = message.subject().unwrap_or_default().to_string();
+    for body in (0..message.text_body_count()).filter_map(|pos| message.body_text(pos)) {
+        text.push('\n');
+        text.push_str(&body);
+    }
+    text
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use utils::suffixlist::PublicSuffix;
+
+    use crate::bayes::{BayesClassifier, BayesModel};
+
+    use super::TrainProgress;
+
+    fn maildir() -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .parent()
+            .unwrap()
+            .parent()
+            .unwrap()
+            .join("tests")
+            .join("resources")
+            .join("nlp")
+            .join("maildir")
+    }
+
+    #[test]
+    fn train_maildir() {
+        let dir = maildir();
+
+        let mut model = BayesModel::default();
+        let report = model
+            .train_maildir(&dir, true, &PublicSuffix::default())
+            .unwrap();
+
+        assert_eq!(report.learned, 3);
+        assert_eq!(report.errors.len(), 1, "{:?}", report.errors);
+        assert!(report.errors[0].0.ends_with("not-a-message"));
+        assert_eq!(model.spam_learns, 3);
+        assert_eq!(model.ham_learns, 0);
+        assert!(!model.weights.is_empty());
+
+        // Without a `new` directory and with a message without tokens
+        let partial = std::env::temp_dir().join(format!("bayes-maildir-{}", std::process::id()));
+        let cur = partial.join("cur");
+        std::fs::create_dir_all(&cur).unwrap();
+        std::fs::copy(
+            dir.join("cur").join("1700000000.M1P1.example"),
+            cur.join("1700000000.M1P1.example"),
+        )
+        .unwrap();
+        std::fs::write(cur.join("1700000003.M4P1.example"), "Subject: !!!\n\n...\n").unwrap();
+        let mut model = BayesModel::default();
+        let report = model.train_maildir(&partial, false, &PublicSuffix::default());
+        std::fs::remove_dir_all(&partial).unwrap();
+        let report = report.unwrap();
+        assert_eq!(report.learned, 1);
+        assert_eq!(report.errors.len(), 1, "{:?}", report.errors);
+        assert!(report.errors[0].0.ends_with("1700000003.M4P1.example"));
+        assert_eq!((model.spam_learns, model.ham_learns), (0, 1));
+
+        assert!(model
+            .train_maildir(&partial, false, &PublicSuffix::default())
+            .is_err());
+    }
+
+    #[test]
+    fn train_maildir_progress() {
+        for (every, calls) in [(1, 4), (2, 2), (3, 1), (5, 0)] {
+            let mut progress = Vec::new();
+            let report = BayesModel::default()
+                .train_maildir_with_progress(
+                    maildir(),
+                    true,
+                    &PublicSuffix::default(),
+                    every,
+                    |update| progress.push(update),
+                )
+                .unwrap();
+
+            assert_eq!(progress.len(), calls, "{progress:?}");
+            assert!(progress
+                .iter()
+                .all(|update| (update.learned + update.errors) % every == 0));
+            if every == 1 {
+                assert_eq!(
+                    progress.last(),
+                    Some(&TrainProgress {
+                        learned: report.learned,
+                        errors: report.errors.len()
+                    })
+                );
+            }
+        }
+    }
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn train_csv() {
+        let csv = std::fs::read(maildir().parent().unwrap().join("labeled.csv")).unwrap();
+
+        let mut model = BayesModel::default();
+        let report = model
+            .train_csv(&csv[..], "text", "label", &PublicSuffix::default())
+            .unwrap();
+        assert_eq!((report.spam, report.ham), (2, 3));
+        assert_eq!(report.malformed, [8, 9, 10]);
+        assert_eq!((model.spam_learns, model.ham_learns), (2, 3));
+        assert_eq!(model.learned_messages, 5);
+
+        let err = model
+            .train_csv(&csv[..], "body", "label", &PublicSuffix::default())
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!((model.spam_learns, model.ham_learns), (2, 3));
+    }
+
+    #[test]
+    fn scan_maildir() {
+        let suffixes = PublicSuffix::default();
+        let mut model = BayesModel::default();
+        for _ in 0..3 {
+            for (text, is_spam) in [
+                ("claim your free cruise prize with your bank details", true),
+                ("cheap medication without prescription, special offer", true),
+                (
+                    "the investment program doubles your money every week",
+                    false,
+                ),
+            ] {
+                model.train_text(text, is_spam, &suffixes).unwrap();
+            }
+        }
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 1,
+            ..Default::default()
+        };
+
+        let (spam_learns, ham_learns) = (model.spam_learns, model.ham_learns);
+        let report = model
+            .scan_maildir(&classifier, maildir(), &suffixes, 2)
+            .unwrap();
+        assert_eq!(report.spam, 2);
+        assert_eq!(report.ham, 1);
+        assert_eq!(report.unclassified, 0);
+        assert_eq!(report.errors.len(), 1, "{:?}", report.errors);
+        assert!(report.errors[0].0.ends_with("not-a-message"));
+        assert_eq!(report.top.len(), 2);
+        assert!(report.top[0].1 >= report.top[1].1);
+        assert!(report
+            .top
+            .iter()
+            .all(|(path, score)| classifier.is_spam(*score)
+                && path.parent().unwrap().ends_with("cur")));
+        assert_eq!(
+            (model.spam_learns, model.ham_learns),
+            (spam_learns, ham_learns)
+        );
+    }
+}
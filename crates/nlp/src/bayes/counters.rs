@@ -0,0 +1,172 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::fmt::Display;
+
+use super::{BayesModel, TokenHash};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CountersError {
+    // A token was learned into a class more times than the class was learned
+    ExceedsLearns {
+        token: TokenHash,
+        is_spam: bool,
+        hits: u32,
+        learns: u32,
+    },
+}
+
+impl BayesModel {
+    /// Returns the number of ham and spam messages learned.
+    pub fn learn_counters(&self) -> (u32, u32) {
+        (self.ham_learns, self.spam_learns)
+    }
+
+    /// Returns the fraction of the learned messages that were spam, or None
+    /// when the model is untrained. Its `log_odds` can be supplied as the
+    /// prior of `BayesClassifier::classify_with_prior` to correct scores for
+    /// the class balance of the training mix.
+    pub fn spam_base_rate(&self) -> Option<f64> {
+        let total = self.spam_learns as u64 + self.ham_learns as u64;
+        (total > 0).then(|| self.spam_learns as f64 / total as f64)
+    }
+
+    /// Replaces the number of ham and spam messages learned, leaving the token
+    /// weights untouched, to repair counters that went out of sync with them
+    /// (e.g. after a partial import). The counters are refused if any token
+    /// was seen in a class more often than the new number of learns of that
+    /// class, which can also happen when a token repeats within messages.
+    pub fn set_learn_counters(&mut self, ham: u32, spam: u32) -> Result<(), CountersError> {
+        for (token, weights) in &self.weights {
+            for (is_spam, hits, learns) in [(false, weights.ham, ham), (true, weights.spam, spam)] {
+                if hits > learns {
+                    return Err(CountersError::ExceedsLearns {
+                        token: *token,
+                        is_spam,
+                        hits,
+                        learns,
+                    });
+                }
+            }
+        }
+
+        self.ham_learns = ham;
+        self.spam_learns = spam;
+        self.version += 1;
+        Ok(())
+    }
+}
+
+impl Display for CountersError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CountersError::ExceedsLearns {
+                token,
+                is_spam,
+                hits,
+                learns,
+            } => write!(
+                f,
+                "Token {:032x} was learned {hits} times as {}, more than the {learns} learns requested",
+                u128::from(*token),
+                if *is_spam { "spam" } else { "ham" }
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CountersError {}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        bayes::{counters::CountersError, BayesClassifier, BayesModel, TokenHash},
+        tokenizers::osb::OsbToken,
+    };
+
+    fn tokens(hashes: &[u64]) -> Vec<OsbToken<TokenHash>> {
+        hashes
+            .iter()
+            .map(|&h1| OsbToken {
+                inner: TokenHash { h1, h2: 0 },
+                idx: 0,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn learn_counters() {
+        let classifier = BayesClassifier {
+            min_learns: 10,
+            min_tokens: 1,
+            min_token_hits: 1,
+            ..Default::default()
+        };
+        let mut model = BayesModel::default();
+        for _ in 0..3 {
+            model.train(tokens(&[1, 2, 3]), true);
+            model.train(tokens(&[4, 5, 6]), false);
+        }
+        assert_eq!(model.learn_counters(), (3, 3));
+        assert_eq!(
+            model.classify_token_hashes(&classifier, tokens(&[1, 2, 3]).into_iter()),
+            None
+        );
+
+        // Raising the counters past `min_learns` leaves the weights untouched
+        let weights = model.weights.clone();
+        let version = model.version;
+        model.set_learn_counters(10, 12).unwrap();
+        assert_eq!(model.learn_counters(), (10, 12));
+        assert_eq!(model.weights, weights);
+        assert!(model.version > version);
+        let score = model
+            .classify_token_hashes(&classifier, tokens(&[1, 2, 3]).into_iter())
+            .unwrap();
+        assert!(score > 0.5, "{score}");
+
+        // Counters below the token totals are refused
+        assert!(matches!(
+            model.set_learn_counters(10, 2),
+            Err(CountersError::ExceedsLearns {
+                is_spam: true,
+                hits: 3,
+                learns: 2,
+                ..
+            })
+        ));
+        assert_eq!(model.learn_counters(), (10, 12));
+    }
+
+    #[test]
+    fn spam_base_rate() {
+        let mut model = BayesModel::default();
+        assert_eq!(model.spam_base_rate(), None);
+
+        for i in 0..20 {
+            model.train(tokens(&[i]), i % 4 == 0);
+        }
+        assert_eq!(model.learn_counters(), (15, 5));
+        assert_eq!(model.spam_base_rate(), Some(0.25));
+    }
+}
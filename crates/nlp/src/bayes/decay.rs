@@ -0,0 +1,346 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    collections::HashMap,
+    hash::BuildHasherDefault,
+    time::{Duration, SystemTime},
+};
+
+use nohash::NoHashHasher;
+use serde::{Deserialize, Serialize};
+
+use crate::tokenizers::osb::OsbToken;
+
+use super::{classify::TokenInput, BayesClassifier, BayesModel, TokenHash, Weights};
+
+/// Source of the current time, in seconds since an arbitrary epoch.
+pub trait Clock {
+    fn now(&self) -> u64;
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::UNIX_EPOCH
+            .elapsed()
+            .map_or(0, |elapsed| elapsed.as_secs())
+    }
+}
+
+/// Class counts that decay over time, as of the time they were last updated.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct DecayedWeights {
+    pub spam: f64,
+    pub ham: f64,
+    pub updated: u64,
+}
+
+/// Model whose counts favor recent messages. On every learn, the counts of
+/// each token and class are updated as `count * decay^Δt + 1`, where `Δt` is
+/// the number of seconds since they were last updated, and they are decayed
+/// to the current time when classifying. Tokens whose decayed count falls
+/// below `min_token_hits` are ignored.
+#[derive(Debug)]
+pub struct DecayedModel<C = SystemClock> {
+    pub weights: HashMap<TokenHash, DecayedWeights, BuildHasherDefault<NoHashHasher<TokenHash>>>,
+    pub learns: DecayedWeights,
+    // Factor applied to the counts for each elapsed second
+    pub decay: f64,
+    pub clock: C,
+}
+
+impl<C: Clock> DecayedModel<C> {
+    pub fn new(decay: f64, clock: C) -> Self {
+        DecayedModel {
+            weights: HashMap::default(),
+            learns: DecayedWeights::default(),
+            decay: decay.clamp(0.0, 1.0),
+            clock,
+        }
+    }
+
+    /// Creates a model whose counts halve every `half_life`.
+    pub fn with_half_life(half_life: Duration, clock: C) -> Self {
+        Self::new(0.5f64.powf(1.0 / half_life.as_secs_f64().max(1.0)), clock)
+    }
+
+    pub fn train<T>(&mut self, tokens: T, is_spam: bool)
+    where
+        T: IntoIterator<Item = OsbToken<TokenHash>>,
+    {
+        let now = self.clock.now();
+        let decay = self.decay;
+
+        self.learns = self.learns.learn(is_spam, now, decay);
+        for token in tokens {
+            let weights = self.weights.entry(token.inner).or_default();
+            *weights = weights.learn(is_spam, now, decay);
+        }
+    }
+
+    pub fn classify<T>(&self, classifier: &BayesClassifier, tokens: T) -> Option<f64>
+    where
+        T: Iterator<Item = OsbToken<TokenHash>>,
+    {
+        let now = self.clock.now();
+        let learns = self.learns.decayed(now, self.decay);
+
+        classifier
+            .classify_tokens(
+                tokens.map(|t| {
+                    let weights = self
+                        .weights
+                        .get(&t.inner)
+                        .map(|weights| weights.decayed(now, self.decay))
+                        .unwrap_or_default();
+                    let spam_freq = weights.spam / learns.spam.max(f64::EPSILON);
+                    let ham_freq = weights.ham / learns.ham.max(f64::EPSILON);
+
+                    TokenInput {
+                        // Only the total number of hits is used along a known probability
+                        weights: Weights {
+                            spam: (weights.spam + weights.ham).round() as u32,
+                            ham: 0,
+                        },
+                        idx: t.idx,
+                        hash: Some(t.inner),
+                        doc_freq: None,
+                        spam_prob: (spam_freq + ham_freq > 0.0)
+                            .then(|| spam_freq / (spam_freq + ham_freq)),
+                    }
+                }),
+                learns.ham.round() as u32,
+                learns.spam.round() as u32,
+            )
+            .map(|result| result.score)
+    }
+}
+
+impl DecayedWeights {
+    /// Returns the counts decayed to `now`.
+    pub fn decayed(&self, now: u64, decay: f64) -> Self {
+        let factor = decay.powf(now.saturating_sub(self.updated) as f64);
+        DecayedWeights {
+            spam: self.spam * factor,
+            ham: self.ham * factor,
+            updated: now.max(self.updated),
+        }
+    }
+
+    fn learn(&self, is_spam: bool, now: u64, decay: f64) -> Self {
+        let mut weights = self.decayed(now, decay);
+        if is_spam {
+            weights.spam += 1.0;
+        } else {
+            weights.ham += 1.0;
+        }
+        weights
+    }
+}
+
+/// Decays the counts of a model by `factor` each time it learns `every`
+/// more messages, so that it stays fresh in proportion to its activity.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DecaySchedule {
+    pub every: u64,
+    pub factor: f64,
+}
+
+impl BayesModel {
+    /// Multiplies the token counts, document frequencies and learn counters,
+    /// including those of every namespace, by `factor`, rounding down. Tokens
+    /// and namespaces left without counts are removed.
+    pub fn decay(&mut self, factor: f64) {
+        let factor = factor.clamp(0.0, 1.0);
+        let scale = |count: u32| (count as f64 * factor) as u32;
+
+        self.weights.retain(|_, weights| {
+            weights.spam = scale(weights.spam);
+            weights.ham = scale(weights.ham);
+            weights.spam > 0 || weights.ham > 0
+        });
+        let weights = &self.weights;
+        self.doc_freq.retain(|hash, count| {
+            *count = scale(*count);
+            *count > 0 && weights.contains_key(hash)
+        });
+        self.class_index.clear();
+        self.spam_learns = scale(self.spam_learns);
+        self.ham_learns = scale(self.ham_learns);
+        self.namespaces.retain(|_, learns| {
+            learns.spam = scale(learns.spam);
+            learns.ham = scale(learns.ham);
+            learns.spam > 0 || learns.ham > 0
+        });
+        self.version += 1;
+    }
+
+    // Applies the decay schedule once `learned_messages` reaches a milestone
+    pub(crate) fn scheduled_decay(&mut self) {
+        if let Some(schedule) = self.decay_schedule {
+            if schedule.every > 0 && self.learned_messages.is_multiple_of(schedule.every) {
+                self.decay(schedule.factor);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::Cell, time::Duration};
+
+    use crate::{
+        bayes::{namespace::Namespace, BayesClassifier, BayesModel, TokenHash, Weights},
+        tokenizers::osb::OsbToken,
+    };
+
+    use super::{Clock, DecaySchedule, DecayedModel};
+
+    const DAY: u64 = 86400;
+
+    struct ManualClock(Cell<u64>);
+
+    impl Clock for &ManualClock {
+        fn now(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    fn tokens(h1: u64) -> Vec<OsbToken<TokenHash>> {
+        vec![OsbToken {
+            inner: TokenHash { h1, h2: 0 },
+            idx: 0,
+        }]
+    }
+
+    #[test]
+    fn decayed_counts() {
+        let clock = ManualClock(Cell::new(0));
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 1,
+            min_token_hits: 1,
+            ..Default::default()
+        };
+        let mut model = DecayedModel::with_half_life(Duration::from_secs(7 * DAY), &clock);
+        let (old, fresh, neutral) = (1, 2, 3);
+
+        for _ in 0..8 {
+            model.train(tokens(old), true);
+            model.train(tokens(neutral), false);
+        }
+        let score =
+            |model: &DecayedModel<_>, h1| model.classify(&classifier, tokens(h1).into_iter());
+        let old_score = score(&model, old).unwrap();
+
+        // A week later a new spam campaign starts
+        clock.0.set(7 * DAY);
+        for _ in 0..8 {
+            model.train(tokens(fresh), true);
+            model.train(tokens(neutral), false);
+        }
+        let weights = model.weights[&TokenHash { h1: old, h2: 0 }];
+        assert_eq!(weights.decayed(7 * DAY, model.decay).spam.round(), 4.0);
+        assert!(score(&model, old).unwrap() < score(&model, fresh).unwrap());
+        assert!(score(&model, old).unwrap() < old_score);
+
+        // Two months later, the old token no longer counts
+        clock.0.set(9 * 7 * DAY);
+        model.train(tokens(fresh), true);
+        model.train(tokens(neutral), false);
+        assert_eq!(score(&model, old), None);
+        assert!(score(&model, fresh).is_some());
+    }
+
+    #[test]
+    fn milestone_decay() {
+        let mut model = BayesModel {
+            decay_schedule: Some(DecaySchedule {
+                every: 10,
+                factor: 0.5,
+            }),
+            ..Default::default()
+        };
+        let tokens = |hashes: &[u64]| {
+            hashes
+                .iter()
+                .map(|&h1| OsbToken {
+                    inner: TokenHash { h1, h2: 0 },
+                    idx: 0,
+                })
+                .collect::<Vec<_>>()
+        };
+        let hash = |h1| TokenHash { h1, h2: 0 };
+
+        for _ in 0..9 {
+            model.train(tokens(&[1, 2]), true);
+        }
+        model.train(tokens(&[3]), false);
+        assert_eq!(model.learned_messages, 10);
+        // Decayed once as the tenth message was learned
+        assert_eq!(model.weights[&hash(1)], Weights { spam: 4, ham: 0 });
+        assert_eq!(model.doc_freq[&hash(1)], 4);
+        assert_eq!((model.spam_learns, model.ham_learns), (4, 0));
+        assert!(!model.weights.contains_key(&hash(3)));
+
+        for _ in 0..9 {
+            model.train(tokens(&[1]), true);
+        }
+        assert_eq!(model.weights[&hash(1)], Weights { spam: 13, ham: 0 });
+        model.train(tokens(&[1]), true);
+        assert_eq!(model.weights[&hash(1)], Weights { spam: 7, ham: 0 });
+        assert_eq!(model.weights[&hash(2)], Weights { spam: 2, ham: 0 });
+        assert_eq!(model.spam_learns, 7);
+
+        // Namespaces decay along with the tokens they learned
+        let mut model = BayesModel {
+            decay_schedule: Some(DecaySchedule {
+                every: 10,
+                factor: 0.5,
+            }),
+            ..Default::default()
+        };
+        let tenant = Namespace(1);
+        for _ in 0..6 {
+            model.train_namespaced(tenant, tokens(&[1]), true);
+        }
+        model.train_namespaced(tenant, tokens(&[2]), false);
+        for _ in 0..10 {
+            model.train(tokens(&[3]), false);
+        }
+        assert_eq!(
+            model.weights[&tenant.token_hash(hash(1))],
+            Weights { spam: 3, ham: 0 }
+        );
+        assert_eq!(model.namespace_learns(tenant), (0, 3));
+        assert_eq!(model.ham_learns, 5);
+
+        model.decay(0.5);
+        assert_eq!(model.namespace_learns(tenant), (0, 1));
+        model.decay(0.5);
+        assert!(model.namespaces.is_empty());
+    }
+}
@@ -0,0 +1,159 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{collections::HashSet, hash::Hash, iter::Peekable};
+
+use crate::tokenizers::osb::OsbToken;
+
+/// Drops the OSB windows of a message that repeat an earlier window, a window
+/// being a unigram (`idx` 0) followed by the bigrams formed with the words
+/// after it. A paragraph pasted several times then contributes its tokens
+/// about once, plus the windows spanning the joins between the copies.
+/// Disabled, the tokens are passed through as they are.
+pub struct DedupWindows<I, T>
+where
+    I: Iterator<Item = OsbToken<T>>,
+{
+    iter: Peekable<I>,
+    enabled: bool,
+    seen: HashSet<Vec<OsbToken<T>>>,
+    window: std::vec::IntoIter<OsbToken<T>>,
+}
+
+impl<I, T> DedupWindows<I, T>
+where
+    I: Iterator<Item = OsbToken<T>>,
+    T: Hash + Eq + Clone,
+{
+    pub fn new(iter: I, enabled: bool) -> Self {
+        DedupWindows {
+            iter: iter.peekable(),
+            enabled,
+            seen: HashSet::new(),
+            window: Vec::new().into_iter(),
+        }
+    }
+}
+
+impl<I, T> Iterator for DedupWindows<I, T>
+where
+    I: Iterator<Item = OsbToken<T>>,
+    T: Hash + Eq + Clone,
+{
+    type Item = OsbToken<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.enabled {
+            return self.iter.next();
+        }
+
+        loop {
+            if let Some(token) = self.window.next() {
+                return Some(token);
+            }
+
+            let mut window = vec![self.iter.next()?];
+            while let Some(token) = self.iter.next_if(|token| token.idx != 0) {
+                window.push(token);
+            }
+            if !self.seen.contains(&window) {
+                self.seen.insert(window.clone());
+                self.window = window.into_iter();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use utils::suffixlist::PublicSuffix;
+
+    use crate::{
+        bayes::{tokenize::BayesTokenizer, BayesClassifier, BayesModel, TokenHash},
+        tokenizers::osb::{OsbToken, OsbTokenizer},
+    };
+
+    use super::DedupWindows;
+
+    #[test]
+    fn repeated_paragraph() {
+        let suffixes = PublicSuffix::default();
+        let paragraph = concat!(
+            "limited offer claim your free prize today by replying with your bank details ",
+            "and postal address so our agents can dispatch the voucher, winners are drawn ",
+            "weekly among subscribers and unclaimed prizes expire after seven days"
+        );
+        let repeated = [paragraph; 3].join(" ");
+
+        let mut model = BayesModel::default();
+        for _ in 0..5 {
+            model.train_text(paragraph, true, &suffixes).unwrap();
+            model
+                .train_text("see you at the team lunch on friday", false, &suffixes)
+                .unwrap();
+        }
+        let tokens = |text: &str| -> Vec<OsbToken<TokenHash>> {
+            OsbTokenizer::with_hasher(
+                BayesTokenizer::new(text, &suffixes),
+                model.window_size,
+                model.token_hasher(),
+            )
+            .collect()
+        };
+
+        let single = tokens(paragraph).len();
+        assert_eq!(
+            DedupWindows::new(tokens(&repeated).into_iter(), false).count(),
+            tokens(&repeated).len()
+        );
+        let deduped = DedupWindows::new(tokens(&repeated).into_iter(), true).collect::<Vec<_>>();
+        assert!(
+            deduped.len() < single * 3 / 2,
+            "{} vs {single}",
+            deduped.len()
+        );
+        // Each window is kept once, in its original order
+        assert_eq!(deduped[..single - 10], tokens(paragraph)[..single - 10]);
+
+        // The evidence of the message is counted about once
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 1,
+            ..Default::default()
+        };
+        let dedup_classifier = BayesClassifier {
+            dedup_windows: true,
+            ..classifier.clone()
+        };
+        let processed = |classifier: &BayesClassifier, text: &str| {
+            model
+                .classify_token_hashes_detailed(classifier, tokens(text).into_iter())
+                .unwrap()
+                .processed_tokens
+        };
+        let single = processed(&classifier, paragraph);
+        assert!(processed(&classifier, &repeated) > single * 5 / 2);
+        assert!(processed(&dedup_classifier, &repeated) < single * 3 / 2);
+        assert_eq!(processed(&dedup_classifier, paragraph), single);
+    }
+}
@@ -0,0 +1,383 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::fmt::Display;
+
+use crate::tokenizers::osb::OsbToken;
+
+use super::{classify::TokenInput, BayesClassifier, BayesModel, TokenHash};
+
+/// Setting of a classifier that likely prevents it from returning verdicts.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Diagnostic {
+    // No token can be strong enough to count
+    MinProbStrengthUnreachable {
+        min_prob_strength: f64,
+    },
+    // No score can be far enough from 0.5
+    DecisionMarginUnreachable {
+        decision_margin: f64,
+    },
+    // Every or no score is spam
+    SpamThresholdOutOfRange {
+        spam_threshold: f64,
+    },
+    // Scores would be labeled for auto-training as both spam and ham
+    AutolearnThresholdsOverlap {
+        autolearn_spam: f64,
+        autolearn_ham: f64,
+    },
+    // The model has not learned enough messages of each class
+    MinLearnsUnreached {
+        min_learns: u32,
+        spam_learns: u32,
+        ham_learns: u32,
+    },
+    // The model is still in its warm-up period
+    WarmupUnreached {
+        warmup_messages: u64,
+        learned_messages: u64,
+    },
+    // No token of the sample was seen `min_token_hits` times
+    AllTokensBelowMinHits {
+        min_token_hits: u32,
+    },
+    // No token of the sample is stronger than `min_prob_strength`
+    AllTokensTooWeak {
+        min_prob_strength: f64,
+    },
+    // No message of the sample has `min_tokens` counted tokens
+    MinTokensUnreached {
+        min_tokens: u32,
+        max_processed: u32,
+    },
+    // No message of the sample adds up to `min_evidence`
+    MinEvidenceUnreached {
+        min_evidence: f64,
+        max_evidence: f64,
+    },
+}
+
+impl BayesClassifier {
+    /// Reports settings that make verdicts impossible or unlikely regardless
+    /// of the model.
+    pub fn diagnose(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        // Token probabilities never exceed 1, so stronger tokens cannot exist
+        if self.min_prob_strength > 0.5 {
+            diagnostics.push(Diagnostic::MinProbStrengthUnreachable {
+                min_prob_strength: self.min_prob_strength,
+            });
+        }
+        if self.decision_margin >= 0.5 {
+            diagnostics.push(Diagnostic::DecisionMarginUnreachable {
+                decision_margin: self.decision_margin,
+            });
+        }
+        if !(self.spam_threshold > 0.0 && self.spam_threshold <= 1.0) {
+            diagnostics.push(Diagnostic::SpamThresholdOutOfRange {
+                spam_threshold: self.spam_threshold,
+            });
+        }
+        if self.autolearn_spam <= self.autolearn_ham {
+            diagnostics.push(Diagnostic::AutolearnThresholdsOverlap {
+                autolearn_spam: self.autolearn_spam,
+                autolearn_ham: self.autolearn_ham,
+            });
+        }
+        diagnostics
+    }
+
+    /// Same as `diagnose`, also reporting the thresholds that filter out
+    /// every token or message of a sample classified against a model. The
+    /// sample should be representative of the traffic, as these only tell
+    /// that no message of the sample could reach a verdict.
+    pub fn diagnose_sample(
+        &self,
+        model: &BayesModel,
+        sample: &[&[OsbToken<TokenHash>]],
+    ) -> Vec<Diagnostic> {
+        let mut diagnostics = self.diagnose();
+        if self.min_learns > 0
+            && (model.spam_learns < self.min_learns || model.ham_learns < self.min_learns)
+        {
+            diagnostics.push(Diagnostic::MinLearnsUnreached {
+                min_learns: self.min_learns,
+                spam_learns: model.spam_learns,
+                ham_learns: model.ham_learns,
+            });
+        }
+        if model.learned_messages < self.warmup_messages {
+            diagnostics.push(Diagnostic::WarmupUnreached {
+                warmup_messages: self.warmup_messages,
+                learned_messages: model.learned_messages,
+            });
+        }
+
+        let (mut has_tokens, mut has_hits) = (false, false);
+        let (mut max_processed, mut max_evidence) = (0, 0.0f64);
+        for tokens in sample {
+            let (mut processed, mut evidence) = (0, 0.0);
+            for token in tokens.iter() {
+                let input = TokenInput {
+                    weights: model.weights.get(&token.inner).copied().unwrap_or_default(),
+                    idx: token.idx,
+                    hash: Some(token.inner),
+                    doc_freq: self.idf_weighting.then(|| {
+                        model
+                            .doc_freq
+                            .get(&token.inner)
+                            .copied()
+                            .unwrap_or_default()
+                    }),
+                    spam_prob: None,
+                };
+                has_tokens = true;
+                has_hits |= self
+                    .combined_token_probs(
+                        &input,
+                        model.ham_learns,
+                        model.spam_learns,
+                        self.min_token_hits,
+                    )
+                    .is_some();
+                if let Some((spam_prob, _)) = self.token_probs(
+                    &input,
+                    model.ham_learns,
+                    model.spam_learns,
+                    self.min_token_hits,
+                ) {
+                    processed += 1;
+                    evidence += (spam_prob - 0.5).abs();
+                }
+            }
+            max_processed = max_processed.max(processed);
+            max_evidence = max_evidence.max(evidence);
+        }
+
+        if !has_tokens {
+            // Nothing to judge the thresholds by
+        } else if !has_hits {
+            diagnostics.push(Diagnostic::AllTokensBelowMinHits {
+                min_token_hits: self.min_token_hits,
+            });
+        } else if max_processed == 0 {
+            diagnostics.push(Diagnostic::AllTokensTooWeak {
+                min_prob_strength: self.min_prob_strength,
+            });
+        } else {
+            if max_processed < self.min_tokens {
+                diagnostics.push(Diagnostic::MinTokensUnreached {
+                    min_tokens: self.min_tokens,
+                    max_processed,
+                });
+            }
+            if max_evidence < self.min_evidence {
+                diagnostics.push(Diagnostic::MinEvidenceUnreached {
+                    min_evidence: self.min_evidence,
+                    max_evidence,
+                });
+            }
+        }
+        diagnostics
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Diagnostic::MinProbStrengthUnreachable { min_prob_strength } => write!(
+                f,
+                "min_prob_strength of {min_prob_strength} filters out every token, must be at most 0.5"
+            ),
+            Diagnostic::DecisionMarginUnreachable { decision_margin } => write!(
+                f,
+                "decision_margin of {decision_margin} rejects every score, must be below 0.5"
+            ),
+            Diagnostic::SpamThresholdOutOfRange { spam_threshold } => write!(
+                f,
+                "spam_threshold of {spam_threshold} is outside of (0, 1]"
+            ),
+            Diagnostic::AutolearnThresholdsOverlap {
+                autolearn_spam,
+                autolearn_ham,
+            } => write!(
+                f,
+                "autolearn_spam of {autolearn_spam} is not above autolearn_ham of {autolearn_ham}"
+            ),
+            Diagnostic::MinLearnsUnreached {
+                min_learns,
+                spam_learns,
+                ham_learns,
+            } => write!(
+                f,
+                "Model learned {spam_learns} spam and {ham_learns} ham messages, min_learns is {min_learns}"
+            ),
+            Diagnostic::WarmupUnreached {
+                warmup_messages,
+                learned_messages,
+            } => write!(
+                f,
+                "Model learned {learned_messages} messages, warmup_messages is {warmup_messages}"
+            ),
+            Diagnostic::AllTokensBelowMinHits { min_token_hits } => write!(
+                f,
+                "No token of the sample was seen min_token_hits ({min_token_hits}) times"
+            ),
+            Diagnostic::AllTokensTooWeak { min_prob_strength } => write!(
+                f,
+                "No token of the sample is stronger than min_prob_strength ({min_prob_strength})"
+            ),
+            Diagnostic::MinTokensUnreached {
+                min_tokens,
+                max_processed,
+            } => write!(
+                f,
+                "Messages of the sample count at most {max_processed} tokens, min_tokens is {min_tokens}"
+            ),
+            Diagnostic::MinEvidenceUnreached {
+                min_evidence,
+                max_evidence,
+            } => write!(
+                f,
+                "Messages of the sample add up to at most {max_evidence:.2} evidence, min_evidence is {min_evidence}"
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use utils::suffixlist::PublicSuffix;
+
+    use crate::{
+        bayes::{tokenize::BayesTokenizer, BayesClassifier, BayesModel},
+        tokenizers::osb::OsbTokenizer,
+    };
+
+    use super::Diagnostic;
+
+    #[test]
+    fn diagnose() {
+        let broken = BayesClassifier {
+            min_prob_strength: 0.6,
+            decision_margin: 0.5,
+            spam_threshold: 1.5,
+            autolearn_spam: 0.5,
+            autolearn_ham: 0.5,
+            ..Default::default()
+        };
+        assert_eq!(
+            broken.diagnose(),
+            [
+                Diagnostic::MinProbStrengthUnreachable {
+                    min_prob_strength: 0.6
+                },
+                Diagnostic::DecisionMarginUnreachable {
+                    decision_margin: 0.5
+                },
+                Diagnostic::SpamThresholdOutOfRange {
+                    spam_threshold: 1.5
+                },
+                Diagnostic::AutolearnThresholdsOverlap {
+                    autolearn_spam: 0.5,
+                    autolearn_ham: 0.5
+                },
+            ]
+        );
+
+        let suffixes = PublicSuffix::default();
+        let mut model = BayesModel::default();
+        for _ in 0..3 {
+            model
+                .train_text("claim your free prize now", true, &suffixes)
+                .unwrap();
+            model
+                .train_text("lunch with the team on friday", false, &suffixes)
+                .unwrap();
+        }
+        let tokens = OsbTokenizer::new(
+            BayesTokenizer::new("claim your free lunch", &suffixes),
+            model.window_size,
+        )
+        .collect::<Vec<_>>();
+        let sample = [tokens.as_slice()];
+
+        let sane = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 1,
+            ..Default::default()
+        };
+        assert_eq!(sane.diagnose_sample(&model, &sample), []);
+        assert_eq!(
+            BayesClassifier::default().diagnose_sample(&model, &sample),
+            [
+                Diagnostic::MinLearnsUnreached {
+                    min_learns: 200,
+                    spam_learns: 3,
+                    ham_learns: 3
+                },
+                Diagnostic::MinTokensUnreached {
+                    min_tokens: 11,
+                    max_processed: 4
+                }
+            ]
+        );
+
+        let diagnose = |classifier: BayesClassifier| classifier.diagnose_sample(&model, &sample);
+        assert_eq!(
+            diagnose(BayesClassifier {
+                min_token_hits: 10,
+                ..sane.clone()
+            }),
+            [Diagnostic::AllTokensBelowMinHits { min_token_hits: 10 }]
+        );
+        assert_eq!(
+            diagnose(BayesClassifier {
+                min_prob_strength: 0.6,
+                ..sane.clone()
+            }),
+            [
+                Diagnostic::MinProbStrengthUnreachable {
+                    min_prob_strength: 0.6
+                },
+                Diagnostic::AllTokensTooWeak {
+                    min_prob_strength: 0.6
+                }
+            ]
+        );
+        assert!(matches!(
+            diagnose(BayesClassifier {
+                min_evidence: 10.0,
+                ..sane.clone()
+            })[..],
+            [Diagnostic::MinEvidenceUnreached { .. }]
+        ));
+        assert!(diagnose(BayesClassifier {
+            warmup_messages: 100,
+            ..sane
+        })[0]
+            .to_string()
+            .contains("warmup_messages"));
+    }
+}
@@ -0,0 +1,225 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use crate::tokenizers::osb::OsbToken;
+
+use super::{BayesClassifier, BayesModel, TokenHash};
+
+/// How the scores of the members of an `Ensemble` are combined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EnsembleStrategy {
+    /// Average of the member scores, weighted by member weight.
+    #[default]
+    WeightedAverage,
+    /// Weighted vote of the members on whether the message is spam, using the
+    /// `spam_threshold` of each member. The score is the weighted average of
+    /// the winning side, and the confidence its share of the vote.
+    Majority,
+}
+
+/// Set of models, each with its own classifier settings and weight, whose
+/// scores are combined into a single verdict.
+#[derive(Debug, Clone, Default)]
+pub struct Ensemble<'x> {
+    members: Vec<EnsembleMember<'x>>,
+    strategy: EnsembleStrategy,
+}
+
+#[derive(Debug, Clone)]
+struct EnsembleMember<'x> {
+    model: &'x BayesModel,
+    classifier: &'x BayesClassifier,
+    weight: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnsembleResult {
+    // Score of each member in insertion order, None when it had no verdict
+    pub scores: Vec<Option<f64>>,
+    // None when no member reached a verdict
+    pub score: Option<f64>,
+    // Share of the voting weight agreeing with `score`, between 0.0 and 1.0
+    pub confidence: f64,
+}
+
+impl<'x> Ensemble<'x> {
+    pub fn new(strategy: EnsembleStrategy) -> Self {
+        Ensemble {
+            members: Vec::new(),
+            strategy,
+        }
+    }
+
+    /// Adds a model classified with `classifier`. Members with a weight of
+    /// zero or less are still scored but never count towards the verdict.
+    pub fn with_model(
+        mut self,
+        model: &'x BayesModel,
+        classifier: &'x BayesClassifier,
+        weight: f64,
+    ) -> Self {
+        self.members.push(EnsembleMember {
+            model,
+            classifier,
+            weight,
+        });
+        self
+    }
+
+    pub fn classify(&self, tokens: &[OsbToken<TokenHash>]) -> EnsembleResult {
+        let scores = self
+            .members
+            .iter()
+            .map(|member| {
+                member
+                    .model
+                    .classify_token_hashes(member.classifier, tokens.iter().cloned())
+            })
+            .collect::<Vec<_>>();
+        let votes = self
+            .members
+            .iter()
+            .zip(&scores)
+            .filter_map(|(member, score)| Some((member, (*score)?)))
+            .filter(|(member, _)| member.weight > 0.0)
+            .collect::<Vec<_>>();
+
+        let (score, confidence) = match self.strategy {
+            EnsembleStrategy::WeightedAverage => (weighted_average(&votes), 1.0),
+            EnsembleStrategy::Majority => {
+                let (spam, ham): (Vec<_>, Vec<_>) = votes
+                    .iter()
+                    .partition(|(member, score)| member.classifier.is_spam(*score));
+                let spam_weight = spam.iter().map(|(member, _)| member.weight).sum::<f64>();
+                let ham_weight = ham.iter().map(|(member, _)| member.weight).sum::<f64>();
+                let total = spam_weight + ham_weight;
+                if spam_weight > ham_weight {
+                    (weighted_average(&spam), spam_weight / total)
+                } else if ham_weight > spam_weight {
+                    (weighted_average(&ham), ham_weight / total)
+                } else {
+                    (weighted_average(&votes), 0.5)
+                }
+            }
+        };
+
+        EnsembleResult {
+            scores,
+            confidence: if score.is_some() { confidence } else { 0.0 },
+            score,
+        }
+    }
+}
+
+fn weighted_average(votes: &[(&EnsembleMember<'_>, f64)]) -> Option<f64> {
+    let total = votes.iter().map(|(member, _)| member.weight).sum::<f64>();
+    (total > 0.0).then(|| {
+        votes
+            .iter()
+            .map(|(member, score)| member.weight * score)
+            .sum::<f64>()
+            / total
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::borrow::Cow;
+
+    use crate::{
+        bayes::{BayesClassifier, BayesModel, TokenHash, OSB_WINDOW_SIZE},
+        tokenizers::osb::{OsbToken, OsbTokenizer},
+    };
+
+    use super::{Ensemble, EnsembleStrategy};
+
+    fn tokens(text: &str) -> Vec<OsbToken<TokenHash>> {
+        OsbTokenizer::new(text.split(' ').map(Cow::from), OSB_WINDOW_SIZE).collect()
+    }
+
+    fn model(spam: &str, ham: &str) -> BayesModel {
+        let mut model = BayesModel::default();
+        for _ in 0..5 {
+            model.train(tokens(spam), true);
+            model.train(tokens(ham), false);
+        }
+        model
+    }
+
+    #[test]
+    fn ensemble_vote() {
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 1,
+            ..Default::default()
+        };
+        let message = "crypto airdrop bonus";
+        let global = model(message, "the quarterly report");
+        let language = model("claim your prize", message);
+        let user = model(message, "lunch on friday");
+        let untrained = BayesModel::default();
+
+        let scores = [&global, &language, &user]
+            .map(|model| model.classify_token_hashes(&classifier, tokens(message).into_iter()));
+        assert!(classifier.is_spam(scores[0].unwrap()));
+        assert!(!classifier.is_spam(scores[1].unwrap()));
+        assert!(classifier.is_spam(scores[2].unwrap()));
+
+        let ensemble = |strategy| {
+            Ensemble::new(strategy)
+                .with_model(&global, &classifier, 1.0)
+                .with_model(&language, &classifier, 3.0)
+                .with_model(&user, &classifier, 1.0)
+                .with_model(&untrained, &classifier, 1.0)
+                .classify(&tokens(message))
+        };
+
+        // The weighted language model outweighs the others
+        let average = ensemble(EnsembleStrategy::WeightedAverage);
+        assert_eq!(&average.scores[..3], &scores[..]);
+        assert_eq!(average.scores[3], None);
+        let expected = (scores[0].unwrap() + 3.0 * scores[1].unwrap() + scores[2].unwrap()) / 5.0;
+        assert!((average.score.unwrap() - expected).abs() < 1e-9);
+        assert!(!classifier.is_spam(average.score.unwrap()));
+
+        // The language model is outvoted only when it weighs less than the rest
+        let majority = ensemble(EnsembleStrategy::Majority);
+        assert!(!classifier.is_spam(majority.score.unwrap()));
+        assert_eq!(majority.score, scores[1]);
+        assert_eq!(majority.confidence, 0.6);
+        let majority = Ensemble::new(EnsembleStrategy::Majority)
+            .with_model(&global, &classifier, 1.0)
+            .with_model(&language, &classifier, 1.0)
+            .with_model(&user, &classifier, 1.0)
+            .classify(&tokens(message));
+        assert!(classifier.is_spam(majority.score.unwrap()));
+        assert!((majority.confidence - 2.0 / 3.0).abs() < 1e-9);
+
+        // No verdict without scored members
+        let empty = Ensemble::new(EnsembleStrategy::Majority)
+            .with_model(&untrained, &classifier, 1.0)
+            .classify(&tokens(message));
+        assert_eq!(empty.score, None);
+        assert_eq!(empty.confidence, 0.0);
+    }
+}
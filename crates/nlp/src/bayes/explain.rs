@@ -0,0 +1,396 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{cmp::Ordering, collections::HashSet};
+
+use crate::tokenizers::osb::OsbToken;
+
+use super::{
+    classify::{log_odds, TokenInput},
+    BayesClassifier, BayesResult, TokenHash, Weights,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BayesExplanation {
+    pub result: Option<BayesResult>,
+    pub tokens: Vec<TokenExplanation>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TokenExplanation {
+    pub hash: TokenHash,
+    pub idx: usize,
+    pub weights: Weights,
+    pub spam_prob: f64,
+    // 95% Wilson score interval of the fraction of spam among the token hits
+    pub spam_interval: (f64, f64),
+}
+
+// Standard score of a two-sided 95% confidence level
+const CONFIDENCE_Z: f64 = 1.96;
+
+impl BayesClassifier {
+    /// Classifies the tokens and returns the `top_n` tokens that contributed
+    /// the most to the verdict, ranked by their deviation from 0.5. Ties are
+    /// broken by token hash so the ranking is reproducible.
+    pub fn explain<T>(
+        &self,
+        tokens: T,
+        ham_learns: u32,
+        spam_learns: u32,
+        top_n: usize,
+    ) -> BayesExplanation
+    where
+        T: Iterator<Item = (TokenHash, OsbToken<Weights>)>,
+    {
+        let tokens = tokens
+            .map(|(hash, token)| TokenInput {
+                hash: Some(hash),
+                ..TokenInput::from(token)
+            })
+            .collect::<Vec<_>>();
+        let result = self.classify_tokens(tokens.iter().copied(), ham_learns, spam_learns);
+
+        let mut seen = HashSet::new();
+        let mut explanation = tokens
+            .iter()
+            .filter_map(|token| {
+                let hash = token.hash?;
+                if !seen.insert(hash) {
+                    return None;
+                }
+                let (spam_prob, _) =
+                    self.token_probs(token, ham_learns, spam_learns, self.min_token_hits)?;
+                Some(TokenExplanation {
+                    hash,
+                    idx: token.idx,
+                    weights: token.weights,
+                    spam_prob,
+                    spam_interval: wilson_interval(
+                        token.weights.spam,
+                        token.weights.spam.saturating_add(token.weights.ham),
+                        CONFIDENCE_Z,
+                    ),
+                })
+            })
+            .collect::<Vec<_>>();
+        explanation.sort_unstable_by(|a, b| {
+            (b.spam_prob - 0.5)
+                .abs()
+                .partial_cmp(&(a.spam_prob - 0.5).abs())
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| a.hash.cmp(&b.hash))
+        });
+        explanation.truncate(top_n);
+
+        BayesExplanation {
+            result,
+            tokens: explanation,
+        }
+    }
+}
+
+/// Decomposition of the log-odds of a score into additive contributions of
+/// the tokens that counted towards it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BayesAttribution {
+    pub result: Option<BayesResult>,
+    // Log-odds of the score, 0.0 without a verdict
+    pub log_odds: f64,
+    // Contribution of each counted token in message order, repeated tokens
+    // contributing once per occurrence
+    pub tokens: Vec<TokenAttribution>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TokenAttribution {
+    pub hash: TokenHash,
+    pub idx: usize,
+    // Log-odds added to the score by the token, positive towards spam
+    pub contribution: f64,
+}
+
+impl BayesClassifier {
+    /// Classifies the tokens and attributes the log-odds of the score to
+    /// them, so that the contributions sum to the log-odds. Each token is
+    /// credited its log-likelihood ratio `ln(spam_prob) - ln(ham_prob)`,
+    /// which already add up to the log-odds under the naive combination;
+    /// under the inverse chi-square or positional combinations, which are not
+    /// additive, the ratios are scaled to sum to the log-odds of the score.
+    pub fn attribute<T>(&self, tokens: T, ham_learns: u32, spam_learns: u32) -> BayesAttribution
+    where
+        T: Iterator<Item = (TokenHash, OsbToken<Weights>)>,
+    {
+        let tokens = tokens
+            .map(|(hash, token)| TokenInput {
+                hash: Some(hash),
+                ..TokenInput::from(token)
+            })
+            .collect::<Vec<_>>();
+        let result = self.classify_tokens(tokens.iter().copied(), ham_learns, spam_learns);
+        let Some(score) = result.as_ref().map(|result| result.score) else {
+            return BayesAttribution {
+                result,
+                log_odds: 0.0,
+                tokens: Vec::new(),
+            };
+        };
+
+        let mut attribution = tokens
+            .iter()
+            .filter_map(|token| {
+                let (spam_prob, ham_prob) =
+                    self.token_probs(token, ham_learns, spam_learns, self.min_token_hits)?;
+                Some(TokenAttribution {
+                    hash: token.hash?,
+                    idx: token.idx,
+                    contribution: spam_prob.ln() - ham_prob.ln(),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let log_odds = log_odds(score);
+        let total = attribution
+            .iter()
+            .map(|token| token.contribution)
+            .sum::<f64>();
+        if total.abs() > f64::EPSILON {
+            let scale = log_odds / total;
+            for token in &mut attribution {
+                token.contribution *= scale;
+            }
+        } else if !attribution.is_empty() {
+            // Evidence that cancels out, split evenly
+            let share = log_odds / attribution.len() as f64;
+            for token in &mut attribution {
+                token.contribution = share;
+            }
+        }
+
+        BayesAttribution {
+            result,
+            log_odds,
+            tokens: attribution,
+        }
+    }
+}
+
+/// Wilson score interval of a proportion of `successes` out of `total`
+/// trials, for the standard score `z`. Returns (0.0, 1.0) without trials.
+pub fn wilson_interval(successes: u32, total: u32, z: f64) -> (f64, f64) {
+    if total == 0 {
+        return (0.0, 1.0);
+    }
+
+    let n = total as f64;
+    let p = successes as f64 / n;
+    let z2 = z * z;
+    let center = (p + z2 / (2.0 * n)) / (1.0 + z2 / n);
+    let margin = z / (1.0 + z2 / n) * (p * (1.0 - p) / n + z2 / (4.0 * n * n)).sqrt();
+    ((center - margin).max(0.0), (center + margin).min(1.0))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        bayes::{classify::log_odds, BayesClassifier, FisherCutoff, TokenHash, Weights},
+        tokenizers::osb::OsbToken,
+    };
+
+    use super::{wilson_interval, BayesAttribution};
+
+    #[test]
+    fn explain_tie_break() {
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            ..Default::default()
+        };
+        let tokens = |order: &[u64]| {
+            let mut tokens = order
+                .iter()
+                .map(|&h1| {
+                    (
+                        TokenHash { h1, h2: 0 },
+                        OsbToken {
+                            inner: Weights { spam: 9, ham: 1 },
+                            idx: 0,
+                        },
+                    )
+                })
+                .collect::<Vec<_>>();
+            tokens.push((
+                TokenHash { h1: 100, h2: 0 },
+                OsbToken {
+                    inner: Weights { spam: 20, ham: 0 },
+                    idx: 0,
+                },
+            ));
+            tokens
+        };
+
+        let mut rankings = Vec::new();
+        for order in [
+            [5, 3, 9, 1, 7, 2, 8, 4, 6, 10, 11, 12],
+            [12, 11, 10, 6, 4, 8, 2, 7, 1, 9, 3, 5],
+            [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12],
+        ] {
+            let explanation = classifier.explain(tokens(&order).into_iter(), 100, 100, 4);
+            assert!(explanation.result.is_some());
+            rankings.push(
+                explanation
+                    .tokens
+                    .iter()
+                    .map(|token| token.hash.h1)
+                    .collect::<Vec<_>>(),
+            );
+        }
+
+        assert_eq!(rankings[0], [100, 1, 2, 3]);
+        assert!(rankings.iter().all(|ranking| ranking == &rankings[0]));
+
+        // Saturated counts
+        let mut saturated = tokens(&[1]);
+        saturated[0].1.inner = Weights {
+            spam: u32::MAX,
+            ham: u32::MAX,
+        };
+        let explanation = classifier.explain(saturated.into_iter(), 100, 100, 4);
+        assert_eq!(explanation.tokens.len(), 2);
+    }
+
+    #[test]
+    fn additive_attribution() {
+        let tokens = |weights: &[(u32, u32)]| {
+            weights
+                .iter()
+                .enumerate()
+                .map(|(h1, &(spam, ham))| {
+                    (
+                        TokenHash {
+                            h1: h1 as u64,
+                            h2: 0,
+                        },
+                        OsbToken {
+                            inner: Weights { spam, ham },
+                            idx: h1 % 3,
+                        },
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let sum = |attribution: &BayesAttribution| {
+            attribution
+                .tokens
+                .iter()
+                .map(|token| token.contribution)
+                .sum::<f64>()
+        };
+
+        for classifier in [
+            BayesClassifier {
+                min_learns: 0,
+                min_tokens: 1,
+                ..Default::default()
+            },
+            // Inverse chi-square for every message
+            BayesClassifier {
+                min_learns: 0,
+                min_tokens: 1,
+                fisher_cutoff: FisherCutoff::Absolute(f64::INFINITY),
+                ..Default::default()
+            },
+        ] {
+            for message in [
+                &[(9, 1), (8, 2), (1, 6), (5, 5)][..],
+                &[(1, 9), (2, 7), (3, 3)],
+                &[(20, 1), (1, 20), (30, 2)],
+            ] {
+                let attribution = classifier.attribute(tokens(message).into_iter(), 100, 100);
+                let result = attribution.result.unwrap();
+                assert_eq!(attribution.tokens.len() as u32, result.processed_tokens);
+                assert!(
+                    (sum(&attribution) - log_odds(result.score)).abs() < 1e-9,
+                    "{attribution:?}"
+                );
+                assert_eq!(attribution.log_odds, log_odds(result.score));
+            }
+        }
+
+        // Spam tokens push towards spam and ham tokens towards ham
+        let attribution = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 1,
+            ..Default::default()
+        }
+        .attribute(tokens(&[(9, 1), (8, 2), (1, 6)]).into_iter(), 100, 100);
+        let contributions = attribution
+            .tokens
+            .iter()
+            .map(|token| token.contribution.signum())
+            .collect::<Vec<_>>();
+        assert_eq!(contributions, [1.0, 1.0, -1.0]);
+
+        // No attribution without a verdict
+        let attribution = BayesClassifier::default().attribute(tokens(&[(9, 1)]).into_iter(), 0, 0);
+        assert!(attribution.result.is_none() && attribution.tokens.is_empty());
+    }
+
+    #[test]
+    fn explain_intervals() {
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 1,
+            ..Default::default()
+        };
+        let tokens = [(1, 8, 2), (2, 800, 200)].map(|(h1, spam, ham)| {
+            (
+                TokenHash { h1, h2: 0 },
+                OsbToken {
+                    inner: Weights { spam, ham },
+                    idx: 0,
+                },
+            )
+        });
+
+        let explanation = classifier.explain(tokens.into_iter(), 1000, 1000, 2);
+        let interval = |h1| {
+            explanation
+                .tokens
+                .iter()
+                .find(|token| token.hash.h1 == h1)
+                .unwrap()
+                .spam_interval
+        };
+        let (rare, frequent) = (interval(1), interval(2));
+
+        // Both contain the observed fraction of 0.8
+        for (low, high) in [rare, frequent] {
+            assert!(low < 0.8 && 0.8 < high, "{low} {high}");
+        }
+        assert!(
+            frequent.1 - frequent.0 < (rare.1 - rare.0) / 5.0,
+            "{rare:?} {frequent:?}"
+        );
+        assert_eq!(wilson_interval(0, 0, 1.96), (0.0, 1.0));
+    }
+}
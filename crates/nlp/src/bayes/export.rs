@@ -0,0 +1,227 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    borrow::Cow,
+    io::{self, Write},
+};
+
+use super::{classify::TokenInput, BayesClassifier, BayesModel, TokenHash};
+
+impl BayesModel {
+    /// Writes the weights of every token as CSV rows of `h1,h2,token,spam,ham`,
+    /// sorted by hash. The `token` column holds the original string returned by
+    /// `token_name` when it is known, and is empty otherwise. Returns the number
+    /// of rows written, excluding the header.
+    #[cfg(feature = "csv")]
+    pub fn export_csv<'x, W, F>(&self, mut writer: W, token_name: F) -> io::Result<usize>
+    where
+        W: Write,
+        F: Fn(&TokenHash) -> Option<Cow<'x, str>>,
+    {
+        let mut hashes = self.weights.keys().collect::<Vec<_>>();
+        hashes.sort_unstable();
+
+        writer.write_all(b"h1,h2,token,spam,ham\n")?;
+        for hash in &hashes {
+            let weights = &self.weights[*hash];
+            let name = token_name(hash).unwrap_or_default();
+            write!(writer, "{:016x},{:016x},", hash.h1, hash.h2)?;
+            if name.contains([',', '"', '\n', '\r']) {
+                write!(writer, "\"{}\"", name.replace('"', "\"\""))?;
+            } else {
+                writer.write_all(name.as_bytes())?;
+            }
+            writeln!(writer, ",{},{}", weights.spam, weights.ham)?;
+        }
+        writer.flush()?;
+
+        Ok(hashes.len())
+    }
+
+    /// Writes the high-signal tokens as a SpamAssassin rule file, with a
+    /// `body`, `describe` and `score` line per token. Only tokens with a name
+    /// returned by `token_name`, at least `min_token_hits` hits and a
+    /// probability at least `min_prob_strength` away from 0.5 are included,
+    /// as judged by `classifier`. A token with spam probability `p` scores
+    /// `max_points * (2p - 1)` points, so spam tokens score up to
+    /// `max_points` and ham tokens down to `-max_points`. Rules are named
+    /// `BAYES_TOK_` followed by the token hash and sorted by it. Returns the
+    /// number of rules written.
+    pub fn export_spamassassin<'x, W, F>(
+        &self,
+        mut writer: W,
+        classifier: &BayesClassifier,
+        max_points: f64,
+        token_name: F,
+    ) -> io::Result<usize>
+    where
+        W: Write,
+        F: Fn(&TokenHash) -> Option<Cow<'x, str>>,
+    {
+        let mut hashes = self.weights.keys().collect::<Vec<_>>();
+        hashes.sort_unstable();
+
+        let mut rules = 0;
+        for hash in hashes {
+            let Some(name) = token_name(hash).filter(|name| !name.is_empty()) else {
+                continue;
+            };
+            let token = TokenInput {
+                weights: self.weights[hash],
+                idx: 0,
+                hash: Some(*hash),
+                doc_freq: None,
+                spam_prob: None,
+            };
+            let Some((spam_prob, _)) = classifier
+                .token_probs(
+                    &token,
+                    self.ham_learns,
+                    self.spam_learns,
+                    classifier.min_token_hits,
+                )
+                .filter(|(spam_prob, _)| (spam_prob - 0.5).abs() >= classifier.min_prob_strength)
+            else {
+                continue;
+            };
+
+            let rule = format!("BAYES_TOK_{:016X}{:016X}", hash.h1, hash.h2);
+            let mut pattern = String::with_capacity(name.len() + 16);
+            for ch in name.chars() {
+                if !ch.is_alphanumeric() && ch != '_' {
+                    pattern.push('\\');
+                }
+                pattern.push(ch);
+            }
+            writeln!(writer, "body     {rule} /(?<!\\w){pattern}(?!\\w)/i")?;
+            writeln!(
+                writer,
+                "describe {rule} Bayes token {}",
+                name.replace(['\n', '\r'], " ")
+            )?;
+            writeln!(
+                writer,
+                "score    {rule} {:.3}\n",
+                max_points * (2.0 * spam_prob - 1.0)
+            )?;
+            rules += 1;
+        }
+        writer.flush()?;
+
+        Ok(rules)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::borrow::Cow;
+
+    use crate::bayes::{BayesClassifier, BayesModel, TokenHash, Weights};
+
+    #[cfg(feature = "csv")]
+    #[test]
+    fn export_csv() {
+        let mut model = BayesModel::default();
+        for (h1, spam, ham) in [(3, 1, 0), (1, 4, 2), (2, 0, 7)] {
+            model
+                .weights
+                .insert(TokenHash { h1, h2: 0 }, Weights { spam, ham });
+        }
+
+        let mut csv = Vec::new();
+        let rows = model
+            .export_csv(&mut csv, |hash| match hash.h1 {
+                1 => Some(Cow::from("free")),
+                2 => Some(Cow::from("say \"hi\", bob")),
+                _ => None,
+            })
+            .unwrap();
+
+        assert_eq!(rows, model.weights.len());
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            concat!(
+                "h1,h2,token,spam,ham\n",
+                "0000000000000001,0000000000000000,free,4,2\n",
+                "0000000000000002,0000000000000000,\"say \"\"hi\"\", bob\",0,7\n",
+                "0000000000000003,0000000000000000,,1,0\n",
+            )
+        );
+    }
+
+    #[test]
+    fn export_spamassassin() {
+        let mut model = BayesModel {
+            spam_learns: 10,
+            ham_learns: 10,
+            ..Default::default()
+        };
+        for (h1, spam, ham) in [(1, 9, 0), (2, 0, 8), (3, 5, 5), (4, 1, 0), (5, 7, 1)] {
+            model
+                .weights
+                .insert(TokenHash { h1, h2: 0 }, Weights { spam, ham });
+        }
+
+        let mut rules = Vec::new();
+        let count = model
+            .export_spamassassin(
+                &mut rules,
+                &BayesClassifier::default(),
+                4.0,
+                |hash| match hash.h1 {
+                    1 => Some(Cow::from("free")),
+                    2 => Some(Cow::from("agenda")),
+                    3 => Some(Cow::from("the")),
+                    4 => Some(Cow::from("rare")),
+                    _ => None,
+                },
+            )
+            .unwrap();
+        let rules = String::from_utf8(rules).unwrap();
+
+        // Unnamed, rarely seen and neutral tokens are skipped
+        assert_eq!(count, 2, "{rules}");
+        let mut scores = Vec::new();
+        for rule in rules.split("\n\n").filter(|rule| !rule.trim().is_empty()) {
+            let lines = rule
+                .lines()
+                .map(|line| line.split_whitespace().collect::<Vec<_>>())
+                .collect::<Vec<_>>();
+            assert_eq!(lines.len(), 3, "{rule}");
+            assert_eq!(
+                lines.iter().map(|line| line[0]).collect::<Vec<_>>(),
+                ["body", "describe", "score"]
+            );
+            assert!(lines.iter().all(|line| line[1] == lines[0][1]));
+            assert!(lines[0][1].starts_with("BAYES_TOK_"));
+            let score = lines[2][2].parse::<f64>().unwrap();
+            assert!((-4.0..=4.0).contains(&score), "{score}");
+            scores.push((lines[0][2], score));
+        }
+        assert_eq!(scores[0].0, "/(?<!\\w)free(?!\\w)/i");
+        assert!(scores[0].1 > 2.0);
+        assert_eq!(scores[1].0, "/(?<!\\w)agenda(?!\\w)/i");
+        assert!(scores[1].1 < -2.0);
+    }
+}
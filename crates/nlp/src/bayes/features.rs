@@ -0,0 +1,426 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use mail_parser::{decoders::html::html_to_text, Message, PartType};
+use utils::suffixlist::PublicSuffix;
+
+use crate::tokenizers::osb::{Gram, OsbToken, OsbTokenizer};
+
+use super::{
+    corpus::message_text, pipeline::PipelineError, tokenize::BayesTokenizer, BayesClassifier,
+    BayesModel, TokenHash,
+};
+
+/// Feature index of the tokens of external extractors, outside of the OSB
+/// window positions and `CHAR_NGRAM_IDX`.
+pub const EXTERNAL_FEATURE_IDX: usize = 6;
+
+/// Source of custom features of a message, such as image hashes or bucketized
+/// embeddings, learned and classified alongside its words. Each feature is
+/// hashed as `<namespace>:<feature>`, so the namespace must be distinct for
+/// every extractor, and the same extractors must be used for training and
+/// classification for the features to carry any weight.
+pub trait FeatureExtractor {
+    fn namespace(&self) -> &str;
+    fn extract(&self, message: &Message<'_>) -> Vec<String>;
+}
+
+/// Signals messages with little or no text, such as image-only or
+/// attachment-only mail, which produce few text tokens but are suspicious
+/// for that very reason. Emits `content:no_text` when the text bodies have
+/// fewer than `min_text_chars` non-whitespace characters, and
+/// `content:mostly_binary` when text makes up less than `min_text_ratio` of
+/// the size of the text and binary parts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextContent {
+    pub min_text_chars: usize,
+    pub min_text_ratio: f64,
+}
+
+impl Default for TextContent {
+    fn default() -> Self {
+        TextContent {
+            min_text_chars: 20,
+            min_text_ratio: 0.01,
+        }
+    }
+}
+
+impl FeatureExtractor for TextContent {
+    fn namespace(&self) -> &str {
+        "content"
+    }
+
+    fn extract(&self, message: &Message<'_>) -> Vec<String> {
+        let (mut text, mut binary) = (0, 0);
+        for part in &message.parts {
+            match &part.body {
+                PartType::Text(body) => {
+                    text += body.chars().filter(|ch| !ch.is_whitespace()).count();
+                }
+                PartType::Html(body) => {
+                    text += html_to_text(body)
+                        .chars()
+                        .filter(|ch| !ch.is_whitespace())
+                        .count();
+                }
+                PartType::Binary(body) | PartType::InlineBinary(body) => binary += body.len(),
+                PartType::Message(_) | PartType::Multipart(_) => (),
+            }
+        }
+
+        let mut features = Vec::new();
+        if text < self.min_text_chars {
+            features.push("no_text".to_string());
+        }
+        if binary > 0 && (text as f64) < self.min_text_ratio * (text + binary) as f64 {
+            features.push("mostly_binary".to_string());
+        }
+        features
+    }
+}
+
+/// Delivery context known to the server but not part of the message, such
+/// as the folder it was delivered to or whether the sender is a contact. Each
+/// key is emitted as `ctx:<key>:<value>`, or as `ctx:<key>` when its value is
+/// empty, after lowercasing both and replacing whitespace with underscores.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeliveryContext {
+    features: Vec<String>,
+}
+
+impl DeliveryContext {
+    pub fn new<K, V>(context: impl IntoIterator<Item = (K, V)>) -> Self
+    where
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let mut features = context
+            .into_iter()
+            .filter_map(|(key, value)| {
+                let key = normalize_context(key.as_ref());
+                let value = normalize_context(value.as_ref());
+                match (key.is_empty(), value.is_empty()) {
+                    (true, _) => None,
+                    (false, true) => Some(key),
+                    (false, false) => Some(format!("{key}:{value}")),
+                }
+            })
+            .collect::<Vec<_>>();
+        features.sort_unstable();
+        features.dedup();
+        DeliveryContext { features }
+    }
+
+    /// Buckets a number of recipients into `one`, `few` (up to 5) or `many`,
+    /// for use as the value of a `recipients` key.
+    pub fn recipients_bucket(recipients: usize) -> &'static str {
+        match recipients {
+            0 | 1 => "one",
+            2..=5 => "few",
+            _ => "many",
+        }
+    }
+}
+
+fn normalize_context(text: &str) -> String {
+    text.split_whitespace()
+        .collect::<Vec<_>>()
+        .join("_")
+        .to_lowercase()
+}
+
+impl FeatureExtractor for DeliveryContext {
+    fn namespace(&self) -> &str {
+        "ctx"
+    }
+
+    fn extract(&self, _: &Message<'_>) -> Vec<String> {
+        self.features.clone()
+    }
+}
+
+impl BayesModel {
+    /// Returns the OSB tokens of the subject and text bodies of a message,
+    /// followed by the features of every extractor.
+    pub fn message_tokens(
+        &self,
+        message: &Message<'_>,
+        suffixes: &PublicSuffix,
+        extractors: &[&dyn FeatureExtractor],
+    ) -> Vec<OsbToken<TokenHash>> {
+        let text = message_text(message);
+        let mut tokens = OsbTokenizer::with_hasher(
+            BayesTokenizer::new(&text, suffixes),
+            self.window_size,
+            self.token_hasher(),
+        )
+        .collect::<Vec<_>>();
+
+        let hasher = self.token_hasher();
+        for extractor in extractors {
+            let namespace = extractor.namespace();
+            for feature in extractor.extract(message) {
+                let feature = format!("{namespace}:{feature}");
+                tokens.push(OsbToken {
+                    inner: hasher.hash_gram(Gram::Uni { t1: &feature }),
+                    idx: EXTERNAL_FEATURE_IDX,
+                });
+            }
+        }
+
+        tokens
+    }
+
+    pub fn train_message(
+        &mut self,
+        message: &Message<'_>,
+        is_spam: bool,
+        suffixes: &PublicSuffix,
+        extractors: &[&dyn FeatureExtractor],
+    ) -> Result<(), PipelineError> {
+        let tokens = self.message_tokens(message, suffixes, extractors);
+        if tokens.is_empty() {
+            return Err(PipelineError::EmptyInput);
+        }
+
+        self.train(tokens, is_spam);
+        Ok(())
+    }
+
+    pub fn classify_message(
+        &self,
+        classifier: &BayesClassifier,
+        message: &Message<'_>,
+        suffixes: &PublicSuffix,
+        extractors: &[&dyn FeatureExtractor],
+    ) -> Result<Option<f64>, PipelineError> {
+        let tokens = self.message_tokens(message, suffixes, extractors);
+        if tokens.is_empty() {
+            return Err(PipelineError::EmptyInput);
+        }
+
+        Ok(self.classify_token_hashes(classifier, tokens.into_iter()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mail_parser::{Message, MessageParser};
+    use utils::suffixlist::PublicSuffix;
+
+    use crate::bayes::{BayesClassifier, BayesModel};
+
+    use super::{DeliveryContext, FeatureExtractor, TextContent};
+
+    // Reads the perceptual hash of the attached image from a header
+    struct ImageHash;
+
+    impl FeatureExtractor for ImageHash {
+        fn namespace(&self) -> &str {
+            "img"
+        }
+
+        fn extract(&self, message: &Message<'_>) -> Vec<String> {
+            message
+                .header("X-Image-Hash")
+                .and_then(|value| value.as_text())
+                .map(|hash| vec![hash.trim().to_string()])
+                .unwrap_or_default()
+        }
+    }
+
+    fn message(image_hash: &str) -> String {
+        format!(
+            concat!(
+                "From: someone@example.com\r\n",
+                "Subject: Hello\r\n",
+                "X-Image-Hash: {}\r\n",
+                "\r\n",
+                "have a look at this picture\r\n",
+            ),
+            image_hash
+        )
+    }
+
+    #[test]
+    fn feature_extractor() {
+        let suffixes = PublicSuffix::default();
+        let parser = MessageParser::default();
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 1,
+            ..Default::default()
+        };
+        let mut model = BayesModel::default();
+        for _ in 0..5 {
+            for (hash, is_spam) in [("f0e1d2c3", true), ("0a1b2c3d", false)] {
+                let raw = message(hash);
+                model
+                    .train_message(
+                        &parser.parse(&raw).unwrap(),
+                        is_spam,
+                        &suffixes,
+                        &[&ImageHash],
+                    )
+                    .unwrap();
+            }
+        }
+
+        let raw = message("f0e1d2c3");
+        let spam = parser.parse(&raw).unwrap();
+        // The text alone is seen equally often in both classes
+        assert_eq!(
+            model
+                .classify_message(&classifier, &spam, &suffixes, &[])
+                .unwrap(),
+            None
+        );
+        let score = model
+            .classify_message(&classifier, &spam, &suffixes, &[&ImageHash])
+            .unwrap()
+            .unwrap();
+        assert!(score > 0.9, "{score}");
+
+        let raw = message("0a1b2c3d");
+        let ham = parser.parse(&raw).unwrap();
+        let score = model
+            .classify_message(&classifier, &ham, &suffixes, &[&ImageHash])
+            .unwrap()
+            .unwrap();
+        assert!(score < 0.1, "{score}");
+    }
+
+    fn image_only(name: &str) -> String {
+        format!(
+            concat!(
+                "From: promo@example.com\r\n",
+                "Content-Type: multipart/mixed; boundary=\"b\"\r\n",
+                "\r\n",
+                "--b\r\n",
+                "Content-Type: image/png; name=\"{}.png\"\r\n",
+                "Content-Transfer-Encoding: base64\r\n",
+                "\r\n",
+                "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mP8z8BQDwAEhQGA\r\n",
+                "--b--\r\n",
+            ),
+            name
+        )
+    }
+
+    #[test]
+    fn text_content() {
+        let suffixes = PublicSuffix::default();
+        let parser = MessageParser::default();
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 1,
+            min_token_hits: 1,
+            ..Default::default()
+        };
+        let content = TextContent::default();
+
+        let raw = image_only("offer");
+        let image = parser.parse(&raw).unwrap();
+        assert_eq!(content.extract(&image), ["no_text", "mostly_binary"]);
+        let raw = message("0a1b2c3d");
+        assert!(content.extract(&parser.parse(&raw).unwrap()).is_empty());
+        let short = TextContent {
+            min_text_chars: 100,
+            ..Default::default()
+        };
+        assert_eq!(short.extract(&parser.parse(&raw).unwrap()), ["no_text"]);
+
+        let mut model = BayesModel::default();
+        for i in 0..3 {
+            let raw = image_only(&format!("image{i}"));
+            model
+                .train_message(&parser.parse(&raw).unwrap(), true, &suffixes, &[&content])
+                .unwrap();
+            let raw = message("0a1b2c3d");
+            model
+                .train_message(&parser.parse(&raw).unwrap(), false, &suffixes, &[&content])
+                .unwrap();
+        }
+
+        // Without the signal there is nothing to classify
+        assert!(model
+            .classify_message(&classifier, &image, &suffixes, &[])
+            .is_err());
+        let score = model
+            .classify_message(&classifier, &image, &suffixes, &[&content])
+            .unwrap()
+            .unwrap();
+        assert!(score > 0.9, "{score}");
+    }
+
+    #[test]
+    fn delivery_context() {
+        let suffixes = PublicSuffix::default();
+        let parser = MessageParser::default();
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 1,
+            ..Default::default()
+        };
+        let bulk = DeliveryContext::new([
+            ("recipients", DeliveryContext::recipients_bucket(40)),
+            ("folder", "Inbox"),
+        ]);
+        let contact = DeliveryContext::new([
+            ("known_sender", ""),
+            ("recipients", DeliveryContext::recipients_bucket(1)),
+            ("folder", "Inbox"),
+        ]);
+        assert_eq!(
+            bulk.extract(&parser.parse(&message("")).unwrap()),
+            ["folder:inbox", "recipients:many"]
+        );
+        assert_eq!(
+            DeliveryContext::new([(" Known Sender ", ""), ("", "ignored")]).features,
+            ["known_sender"]
+        );
+
+        let mut model = BayesModel::default();
+        for _ in 0..5 {
+            for (context, is_spam) in [(&bulk, true), (&contact, false)] {
+                let raw = message("ffff");
+                model
+                    .train_message(&parser.parse(&raw).unwrap(), is_spam, &suffixes, &[context])
+                    .unwrap();
+            }
+        }
+
+        let raw = message("ffff");
+        let message = parser.parse(&raw).unwrap();
+        let classify = |context: &DeliveryContext| {
+            model
+                .classify_message(&classifier, &message, &suffixes, &[context])
+                .unwrap()
+        };
+        // The message alone is seen equally often in both classes
+        assert_eq!(classify(&DeliveryContext::default()), None);
+        assert!(classify(&bulk).unwrap() > 0.9);
+        assert!(classify(&contact).unwrap() < 0.1);
+    }
+}
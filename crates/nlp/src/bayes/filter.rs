@@ -0,0 +1,254 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{collections::HashSet, hash::BuildHasherDefault, sync::Arc};
+
+use arc_swap::ArcSwap;
+use nohash::NoHashHasher;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::{BayesClassifier, TokenHash};
+
+type TokenSet = HashSet<TokenHash, BuildHasherDefault<NoHashHasher<TokenHash>>>;
+
+/// Set of tokens that can be updated while it is being used for
+/// classification. Lookups read the current snapshot without locking, and
+/// updates replace it, taking effect for the tokens looked up afterwards.
+/// Cloning copies the current tokens into an independent set.
+pub struct TokenFilter {
+    tokens: ArcSwap<TokenSet>,
+}
+
+impl TokenFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn contains(&self, hash: &TokenHash) -> bool {
+        self.tokens.load().contains(hash)
+    }
+
+    /// Adds a token, returning whether it was not present.
+    pub fn insert(&self, hash: TokenHash) -> bool {
+        let mut inserted = false;
+        self.tokens.rcu(|tokens| {
+            let mut tokens = TokenSet::clone(tokens);
+            inserted = tokens.insert(hash);
+            tokens
+        });
+        inserted
+    }
+
+    /// Removes a token, returning whether it was present.
+    pub fn remove(&self, hash: &TokenHash) -> bool {
+        let mut removed = false;
+        self.tokens.rcu(|tokens| {
+            let mut tokens = TokenSet::clone(tokens);
+            removed = tokens.remove(hash);
+            tokens
+        });
+        removed
+    }
+
+    pub fn clear(&self) {
+        self.tokens.store(Arc::default());
+    }
+
+    pub fn len(&self) -> usize {
+        self.tokens.load().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tokens.load().is_empty()
+    }
+
+    /// Returns a snapshot of the tokens.
+    pub fn tokens(&self) -> Arc<TokenSet> {
+        self.tokens.load_full()
+    }
+}
+
+impl BayesClassifier {
+    pub fn add_allow_token(&self, hash: TokenHash) -> bool {
+        self.token_allow.insert(hash)
+    }
+
+    pub fn remove_allow_token(&self, hash: &TokenHash) -> bool {
+        self.token_allow.remove(hash)
+    }
+
+    pub fn add_deny_token(&self, hash: TokenHash) -> bool {
+        self.token_deny.insert(hash)
+    }
+
+    pub fn remove_deny_token(&self, hash: &TokenHash) -> bool {
+        self.token_deny.remove(hash)
+    }
+
+    pub fn add_floor_token(&self, hash: TokenHash) -> bool {
+        self.spam_floor.insert(hash)
+    }
+
+    pub fn remove_floor_token(&self, hash: &TokenHash) -> bool {
+        self.spam_floor.remove(hash)
+    }
+}
+
+impl Default for TokenFilter {
+    fn default() -> Self {
+        TokenFilter {
+            tokens: ArcSwap::from_pointee(TokenSet::default()),
+        }
+    }
+}
+
+impl Clone for TokenFilter {
+    fn clone(&self) -> Self {
+        TokenFilter {
+            tokens: ArcSwap::new(self.tokens.load_full()),
+        }
+    }
+}
+
+impl std::fmt::Debug for TokenFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.tokens.load().iter()).finish()
+    }
+}
+
+impl FromIterator<TokenHash> for TokenFilter {
+    fn from_iter<T: IntoIterator<Item = TokenHash>>(iter: T) -> Self {
+        TokenFilter {
+            tokens: ArcSwap::from_pointee(iter.into_iter().collect()),
+        }
+    }
+}
+
+impl Serialize for TokenFilter {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // Sorted so that serialized configurations are reproducible
+        let tokens = self.tokens.load();
+        let mut tokens = tokens.iter().collect::<Vec<_>>();
+        tokens.sort_unstable();
+        serializer.collect_seq(tokens)
+    }
+}
+
+impl<'de> Deserialize<'de> for TokenFilter {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        TokenSet::deserialize(deserializer).map(|tokens| TokenFilter {
+            tokens: ArcSwap::from_pointee(tokens),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, Barrier,
+        },
+        thread,
+    };
+
+    use crate::{
+        bayes::{BayesClassifier, TokenHash, Weights},
+        tokenizers::osb::OsbToken,
+    };
+
+    #[test]
+    fn hot_deny_token() {
+        let classifier = Arc::new(BayesClassifier {
+            min_learns: 0,
+            ..Default::default()
+        });
+        let spammy = TokenHash { h1: 99, h2: 0 };
+        let tokens = (0..12)
+            .map(|h1| (TokenHash { h1, h2: 0 }, Weights { spam: 6, ham: 4 }))
+            .chain([(spammy, Weights { spam: 50, ham: 0 })])
+            .map(|(hash, inner)| (hash, OsbToken { inner, idx: 0 }))
+            .collect::<Vec<_>>();
+        let baseline = classifier
+            .classify_hashed(tokens[..12].iter().cloned(), 100, 100)
+            .unwrap()
+            .score;
+        let with_spammy = classifier
+            .classify_hashed(tokens.iter().cloned(), 100, 100)
+            .unwrap()
+            .score;
+        assert_ne!(baseline, with_spammy);
+
+        let denied = Arc::new(AtomicBool::new(false));
+        let barrier = Arc::new(Barrier::new(5));
+        let readers = (0..4)
+            .map(|_| {
+                let (classifier, tokens, denied, barrier) = (
+                    classifier.clone(),
+                    tokens.clone(),
+                    denied.clone(),
+                    barrier.clone(),
+                );
+                thread::spawn(move || {
+                    barrier.wait();
+                    for _ in 0..2000 {
+                        // Read the flag first, the deny token was added before it was set
+                        let is_denied = denied.load(Ordering::Acquire);
+                        let score = classifier
+                            .classify_hashed(tokens.iter().cloned(), 100, 100)
+                            .unwrap()
+                            .score;
+                        assert!(score == baseline || score == with_spammy);
+                        if is_denied {
+                            assert_eq!(score, baseline);
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        barrier.wait();
+        assert!(classifier.add_deny_token(spammy));
+        assert!(!classifier.add_deny_token(spammy));
+        denied.store(true, Ordering::Release);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        assert_eq!(
+            classifier
+                .classify_hashed(tokens.iter().cloned(), 100, 100)
+                .unwrap()
+                .score,
+            baseline
+        );
+        assert!(classifier.remove_deny_token(&spammy));
+        assert_eq!(
+            classifier
+                .classify_hashed(tokens.iter().cloned(), 100, 100)
+                .unwrap()
+                .score,
+            with_spammy
+        );
+    }
+}
@@ -0,0 +1,138 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use xxhash_rust::xxh3::Xxh3;
+
+use super::{hasher::HashAlgorithm, BayesModel};
+
+impl BayesModel {
+    /// Returns a stable identifier of the exact state of the model, suitable
+    /// for keying caches or routing between model versions. It covers the
+    /// hasher, OSB window, class token limit, learn counters, including those
+    /// of every namespace, and every token entry in hash order, so it survives snapshots and changes with any
+    /// learn. Live metrics and `version` are not included.
+    pub fn model_fingerprint(&self) -> u64 {
+        let mut hasher = Xxh3::new();
+        match &self.hasher {
+            HashAlgorithm::Xxh3Farmhash => hasher.update(&[0]),
+            HashAlgorithm::Xxh3_128 => hasher.update(&[1]),
+            HashAlgorithm::Custom { name, seed } => {
+                hasher.update(&[2]);
+                hasher.update(&(name.len() as u64).to_le_bytes());
+                hasher.update(name.as_bytes());
+                hasher.update(&seed.to_le_bytes());
+            }
+        }
+        hasher.update(&(self.window_size as u64).to_le_bytes());
+        hasher.update(
+            &self
+                .class_token_limit
+                .map_or(u64::MAX, |limit| limit as u64)
+                .to_le_bytes(),
+        );
+        hasher.update(&self.spam_learns.to_le_bytes());
+        hasher.update(&self.ham_learns.to_le_bytes());
+        hasher.update(&self.learned_messages.to_le_bytes());
+
+        let mut weights = self.weights.iter().collect::<Vec<_>>();
+        weights.sort_unstable_by_key(|(hash, _)| **hash);
+        hasher.update(&(weights.len() as u64).to_le_bytes());
+        for (hash, weights) in weights {
+            hasher.update(&hash.h1.to_le_bytes());
+            hasher.update(&hash.h2.to_le_bytes());
+            hasher.update(&weights.spam.to_le_bytes());
+            hasher.update(&weights.ham.to_le_bytes());
+        }
+
+        let mut doc_freq = self.doc_freq.iter().collect::<Vec<_>>();
+        doc_freq.sort_unstable_by_key(|(hash, _)| **hash);
+        hasher.update(&(doc_freq.len() as u64).to_le_bytes());
+        for (hash, count) in doc_freq {
+            hasher.update(&hash.h1.to_le_bytes());
+            hasher.update(&hash.h2.to_le_bytes());
+            hasher.update(&count.to_le_bytes());
+        }
+
+        hasher.update(&(self.namespaces.len() as u64).to_le_bytes());
+        for (namespace, learns) in &self.namespaces {
+            hasher.update(&namespace.0.to_le_bytes());
+            hasher.update(&learns.spam.to_le_bytes());
+            hasher.update(&learns.ham.to_le_bytes());
+        }
+
+        hasher.digest()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use utils::suffixlist::PublicSuffix;
+
+    use crate::bayes::{namespace::Namespace, BayesModel, Weights};
+
+    #[test]
+    fn model_fingerprint() {
+        let suffixes = PublicSuffix::default();
+        let mut model = BayesModel::default();
+        model
+            .train_text("claim your free prize now", true, &suffixes)
+            .unwrap();
+        model
+            .train_text("lunch with the team on friday", false, &suffixes)
+            .unwrap();
+        let fingerprint = model.model_fingerprint();
+        assert_eq!(model.model_fingerprint(), fingerprint);
+
+        // Stable across reloads
+        let restored =
+            BayesModel::from_snapshot(&model.to_snapshot().unwrap(), model.hasher.clone()).unwrap();
+        assert_eq!(restored.model_fingerprint(), fingerprint);
+        assert_eq!(
+            BayesModel::default().model_fingerprint(),
+            BayesModel::default().model_fingerprint()
+        );
+
+        // Changes with every learn
+        model
+            .train_text("claim your free prize now", true, &suffixes)
+            .unwrap();
+        let learned = model.model_fingerprint();
+        assert_ne!(learned, fingerprint);
+        model
+            .train_text("lunch with the team on friday", false, &suffixes)
+            .unwrap();
+        assert_ne!(model.model_fingerprint(), learned);
+
+        // And with the configuration
+        let fingerprint = model.model_fingerprint();
+        model.window_size = 3;
+        assert_ne!(model.model_fingerprint(), fingerprint);
+
+        // And with the learn counters of namespaces
+        let fingerprint = model.model_fingerprint();
+        model
+            .namespaces
+            .insert(Namespace(1), Weights { spam: 1, ham: 0 });
+        assert_ne!(model.model_fingerprint(), fingerprint);
+    }
+}
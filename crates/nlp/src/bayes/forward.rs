@@ -0,0 +1,155 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use utils::suffixlist::PublicSuffix;
+
+use super::{pipeline::PipelineError, BayesModel};
+
+/// Which part of a forwarded message is learned, e.g. when users forward
+/// spam to an abuse address with a note of their own.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ForwardedTraining {
+    // Learn the forwarding text along with the forwarded message
+    #[default]
+    Whole,
+    // Learn only the forwarded message
+    Inner,
+}
+
+// Lines introducing a forwarded message in common mail clients
+const BOUNDARIES: &[&str] = &[
+    "---------- forwarded message ---------",
+    "-------- forwarded message --------",
+    "-------- original message --------",
+    "-----original message-----",
+    "begin forwarded message:",
+];
+
+// Headers summarized by mail clients after the boundary
+const HEADERS: &[&str] = &["from", "sent", "date", "to", "cc", "reply-to", "subject"];
+
+/// Returns the content of the first message forwarded within a text, made
+/// of its subject and body, or None if the text is not a forward.
+pub fn forwarded_content(text: &str) -> Option<String> {
+    let mut lines = text.lines();
+    lines.find(|line| {
+        let line = line.trim().to_lowercase();
+        BOUNDARIES.iter().any(|boundary| line == *boundary)
+    })?;
+
+    let mut content = String::new();
+    let mut lines = lines.skip_while(|line| line.trim().is_empty()).peekable();
+    while let Some((name, value)) = lines.peek().and_then(|line| line.split_once(':')) {
+        let name = name.trim().to_lowercase();
+        if !HEADERS.contains(&name.as_str()) {
+            break;
+        }
+        if name == "subject" {
+            content.push_str(value.trim());
+            content.push('\n');
+        }
+        lines.next();
+    }
+    for line in lines.skip_while(|line| line.trim().is_empty()) {
+        content.push_str(line);
+        content.push('\n');
+    }
+
+    Some(content)
+}
+
+impl BayesModel {
+    /// Same as `train_text`, learning only the forwarded message when the
+    /// text is a forward and `forwarded` is `ForwardedTraining::Inner`.
+    /// Texts that are not forwards are learned whole.
+    pub fn train_text_forwarded(
+        &mut self,
+        text: &str,
+        is_spam: bool,
+        suffixes: &PublicSuffix,
+        forwarded: ForwardedTraining,
+    ) -> Result<(), PipelineError> {
+        match forwarded {
+            ForwardedTraining::Inner => match forwarded_content(text) {
+                Some(content) => self.train_text(&content, is_spam, suffixes),
+                None => self.train_text(text, is_spam, suffixes),
+            },
+            ForwardedTraining::Whole => self.train_text(text, is_spam, suffixes),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use utils::suffixlist::PublicSuffix;
+
+    use crate::bayes::BayesModel;
+
+    use super::{forwarded_content, ForwardedTraining};
+
+    const FORWARD: &str = concat!(
+        "FYI, got this spam again\n",
+        "\n",
+        "---------- Forwarded message ---------\n",
+        "From: Lottery Office <winner@example.com>\n",
+        "Date: Mon, 1 Jan 2024 at 10:00\n",
+        "Subject: You have won\n",
+        "To: <jane@example.org>\n",
+        "\n",
+        "Claim your prize of one million dollars today\n",
+    );
+
+    #[test]
+    fn forwarded_spam() {
+        let suffixes = PublicSuffix::default();
+        let inner = "You have won\nClaim your prize of one million dollars today\n";
+        assert_eq!(forwarded_content(FORWARD).as_deref(), Some(inner));
+        assert_eq!(forwarded_content("Claim your prize"), None);
+
+        let train = |text, forwarded| {
+            let mut model = BayesModel::default();
+            model
+                .train_text_forwarded(text, true, &suffixes, forwarded)
+                .unwrap();
+            model
+        };
+
+        // Only the inner content is learned
+        let forwarded = train(FORWARD, ForwardedTraining::Inner);
+        assert_eq!(
+            forwarded.weights,
+            train(inner, ForwardedTraining::Whole).weights
+        );
+        assert_eq!(forwarded.spam_learns, 1);
+
+        // Unless the whole message is requested
+        let whole = train(FORWARD, ForwardedTraining::Whole);
+        assert!(whole.weights.len() > forwarded.weights.len());
+
+        // Messages that are not forwards are learned whole
+        assert_eq!(
+            train("Claim your prize", ForwardedTraining::Inner).weights,
+            train("Claim your prize", ForwardedTraining::Whole).weights
+        );
+    }
+}
@@ -0,0 +1,166 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use serde::{Deserialize, Serialize};
+
+/// Detection of "good word" attacks, where spammers pad a message with
+/// common ham words to drag its score down. A message is suspicious when it
+/// has at least `min_spam_tokens` strongly spam tokens while strongly ham
+/// tokens make up at least `min_ham_ratio` of its counted tokens. Tokens are
+/// strong when their spam probability is at least `strength` away from 0.5.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GoodWordDefense {
+    pub strength: f64,
+    pub min_ham_ratio: f64,
+    pub min_spam_tokens: usize,
+    #[serde(default)]
+    pub action: GoodWordAction,
+}
+
+/// Handling of suspicious messages. Both actions set `goodword_attack` on
+/// the result, while `Discount` also pulls the probabilities of the ham
+/// tokens towards 0.5 by the given factor, dropping those left weaker than
+/// `min_prob_strength`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum GoodWordAction {
+    Flag,
+    Discount(f64),
+}
+
+impl Default for GoodWordAction {
+    fn default() -> Self {
+        GoodWordAction::Discount(0.2)
+    }
+}
+
+impl Default for GoodWordDefense {
+    fn default() -> Self {
+        GoodWordDefense {
+            strength: 0.4,
+            min_ham_ratio: 0.5,
+            min_spam_tokens: 2,
+            action: GoodWordAction::default(),
+        }
+    }
+}
+
+impl GoodWordDefense {
+    /// Applies the defense to the spam and ham probabilities and positions of
+    /// the tokens of a message, returning whether it looks like an attack.
+    pub(crate) fn apply(
+        &self,
+        contributions: &mut Vec<(f64, f64, usize)>,
+        min_prob_strength: f64,
+    ) -> bool {
+        let strong_spam = contributions
+            .iter()
+            .filter(|(spam_prob, _, _)| *spam_prob >= 0.5 + self.strength)
+            .count();
+        let strong_ham = contributions
+            .iter()
+            .filter(|(spam_prob, _, _)| *spam_prob <= 0.5 - self.strength)
+            .count();
+        if strong_spam < self.min_spam_tokens
+            || (strong_ham as f64) < self.min_ham_ratio * contributions.len() as f64
+        {
+            return false;
+        }
+
+        if let GoodWordAction::Discount(factor) = self.action {
+            contributions.retain_mut(|(spam_prob, ham_prob, _)| {
+                if *spam_prob >= 0.5 {
+                    return true;
+                }
+                *spam_prob = 0.5 + (*spam_prob - 0.5) * factor;
+                *ham_prob = 0.5 + (*ham_prob - 0.5) * factor;
+                let strength = (*spam_prob - 0.5).abs();
+                strength > 0.0 && strength >= min_prob_strength
+            });
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        bayes::{BayesClassifier, Weights},
+        tokenizers::osb::OsbToken,
+    };
+
+    use super::{GoodWordAction, GoodWordDefense};
+
+    fn tokens(weights: &[(u32, u32)]) -> Vec<OsbToken<Weights>> {
+        weights
+            .iter()
+            .map(|&(spam, ham)| OsbToken {
+                inner: Weights { spam, ham },
+                idx: 0,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn goodword_attack() {
+        // A few strongly spam tokens padded with many common ham words
+        let mut padded = vec![(50, 0); 3];
+        padded.extend([(0, 50); 20]);
+        let padded = tokens(&padded);
+        let mut ham = vec![(0, 50); 20];
+        ham.push((50, 0));
+        let ham = tokens(&ham);
+
+        let mut classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 1,
+            ..Default::default()
+        };
+        let classify = |classifier: &BayesClassifier, tokens: &[OsbToken<Weights>]| {
+            classifier
+                .classify_detailed(tokens.iter().cloned(), 100, 100)
+                .unwrap()
+        };
+
+        let plain = classify(&classifier, &padded);
+        assert!(plain.score < 0.5, "{}", plain.score);
+        assert!(!plain.goodword_attack);
+
+        classifier.goodword_defense = Some(GoodWordDefense {
+            action: GoodWordAction::Flag,
+            ..Default::default()
+        });
+        let flagged = classify(&classifier, &padded);
+        assert!(flagged.goodword_attack);
+        assert_eq!(flagged.score, plain.score);
+
+        classifier.goodword_defense = Some(GoodWordDefense::default());
+        let defended = classify(&classifier, &padded);
+        assert!(defended.goodword_attack);
+        assert!(classifier.is_spam(defended.score), "{}", defended.score);
+
+        // A single spam token is not enough to suspect an attack
+        let result = classify(&classifier, &ham);
+        assert!(!result.goodword_attack);
+        assert!(result.score < 0.5);
+    }
+}
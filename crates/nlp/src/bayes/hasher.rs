@@ -0,0 +1,270 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{borrow::Cow, fmt::Debug, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+
+use crate::tokenizers::osb::{Gram, OsbTokenizer};
+
+use super::{quantize::QuantizedModel, BayesModel, TokenHash};
+
+/// Identifier of the hasher a model was trained with, recorded in snapshots
+/// so that models are only loaded along with the same hasher. Hashers
+/// defined outside of this crate, for instance to match the tokens of an
+/// external system, identify themselves by name and seed.
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq, Eq, Hash)]
+pub enum HashAlgorithm {
+    #[default]
+    Xxh3Farmhash,
+    Xxh3_128,
+    Custom {
+        name: String,
+        seed: u64,
+    },
+}
+
+pub trait TokenHasher: Debug + Send + Sync {
+    fn algorithm(&self) -> HashAlgorithm;
+
+    fn hash(&self, bytes: &[u8]) -> TokenHash;
+
+    fn hash_gram(&self, gram: Gram<'_>) -> TokenHash {
+        match gram {
+            Gram::Uni { t1 } => self.hash(t1.as_bytes()),
+            Gram::Bi { t1, t2 } => {
+                let mut buf = Vec::with_capacity(t1.len() + t2.len() + 1);
+                buf.extend_from_slice(t1.as_bytes());
+                buf.push(b' ');
+                buf.extend_from_slice(t2.as_bytes());
+                self.hash(&buf)
+            }
+        }
+    }
+}
+
+// 64-bit xxh3 and farmhash, the original token hashing scheme
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DefaultTokenHasher;
+
+// Both halves of a 128-bit xxh3 hash
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Xxh3TokenHasher;
+
+impl TokenHasher for DefaultTokenHasher {
+    fn algorithm(&self) -> HashAlgorithm {
+        HashAlgorithm::Xxh3Farmhash
+    }
+
+    fn hash(&self, bytes: &[u8]) -> TokenHash {
+        TokenHash {
+            h1: xxhash_rust::xxh3::xxh3_64(bytes),
+            h2: farmhash::hash64(bytes),
+        }
+    }
+}
+
+impl TokenHasher for Xxh3TokenHasher {
+    fn algorithm(&self) -> HashAlgorithm {
+        HashAlgorithm::Xxh3_128
+    }
+
+    fn hash(&self, bytes: &[u8]) -> TokenHash {
+        let hash = xxhash_rust::xxh3::xxh3_128(bytes);
+        TokenHash {
+            h1: (hash >> 64) as u64,
+            h2: hash as u64,
+        }
+    }
+}
+
+impl HashAlgorithm {
+    /// Returns the hasher of a built-in algorithm, or None for custom ones,
+    /// which have to be supplied by the caller.
+    pub fn builtin(&self) -> Option<Arc<dyn TokenHasher>> {
+        match self {
+            HashAlgorithm::Xxh3Farmhash => Some(Arc::new(DefaultTokenHasher)),
+            HashAlgorithm::Xxh3_128 => Some(Arc::new(Xxh3TokenHasher)),
+            HashAlgorithm::Custom { .. } => None,
+        }
+    }
+}
+
+// Models deserialized without their custom hasher fall back to the default
+// hasher, see `from_snapshot_with_hasher`
+pub(crate) fn resolve_hasher(
+    algorithm: &HashAlgorithm,
+    hasher: &Option<Arc<dyn TokenHasher>>,
+) -> Arc<dyn TokenHasher> {
+    hasher
+        .clone()
+        .or_else(|| algorithm.builtin())
+        .unwrap_or_else(|| Arc::new(DefaultTokenHasher))
+}
+
+impl BayesModel {
+    /// Returns the hasher that messages must be tokenized with for this model.
+    pub fn token_hasher(&self) -> Arc<dyn TokenHasher> {
+        resolve_hasher(&self.hasher, &self.token_hasher)
+    }
+
+    /// Sets the hasher of an empty model, recording its identifier.
+    pub fn set_token_hasher(&mut self, hasher: Arc<dyn TokenHasher>) {
+        self.hasher = hasher.algorithm();
+        self.token_hasher = Some(hasher);
+    }
+}
+
+impl QuantizedModel {
+    pub fn token_hasher(&self) -> Arc<dyn TokenHasher> {
+        resolve_hasher(&self.hasher, &self.token_hasher)
+    }
+}
+
+impl<'x, I> OsbTokenizer<'x, I, TokenHash>
+where
+    I: Iterator<Item = Cow<'x, str>>,
+{
+    pub fn with_hasher(iter: I, window_size: usize, hasher: Arc<dyn TokenHasher>) -> Self {
+        Self::with_converter(iter, window_size, move |gram| hasher.hash_gram(gram))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, sync::Arc};
+
+    use utils::suffixlist::PublicSuffix;
+
+    use crate::{
+        bayes::{
+            snapshot::SnapshotError, tokenize::BayesTokenizer, BayesClassifier, BayesModel,
+            TokenHash, OSB_WINDOW_SIZE,
+        },
+        tokenizers::osb::OsbTokenizer,
+    };
+
+    use super::{HashAlgorithm, TokenHasher};
+
+    // Seeded hasher defined outside of the built-in algorithms
+    #[derive(Debug)]
+    struct SeededHasher(u64);
+
+    impl TokenHasher for SeededHasher {
+        fn algorithm(&self) -> HashAlgorithm {
+            HashAlgorithm::Custom {
+                name: "xxh3-seeded".to_string(),
+                seed: self.0,
+            }
+        }
+
+        fn hash(&self, bytes: &[u8]) -> TokenHash {
+            TokenHash {
+                h1: xxhash_rust::xxh3::xxh3_64_with_seed(bytes, self.0),
+                h2: xxhash_rust::xxh3::xxh3_64_with_seed(bytes, !self.0),
+            }
+        }
+    }
+
+    fn token_set(text: &str, algorithm: HashAlgorithm) -> HashSet<TokenHash> {
+        OsbTokenizer::with_hasher(
+            BayesTokenizer::new(text, &PublicSuffix::default()),
+            OSB_WINDOW_SIZE,
+            algorithm.builtin().unwrap(),
+        )
+        .map(|t| t.inner)
+        .collect()
+    }
+
+    #[test]
+    fn token_hashers() {
+        let text = "limited time offer, buy cheap watches and get a free gift today";
+
+        let default = token_set(text, HashAlgorithm::Xxh3Farmhash);
+        let xxh3 = token_set(text, HashAlgorithm::Xxh3_128);
+
+        assert_eq!(default, token_set(text, HashAlgorithm::Xxh3Farmhash));
+        assert_eq!(xxh3, token_set(text, HashAlgorithm::Xxh3_128));
+        assert_eq!(default.len(), xxh3.len());
+        assert!(default.is_disjoint(&xxh3));
+
+        // The default hasher must match the From<Gram> conversion
+        assert_eq!(
+            default,
+            OsbTokenizer::<_, TokenHash>::new(
+                BayesTokenizer::new(text, &PublicSuffix::default()),
+                OSB_WINDOW_SIZE,
+            )
+            .map(|t| t.inner)
+            .collect::<HashSet<_>>()
+        );
+    }
+
+    #[test]
+    fn custom_hasher() {
+        let suffixes = PublicSuffix::default();
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 1,
+            ..Default::default()
+        };
+        let mut model = BayesModel::default();
+        model.set_token_hasher(Arc::new(SeededHasher(7)));
+        for _ in 0..3 {
+            model
+                .train_text("claim your free prize now", true, &suffixes)
+                .unwrap();
+            model
+                .train_text("lunch with the team on friday", false, &suffixes)
+                .unwrap();
+        }
+        let hashes = |hasher: &dyn TokenHasher| hasher.hash(b"prize");
+        assert!(model.weights.contains_key(&hashes(&SeededHasher(7))));
+        assert_ne!(hashes(&SeededHasher(7)), hashes(&SeededHasher(8)));
+
+        let snapshot = model.to_snapshot().unwrap();
+        let restored =
+            BayesModel::from_snapshot_with_hasher(&snapshot, Arc::new(SeededHasher(7))).unwrap();
+        let text = "claim your prize";
+        assert_eq!(
+            restored
+                .classify_text(&classifier, text, &suffixes)
+                .unwrap(),
+            model.classify_text(&classifier, text, &suffixes).unwrap()
+        );
+        assert!(model
+            .classify_text(&classifier, text, &suffixes)
+            .unwrap()
+            .is_some_and(|score| score > 0.9));
+
+        // A different seed or a missing hasher is refused
+        assert!(matches!(
+            BayesModel::from_snapshot_with_hasher(&snapshot, Arc::new(SeededHasher(8))),
+            Err(SnapshotError::HasherMismatch { .. })
+        ));
+        assert!(matches!(
+            BayesModel::from_snapshot(&snapshot, SeededHasher(7).algorithm()),
+            Err(SnapshotError::UnresolvedHasher(_))
+        ));
+    }
+}
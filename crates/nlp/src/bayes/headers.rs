@@ -0,0 +1,185 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use mail_parser::{Message, PartType};
+use utils::suffixlist::PublicSuffix;
+
+use super::{
+    subject::{decode_header, SubjectTokenizer},
+    tokenize::BayesTokenizer,
+};
+
+// Tokenized headers and the prefix of their tokens
+const HEADERS: &[(&str, &str)] = &[
+    ("from", "from"),
+    ("to", "to"),
+    ("cc", "cc"),
+    ("reply-to", "reply-to"),
+    ("subject", "subj"),
+];
+
+/// Tokenizes the envelope headers of a message, prefixing each token with the
+/// header it came from (`from:`, `to:`, `cc:`, `reply-to:` or `subj:`).
+/// Messages forwarded as attachments repeat the headers of the original, so
+/// the headers of nested `message/rfc822` parts are skipped unless enabled.
+#[derive(Debug, Clone, Copy)]
+pub struct HeaderTokenizer<'x> {
+    suffixes: &'x PublicSuffix,
+    nested: bool,
+}
+
+impl<'x> HeaderTokenizer<'x> {
+    pub fn new(suffixes: &'x PublicSuffix) -> Self {
+        HeaderTokenizer {
+            suffixes,
+            nested: false,
+        }
+    }
+
+    /// Also tokenizes the headers of nested messages.
+    pub fn nested(mut self, nested: bool) -> Self {
+        self.nested = nested;
+        self
+    }
+
+    pub fn tokenize(&self, message: &Message<'_>) -> Vec<String> {
+        let mut tokens = Vec::new();
+        self.tokenize_message(message, &mut tokens);
+        tokens
+    }
+
+    fn tokenize_message(&self, message: &Message<'_>, tokens: &mut Vec<String>) {
+        for header in message.headers() {
+            let name = header.name.as_str();
+            let Some((_, prefix)) = HEADERS
+                .iter()
+                .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            else {
+                continue;
+            };
+            let Some(value) = message
+                .raw_message
+                .get(header.offset_start..header.offset_end)
+                .and_then(|value| std::str::from_utf8(value).ok())
+            else {
+                continue;
+            };
+
+            let value = decode_header(value.trim());
+            if *prefix == "subj" {
+                tokens.extend(
+                    SubjectTokenizer::new(&value, self.suffixes).map(|token| token.into_owned()),
+                );
+            } else {
+                tokens.extend(
+                    BayesTokenizer::new(&value, self.suffixes)
+                        .map(|token| format!("{prefix}:{token}")),
+                );
+            }
+        }
+
+        if self.nested {
+            for part in &message.parts {
+                if let PartType::Message(nested) = &part.body {
+                    self.tokenize_message(nested, tokens);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mail_parser::MessageParser;
+    use utils::suffixlist::PublicSuffix;
+
+    use super::HeaderTokenizer;
+
+    const FORWARDED: &str = concat!(
+        "From: alice@example.org\r\n",
+        "To: abuse@example.org\r\n",
+        "Subject: Fwd: reported\r\n",
+        "Content-Type: multipart/mixed; boundary=\"outer\"\r\n",
+        "\r\n",
+        "--outer\r\n",
+        "Content-Type: text/plain\r\n",
+        "\r\n",
+        "please have a look\r\n",
+        "--outer\r\n",
+        "Content-Type: message/rfc822\r\n",
+        "\r\n",
+        "From: winner@lottery.test\r\n",
+        "Reply-To: claims@lottery.test\r\n",
+        "Subject: =?utf-8?q?Claim_your_prize?=\r\n",
+        "Content-Type: multipart/mixed; boundary=\"inner\"\r\n",
+        "\r\n",
+        "--inner\r\n",
+        "Content-Type: text/plain\r\n",
+        "\r\n",
+        "you won\r\n",
+        "--inner\r\n",
+        "Content-Type: message/rfc822\r\n",
+        "\r\n",
+        "From: origin@relay.test\r\n",
+        "Subject: original\r\n",
+        "\r\n",
+        "body\r\n",
+        "--inner--\r\n",
+        "--outer--\r\n",
+    );
+
+    #[test]
+    fn nested_headers() {
+        let suffixes = PublicSuffix::default();
+        let message = MessageParser::default().parse(FORWARDED).unwrap();
+
+        let tokens = HeaderTokenizer::new(&suffixes).tokenize(&message);
+        assert!(tokens.iter().any(|t| t == "subj:report"), "{tokens:?}");
+        assert!(tokens.iter().any(|t| t.starts_with("from:")), "{tokens:?}");
+        assert!(tokens.iter().any(|t| t.starts_with("to:")), "{tokens:?}");
+        for token in &tokens {
+            assert!(
+                !token.contains("lotteri")
+                    && !token.contains("relay")
+                    && !token.starts_with("reply-to:")
+                    && token != "subj:prize"
+                    && token != "subj:origin",
+                "{tokens:?}"
+            );
+        }
+
+        // Nested headers are tokenized on request, at every level
+        let nested = HeaderTokenizer::new(&suffixes)
+            .nested(true)
+            .tokenize(&message);
+        assert!(nested.starts_with(&tokens), "{nested:?}");
+        for expected in ["subj:prize", "subj:origin"] {
+            assert!(nested.iter().any(|t| t == expected), "{nested:?}");
+        }
+        assert!(
+            nested.iter().any(|t| t.starts_with("reply-to:")),
+            "{nested:?}"
+        );
+        assert!(nested.iter().any(|t| t.contains("relay")), "{nested:?}");
+    }
+}
@@ -0,0 +1,180 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{collections::HashMap, hash::BuildHasherDefault};
+
+use nohash::NoHashHasher;
+use utils::suffixlist::PublicSuffix;
+
+use crate::tokenizers::osb::OsbTokenizer;
+
+use super::{
+    cache::BayesVerdictCache, pipeline::PipelineError, tokenize::BayesTokenizer, BayesModel,
+};
+
+/// A message that was moved into a folder with a training meaning, such as
+/// "Junk" (spam) or "Inbox" (ham).
+#[derive(Debug, Clone, Copy)]
+pub struct FolderEvent<'x> {
+    pub message_id: &'x str,
+    pub text: &'x str,
+    pub is_spam: bool,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct IngestReport {
+    pub learned: usize,
+    pub relearned: usize,
+    pub duplicates: usize,
+    pub empty: usize,
+}
+
+/// Trains a model incrementally from a stream of folder moves. Each message is
+/// learned at most once per class: moving it again to a folder with the same
+/// label is ignored, while moving it to the opposite label relearns it.
+#[derive(Debug, Default)]
+pub struct FolderLearner {
+    learned: HashMap<u64, bool, BuildHasherDefault<NoHashHasher<u64>>>,
+}
+
+impl FolderLearner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn ingest<'x>(
+        &mut self,
+        model: &mut BayesModel,
+        events: impl IntoIterator<Item = FolderEvent<'x>>,
+        suffixes: &PublicSuffix,
+    ) -> IngestReport {
+        let mut report = IngestReport::default();
+
+        for event in events {
+            let id = BayesVerdictCache::fingerprint(event.message_id);
+            match self.learned.get(&id).copied() {
+                Some(is_spam) if is_spam == event.is_spam => {
+                    report.duplicates += 1;
+                }
+                Some(is_spam) => {
+                    model.relearn(
+                        OsbTokenizer::with_hasher(
+                            BayesTokenizer::new(event.text, suffixes),
+                            model.window_size,
+                            model.token_hasher(),
+                        ),
+                        is_spam,
+                        event.is_spam,
+                    );
+                    self.learned.insert(id, event.is_spam);
+                    report.relearned += 1;
+                }
+                None => match model.train_text(event.text, event.is_spam, suffixes) {
+                    Ok(()) => {
+                        self.learned.insert(id, event.is_spam);
+                        report.learned += 1;
+                    }
+                    Err(PipelineError::EmptyInput) => {
+                        report.empty += 1;
+                    }
+                },
+            }
+        }
+
+        report
+    }
+
+    pub fn len(&self) -> usize {
+        self.learned.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.learned.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use utils::suffixlist::PublicSuffix;
+
+    use crate::bayes::BayesModel;
+
+    use super::{FolderEvent, FolderLearner, IngestReport};
+
+    #[test]
+    fn folder_moves() {
+        let suffixes = PublicSuffix::default();
+        let event = |message_id, is_spam| FolderEvent {
+            message_id,
+            text: if message_id == "<2@host>" {
+                "cheap pills online"
+            } else {
+                "meeting notes attached"
+            },
+            is_spam,
+        };
+        let mut model = BayesModel::default();
+        let mut learner = FolderLearner::new();
+
+        let report = learner.ingest(
+            &mut model,
+            [
+                // Moved to Junk
+                event("<1@host>", true),
+                event("<2@host>", true),
+                // Moved to Junk again from another folder
+                event("<2@host>", true),
+                // Rescued from Junk back to the Inbox
+                event("<1@host>", false),
+                FolderEvent {
+                    message_id: "<3@host>",
+                    text: "   ",
+                    is_spam: false,
+                },
+            ],
+            &suffixes,
+        );
+
+        assert_eq!(
+            report,
+            IngestReport {
+                learned: 2,
+                relearned: 1,
+                duplicates: 1,
+                empty: 1,
+            }
+        );
+        assert_eq!((model.spam_learns, model.ham_learns), (1, 1));
+        assert_eq!(learner.len(), 2);
+
+        // Only the rescued message contributes ham weights
+        let mut ham_model = BayesModel::default();
+        ham_model
+            .train_text("meeting notes attached", false, &suffixes)
+            .unwrap();
+        for (hash, weights) in &ham_model.weights {
+            assert_eq!(model.weights[hash].ham, weights.ham);
+            assert_eq!(model.weights[hash].spam, 0);
+        }
+    }
+}
@@ -0,0 +1,264 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{borrow::Cow, sync::Arc};
+
+use ahash::AHashMap;
+
+use crate::language::Language;
+
+use super::tokenize::{BayesTokenizer, RawToken};
+
+/// Interns the tokens produced by `BayesTokenizer`, so that repeated words
+/// within and across messages share a single allocation and are only
+/// lowercased and stemmed once.
+///
+/// Once the interner holds `max_strings` strings it is cleared before the
+/// next string is interned or message tokenized, so that the ids returned
+/// until then no longer resolve to their strings.
+#[derive(Debug, Clone)]
+pub struct StringInterner {
+    strings: Strings,
+    // Stemmed words, by language and `collapse_repeats` setting
+    words: AHashMap<(Language, usize), AHashMap<Box<str>, Option<u32>>>,
+    max_strings: usize,
+}
+
+pub const DEFAULT_MAX_STRINGS: usize = 1 << 20;
+
+#[derive(Debug, Default, Clone)]
+struct Strings {
+    ids: AHashMap<Arc<str>, u32>,
+    values: Vec<Arc<str>>,
+}
+
+impl StringInterner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the number of strings after which the interner is cleared,
+    /// `DEFAULT_MAX_STRINGS` by default.
+    pub fn with_max_strings(mut self, max_strings: usize) -> Self {
+        self.max_strings = max_strings;
+        self
+    }
+
+    pub fn intern(&mut self, token: Cow<'_, str>) -> u32 {
+        self.evict();
+        self.strings.intern(token)
+    }
+
+    /// Tokenizes a message into interned ids. Use `tokens` to feed them to
+    /// `OsbTokenizer`, which produces the same hashes as the plain tokenizer.
+    pub fn tokenize(&mut self, tokenizer: &mut BayesTokenizer<'_, '_>) -> Vec<u32> {
+        self.evict();
+        let mut ids = Vec::new();
+        let words = self
+            .words
+            .entry((tokenizer.language(), tokenizer.max_repeats()))
+            .or_default();
+
+        loop {
+            while let Some(token) = tokenizer.pop_pending() {
+                ids.push(self.strings.intern(token));
+            }
+            let Some(token) = tokenizer.next_raw() else {
+                break;
+            };
+
+            match token {
+                RawToken::Word(word) => {
+                    if let Some(id) = words.get(word.as_ref()) {
+                        ids.extend(*id);
+                        continue;
+                    }
+
+                    let id = tokenizer
+                        .stem(&word)
+                        .map(|token| self.strings.intern(token));
+                    ids.extend(id);
+                    if !tokenizer.has_pending() {
+                        // Words split into several tokens are not memoized
+                        words.insert(word.as_ref().into(), id);
+                    }
+                }
+                RawToken::Token(token) => {
+                    ids.push(self.strings.intern(token));
+                }
+            }
+        }
+
+        ids
+    }
+
+    pub fn tokens<'x>(&'x self, ids: &'x [u32]) -> impl Iterator<Item = Cow<'x, str>> + 'x {
+        ids.iter().map(|id| Cow::Borrowed(self.resolve(*id)))
+    }
+
+    pub fn resolve(&self, id: u32) -> &str {
+        &self.strings.values[id as usize]
+    }
+
+    pub fn len(&self) -> usize {
+        self.strings.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.values.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.strings.ids.clear();
+        self.strings.values.clear();
+        self.words.clear();
+    }
+
+    fn evict(&mut self) {
+        if self.strings.values.len() >= self.max_strings {
+            self.clear();
+        }
+    }
+}
+
+impl Default for StringInterner {
+    fn default() -> Self {
+        Self {
+            strings: Strings::default(),
+            words: AHashMap::new(),
+            max_strings: DEFAULT_MAX_STRINGS,
+        }
+    }
+}
+
+impl Strings {
+    fn intern(&mut self, token: Cow<'_, str>) -> u32 {
+        if let Some(id) = self.ids.get(token.as_ref()) {
+            *id
+        } else {
+            let id = self.values.len() as u32;
+            let value: Arc<str> = token.into();
+            self.ids.insert(value.clone(), id);
+            self.values.push(value);
+            id
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use utils::suffixlist::PublicSuffix;
+
+    use crate::{
+        bayes::{tokenize::BayesTokenizer, TokenHash, OSB_WINDOW_SIZE},
+        language::Language,
+        tokenizers::osb::{OsbToken, OsbTokenizer},
+    };
+
+    use super::StringInterner;
+
+    #[test]
+    fn interned_hashes() {
+        let suffixes = PublicSuffix::default();
+        let mut interner = StringInterner::new();
+
+        for (text, language) in [
+            (
+                "Running runners run! Visit https://example.org/run for 10 runs.",
+                Language::English,
+            ),
+            (
+                "The runners were running, running, running in the rain.",
+                Language::English,
+            ),
+            ("我们在北京的清华大学学习", Language::Mandarin),
+            ("The runners were running again.", Language::English),
+        ] {
+            let expected = OsbTokenizer::<_, TokenHash>::new(
+                BayesTokenizer::with_language(text, &suffixes, language),
+                OSB_WINDOW_SIZE,
+            )
+            .collect::<Vec<_>>();
+            let ids = interner.tokenize(&mut BayesTokenizer::with_language(
+                text, &suffixes, language,
+            ));
+            let interned: Vec<OsbToken<TokenHash>> =
+                OsbTokenizer::new(interner.tokens(&ids), OSB_WINDOW_SIZE).collect();
+
+            assert!(!expected.is_empty());
+            assert_eq!(expected, interned, "{text}");
+        }
+
+        // Repeated words were interned once across messages
+        let run = (0..interner.len() as u32)
+            .filter(|id| interner.resolve(*id) == "run")
+            .count();
+        assert_eq!(run, 1);
+    }
+
+    #[test]
+    fn interner_options() {
+        let suffixes = PublicSuffix::default();
+        let mut interner = StringInterner::new();
+
+        for max_repeats in [0, 1, 2, 0] {
+            let text = "freeeee offer, freeeee!";
+            let tokenizer = || {
+                BayesTokenizer::with_language(text, &suffixes, Language::English)
+                    .collapse_repeats(max_repeats)
+            };
+            let expected =
+                OsbTokenizer::<_, TokenHash>::new(tokenizer(), OSB_WINDOW_SIZE).collect::<Vec<_>>();
+            let ids = interner.tokenize(&mut tokenizer());
+            let interned: Vec<OsbToken<TokenHash>> =
+                OsbTokenizer::new(interner.tokens(&ids), OSB_WINDOW_SIZE).collect();
+            assert_eq!(expected, interned, "{max_repeats}");
+        }
+    }
+
+    #[test]
+    fn interner_max_strings() {
+        let suffixes = PublicSuffix::default();
+        let mut interner = StringInterner::new().with_max_strings(8);
+
+        for round in 0..20 {
+            let text = format!("word{round} another{round} running runners run");
+            let ids = interner.tokenize(&mut BayesTokenizer::with_language(
+                &text,
+                &suffixes,
+                Language::English,
+            ));
+            assert!(interner.len() < 8 + 5, "{}", interner.len());
+
+            // Ids of the current message still resolve after evictions
+            let expected = OsbTokenizer::<_, TokenHash>::new(
+                BayesTokenizer::with_language(&text, &suffixes, Language::English),
+                OSB_WINDOW_SIZE,
+            )
+            .collect::<Vec<_>>();
+            let interned: Vec<OsbToken<TokenHash>> =
+                OsbTokenizer::new(interner.tokens(&ids), OSB_WINDOW_SIZE).collect();
+            assert_eq!(expected, interned, "{text}");
+        }
+    }
+}
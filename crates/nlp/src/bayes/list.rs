@@ -0,0 +1,128 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::borrow::Cow;
+
+/// Emits tokens from the mailing list headers of a message, which set
+/// legitimate bulk mail apart from spam: `list_id:<id>` for `List-Id`,
+/// `has_unsub:<method>` for each `mailto` or `http` method of
+/// `List-Unsubscribe` (and `has_unsub:one_click` when `List-Unsubscribe-Post`
+/// is present) and `precedence:<value>` for `Precedence`. Other headers are
+/// ignored.
+pub struct ListHeaderTokenizer<'x> {
+    tokens: std::vec::IntoIter<Cow<'x, str>>,
+}
+
+impl<'x> ListHeaderTokenizer<'x> {
+    /// Creates a tokenizer from `(name, value)` header pairs.
+    pub fn new(headers: &[(&str, &str)]) -> Self {
+        let mut tokens = Vec::new();
+
+        for (name, value) in headers {
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("List-Id") {
+                // The identifier is enclosed in angle brackets after an optional phrase
+                let id = value
+                    .rsplit_once('<')
+                    .and_then(|(_, id)| id.split_once('>'))
+                    .map_or(value, |(id, _)| id)
+                    .trim();
+                if !id.is_empty() {
+                    tokens.push(Cow::from(format!("list_id:{}", id.to_lowercase())));
+                }
+            } else if name.eq_ignore_ascii_case("List-Unsubscribe") {
+                let mut methods = value
+                    .split(',')
+                    .filter_map(|uri| {
+                        let uri = uri.trim().trim_start_matches('<').to_lowercase();
+                        if uri.starts_with("mailto:") {
+                            Some("mailto")
+                        } else if uri.starts_with("https:") || uri.starts_with("http:") {
+                            Some("http")
+                        } else {
+                            None
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                methods.sort_unstable();
+                methods.dedup();
+                tokens.extend(
+                    methods
+                        .into_iter()
+                        .map(|method| Cow::from(format!("has_unsub:{method}"))),
+                );
+            } else if name.eq_ignore_ascii_case("List-Unsubscribe-Post") {
+                tokens.push(Cow::from("has_unsub:one_click"));
+            } else if name.eq_ignore_ascii_case("Precedence") && !value.is_empty() {
+                tokens.push(Cow::from(format!("precedence:{}", value.to_lowercase())));
+            }
+        }
+
+        Self {
+            tokens: tokens.into_iter(),
+        }
+    }
+}
+
+impl<'x> Iterator for ListHeaderTokenizer<'x> {
+    type Item = Cow<'x, str>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.tokens.next()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ListHeaderTokenizer;
+
+    #[test]
+    fn newsletter_headers() {
+        let headers = [
+            ("From", "Example News <news@example.com>"),
+            ("List-Id", "\"Example Weekly\" <Weekly.News.Example.com>"),
+            (
+                "List-Unsubscribe",
+                "<mailto:unsub@example.com?subject=unsubscribe>, <https://example.com/u/1234>",
+            ),
+            ("List-Unsubscribe-Post", "List-Unsubscribe=One-Click"),
+            ("Precedence", " Bulk "),
+        ];
+
+        assert_eq!(
+            ListHeaderTokenizer::new(&headers).collect::<Vec<_>>(),
+            [
+                "list_id:weekly.news.example.com",
+                "has_unsub:http",
+                "has_unsub:mailto",
+                "has_unsub:one_click",
+                "precedence:bulk",
+            ]
+        );
+        assert_eq!(
+            ListHeaderTokenizer::new(&[("list-id", "plain.example.org")]).collect::<Vec<_>>(),
+            ["list_id:plain.example.org"]
+        );
+        assert_eq!(ListHeaderTokenizer::new(&[("Subject", "Hi")]).count(), 0);
+    }
+}
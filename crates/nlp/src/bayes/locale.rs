@@ -0,0 +1,172 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use ahash::{AHashMap, AHashSet};
+use utils::suffixlist::PublicSuffix;
+
+use super::{classify::apply_prior, pipeline::PipelineError, BayesClassifier, BayesModel};
+
+/// Keywords that are spam indicators only in some locales. Each keyword or
+/// phrase of the locale found in a message adds the locale adjustment, as
+/// log-odds, to the score of the learned model.
+#[derive(Debug, Default, Clone)]
+pub struct LocaleKeywords {
+    locales: AHashMap<String, LocaleBoost>,
+}
+
+#[derive(Debug, Default, Clone)]
+struct LocaleBoost {
+    keywords: AHashSet<String>,
+    adjustment: f64,
+}
+
+impl LocaleKeywords {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds keywords to a locale such as `pt-BR`, replacing its adjustment.
+    pub fn insert<'x>(
+        &mut self,
+        locale: &str,
+        keywords: impl IntoIterator<Item = &'x str>,
+        adjustment: f64,
+    ) {
+        let boost = self.locales.entry(normalize_locale(locale)).or_default();
+        boost.keywords.extend(
+            keywords
+                .into_iter()
+                .map(normalize)
+                .filter(|k| !k.is_empty()),
+        );
+        boost.adjustment = adjustment;
+    }
+
+    /// Returns the prior adjustment of a text for a locale, falling back to
+    /// the primary language of the locale (e.g. `pt` for `pt-BR`).
+    pub fn prior(&self, locale: &str, text: &str) -> f64 {
+        let locale = normalize_locale(locale);
+        let Some(boost) = self.locales.get(&locale).or_else(|| {
+            self.locales
+                .get(locale.split_once('-').map_or(locale.as_str(), |(l, _)| l))
+        }) else {
+            return 0.0;
+        };
+
+        // Pad with spaces to match whole words only
+        let text = format!(" {} ", normalize(text));
+        boost
+            .keywords
+            .iter()
+            .filter(|keyword| text.contains(&format!(" {keyword} ")))
+            .count() as f64
+            * boost.adjustment
+    }
+}
+
+fn normalize_locale(locale: &str) -> String {
+    locale.trim().to_lowercase().replace('_', "-")
+}
+
+// Lowercases a text and separates its words by single spaces
+fn normalize(text: &str) -> String {
+    text.split(|ch: char| !ch.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl BayesModel {
+    /// Same as `classify_text`, adding the prior of the locale keywords found
+    /// in the text when a locale is supplied.
+    pub fn classify_text_locale(
+        &self,
+        classifier: &BayesClassifier,
+        text: &str,
+        suffixes: &PublicSuffix,
+        keywords: &LocaleKeywords,
+        locale: Option<&str>,
+    ) -> Result<Option<f64>, PipelineError> {
+        let score = self.classify_text(classifier, text, suffixes)?;
+        let prior = locale.map_or(0.0, |locale| keywords.prior(locale, text));
+        Ok(if prior != 0.0 {
+            score.map(|score| apply_prior(score, prior))
+        } else {
+            score
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use utils::suffixlist::PublicSuffix;
+
+    use crate::bayes::{BayesClassifier, BayesModel};
+
+    use super::LocaleKeywords;
+
+    #[test]
+    fn locale_keywords() {
+        let suffixes = PublicSuffix::default();
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 0,
+            min_token_hits: 1,
+            ..Default::default()
+        };
+        let mut model = BayesModel::default();
+        model
+            .train_text("claim your prize now", true, &suffixes)
+            .unwrap();
+        model
+            .train_text("lunch with the team on friday", false, &suffixes)
+            .unwrap();
+
+        let mut keywords = LocaleKeywords::new();
+        keywords.insert("pt-BR", ["Pix", "ganhe agora"], 1.5);
+
+        let text = "claim your prize now ganhe agora";
+        let plain = model
+            .classify_text(&classifier, text, &suffixes)
+            .unwrap()
+            .unwrap();
+        let classify = |locale| {
+            model
+                .classify_text_locale(&classifier, text, &suffixes, &keywords, locale)
+                .unwrap()
+                .unwrap()
+        };
+
+        assert_eq!(classify(None), plain);
+        assert_eq!(classify(Some("en-US")), plain);
+        assert!(classify(Some("pt-BR")) > plain);
+        assert_eq!(keywords.prior("PT_br", text), 1.5);
+        assert_eq!(
+            keywords.prior("pt-BR", "pagamento via pix, ganhe agora!"),
+            3.0
+        );
+        // Only whole words match
+        assert_eq!(keywords.prior("pt-BR", "pixel"), 0.0);
+    }
+}
@@ -0,0 +1,199 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::BayesModel;
+
+/// Running counters of the verdicts issued and of the corrections received
+/// for them, used to estimate the live accuracy of the classifier.
+#[derive(Debug, Default)]
+pub struct LiveMetrics {
+    spam_verdicts: AtomicU64,
+    ham_verdicts: AtomicU64,
+    false_positives: AtomicU64,
+    false_negatives: AtomicU64,
+    evictions: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LiveMetricsReport {
+    pub verdicts: u64,
+    pub corrections: u64,
+    pub accuracy: f64,
+    // Ham incorrectly classified as spam, over all actual ham
+    pub false_positive_rate: f64,
+    // Spam incorrectly classified as ham, over all actual spam
+    pub false_negative_rate: f64,
+    // Tokens pruned to keep the model within its memory budget
+    pub evictions: u64,
+}
+
+impl LiveMetrics {
+    pub fn record_verdict(&self, is_spam: bool) {
+        if is_spam {
+            &self.spam_verdicts
+        } else {
+            &self.ham_verdicts
+        }
+        .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records that a verdict was wrong, `was_spam` being the class it was
+    /// classified as.
+    pub fn record_correction(&self, was_spam: bool) {
+        if was_spam {
+            &self.false_positives
+        } else {
+            &self.false_negatives
+        }
+        .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_evictions(&self, count: u64) {
+        self.evictions.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn report(&self) -> LiveMetricsReport {
+        let spam_verdicts = self.spam_verdicts.load(Ordering::Relaxed);
+        let ham_verdicts = self.ham_verdicts.load(Ordering::Relaxed);
+        let false_positives = self.false_positives.load(Ordering::Relaxed);
+        let false_negatives = self.false_negatives.load(Ordering::Relaxed);
+
+        let verdicts = spam_verdicts + ham_verdicts;
+        let corrections = false_positives + false_negatives;
+        let actual_ham = (ham_verdicts + false_positives).saturating_sub(false_negatives);
+        let actual_spam = (spam_verdicts + false_negatives).saturating_sub(false_positives);
+        let rate = |count: u64, total: u64| {
+            if total > 0 {
+                count as f64 / total as f64
+            } else {
+                0.0
+            }
+        };
+
+        LiveMetricsReport {
+            verdicts,
+            corrections,
+            accuracy: if verdicts > 0 {
+                1.0 - rate(corrections, verdicts)
+            } else {
+                1.0
+            },
+            false_positive_rate: rate(false_positives, actual_ham),
+            false_negative_rate: rate(false_negatives, actual_spam),
+            evictions: self.evictions.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Counters of the token lookups of an `AsyncClassifier`.
+#[derive(Debug, Default)]
+pub struct LookupMetrics {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    skipped: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LookupMetricsReport {
+    // Tokens served from the cache
+    pub hits: u64,
+    // Tokens fetched from the store
+    pub misses: u64,
+    // Tokens known to have never been learned, without any lookup
+    pub skipped: u64,
+}
+
+impl LookupMetrics {
+    pub fn record(&self, hits: u64, misses: u64, skipped: u64) {
+        self.hits.fetch_add(hits, Ordering::Relaxed);
+        self.misses.fetch_add(misses, Ordering::Relaxed);
+        self.skipped.fetch_add(skipped, Ordering::Relaxed);
+    }
+
+    pub fn report(&self) -> LookupMetricsReport {
+        LookupMetricsReport {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            skipped: self.skipped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl BayesModel {
+    /// Records the verdict given to a message, so that later corrections done
+    /// with `relearn` can be reflected in `live_metrics`.
+    pub fn record_verdict(&self, is_spam: bool) {
+        self.metrics.record_verdict(is_spam);
+    }
+
+    pub fn live_metrics(&self) -> LiveMetricsReport {
+        self.metrics.report()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        bayes::{BayesModel, TokenHash},
+        tokenizers::osb::OsbToken,
+    };
+
+    #[test]
+    fn live_metrics() {
+        let mut model = BayesModel::default();
+        let tokens = |h1| {
+            [OsbToken {
+                inner: TokenHash { h1, h2: 0 },
+                idx: 0,
+            }]
+        };
+        assert_eq!(model.live_metrics().accuracy, 1.0);
+
+        // 6 messages classified as spam, 4 as ham
+        for h1 in 0..10 {
+            model.train(tokens(h1), h1 < 6);
+            model.record_verdict(h1 < 6);
+        }
+        // One spam verdict was ham, two ham verdicts were spam
+        model.relearn(tokens(0), true, false);
+        model.relearn(tokens(6), false, true);
+        model.relearn(tokens(7), false, true);
+        // Not a correction
+        model.relearn(tokens(8), false, false);
+
+        let metrics = model.live_metrics();
+        assert_eq!((metrics.verdicts, metrics.corrections), (10, 3));
+        assert!((metrics.accuracy - 0.7).abs() < 1e-9, "{metrics:?}");
+        // Actual ham: 4 - 2 + 1 = 3, actual spam: 6 - 1 + 2 = 7
+        assert!(
+            (metrics.false_positive_rate - 1.0 / 3.0).abs() < 1e-9,
+            "{metrics:?}"
+        );
+        assert!(
+            (metrics.false_negative_rate - 2.0 / 7.0).abs() < 1e-9,
+            "{metrics:?}"
+        );
+    }
+}
@@ -0,0 +1,382 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    convert::Infallible,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use memmap2::Mmap;
+
+use crate::tokenizers::osb::OsbToken;
+
+use super::{store::TokenStore, BayesModel, TokenHash, Weights};
+
+const MAGIC: &[u8; 4] = b"BTS1";
+const HEADER_LEN: usize = 24;
+const ENTRY_LEN: usize = 24;
+
+/// Token store backed by a memory-mapped file of token weights sorted by hash,
+/// looked up with a binary search. Messages learned after opening the file are
+/// kept in memory until `compact` merges them into a new file.
+///
+/// Durability: `write` and `compact` write the whole file to a temporary path,
+/// sync it to disk and then rename it over the previous one, so a crash leaves
+/// either the old or the new file in place, never a partial one. Pending learns
+/// that were not compacted are lost on a crash. Document frequencies and the
+/// learn counters of namespaces are not stored.
+///
+/// File layout (little endian): `BTS1`, ham learns (u32), spam learns (u32),
+/// reserved (u32), token count (u64), then the tokens as `h1` (u64), `h2` (u64),
+/// spam (u32) and ham (u32).
+pub struct MmapTokenStore {
+    path: PathBuf,
+    map: Mmap,
+    ham_learns: u32,
+    spam_learns: u32,
+    pending: BayesModel,
+}
+
+impl MmapTokenStore {
+    /// Writes a model to `path`, replacing any existing file.
+    pub fn write(path: impl AsRef<Path>, model: &BayesModel) -> io::Result<()> {
+        let mut tokens = model
+            .weights
+            .iter()
+            .map(|(hash, weights)| (*hash, *weights))
+            .collect::<Vec<_>>();
+        tokens.sort_unstable_by_key(|(hash, _)| *hash);
+        write_file(
+            path.as_ref(),
+            model.ham_learns,
+            model.spam_learns,
+            tokens.into_iter(),
+        )
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = File::open(&path)?;
+        // The file is only ever replaced by renaming, never modified in place
+        let map = unsafe { Mmap::map(&file)? };
+
+        if map.len() < HEADER_LEN || &map[0..4] != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid token store header",
+            ));
+        }
+        let count = read_u64(&map, 16) as usize;
+        if count
+            .checked_mul(ENTRY_LEN)
+            .is_none_or(|len| map.len() - HEADER_LEN != len)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Truncated token store",
+            ));
+        }
+
+        Ok(MmapTokenStore {
+            ham_learns: read_u32(&map, 4),
+            spam_learns: read_u32(&map, 8),
+            path,
+            map,
+            pending: BayesModel::default(),
+        })
+    }
+
+    /// Learns a message into the in-memory pending batch.
+    pub fn learn<T>(&mut self, tokens: T, is_spam: bool)
+    where
+        T: IntoIterator<Item = OsbToken<TokenHash>>,
+    {
+        self.pending.train(tokens, is_spam);
+    }
+
+    /// Number of learned messages not yet compacted into the file.
+    pub fn pending_learns(&self) -> u32 {
+        self.pending
+            .ham_learns
+            .saturating_add(self.pending.spam_learns)
+    }
+
+    /// Merges the pending learns into a new file and maps it.
+    pub fn compact(&mut self) -> io::Result<()> {
+        if self.pending_learns() == 0 {
+            return Ok(());
+        }
+
+        let mut pending = self
+            .pending
+            .weights
+            .iter()
+            .map(|(hash, weights)| (*hash, *weights))
+            .collect::<Vec<_>>();
+        pending.sort_unstable_by_key(|(hash, _)| *hash);
+
+        let mut stored = (0..self.len()).map(|idx| self.entry(idx)).peekable();
+        let mut pending = pending.into_iter().peekable();
+        let merged = std::iter::from_fn(|| match (stored.peek(), pending.peek()) {
+            (Some((a, _)), Some((b, _))) if a == b => {
+                let ((hash, a), (_, b)) = (stored.next()?, pending.next()?);
+                Some((
+                    hash,
+                    Weights {
+                        spam: a.spam.saturating_add(b.spam),
+                        ham: a.ham.saturating_add(b.ham),
+                    },
+                ))
+            }
+            (Some((a, _)), Some((b, _))) if b < a => pending.next(),
+            (Some(_), _) => stored.next(),
+            (None, _) => pending.next(),
+        });
+
+        let (ham_learns, spam_learns) = self.learns_with_pending();
+        write_file(&self.path, ham_learns, spam_learns, merged)?;
+        // The file holds the pending learns now, which must not be merged
+        // again by a later compaction even if it cannot be mapped
+        self.pending = BayesModel::default();
+        *self = Self::open(&self.path)?;
+        Ok(())
+    }
+
+    /// Returns the weights of a token, including pending learns.
+    pub fn get(&self, hash: &TokenHash) -> Weights {
+        let mut weights = self.get_stored(hash).unwrap_or_default();
+        if let Some(pending) = self.pending.weights.get(hash) {
+            weights.spam = weights.spam.saturating_add(pending.spam);
+            weights.ham = weights.ham.saturating_add(pending.ham);
+        }
+        weights
+    }
+
+    fn get_stored(&self, hash: &TokenHash) -> Option<Weights> {
+        let (mut lo, mut hi) = (0, self.len());
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            let (key, weights) = self.entry(mid);
+            match key.cmp(hash) {
+                std::cmp::Ordering::Equal => return Some(weights),
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+            }
+        }
+        None
+    }
+
+    fn entry(&self, idx: usize) -> (TokenHash, Weights) {
+        let offset = HEADER_LEN + idx * ENTRY_LEN;
+        (
+            TokenHash {
+                h1: read_u64(&self.map, offset),
+                h2: read_u64(&self.map, offset + 8),
+            },
+            Weights {
+                spam: read_u32(&self.map, offset + 16),
+                ham: read_u32(&self.map, offset + 20),
+            },
+        )
+    }
+
+    fn learns_with_pending(&self) -> (u32, u32) {
+        (
+            self.ham_learns.saturating_add(self.pending.ham_learns),
+            self.spam_learns.saturating_add(self.pending.spam_learns),
+        )
+    }
+
+    /// Number of tokens in the file.
+    pub fn len(&self) -> usize {
+        (self.map.len() - HEADER_LEN) / ENTRY_LEN
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl TokenStore for MmapTokenStore {
+    type Error = Infallible;
+
+    async fn get_weights(&self, hash: TokenHash) -> Result<Weights, Self::Error> {
+        Ok(self.get(&hash))
+    }
+
+    async fn learns(&self) -> Result<(u32, u32), Self::Error> {
+        Ok(self.learns_with_pending())
+    }
+}
+
+fn write_file(
+    path: &Path,
+    ham_learns: u32,
+    spam_learns: u32,
+    tokens: impl Iterator<Item = (TokenHash, Weights)>,
+) -> io::Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let mut file = BufWriter::new(File::create(&tmp_path)?);
+    file.write_all(MAGIC)?;
+    file.write_all(&ham_learns.to_le_bytes())?;
+    file.write_all(&spam_learns.to_le_bytes())?;
+    file.write_all(&0u32.to_le_bytes())?;
+    // Placeholder for the token count, which is only known at the end
+    file.write_all(&0u64.to_le_bytes())?;
+    let mut count = 0u64;
+    for (hash, weights) in tokens {
+        file.write_all(&hash.h1.to_le_bytes())?;
+        file.write_all(&hash.h2.to_le_bytes())?;
+        file.write_all(&weights.spam.to_le_bytes())?;
+        file.write_all(&weights.ham.to_le_bytes())?;
+        count += 1;
+    }
+
+    let mut file = file.into_inner().map_err(|err| err.into_error())?;
+    io::Seek::seek(&mut file, io::SeekFrom::Start(16))?;
+    file.write_all(&count.to_le_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod test {
+    use utils::suffixlist::PublicSuffix;
+
+    use crate::{
+        bayes::{
+            tokenize::BayesTokenizer, BayesClassifier, BayesModel, TokenHash, Weights,
+            OSB_WINDOW_SIZE,
+        },
+        tokenizers::osb::{OsbToken, OsbTokenizer},
+    };
+
+    use super::MmapTokenStore;
+
+    fn tokens(text: &str) -> Vec<OsbToken<TokenHash>> {
+        OsbTokenizer::new(
+            BayesTokenizer::new(text, &PublicSuffix::default()),
+            OSB_WINDOW_SIZE,
+        )
+        .collect()
+    }
+
+    #[tokio::test]
+    async fn mmap_store() {
+        let path = std::env::temp_dir().join(format!("bayes-mmap-{}.bin", std::process::id()));
+        let spam = "buy cheap pills online now limited offer";
+        let ham = "please find the meeting notes attached below";
+
+        let mut model = BayesModel::default();
+        model.train(tokens(spam), true);
+        model.train(tokens(ham), false);
+        MmapTokenStore::write(&path, &model).unwrap();
+
+        let mut store = MmapTokenStore::open(&path).unwrap();
+        assert_eq!(store.len(), model.weights.len());
+        for (hash, weights) in &model.weights {
+            assert_eq!(store.get(hash), *weights);
+        }
+        assert_eq!(store.get(&TokenHash { h1: 1, h2: 2 }), Default::default());
+
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 0,
+            min_token_hits: 0,
+            ..Default::default()
+        };
+        assert_eq!(
+            classifier
+                .classify_async(&store, tokens(spam))
+                .await
+                .unwrap(),
+            classifier
+                .classify_async(&model, tokens(spam))
+                .await
+                .unwrap(),
+        );
+
+        // Pending learns are visible before and after compaction
+        model.train(tokens(spam), true);
+        store.learn(tokens(spam), true);
+        assert_eq!(store.pending_learns(), 1);
+        store.compact().unwrap();
+        assert_eq!(store.pending_learns(), 0);
+
+        let store = MmapTokenStore::open(&path).unwrap();
+        assert_eq!(store.len(), model.weights.len());
+        for (hash, weights) in &model.weights {
+            assert_eq!(store.get(hash), *weights);
+        }
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn mmap_store_saturates() {
+        let path = std::env::temp_dir().join(format!("bayes-mmap-sat-{}.bin", std::process::id()));
+        let hash = TokenHash { h1: 1, h2: 2 };
+        let mut model = BayesModel {
+            spam_learns: u32::MAX,
+            ..Default::default()
+        };
+        model.weights.insert(
+            hash,
+            Weights {
+                spam: u32::MAX - 1,
+                ham: 0,
+            },
+        );
+        MmapTokenStore::write(&path, &model).unwrap();
+
+        let mut store = MmapTokenStore::open(&path).unwrap();
+        for _ in 0..2 {
+            store.learn(
+                [OsbToken {
+                    inner: hash,
+                    idx: 0,
+                }],
+                true,
+            );
+        }
+        assert_eq!(store.get(&hash).spam, u32::MAX);
+        store.compact().unwrap();
+        assert_eq!(store.get(&hash).spam, u32::MAX);
+        assert_eq!(store.learns_with_pending(), (0, u32::MAX));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -21,23 +21,222 @@
  * for more details.
 */
 
-use std::{collections::HashMap, hash::BuildHasherDefault};
+#[cfg(feature = "std")]
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::BuildHasherDefault,
+};
 
+#[cfg(feature = "std")]
 use nohash::NoHashHasher;
 use serde::{Deserialize, Serialize};
 
+#[cfg(feature = "std")]
 use crate::tokenizers::osb::Gram;
 
+#[cfg(feature = "std")]
+use self::{
+    filter::TokenFilter,
+    hasher::{DefaultTokenHasher, HashAlgorithm, TokenHasher},
+    metrics::LiveMetrics,
+    namespace::Namespace,
+};
+
+#[cfg(feature = "std")]
+pub mod agreement;
+#[cfg(feature = "std")]
+pub mod analysis;
+#[cfg(feature = "std")]
+pub mod auth;
+#[cfg(feature = "std")]
+pub mod blend;
+#[cfg(feature = "std")]
+pub mod bloom;
+#[cfg(feature = "std")]
 pub mod cache;
 pub mod classify;
+#[cfg(feature = "std")]
+pub mod config;
+#[cfg(feature = "std")]
+pub mod corpus;
+#[cfg(feature = "std")]
+pub mod counters;
+#[cfg(feature = "std")]
+pub mod decay;
+#[cfg(feature = "std")]
+pub mod dedup;
+#[cfg(feature = "std")]
+pub mod diagnose;
+#[cfg(feature = "std")]
+pub mod ensemble;
+#[cfg(feature = "std")]
+pub mod explain;
+#[cfg(feature = "std")]
+pub mod export;
+#[cfg(feature = "std")]
+pub mod features;
+#[cfg(feature = "std")]
+pub mod filter;
+#[cfg(feature = "std")]
+pub mod fingerprint;
+#[cfg(feature = "std")]
+pub mod forward;
+#[cfg(feature = "std")]
+pub mod goodword;
+#[cfg(feature = "std")]
+pub mod hasher;
+#[cfg(feature = "std")]
+pub mod headers;
+#[cfg(feature = "std")]
+pub mod ingest;
+#[cfg(feature = "std")]
+pub mod interner;
+#[cfg(feature = "std")]
+pub mod list;
+#[cfg(feature = "std")]
+pub mod locale;
+#[cfg(feature = "std")]
+pub mod metrics;
+#[cfg(feature = "mmap")]
+pub mod mmap;
+#[cfg(feature = "std")]
+pub mod namespace;
+#[cfg(feature = "std")]
+pub mod ngram;
+#[cfg(feature = "std")]
+pub mod novelty;
+#[cfg(feature = "std")]
+pub mod ocr;
+#[cfg(feature = "std")]
+pub mod parts;
+#[cfg(feature = "std")]
+pub mod percentile;
+#[cfg(feature = "std")]
+pub mod phone;
+#[cfg(feature = "std")]
+pub mod pipeline;
+#[cfg(feature = "std")]
+pub mod quantize;
+#[cfg(feature = "async")]
+pub mod queue;
+#[cfg(feature = "std")]
+pub mod received;
+#[cfg(feature = "std")]
+pub mod record;
+#[cfg(feature = "std")]
+pub mod sample;
+#[cfg(feature = "std")]
+pub mod scorer;
+#[cfg(feature = "std")]
+pub mod shadow;
+#[cfg(feature = "std")]
+pub mod shared;
+#[cfg(feature = "std")]
+pub mod snapshot;
+#[cfg(feature = "store")]
+pub mod store;
+#[cfg(feature = "std")]
+pub mod stream;
+#[cfg(feature = "std")]
+pub mod structure;
+#[cfg(feature = "std")]
+pub mod subject;
+#[cfg(all(test, feature = "std"))]
+pub(crate) mod synthetic;
+#[cfg(feature = "std")]
+pub mod temporal;
+#[cfg(feature = "std")]
 pub mod tokenize;
+#[cfg(feature = "std")]
 pub mod train;
+#[cfg(feature = "std")]
+pub mod tune;
+#[cfg(feature = "wallet")]
+pub mod wallet;
+
+pub const OSB_WINDOW_SIZE: usize = 5;
+
+/// Number of feature positions (`OsbToken::idx`): the OSB window, character
+/// n-grams and external features.
+pub const FEATURE_POSITIONS: usize = 8;
 
-#[derive(Debug, Serialize, Deserialize, Default)]
+#[cfg(feature = "std")]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct BayesModel {
+    #[serde(serialize_with = "snapshot::serialize_sorted")]
     pub weights: HashMap<TokenHash, Weights, BuildHasherDefault<NoHashHasher<TokenHash>>>,
     pub spam_learns: u32,
     pub ham_learns: u32,
+    #[serde(default)]
+    pub hasher: HashAlgorithm,
+    // Hasher of a custom `hasher`, which snapshots cannot restore
+    #[serde(skip)]
+    pub token_hasher: Option<std::sync::Arc<dyn TokenHasher>>,
+    #[serde(default, serialize_with = "snapshot::serialize_sorted")]
+    pub doc_freq: HashMap<TokenHash, u32, BuildHasherDefault<NoHashHasher<TokenHash>>>,
+    #[serde(default)]
+    pub version: u64,
+    // Messages trained since the model was created, never decremented
+    #[serde(default)]
+    pub learned_messages: u64,
+    // Maximum number of distinct tokens learned into each class
+    #[serde(default)]
+    pub class_token_limit: Option<usize>,
+    #[serde(skip)]
+    pub metrics: LiveMetrics,
+    // OSB window the model was trained with
+    #[serde(default = "default_window_size")]
+    pub window_size: usize,
+    // Estimated size of the token tables above which the lowest value
+    // tokens are pruned after each learn
+    #[serde(default)]
+    pub memory_budget: Option<train::MemoryBudget>,
+    #[serde(default)]
+    pub decay_schedule: Option<decay::DecaySchedule>,
+    // Learn counters of the namespaces other than the default one
+    #[serde(default)]
+    pub namespaces: BTreeMap<Namespace, Weights>,
+    #[serde(skip)]
+    pub class_index: train::ClassTokenIndex,
+}
+
+#[cfg(feature = "std")]
+impl Default for BayesModel {
+    fn default() -> Self {
+        BayesModel {
+            weights: HashMap::default(),
+            spam_learns: 0,
+            ham_learns: 0,
+            hasher: HashAlgorithm::default(),
+            token_hasher: None,
+            doc_freq: HashMap::default(),
+            version: 0,
+            learned_messages: 0,
+            class_token_limit: None,
+            metrics: LiveMetrics::default(),
+            window_size: OSB_WINDOW_SIZE,
+            memory_budget: None,
+            decay_schedule: None,
+            namespaces: BTreeMap::new(),
+            class_index: train::ClassTokenIndex::default(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+fn default_window_size() -> usize {
+    OSB_WINDOW_SIZE
+}
+
+/// Advances a SplitMix64 generator and returns its next output, which is
+/// also a well mixed hash of the state.
+#[cfg(feature = "std")]
+pub(crate) fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E3779B97F4A7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -46,9 +245,245 @@ pub struct BayesClassifier {
     pub min_tokens: u32,
     pub min_prob_strength: f64,
     pub min_learns: u32,
+    pub prob_clamp: Option<f64>,
+    #[serde(default = "default_thread_alpha")]
+    pub thread_alpha: f64,
+    #[serde(default)]
+    pub idf_weighting: bool,
+    #[serde(default)]
+    pub fisher_cutoff: FisherCutoff,
+    // Minimum distance of the score from 0.5 required to return a verdict
+    #[serde(default = "default_decision_margin")]
+    pub decision_margin: f64,
+    // Verdicts are deferred until the model has learned this many messages
+    #[serde(default)]
+    pub warmup_messages: u64,
+    // Handling of models that have only learned messages of one class
+    #[serde(default)]
+    pub single_class: SingleClassPolicy,
+    #[serde(default)]
+    pub smoothing: Smoothing,
+    #[serde(default)]
+    pub gap_decay: Option<GapDecay>,
+    #[serde(default)]
+    pub combination: TokenCombination,
+    // The inverse chi-square results are clamped to [eps, 1 - eps]
+    #[serde(default = "default_chi_square_eps")]
+    pub chi_square_eps: f64,
+    // Scores at or beyond these thresholds, backed by at least
+    // `autolearn_min_tokens` tokens, are labeled for auto-training
+    #[serde(default = "default_autolearn_spam")]
+    pub autolearn_spam: f64,
+    #[serde(default = "default_autolearn_ham")]
+    pub autolearn_ham: f64,
+    #[serde(default = "default_autolearn_min_tokens")]
+    pub autolearn_min_tokens: u32,
+    // Minimum sum of the deviations from 0.5 of the counted tokens, so that
+    // many weakly signalled tokens do not add up to a verdict
+    #[serde(default)]
+    pub min_evidence: f64,
+    // Models with fewer distinct tokens in either class are still warming up
+    #[serde(default)]
+    pub min_class_tokens: usize,
+    // Scores at or above this threshold are spam, see `tune_threshold`
+    #[serde(default = "default_spam_threshold")]
+    pub spam_threshold: f64,
+    // Tokens that always count regardless of `min_token_hits`
+    #[cfg(feature = "std")]
+    #[serde(default)]
+    pub token_allow: TokenFilter,
+    // Tokens that never count
+    #[cfg(feature = "std")]
+    #[serde(default)]
+    pub token_deny: TokenFilter,
+    // Messages with any of these tokens score at least `spam_floor_score`
+    #[cfg(feature = "std")]
+    #[serde(default)]
+    pub spam_floor: TokenFilter,
+    #[serde(default = "default_spam_floor_score")]
+    pub spam_floor_score: f64,
+    // Tokens stop being read once the gap between the spam and ham log
+    // probabilities reaches this value, after at least `min_tokens` tokens.
+    // The score may then differ slightly from that of all the tokens, though
+    // the verdict rarely does. Ignored with `stable_summation` and
+    // `goodword_defense`.
+    #[serde(default)]
+    pub early_exit: Option<f64>,
+    // Repeated OSB windows of a message are only counted once, see
+    // `DedupWindows`
+    #[cfg(feature = "std")]
+    #[serde(default)]
+    pub dedup_windows: bool,
+    // The probabilities of the repeats of a token within a message are
+    // pulled towards 0.5 by this factor per earlier occurrence, so the first
+    // occurrence counts fully and each repeat a bit less. Only tokens
+    // classified along with their hash are affected.
+    #[cfg(feature = "std")]
+    #[serde(default)]
+    pub repeat_decay: Option<f64>,
+    // Spam verdicts of `classify_text_agreement` require both the words and
+    // the links of a message to score as spam
+    #[cfg(feature = "std")]
+    #[serde(default)]
+    pub link_agreement: Option<agreement::LinkAgreement>,
+    // Token probabilities are summed in a canonical order, so that the score
+    // does not depend on the order of the tokens down to the last bit
+    #[cfg(feature = "std")]
+    #[serde(default)]
+    pub stable_summation: bool,
+    // Defense against ham words padding spam messages, off by default
+    #[cfg(feature = "std")]
+    #[serde(default)]
+    pub goodword_defense: Option<goodword::GoodWordDefense>,
+}
+
+/// Thresholds replacing those of a `BayesClassifier` for a single call.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ClassifyOverrides {
+    pub min_token_hits: Option<u32>,
+    pub min_tokens: Option<u32>,
+    pub decision_margin: Option<f64>,
+}
+
+/// Limit on the combined log probabilities above which the inverse chi-square
+/// is used, falling back to the naive method below it. An absolute limit
+/// depends on the number of tokens, since each one adds to the sums, while a
+/// per-token limit compares the average evidence of each token instead.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum FisherCutoff {
+    Absolute(f64),
+    PerToken(f64),
+}
+
+// Defaults to the absolute limit used by RSpamd
+impl Default for FisherCutoff {
+    fn default() -> Self {
+        FisherCutoff::Absolute(-300.0)
+    }
+}
+
+/// Decay of the evidence of OSB bigrams with the gap between their words,
+/// where `gap` is the number of words skipped (0 for adjacent words, up to 3
+/// for the widest pairs of the window). The deviation from 0.5 of a bigram
+/// probability is scaled by `base * ratio^gap`, capped at 1.0, once the
+/// `FEATURE_WEIGHT` slot of its position has been applied, so the decay
+/// multiplies the slot weight rather than replacing it. Unigrams and
+/// character n-grams are not affected. Decayed bigrams can fall below
+/// `min_prob_strength` and be skipped.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GapDecay {
+    pub base: f64,
+    pub ratio: f64,
+}
+
+/// Combination of the token probabilities into a score. `Flat` applies
+/// Fisher's method to all the tokens at once. `Positional` applies it to the
+/// tokens of each feature position on its own, then applies it again to the
+/// scores of the positions, the log probabilities of each position being
+/// scaled by its weight (positions with a zero weight are left out).
+///
+/// Fisher's method assumes independent evidence, but the OSB tokens of a
+/// position repeat the words of the other positions, so the flat combination
+/// counts each word several times and grows overconfident on long messages.
+/// Combining by position first turns every position into a single piece of
+/// evidence, bounding how much correlated positions can reinforce each other,
+/// and lets the weights tune how much each kind of feature is trusted. The
+/// second level follows a chi-square distribution only with unit weights;
+/// other weights are a heuristic scaling of the evidence.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum TokenCombination {
+    #[default]
+    Flat,
+    Positional {
+        weights: [f64; FEATURE_POSITIONS],
+    },
+}
+
+/// Smoothing of the per-token class frequencies before they are combined.
+/// With `Laplace(alpha)`, a token seen `n` times in `learns` messages of a
+/// class has a frequency of `(n + alpha) / (learns + 2 * alpha)`, pulling the
+/// probabilities of rarely seen tokens towards 0.5. Defaults to `None`, the
+/// maximum-likelihood frequencies `n / learns`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum Smoothing {
+    #[default]
+    None,
+    Laplace(f64),
+}
+
+/// How a model that has learned messages of only one class is handled. The
+/// frequencies of the untrained class are computed over a single learn, so
+/// any token seen during training pulls every message towards the trained
+/// class. By default no verdict is reached, reported as `BayesVerdict::Warming`,
+/// until both classes have been learned.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SingleClassPolicy {
+    #[default]
+    Warming,
+    Classify,
+}
+
+/// Outcome of a classification that distinguishes a model still in its
+/// warm-up period, during which scores are not reported.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BayesVerdict {
+    Warming,
+    Undecided,
+    Score(f64),
+}
+
+/// Scores from `low` to `high`, both included, that are too uncertain to be
+/// trusted and are handed to a secondary classifier by `classify_tiered`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct UncertainBand {
+    pub low: f64,
+    pub high: f64,
+}
+
+impl Default for UncertainBand {
+    fn default() -> Self {
+        UncertainBand {
+            low: 0.2,
+            high: 0.8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tier {
+    Primary,
+    Secondary,
+}
+
+/// Score of `classify_tiered` and the classifier that produced it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TieredResult {
+    pub score: Option<f64>,
+    pub tier: Tier,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BayesResult {
+    pub score: f64,
+    pub processed_tokens: u32,
+    pub confidence: f64,
+    pub(crate) autolearn: Option<bool>,
+    // Token of `spam_floor` that raised the score to `spam_floor_score`
+    pub floor_token: Option<TokenHash>,
+    // Whether the message looks padded with ham words, see `goodword_defense`
+    pub goodword_attack: bool,
+}
+
+impl BayesResult {
+    /// Returns whether the message should be auto-trained as spam (`true`) or
+    /// ham (`false`), or None when the classification is not confident or
+    /// well-evidenced enough to be learned without risking reinforcing errors.
+    pub fn autolearn_verdict(&self) -> Option<bool> {
+        self.autolearn
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize, Default, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct TokenHash {
     pub h1: u64,
     pub h2: u64,
@@ -67,45 +502,114 @@ impl BayesClassifier {
             min_tokens: 11,
             min_prob_strength: 0.05,
             min_learns: 200,
+            prob_clamp: None,
+            thread_alpha: default_thread_alpha(),
+            idf_weighting: false,
+            fisher_cutoff: FisherCutoff::default(),
+            decision_margin: default_decision_margin(),
+            warmup_messages: 0,
+            single_class: SingleClassPolicy::default(),
+            smoothing: Smoothing::default(),
+            gap_decay: None,
+            combination: TokenCombination::Flat,
+            chi_square_eps: default_chi_square_eps(),
+            autolearn_spam: default_autolearn_spam(),
+            autolearn_ham: default_autolearn_ham(),
+            autolearn_min_tokens: default_autolearn_min_tokens(),
+            min_evidence: 0.0,
+            min_class_tokens: 0,
+            spam_threshold: default_spam_threshold(),
+            #[cfg(feature = "std")]
+            token_allow: TokenFilter::default(),
+            #[cfg(feature = "std")]
+            token_deny: TokenFilter::default(),
+            #[cfg(feature = "std")]
+            spam_floor: TokenFilter::default(),
+            spam_floor_score: default_spam_floor_score(),
+            early_exit: None,
+            #[cfg(feature = "std")]
+            dedup_windows: false,
+            #[cfg(feature = "std")]
+            repeat_decay: None,
+            #[cfg(feature = "std")]
+            link_agreement: None,
+            #[cfg(feature = "std")]
+            stable_summation: false,
+            #[cfg(feature = "std")]
+            goodword_defense: None,
         }
     }
 }
 
+fn default_thread_alpha() -> f64 {
+    0.5
+}
+
+fn default_decision_margin() -> f64 {
+    0.05
+}
+
+fn default_spam_threshold() -> f64 {
+    0.5
+}
+
+fn default_spam_floor_score() -> f64 {
+    0.95
+}
+
+fn default_chi_square_eps() -> f64 {
+    1e-12
+}
+
+fn default_autolearn_spam() -> f64 {
+    0.99
+}
+
+fn default_autolearn_ham() -> f64 {
+    0.01
+}
+
+fn default_autolearn_min_tokens() -> u32 {
+    50
+}
+
 impl Default for BayesClassifier {
     fn default() -> Self {
         Self::new()
     }
 }
 
+#[cfg(feature = "std")]
 impl From<Gram<'_>> for TokenHash {
     fn from(value: Gram<'_>) -> Self {
-        match value {
-            Gram::Uni { t1 } => TokenHash {
-                h1: xxhash_rust::xxh3::xxh3_64(t1.as_bytes()),
-                h2: farmhash::hash64(t1.as_bytes()),
-            },
-            Gram::Bi { t1, t2, .. } => {
-                let mut buf = Vec::with_capacity(t1.len() + t2.len() + 1);
-                buf.extend_from_slice(t1.as_bytes());
-                buf.push(b' ');
-                buf.extend_from_slice(t2.as_bytes());
-                TokenHash {
-                    h1: xxhash_rust::xxh3::xxh3_64(&buf),
-                    h2: farmhash::hash64(&buf),
-                }
-            }
-        }
+        DefaultTokenHasher.hash_gram(value)
     }
 }
 
-impl std::hash::Hash for TokenHash {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+impl core::hash::Hash for TokenHash {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
         state.write_u64(self.h1 ^ self.h2);
     }
 }
 
 impl nohash::IsEnabled for TokenHash {}
 
+// Token ids from other systems, with `h1` as the high and `h2` as the low half
+impl From<u128> for TokenHash {
+    fn from(value: u128) -> Self {
+        TokenHash {
+            h1: (value >> 64) as u64,
+            h2: value as u64,
+        }
+    }
+}
+
+impl From<TokenHash> for u128 {
+    fn from(value: TokenHash) -> Self {
+        ((value.h1 as u128) << 64) | value.h2 as u128
+    }
+}
+
 impl From<i64> for Weights {
     fn from(value: i64) -> Self {
         Weights {
@@ -0,0 +1,399 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use serde::{Deserialize, Serialize};
+
+use crate::tokenizers::osb::OsbToken;
+
+#[cfg(feature = "store")]
+use super::store::TokenStore;
+use super::{
+    splitmix64, train::TokenSet, BayesClassifier, BayesModel, BayesResult, TokenHash, Weights,
+};
+
+/// Tenant of a model kept in a store shared with other models. The namespace
+/// is mixed into every token hash, so that the same token learned by two
+/// tenants is stored under two unrelated hashes, and the learn counters of
+/// the tenant are kept in `BayesModel::namespaces`.
+///
+/// The default namespace leaves hashes untouched and keeps its counters in
+/// `spam_learns` and `ham_learns`, so data trained before namespaces existed
+/// belongs to it as is. To share a store with other tenants, a model is
+/// migrated to its own namespace with `BayesModel::move_to_namespace`, which
+/// rehashes its tokens and moves its counters, before merging it into the
+/// store. Version 1 snapshots stored the counters of each namespace as the
+/// weights of a reserved token hash instead, which
+/// `BayesModel::restore_namespace_learns` moves back to the namespace.
+///
+/// The class token limit, memory budget and decay schedule of a model bound
+/// the store as a whole: they rank and evict the tokens of every namespace
+/// together, so a busy tenant can evict the tokens of a quiet one. They run
+/// as the default namespace learns, or when called explicitly, but not from
+/// `BayesModel::train_namespaced`.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct Namespace(pub u64);
+
+impl Namespace {
+    pub const DEFAULT: Namespace = Namespace(0);
+
+    pub fn is_default(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Hash of a token within this namespace.
+    pub fn token_hash(&self, hash: TokenHash) -> TokenHash {
+        if self.is_default() {
+            hash
+        } else {
+            TokenHash {
+                h1: hash.h1 ^ mix(self.0),
+                h2: hash.h2 ^ mix(!self.0),
+            }
+        }
+    }
+
+    // Hash whose weights held the spam and ham learns of the namespace in
+    // version 1 snapshots
+    fn legacy_learns_hash(&self) -> TokenHash {
+        self.token_hash(TokenHash::default())
+    }
+}
+
+fn mix(value: u64) -> u64 {
+    splitmix64(&mut { value })
+}
+
+impl BayesModel {
+    /// Same as `train`, learning the tokens into a namespace. Outside of the
+    /// default namespace, `spam_learns` and `ham_learns` are left as they
+    /// are and the counters of the namespace grow instead. The decay
+    /// schedule, class token limit and memory budget of the model are not
+    /// applied, see `Namespace`.
+    pub fn train_namespaced<T>(&mut self, namespace: Namespace, tokens: T, is_spam: bool)
+    where
+        T: IntoIterator<Item = OsbToken<TokenHash>>,
+    {
+        if namespace.is_default() {
+            return self.train(tokens, is_spam);
+        }
+
+        let learns = self.namespaces.entry(namespace).or_default();
+        if is_spam {
+            learns.spam = learns.spam.saturating_add(1);
+        } else {
+            learns.ham = learns.ham.saturating_add(1);
+        }
+        self.version += 1;
+
+        let mut seen = TokenSet::default();
+        for token in tokens {
+            let hash = namespace.token_hash(token.inner);
+            let hs = self.weights.entry(hash).or_default();
+            let before = *hs;
+            if is_spam {
+                hs.spam = hs.spam.saturating_add(1);
+            } else {
+                hs.ham = hs.ham.saturating_add(1);
+            }
+            self.class_index.update(hash, before, *hs);
+            if seen.insert(hash) {
+                let df = self.doc_freq.entry(hash).or_default();
+                *df = df.saturating_add(1);
+            }
+        }
+    }
+
+    /// Returns the number of ham and spam messages learned into a namespace.
+    pub fn namespace_learns(&self, namespace: Namespace) -> (u32, u32) {
+        if namespace.is_default() {
+            (self.ham_learns, self.spam_learns)
+        } else {
+            let learns = self.namespaces.get(&namespace).copied().unwrap_or_default();
+            (learns.ham, learns.spam)
+        }
+    }
+
+    /// Classifies a message with the tokens and counters of a namespace.
+    pub fn classify_namespaced(
+        &self,
+        classifier: &BayesClassifier,
+        namespace: Namespace,
+        tokens: impl IntoIterator<Item = OsbToken<TokenHash>>,
+    ) -> Option<BayesResult> {
+        let (ham_learns, spam_learns) = self.namespace_learns(namespace);
+        classifier.classify_hashed(
+            tokens.into_iter().map(|token| {
+                let weights = self
+                    .weights
+                    .get(&namespace.token_hash(token.inner))
+                    .copied()
+                    .unwrap_or_default();
+                (
+                    token.inner,
+                    OsbToken {
+                        inner: weights,
+                        idx: token.idx,
+                    },
+                )
+            }),
+            ham_learns,
+            spam_learns,
+        )
+    }
+
+    /// Moves a model of the default namespace into another one, rehashing its
+    /// tokens and turning its learn counters into those of the namespace.
+    pub fn move_to_namespace(&mut self, namespace: Namespace) {
+        if namespace.is_default() {
+            return;
+        }
+
+        self.weights = std::mem::take(&mut self.weights)
+            .into_iter()
+            .map(|(hash, weights)| (namespace.token_hash(hash), weights))
+            .collect();
+        self.doc_freq = std::mem::take(&mut self.doc_freq)
+            .into_iter()
+            .map(|(hash, count)| (namespace.token_hash(hash), count))
+            .collect();
+        let learns = self.namespaces.entry(namespace).or_default();
+        learns.spam = learns.spam.saturating_add(self.spam_learns);
+        learns.ham = learns.ham.saturating_add(self.ham_learns);
+        (self.spam_learns, self.ham_learns) = (0, 0);
+        self.class_index.clear();
+        self.version += 1;
+    }
+
+    /// Moves the learn counters of a namespace restored from a version 1
+    /// snapshot out of the token weights. Returns false if the model holds
+    /// no such counters.
+    pub fn restore_namespace_learns(&mut self, namespace: Namespace) -> bool {
+        if namespace.is_default() {
+            return false;
+        }
+        let hash = namespace.legacy_learns_hash();
+        let Some(legacy) = self.weights.remove(&hash) else {
+            return false;
+        };
+        self.doc_freq.remove(&hash);
+        self.class_index.update(hash, legacy, Weights::default());
+
+        let learns = self.namespaces.entry(namespace).or_default();
+        learns.spam = learns.spam.saturating_add(legacy.spam);
+        learns.ham = learns.ham.saturating_add(legacy.ham);
+        self.version += 1;
+        true
+    }
+}
+
+/// View of the models of one namespace of a shared store, e.g. for
+/// `BayesClassifier::classify_async`.
+#[cfg(feature = "store")]
+#[derive(Debug, Clone, Copy)]
+pub struct NamespacedStore<'x, S> {
+    store: &'x S,
+    namespace: Namespace,
+}
+
+#[cfg(feature = "store")]
+impl<'x, S: TokenStore> NamespacedStore<'x, S> {
+    pub fn new(store: &'x S, namespace: Namespace) -> Self {
+        NamespacedStore { store, namespace }
+    }
+}
+
+#[cfg(feature = "store")]
+impl<S: TokenStore> TokenStore for NamespacedStore<'_, S> {
+    type Error = S::Error;
+
+    async fn get_weights(&self, hash: TokenHash) -> Result<Weights, Self::Error> {
+        self.store
+            .get_weights(self.namespace.token_hash(hash))
+            .await
+    }
+
+    async fn lookup_many(&self, hashes: &[TokenHash]) -> Result<Vec<Weights>, Self::Error> {
+        let hashes = hashes
+            .iter()
+            .map(|hash| self.namespace.token_hash(*hash))
+            .collect::<Vec<_>>();
+        self.store.lookup_many(&hashes).await
+    }
+
+    async fn learns(&self) -> Result<(u32, u32), Self::Error> {
+        self.store.namespace_learns(self.namespace).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::borrow::Cow;
+
+    use crate::{
+        bayes::{
+            hasher::HashAlgorithm,
+            train::{MemoryBudget, PruneStrategy},
+            BayesClassifier, BayesModel, TokenHash, Weights, OSB_WINDOW_SIZE,
+        },
+        tokenizers::osb::{OsbToken, OsbTokenizer},
+    };
+
+    use super::Namespace;
+    #[cfg(feature = "store")]
+    use super::NamespacedStore;
+
+    fn tokens(text: &str) -> Vec<OsbToken<TokenHash>> {
+        OsbTokenizer::new(text.split(' ').map(Cow::from), OSB_WINDOW_SIZE).collect()
+    }
+
+    #[tokio::test]
+    async fn separate_namespaces() {
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 1,
+            ..Default::default()
+        };
+        let (alice, bob) = (Namespace(1), Namespace(2));
+        let text = "crypto airdrop bonus";
+        let mut store = BayesModel::default();
+        for _ in 0..3 {
+            store.train_namespaced(alice, tokens(text), true);
+            store.train_namespaced(alice, tokens("quarterly report"), false);
+            store.train_namespaced(bob, tokens(text), false);
+        }
+        store.train_namespaced(bob, tokens("claim your prize"), true);
+
+        // The same text keeps separate counts in each namespace
+        let weights =
+            |namespace: Namespace| store.weights[&namespace.token_hash(tokens(text)[0].inner)];
+        assert_eq!((weights(alice).spam, weights(alice).ham), (3, 0));
+        assert_eq!((weights(bob).spam, weights(bob).ham), (0, 3));
+        assert_eq!(store.namespace_learns(alice), (3, 3));
+        assert_eq!(store.namespace_learns(bob), (3, 1));
+        assert_eq!((store.ham_learns, store.spam_learns), (0, 0));
+        assert!(!store.weights.contains_key(&tokens(text)[0].inner));
+
+        // The counters are not token weights, and survive snapshots
+        assert!([alice, bob]
+            .iter()
+            .all(|namespace| !store.weights.contains_key(&namespace.legacy_learns_hash())));
+        let restored =
+            BayesModel::from_snapshot(&store.to_snapshot().unwrap(), HashAlgorithm::default())
+                .unwrap();
+        assert_eq!(restored.namespaces, store.namespaces);
+
+        let alice_score = store
+            .classify_namespaced(&classifier, alice, tokens(text))
+            .unwrap();
+        let bob_score = store
+            .classify_namespaced(&classifier, bob, tokens(text))
+            .unwrap();
+        assert!(alice_score.score > 0.5 && bob_score.score < 0.5);
+        #[cfg(feature = "store")]
+        assert_eq!(
+            classifier
+                .classify_async(&NamespacedStore::new(&store, alice), tokens(text))
+                .await
+                .unwrap(),
+            Some(alice_score)
+        );
+        assert_eq!(
+            store.classify_namespaced(&classifier, Namespace(3), tokens(text)),
+            None
+        );
+
+        // A single tenant model migrated into a namespace classifies the same
+        let mut model = BayesModel::default();
+        for _ in 0..3 {
+            model.train(tokens(text), true);
+            model.train(tokens("quarterly report"), false);
+        }
+        let before = namespace_score(&model, &classifier, Namespace::DEFAULT, text);
+        model.move_to_namespace(alice);
+        assert_eq!(namespace_score(&model, &classifier, alice, text), before);
+        assert_eq!(model.namespace_learns(Namespace::DEFAULT), (0, 0));
+
+        // Counters stored among the weights by version 1 snapshots
+        model
+            .weights
+            .insert(bob.legacy_learns_hash(), Weights { spam: 2, ham: 5 });
+        assert!(model.restore_namespace_learns(bob));
+        assert!(!model.restore_namespace_learns(bob));
+        assert!(!model.weights.contains_key(&bob.legacy_learns_hash()));
+        assert_eq!(model.namespace_learns(bob), (5, 2));
+    }
+
+    #[test]
+    fn namespaced_train_hooks() {
+        // The limits of the model are not applied to namespaced learns
+        let tenant = Namespace(1);
+        let text = tokens("crypto airdrop bonus");
+        let learned = |model: &BayesModel| {
+            text.iter()
+                .filter(|token| model.weights.contains_key(&tenant.token_hash(token.inner)))
+                .count()
+        };
+        let mut model = BayesModel {
+            class_token_limit: Some(1),
+            ..Default::default()
+        };
+        model.train_namespaced(tenant, text.clone(), true);
+        assert_eq!(learned(&model), text.len());
+        assert_eq!(model.learned_messages, 0);
+
+        // But they bound the whole store once the default namespace learns
+        model.train(tokens("quarterly report"), true);
+        assert_eq!(learned(&model), 0);
+        assert_eq!(model.weights.values().filter(|w| w.spam > 0).count(), 1);
+        assert_eq!(model.namespace_learns(tenant), (0, 1));
+        assert_eq!(model.namespace_learns(Namespace::DEFAULT), (0, 1));
+
+        let budget = MemoryBudget {
+            bytes: model.memory_usage() + 2 * size_of::<(TokenHash, Weights)>(),
+            strategy: PruneStrategy::WeakestProbability,
+        };
+        let mut model = BayesModel {
+            memory_budget: Some(budget),
+            ..Default::default()
+        };
+        model.train_namespaced(tenant, text.clone(), true);
+        model.train_namespaced(tenant, tokens("quarterly report"), false);
+        assert!(model.memory_usage() > budget.bytes);
+        model.train(tokens("lunch"), false);
+        assert!(model.memory_usage() <= budget.bytes);
+        assert!(learned(&model) < text.len());
+    }
+
+    fn namespace_score(
+        model: &BayesModel,
+        classifier: &BayesClassifier,
+        namespace: Namespace,
+        text: &str,
+    ) -> Option<f64> {
+        model
+            .classify_namespaced(classifier, namespace, tokens(text))
+            .map(|result| result.score)
+    }
+}
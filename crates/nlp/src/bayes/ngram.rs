@@ -0,0 +1,167 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{borrow::Cow, sync::Arc};
+
+use utils::suffixlist::PublicSuffix;
+
+use crate::tokenizers::osb::{Gram, OsbToken, OsbTokenizer};
+
+use super::{
+    hasher::TokenHasher, tokenize::BayesTokenizer, BayesClassifier, BayesModel, TokenHash,
+};
+
+/// Feature index of character n-grams, outside of the OSB window positions.
+pub const CHAR_NGRAM_IDX: usize = 5;
+
+/// Supplements the word features of short messages, such as subject-only
+/// ones, with the character n-grams of their words so they still reach
+/// `min_tokens`. Messages must be trained and classified with the same
+/// settings for the n-gram features to carry any weight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CharNgrams {
+    // Length of each n-gram in characters
+    pub n: usize,
+    // Messages with fewer words than this get n-gram features
+    pub min_words: usize,
+}
+
+impl Default for CharNgrams {
+    fn default() -> Self {
+        CharNgrams { n: 3, min_words: 5 }
+    }
+}
+
+impl CharNgrams {
+    pub fn tokenize(
+        &self,
+        text: &str,
+        suffixes: &PublicSuffix,
+        hasher: Arc<dyn TokenHasher>,
+        window_size: usize,
+    ) -> Vec<OsbToken<TokenHash>> {
+        let words = BayesTokenizer::new(text, suffixes).collect::<Vec<_>>();
+        let mut tokens = OsbTokenizer::with_hasher(
+            words.iter().map(|word| Cow::Borrowed(word.as_ref())),
+            window_size,
+            hasher.clone(),
+        )
+        .collect::<Vec<_>>();
+
+        if words.len() < self.min_words && self.n > 0 {
+            for word in &words {
+                // Pad words so that n-grams at their boundaries are distinct
+                let chars = [' ']
+                    .into_iter()
+                    .chain(word.chars())
+                    .chain([' '])
+                    .collect::<Vec<_>>();
+                for ngram in chars.windows(self.n) {
+                    let mut feature = String::with_capacity(4 + ngram.len());
+                    feature.push_str("chr:");
+                    feature.extend(ngram);
+                    tokens.push(OsbToken {
+                        inner: hasher.hash_gram(Gram::Uni { t1: &feature }),
+                        idx: CHAR_NGRAM_IDX,
+                    });
+                }
+            }
+        }
+
+        tokens
+    }
+}
+
+impl BayesModel {
+    pub fn train_text_ngrams(
+        &mut self,
+        text: &str,
+        is_spam: bool,
+        suffixes: &PublicSuffix,
+        ngrams: &CharNgrams,
+    ) {
+        let tokens = ngrams.tokenize(text, suffixes, self.token_hasher(), self.window_size);
+        self.train(tokens, is_spam);
+    }
+
+    pub fn classify_text_ngrams(
+        &self,
+        classifier: &BayesClassifier,
+        text: &str,
+        suffixes: &PublicSuffix,
+        ngrams: &CharNgrams,
+    ) -> Option<f64> {
+        self.classify_token_hashes(
+            classifier,
+            ngrams
+                .tokenize(text, suffixes, self.token_hasher(), self.window_size)
+                .into_iter(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use utils::suffixlist::PublicSuffix;
+
+    use crate::bayes::{BayesClassifier, BayesModel};
+
+    use super::CharNgrams;
+
+    #[test]
+    fn short_subjects() {
+        let suffixes = PublicSuffix::default();
+        let ngrams = CharNgrams::default();
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            ..Default::default()
+        };
+
+        let mut model = BayesModel::default();
+        for _ in 0..3 {
+            for subject in ["cheap pills", "cheap watches", "discount pills"] {
+                model.train_text_ngrams(subject, true, &suffixes, &ngrams);
+            }
+            for subject in ["meeting notes", "project update", "lunch tomorrow"] {
+                model.train_text_ngrams(subject, false, &suffixes, &ngrams);
+            }
+        }
+
+        // Word features alone are below `min_tokens`
+        assert_eq!(
+            model
+                .classify_text(&classifier, "cheap pills", &suffixes)
+                .unwrap(),
+            None
+        );
+
+        let score = model
+            .classify_text_ngrams(&classifier, "cheap pills", &suffixes, &ngrams)
+            .unwrap();
+        assert!(score > 0.5, "{score}");
+        let score = model
+            .classify_text_ngrams(&classifier, "meeting notes", &suffixes, &ngrams)
+            .unwrap();
+        assert!(score < 0.5, "{score}");
+    }
+}
@@ -0,0 +1,162 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use utils::suffixlist::PublicSuffix;
+
+use crate::tokenizers::osb::{OsbToken, OsbTokenizer};
+
+use super::{
+    classify::apply_prior, pipeline::PipelineError, tokenize::BayesTokenizer, BayesClassifier,
+    BayesModel, TokenHash,
+};
+
+/// Score adjustment, as log-odds, for messages where more than `threshold` of
+/// the OSB tokens were never learned by the model, as is common in machine
+/// generated spam.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NoveltyPenalty {
+    pub threshold: f64,
+    pub adjustment: f64,
+}
+
+impl Default for NoveltyPenalty {
+    fn default() -> Self {
+        NoveltyPenalty {
+            threshold: 0.8,
+            adjustment: 1.0,
+        }
+    }
+}
+
+impl BayesModel {
+    /// Returns the fraction of the tokens that are absent from the model, or
+    /// None when there are no tokens.
+    pub fn novelty_ratio<'x>(
+        &self,
+        tokens: impl IntoIterator<Item = &'x OsbToken<TokenHash>>,
+    ) -> Option<f64> {
+        let (novel, total) = tokens
+            .into_iter()
+            .fold((0u32, 0u32), |(novel, total), token| {
+                (
+                    novel + !self.weights.contains_key(&token.inner) as u32,
+                    total + 1,
+                )
+            });
+        (total > 0).then(|| novel as f64 / total as f64)
+    }
+
+    /// Same as `classify_text`, adding the novelty adjustment to the score
+    /// when the novelty ratio of the text exceeds the threshold.
+    pub fn classify_text_novelty(
+        &self,
+        classifier: &BayesClassifier,
+        text: &str,
+        suffixes: &PublicSuffix,
+        penalty: &NoveltyPenalty,
+    ) -> Result<Option<f64>, PipelineError> {
+        let tokens = OsbTokenizer::with_hasher(
+            BayesTokenizer::new(text, suffixes),
+            self.window_size,
+            self.token_hasher(),
+        )
+        .collect::<Vec<_>>();
+        let ratio = self
+            .novelty_ratio(&tokens)
+            .ok_or(PipelineError::EmptyInput)?;
+
+        let score = self.classify_token_hashes(classifier, tokens.into_iter());
+        Ok(if ratio > penalty.threshold {
+            score.map(|score| apply_prior(score, penalty.adjustment))
+        } else {
+            score
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use utils::suffixlist::PublicSuffix;
+
+    use crate::{
+        bayes::{tokenize::BayesTokenizer, BayesClassifier, BayesModel},
+        tokenizers::osb::OsbTokenizer,
+    };
+
+    use super::NoveltyPenalty;
+
+    #[test]
+    fn novelty_penalty() {
+        let suffixes = PublicSuffix::default();
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 0,
+            min_token_hits: 1,
+            ..Default::default()
+        };
+        let mut model = BayesModel::default();
+        model
+            .train_text("claim your prize now", true, &suffixes)
+            .unwrap();
+        model
+            .train_text("lunch with the team on friday", false, &suffixes)
+            .unwrap();
+        let penalty = NoveltyPenalty {
+            threshold: 0.5,
+            adjustment: 2.0,
+        };
+        let ratio = |text| {
+            let tokens = OsbTokenizer::with_hasher(
+                BayesTokenizer::new(text, &suffixes),
+                model.window_size,
+                model.token_hasher(),
+            )
+            .collect::<Vec<_>>();
+            model.novelty_ratio(&tokens).unwrap()
+        };
+
+        let familiar = "lunch with the team on friday";
+        assert_eq!(ratio(familiar), 0.0);
+        assert_eq!(
+            model
+                .classify_text_novelty(&classifier, familiar, &suffixes, &penalty)
+                .unwrap(),
+            model
+                .classify_text(&classifier, familiar, &suffixes)
+                .unwrap()
+        );
+
+        let novel = "zqvx brlm kwtp your prize";
+        assert!(ratio(novel) > 0.5, "{}", ratio(novel));
+        assert_eq!(ratio("zqvx brlm kwtp"), 1.0);
+        let plain = model
+            .classify_text(&classifier, novel, &suffixes)
+            .unwrap()
+            .unwrap();
+        let penalized = model
+            .classify_text_novelty(&classifier, novel, &suffixes, &penalty)
+            .unwrap()
+            .unwrap();
+        assert!(penalized > plain, "{penalized} <= {plain}");
+    }
+}
@@ -0,0 +1,182 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use mail_parser::{Message, MimeHeaders, PartType};
+use utils::suffixlist::PublicSuffix;
+
+use super::{features::FeatureExtractor, tokenize::BayesTokenizer};
+
+/// Text recognition of images, provided by an external engine. Returns the
+/// text found in an image of the given content type (e.g. `image/png`), or
+/// None when it could not be recognized.
+pub trait OcrEngine {
+    fn recognize(&self, content_type: &str, image: &[u8]) -> Option<String>;
+}
+
+impl<F> OcrEngine for F
+where
+    F: Fn(&str, &[u8]) -> Option<String>,
+{
+    fn recognize(&self, content_type: &str, image: &[u8]) -> Option<String> {
+        self(content_type, image)
+    }
+}
+
+/// Feature extractor emitting an `ocr:<token>` feature for each token of the
+/// text recognized in the attached and inline images of a message, so that
+/// spam hiding its pitch in pictures can be learned.
+pub struct OcrExtractor<'x, E> {
+    engine: E,
+    suffixes: &'x PublicSuffix,
+}
+
+impl<'x, E: OcrEngine> OcrExtractor<'x, E> {
+    pub fn new(engine: E, suffixes: &'x PublicSuffix) -> Self {
+        OcrExtractor { engine, suffixes }
+    }
+}
+
+impl<E: OcrEngine> FeatureExtractor for OcrExtractor<'_, E> {
+    fn namespace(&self) -> &str {
+        "ocr"
+    }
+
+    fn extract(&self, message: &Message<'_>) -> Vec<String> {
+        let mut features = Vec::new();
+        for part in &message.parts {
+            let (PartType::Binary(image) | PartType::InlineBinary(image)) = &part.body else {
+                continue;
+            };
+            let Some(content_type) = part
+                .content_type()
+                .filter(|ct| ct.ctype().eq_ignore_ascii_case("image"))
+            else {
+                continue;
+            };
+            let content_type = format!(
+                "image/{}",
+                content_type.subtype().unwrap_or_default().to_lowercase()
+            );
+
+            if let Some(text) = self.engine.recognize(&content_type, image) {
+                features.extend(
+                    BayesTokenizer::new(&text, self.suffixes).map(|token| token.into_owned()),
+                );
+            }
+        }
+        features
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mail_parser::MessageParser;
+    use utils::suffixlist::PublicSuffix;
+
+    use crate::{
+        bayes::{features::EXTERNAL_FEATURE_IDX, BayesClassifier, BayesModel},
+        tokenizers::osb::{Gram, OsbToken},
+    };
+
+    use super::{FeatureExtractor, OcrExtractor};
+
+    fn message(image: &str) -> String {
+        format!(
+            concat!(
+                "From: someone@example.com\r\n",
+                "Subject: Hello\r\n",
+                "Content-Type: multipart/mixed; boundary=\"inner\"\r\n",
+                "\r\n",
+                "--inner\r\n",
+                "Content-Type: text/plain\r\n",
+                "\r\n",
+                "have a look at this picture\r\n",
+                "--inner\r\n",
+                "Content-Type: image/png\r\n",
+                "Content-Disposition: inline\r\n",
+                "Content-Transfer-Encoding: base64\r\n",
+                "\r\n",
+                "{}\r\n",
+                "--inner--\r\n",
+            ),
+            image
+        )
+    }
+
+    #[test]
+    fn ocr_tokens() {
+        let suffixes = PublicSuffix::default();
+        let parser = MessageParser::default();
+        let engine = |content_type: &str, image: &[u8]| {
+            assert_eq!(content_type, "image/png");
+            match image {
+                b"img-spam" => Some("Claim your cash prize now".to_string()),
+                b"img-ham" => Some("Quarterly team photo".to_string()),
+                _ => None,
+            }
+        };
+        let ocr = OcrExtractor::new(engine, &suffixes);
+        let (spam, ham) = (message("aW1nLXNwYW0="), message("aW1nLWhhbQ=="));
+        let (spam, ham) = (parser.parse(&spam).unwrap(), parser.parse(&ham).unwrap());
+
+        let features = ocr.extract(&spam);
+        assert!(features.iter().any(|token| token == "cash"), "{features:?}");
+
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 1,
+            ..Default::default()
+        };
+        let mut model = BayesModel::default();
+        for _ in 0..5 {
+            for (message, is_spam) in [(&spam, true), (&ham, false)] {
+                model
+                    .train_message(message, is_spam, &suffixes, &[&ocr])
+                    .unwrap();
+            }
+        }
+
+        let tokens = model.message_tokens(&spam, &suffixes, &[&ocr]);
+        let cash = OsbToken {
+            inner: model.token_hasher().hash_gram(Gram::Uni { t1: "ocr:cash" }),
+            idx: EXTERNAL_FEATURE_IDX,
+        };
+        assert!(tokens.contains(&cash));
+
+        // The text alone is seen equally often in both classes
+        assert_eq!(
+            model
+                .classify_message(&classifier, &spam, &suffixes, &[])
+                .unwrap(),
+            None
+        );
+        let classify = |message| {
+            model
+                .classify_message(&classifier, message, &suffixes, &[&ocr])
+                .unwrap()
+                .unwrap()
+        };
+        assert!(classify(&spam) > 0.9);
+        assert!(classify(&ham) < 0.1);
+    }
+}
@@ -0,0 +1,148 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{borrow::Cow, collections::BTreeMap};
+
+use mail_parser::{decoders::html::html_to_text, Message, PartType};
+use utils::suffixlist::PublicSuffix;
+
+use crate::tokenizers::osb::OsbTokenizer;
+
+use super::{tokenize::BayesTokenizer, BayesClassifier, BayesModel};
+
+/// Scores of the text and HTML parts of a message, classified separately.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PartScores {
+    // Score of each part by part id, None when the part produced tokens but
+    // no verdict was reached
+    pub parts: BTreeMap<usize, Option<f64>>,
+    // Score of the tokens of all the parts classified together
+    pub aggregate: Option<f64>,
+}
+
+impl PartScores {
+    /// Returns the id and score of the part that scored closest to spam.
+    pub fn most_spammy(&self) -> Option<(usize, f64)> {
+        self.parts
+            .iter()
+            .filter_map(|(id, score)| score.map(|score| (*id, score)))
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+    }
+}
+
+impl BayesModel {
+    /// Tokenizes and classifies every text and HTML part of a message on its
+    /// own, HTML parts being converted to text first. Parts without tokens
+    /// are left out.
+    pub fn classify_parts(
+        &self,
+        classifier: &BayesClassifier,
+        message: &Message<'_>,
+        suffixes: &PublicSuffix,
+    ) -> PartScores {
+        let mut scores = PartScores::default();
+        let mut all_tokens = Vec::new();
+
+        for (id, part) in message.parts.iter().enumerate() {
+            let text = match &part.body {
+                PartType::Text(text) => Cow::from(text.as_ref()),
+                PartType::Html(html) => Cow::from(html_to_text(html)),
+                _ => continue,
+            };
+            let tokens = OsbTokenizer::with_hasher(
+                BayesTokenizer::new(&text, suffixes),
+                self.window_size,
+                self.token_hasher(),
+            )
+            .collect::<Vec<_>>();
+            if tokens.is_empty() {
+                continue;
+            }
+
+            scores.parts.insert(
+                id,
+                self.classify_token_hashes(classifier, tokens.iter().cloned()),
+            );
+            all_tokens.extend(tokens);
+        }
+
+        if !all_tokens.is_empty() {
+            scores.aggregate = self.classify_token_hashes(classifier, all_tokens.into_iter());
+        }
+        scores
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mail_parser::MessageParser;
+    use utils::suffixlist::PublicSuffix;
+
+    use crate::bayes::{BayesClassifier, BayesModel};
+
+    const ALTERNATIVE: &str = concat!(
+        "From: promo@example.com\r\n",
+        "Subject: Hello\r\n",
+        "Content-Type: multipart/alternative; boundary=\"inner\"\r\n",
+        "\r\n",
+        "--inner\r\n",
+        "Content-Type: text/plain\r\n",
+        "\r\n",
+        "see you at the team lunch on friday\r\n",
+        "--inner\r\n",
+        "Content-Type: text/html\r\n",
+        "\r\n",
+        "<p>claim your <b>cash prize</b> now</p>\r\n",
+        "--inner--\r\n",
+    );
+
+    #[test]
+    fn classify_parts() {
+        let suffixes = PublicSuffix::default();
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 1,
+            min_token_hits: 1,
+            ..Default::default()
+        };
+        let mut model = BayesModel::default();
+        model
+            .train_text("claim your cash prize now", true, &suffixes)
+            .unwrap();
+        model
+            .train_text("see you at the team lunch on friday", false, &suffixes)
+            .unwrap();
+
+        let message = MessageParser::default().parse(ALTERNATIVE).unwrap();
+        let scores = model.classify_parts(&classifier, &message, &suffixes);
+
+        assert_eq!(scores.parts.len(), 2, "{scores:?}");
+        let text = scores.parts[&1].unwrap();
+        let html = scores.parts[&2].unwrap();
+        assert!(text < 0.5, "{scores:?}");
+        assert!(html > 0.5, "{scores:?}");
+        assert_eq!(scores.most_spammy(), Some((2, html)));
+        let aggregate = scores.aggregate.unwrap();
+        assert!(text < aggregate && aggregate < html, "{scores:?}");
+    }
+}
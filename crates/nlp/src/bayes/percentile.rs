@@ -0,0 +1,152 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+/// Rolling window of the most recent scores, mapping raw scores to their
+/// percentile within it. Raw probabilities drift as the model is retrained,
+/// while a percentile threshold such as "the 5% spammiest messages" keeps
+/// its meaning. The window holds at most `capacity` scores, dropping the
+/// oldest first, and every lookup scans it, so it should be sized to a few
+/// thousand scores.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScoreCalibration {
+    scores: VecDeque<f64>,
+    capacity: usize,
+}
+
+impl ScoreCalibration {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        ScoreCalibration {
+            scores: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Adds a score to the window. Non-finite scores are ignored.
+    pub fn observe(&mut self, score: f64) {
+        if !score.is_finite() {
+            return;
+        }
+        while self.scores.len() >= self.capacity {
+            self.scores.pop_front();
+        }
+        self.scores.push_back(score);
+    }
+
+    /// Returns the fraction of the window scoring below `score`, counting
+    /// equal scores as half below, or None while the window is empty.
+    pub fn percentile(&self, score: f64) -> Option<f64> {
+        if self.scores.is_empty() {
+            return None;
+        }
+        let (below, equal) = self.scores.iter().fold((0, 0), |(below, equal), s| {
+            if *s < score {
+                (below + 1, equal)
+            } else if *s == score {
+                (below, equal + 1)
+            } else {
+                (below, equal)
+            }
+        });
+        Some((below as f64 + equal as f64 / 2.0) / self.scores.len() as f64)
+    }
+
+    /// Returns the lowest score of the window at or above the `q` quantile,
+    /// e.g. the threshold of the 5% spammiest messages for 0.95.
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        let mut scores = self.scores.iter().copied().collect::<Vec<_>>();
+        if scores.is_empty() {
+            return None;
+        }
+        scores.sort_unstable_by(f64::total_cmp);
+        let pos = (q.clamp(0.0, 1.0) * (scores.len() - 1) as f64).round() as usize;
+        Some(scores[pos])
+    }
+
+    /// Records a raw score and returns its percentile within the window.
+    pub fn normalize(&mut self, score: f64) -> Option<f64> {
+        self.observe(score);
+        self.percentile(score)
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ScoreCalibration;
+
+    #[test]
+    fn rolling_percentiles() {
+        let mut calibration = ScoreCalibration::new(1000);
+        assert_eq!(calibration.percentile(0.5), None);
+
+        // Scores spread over [0, 1)
+        for i in 0..1000 {
+            calibration.observe((i * 7919 % 1000) as f64 / 1000.0);
+        }
+        let close = |a: f64, b: f64| (a - b).abs() < 0.01;
+        assert!(close(calibration.percentile(0.95).unwrap(), 0.95));
+        assert!(close(calibration.percentile(0.25).unwrap(), 0.25));
+        assert!(close(calibration.quantile(0.95).unwrap(), 0.95));
+
+        // After retraining, scores only span [0.4, 0.6)
+        for i in 0..1500 {
+            calibration.observe(0.4 + (i * 7919 % 1000) as f64 / 5000.0);
+        }
+        assert_eq!(calibration.len(), 1000);
+        assert!(close(calibration.percentile(0.59).unwrap(), 0.95));
+        assert_eq!(calibration.percentile(0.95), Some(1.0));
+        assert_eq!(calibration.percentile(0.1), Some(0.0));
+        assert!(close(calibration.quantile(0.95).unwrap(), 0.59));
+
+        // The window can be saved and restored
+        let restored: ScoreCalibration =
+            serde_json::from_str(&serde_json::to_string(&calibration).unwrap()).unwrap();
+        assert_eq!(restored.len(), 1000);
+        assert_eq!(restored.capacity(), 1000);
+        assert!(close(restored.percentile(0.59).unwrap(), 0.95));
+
+        let mut tiny = ScoreCalibration::new(2);
+        tiny.observe(f64::NAN);
+        assert!(tiny.is_empty());
+        assert_eq!(tiny.normalize(0.3), Some(0.5));
+        assert_eq!(tiny.normalize(0.7), Some(0.75));
+        assert_eq!(tiny.normalize(0.1), Some(0.25));
+        assert_eq!(tiny.len(), 2);
+    }
+}
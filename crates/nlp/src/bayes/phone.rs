@@ -0,0 +1,198 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::borrow::Cow;
+
+/// Emits a `phone:+<digits>` token for each phone number found in the text.
+/// Numbers without an international prefix are assumed to belong to
+/// `country_code`, after removing any trunk prefix.
+pub struct PhoneTokenizer<'x> {
+    text: &'x str,
+    pos: usize,
+    country_code: &'x str,
+}
+
+// Largest national number without an international prefix, including the trunk prefix
+const MAX_NATIONAL_DIGITS: usize = 11;
+
+impl<'x> PhoneTokenizer<'x> {
+    pub fn new(text: &'x str, country_code: &'x str) -> Self {
+        Self {
+            text,
+            pos: 0,
+            country_code: country_code.trim_start_matches('+'),
+        }
+    }
+}
+
+impl<'x> Iterator for PhoneTokenizer<'x> {
+    type Item = Cow<'x, str>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let bytes = self.text.as_bytes();
+
+        while self.pos < bytes.len() {
+            let start = self.pos;
+            self.pos += 1;
+
+            if matches!(bytes[start], b'0'..=b'9' | b'+' | b'(')
+                && (start == 0 || !bytes[start - 1].is_ascii_alphanumeric())
+            {
+                if let Some((len, phone)) = parse_phone(&bytes[start..], self.country_code) {
+                    self.pos = start + len;
+                    return Some(phone.into());
+                }
+            }
+        }
+
+        None
+    }
+}
+
+fn parse_phone(bytes: &[u8], country_code: &str) -> Option<(usize, String)> {
+    let has_plus = bytes[0] == b'+';
+    let mut digits = String::with_capacity(16);
+    let mut groups = Vec::with_capacity(5);
+    let mut group_len = 0;
+    let mut separators = 0;
+    let mut end = 0;
+
+    for (pos, &ch) in bytes.iter().enumerate().skip(has_plus as usize) {
+        match ch {
+            b'0'..=b'9' => {
+                digits.push(ch as char);
+                group_len += 1;
+                separators = 0;
+                end = pos + 1;
+            }
+            b' ' | b'-' | b'.' | b'(' | b')' if separators < 2 => {
+                if group_len > 0 {
+                    groups.push(group_len);
+                    group_len = 0;
+                }
+                separators += 1;
+            }
+            _ => break,
+        }
+        if digits.len() > 15 {
+            return None;
+        }
+    }
+    if group_len > 0 {
+        groups.push(group_len);
+    }
+
+    // The number must not be part of a longer word
+    if bytes.get(end).is_some_and(|ch| ch.is_ascii_alphanumeric()) {
+        return None;
+    }
+
+    // Nor an IPv4 address
+    if groups.len() == 4
+        && groups.iter().all(|len| *len <= 3)
+        && bytes[..end]
+            .iter()
+            .all(|ch| ch.is_ascii_digit() || *ch == b'.')
+    {
+        return None;
+    }
+
+    let number = if has_plus {
+        // International format, the grouping is free
+        if digits.len() < 8 {
+            return None;
+        }
+        digits
+    } else {
+        // Require a phone-like grouping to rule out order ids and dates
+        if groups.len() < 2
+            || !(1..=4).contains(&groups[0])
+            || groups[1..].iter().any(|len| !(2..=4).contains(len))
+        {
+            return None;
+        }
+
+        if let Some(number) = digits.strip_prefix("00") {
+            if number.len() < 8 {
+                return None;
+            }
+            number.to_string()
+        } else {
+            if !(10..=MAX_NATIONAL_DIGITS).contains(&digits.len()) {
+                return None;
+            }
+            let number = digits.strip_prefix('0').unwrap_or(&digits);
+            if number.starts_with(country_code) && number.len() > 10 {
+                number.to_string()
+            } else {
+                format!("{country_code}{number}")
+            }
+        }
+    };
+
+    Some((end, format!("phone:+{number}")))
+}
+
+#[cfg(test)]
+mod test {
+    use super::PhoneTokenizer;
+
+    #[test]
+    fn phone_numbers() {
+        for text in [
+            "call +1 (555) 123-4567 now",
+            "call 1-555-123-4567 now",
+            "call 555.123.4567 now",
+            "call (555) 123 4567 now",
+            "call +1.555.123.4567 now",
+            "call +15551234567 now",
+            "call 001 555 123 4567 now",
+        ] {
+            assert_eq!(
+                PhoneTokenizer::new(text, "1").collect::<Vec<_>>(),
+                ["phone:+15551234567"],
+                "{text}"
+            );
+        }
+
+        assert_eq!(
+            PhoneTokenizer::new("Ring 020 7946 0958 or +44 20 7946 0958.", "44")
+                .collect::<Vec<_>>(),
+            ["phone:+442079460958", "phone:+442079460958"]
+        );
+
+        for text in [
+            "order 123456789012 shipped",
+            "order #5551234567 shipped",
+            "on 2024-01-15 at 10:30",
+            "card 4111-1111-1111-1111",
+            "version v1.2.3.4.5.6.7.8.9.10",
+            "ref AB555-123-4567",
+            "server 172.16.254.100 is down",
+            "relayed by 10.100.200.150.",
+            "connect to 192.168.1.100:8080",
+        ] {
+            assert_eq!(PhoneTokenizer::new(text, "1").next(), None, "{text}");
+        }
+    }
+}
@@ -0,0 +1,437 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::fmt::Display;
+
+use utils::suffixlist::PublicSuffix;
+
+use crate::tokenizers::osb::{OsbToken, OsbTokenizer};
+
+use super::{
+    cache::BayesVerdictCache, classify::TokenInput, dedup::DedupWindows, tokenize::BayesTokenizer,
+    BayesClassifier, BayesModel, BayesResult, BayesVerdict, TokenHash,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PipelineError {
+    // The input produced no tokens (empty, whitespace or punctuation only)
+    EmptyInput,
+}
+
+impl BayesModel {
+    pub fn train_text(
+        &mut self,
+        text: &str,
+        is_spam: bool,
+        suffixes: &PublicSuffix,
+    ) -> Result<(), PipelineError> {
+        let mut tokens = OsbTokenizer::with_hasher(
+            BayesTokenizer::new(text, suffixes),
+            self.window_size,
+            self.token_hasher(),
+        )
+        .peekable();
+        if tokens.peek().is_none() {
+            return Err(PipelineError::EmptyInput);
+        }
+
+        self.train(tokens, is_spam);
+        Ok(())
+    }
+
+    /// Classifies a text against this model. Returns `Ok(None)` when the model
+    /// could not reach a verdict, and `Err(PipelineError::EmptyInput)` when the
+    /// text did not contain anything to classify.
+    pub fn classify_text(
+        &self,
+        classifier: &BayesClassifier,
+        text: &str,
+        suffixes: &PublicSuffix,
+    ) -> Result<Option<f64>, PipelineError> {
+        let mut tokens = OsbTokenizer::with_hasher(
+            BayesTokenizer::new(text, suffixes),
+            self.window_size,
+            self.token_hasher(),
+        )
+        .peekable();
+        if tokens.peek().is_none() {
+            return Err(PipelineError::EmptyInput);
+        }
+
+        Ok(self.classify_token_hashes(classifier, tokens))
+    }
+
+    /// Same as `classify_text`, also writing the tokens of the text to `sink`
+    /// so they can be used to train another model without tokenizing twice.
+    pub fn classify_text_collect(
+        &self,
+        classifier: &BayesClassifier,
+        text: &str,
+        suffixes: &PublicSuffix,
+        sink: &mut impl Extend<OsbToken<TokenHash>>,
+    ) -> Result<Option<f64>, PipelineError> {
+        let mut tokens = OsbTokenizer::with_hasher(
+            BayesTokenizer::new(text, suffixes),
+            self.window_size,
+            self.token_hasher(),
+        )
+        .peekable();
+        if tokens.peek().is_none() {
+            return Err(PipelineError::EmptyInput);
+        }
+
+        let mut tokens = tokens.inspect(|token| sink.extend(Some(token.clone())));
+        let score = self.classify_token_hashes(classifier, tokens.by_ref());
+        // The classifier can stop early, e.g. when the model has too few learns
+        tokens.for_each(drop);
+
+        Ok(score)
+    }
+
+    /// Same as `classify_text`, returning `BayesVerdict::Warming` while the
+    /// model has learned fewer than `warmup_messages` messages, only one
+    /// class or fewer than `min_class_tokens` distinct tokens in a class, see
+    /// `BayesClassifier::classify_verdict`.
+    pub fn classify_text_verdict(
+        &self,
+        classifier: &BayesClassifier,
+        text: &str,
+        suffixes: &PublicSuffix,
+    ) -> Result<BayesVerdict, PipelineError> {
+        let score = self.classify_text(classifier, text, suffixes)?;
+        Ok(
+            if classifier.is_warming(self.ham_learns, self.spam_learns, self.learned_messages)
+                || self.lacks_diversity(classifier)
+            {
+                BayesVerdict::Warming
+            } else {
+                score.map_or(BayesVerdict::Undecided, BayesVerdict::Score)
+            },
+        )
+    }
+
+    /// Classifies pre-hashed tokens given as `(hash, idx)` pairs, where `idx`
+    /// is the position of the token within the OSB window. Token hashes are
+    /// 128 bits wide, so ids from other systems must be produced with the
+    /// same `hasher` as the model.
+    pub fn classify_hashes<H>(
+        &self,
+        classifier: &BayesClassifier,
+        ids: impl Iterator<Item = (H, usize)>,
+    ) -> Option<f64>
+    where
+        H: Into<TokenHash>,
+    {
+        self.classify_token_hashes(
+            classifier,
+            ids.map(|(hash, idx)| OsbToken {
+                inner: hash.into(),
+                idx,
+            }),
+        )
+    }
+
+    pub(crate) fn classify_token_hashes(
+        &self,
+        classifier: &BayesClassifier,
+        tokens: impl Iterator<Item = OsbToken<TokenHash>>,
+    ) -> Option<f64> {
+        self.classify_token_hashes_detailed(classifier, tokens)
+            .map(|result| result.score)
+    }
+
+    pub(crate) fn classify_token_hashes_detailed(
+        &self,
+        classifier: &BayesClassifier,
+        tokens: impl Iterator<Item = OsbToken<TokenHash>>,
+    ) -> Option<BayesResult> {
+        if self.lacks_diversity(classifier) {
+            return None;
+        }
+
+        classifier.classify_tokens(
+            DedupWindows::new(tokens, classifier.dedup_windows).map(|t| TokenInput {
+                weights: self.weights.get(&t.inner).copied().unwrap_or_default(),
+                idx: t.idx,
+                hash: Some(t.inner),
+                doc_freq: classifier
+                    .idf_weighting
+                    .then(|| self.doc_freq.get(&t.inner).copied().unwrap_or_default()),
+                spam_prob: None,
+            }),
+            self.ham_learns,
+            self.spam_learns,
+        )
+    }
+
+    /// Same as `classify_text`, but serves identical texts from the verdict
+    /// cache until the model is retrained or the parameters of the
+    /// classifier change, see `BayesVerdictCache::classifier_fingerprint`.
+    pub fn classify_text_cached(
+        &self,
+        classifier: &BayesClassifier,
+        cache: &BayesVerdictCache,
+        text: &str,
+        suffixes: &PublicSuffix,
+    ) -> Result<Option<f64>, PipelineError> {
+        let fingerprint = BayesVerdictCache::classifier_fingerprint(text, classifier);
+        if let Some(score) = cache.get(fingerprint, self.version) {
+            return Ok(score);
+        }
+
+        let score = self.classify_text(classifier, text, suffixes)?;
+        cache.insert(fingerprint, self.version, score);
+        Ok(score)
+    }
+}
+
+impl Display for PipelineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PipelineError::EmptyInput => write!(f, "Input does not contain any tokens"),
+        }
+    }
+}
+
+impl std::error::Error for PipelineError {}
+
+#[cfg(test)]
+mod test {
+    use utils::suffixlist::PublicSuffix;
+
+    use crate::{
+        bayes::{
+            cache::BayesVerdictCache, tokenize::BayesTokenizer, BayesClassifier, BayesModel,
+            BayesVerdict, TokenHash, OSB_WINDOW_SIZE,
+        },
+        tokenizers::osb::OsbTokenizer,
+    };
+
+    use super::PipelineError;
+
+    #[test]
+    fn empty_input() {
+        let suffixes = PublicSuffix::default();
+        let classifier = BayesClassifier::default();
+        let mut model = BayesModel::default();
+
+        for text in ["", "   \t\r\n  ", "... !!! ??? ,;:"] {
+            assert_eq!(
+                model.train_text(text, true, &suffixes),
+                Err(PipelineError::EmptyInput),
+                "{text:?}"
+            );
+            assert_eq!(
+                model.classify_text(&classifier, text, &suffixes),
+                Err(PipelineError::EmptyInput),
+                "{text:?}"
+            );
+        }
+        assert_eq!(model.spam_learns, 0);
+        assert!(model.weights.is_empty());
+
+        // Untrained model, inconclusive but not empty
+        assert_eq!(
+            model.classify_text(&classifier, "hello world", &suffixes),
+            Ok(None)
+        );
+        assert_eq!(model.train_text("hello world", true, &suffixes), Ok(()));
+        assert_eq!(model.spam_learns, 1);
+    }
+
+    #[test]
+    fn verdict_cache() {
+        let suffixes = PublicSuffix::default();
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 0,
+            min_token_hits: 1,
+            ..Default::default()
+        };
+        let cache = BayesVerdictCache::new(16);
+        let mut model = BayesModel::default();
+        let spam = "win a free lottery prize now claim your cash reward today";
+        let ham = "the quarterly meeting notes are attached please review them";
+        for _ in 0..3 {
+            model.train_text(spam, true, &suffixes).unwrap();
+            model.train_text(ham, false, &suffixes).unwrap();
+        }
+
+        let first = model
+            .classify_text_cached(&classifier, &cache, spam, &suffixes)
+            .unwrap();
+        assert!(first.is_some_and(|score| score > 0.5), "{first:?}");
+        let fingerprint = BayesVerdictCache::classifier_fingerprint(spam, &classifier);
+        assert_eq!(cache.get(fingerprint, model.version), Some(first));
+        assert_eq!(
+            model.classify_text_cached(&classifier, &cache, spam, &suffixes),
+            Ok(first)
+        );
+        assert_eq!(cache.len(), 1);
+
+        // Verdicts are not shared with classifiers using other parameters,
+        // including tokens denied after the verdict was cached
+        let strict = BayesClassifier {
+            decision_margin: 0.49999,
+            ..classifier.clone()
+        };
+        assert_ne!(
+            BayesVerdictCache::classifier_fingerprint(spam, &strict),
+            fingerprint
+        );
+        for token in
+            OsbTokenizer::<_, TokenHash>::new(BayesTokenizer::new(spam, &suffixes), OSB_WINDOW_SIZE)
+        {
+            strict.add_deny_token(token.inner);
+        }
+        assert_eq!(
+            model.classify_text_cached(&strict, &cache, spam, &suffixes),
+            Ok(None)
+        );
+        assert_eq!(
+            model.classify_text_cached(&classifier, &cache, spam, &suffixes),
+            Ok(first)
+        );
+        assert_eq!(cache.len(), 2);
+        classifier.add_deny_token(TokenHash { h1: 1, h2: 1 });
+        assert_eq!(cache.get(fingerprint, model.version), Some(first));
+        assert_eq!(
+            cache.get(
+                BayesVerdictCache::classifier_fingerprint(spam, &classifier),
+                model.version
+            ),
+            None
+        );
+
+        // Retraining invalidates the cached verdict
+        model.train_text(ham, false, &suffixes).unwrap();
+        assert_eq!(cache.get(fingerprint, model.version), None);
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn classify_hashes() {
+        let suffixes = PublicSuffix::default();
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 0,
+            min_token_hits: 1,
+            ..Default::default()
+        };
+        let mut model = BayesModel::default();
+        let spam = "win a free lottery prize now claim your cash reward today";
+        let ham = "the quarterly meeting notes are attached please review them";
+        model.train_text(spam, true, &suffixes).unwrap();
+        model.train_text(ham, false, &suffixes).unwrap();
+
+        let ids = OsbTokenizer::<_, TokenHash>::new(
+            BayesTokenizer::new(spam, &suffixes),
+            OSB_WINDOW_SIZE,
+        )
+        .map(|token| (u128::from(token.inner), token.idx))
+        .collect::<Vec<_>>();
+        let score = model.classify_hashes(&classifier, ids.into_iter());
+
+        assert!(score.is_some());
+        assert_eq!(
+            score,
+            model.classify_text(&classifier, spam, &suffixes).unwrap()
+        );
+    }
+
+    #[test]
+    fn classify_collect() {
+        let suffixes = PublicSuffix::default();
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 0,
+            min_token_hits: 1,
+            ..Default::default()
+        };
+        let mut model = BayesModel::default();
+        let spam = "win a free lottery prize now claim your cash reward today";
+        model.train_text(spam, true, &suffixes).unwrap();
+        model
+            .train_text("see you at the meeting tomorrow", false, &suffixes)
+            .unwrap();
+
+        let expected = OsbTokenizer::<_, TokenHash>::new(
+            BayesTokenizer::new(spam, &suffixes),
+            OSB_WINDOW_SIZE,
+        )
+        .collect::<Vec<_>>();
+        let mut collected = Vec::new();
+        assert_eq!(
+            model.classify_text_collect(&classifier, spam, &suffixes, &mut collected),
+            model.classify_text(&classifier, spam, &suffixes)
+        );
+        assert_eq!(collected, expected);
+
+        // Tokens are collected even when the model cannot classify yet
+        let mut collected = Vec::new();
+        assert_eq!(
+            BayesModel::default().classify_text_collect(
+                &BayesClassifier::default(),
+                spam,
+                &suffixes,
+                &mut collected
+            ),
+            Ok(None)
+        );
+        assert_eq!(collected, expected);
+    }
+
+    #[test]
+    fn warmup_period() {
+        let suffixes = PublicSuffix::default();
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 0,
+            min_token_hits: 1,
+            warmup_messages: 4,
+            ..Default::default()
+        };
+        let mut model = BayesModel::default();
+        let spam = "win a free lottery prize now claim your cash reward today";
+        let ham = "the quarterly report is attached please review before monday";
+
+        for (learned, text, is_spam) in [(1, spam, true), (2, ham, false), (3, spam, true)] {
+            model.train_text(text, is_spam, &suffixes).unwrap();
+            assert_eq!(model.learned_messages, learned);
+            assert_eq!(
+                model.classify_text_verdict(&classifier, spam, &suffixes),
+                Ok(BayesVerdict::Warming)
+            );
+        }
+
+        // The counter survives a snapshot round trip
+        model.train_text(ham, false, &suffixes).unwrap();
+        let model =
+            BayesModel::from_snapshot(&model.to_snapshot().unwrap(), model.hasher.clone()).unwrap();
+        assert_eq!(model.learned_messages, 4);
+        assert!(matches!(
+            model.classify_text_verdict(&classifier, spam, &suffixes),
+            Ok(BayesVerdict::Score(score)) if score > 0.5
+        ));
+    }
+}
@@ -0,0 +1,297 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{collections::HashMap, hash::BuildHasherDefault, sync::Arc};
+
+use nohash::NoHashHasher;
+use serde::{Deserialize, Serialize};
+use utils::suffixlist::PublicSuffix;
+
+use crate::tokenizers::osb::{OsbToken, OsbTokenizer};
+
+use super::{
+    classify::{log_odds, TokenInput},
+    hasher::{HashAlgorithm, TokenHasher},
+    pipeline::PipelineError,
+    snapshot::{
+        self, serialize_sorted, SnapshotError, QUANTIZED_SNAPSHOT_MAGIC, QUANTIZED_SNAPSHOT_VERSION,
+    },
+    tokenize::BayesTokenizer,
+    BayesClassifier, BayesModel, TokenHash, Weights,
+};
+
+/// Read-only model that stores the spam probability of each token quantized
+/// to 256 log-odds buckets, together with its number of hits saturated at
+/// 255, in place of the raw counts. Entries take 18 bytes instead of 24 in a
+/// snapshot.
+///
+/// Probabilities of tokens seen in a single class are kept exact, others are
+/// limited to [0.0064, 0.9936] and move by at most 0.02 in log-odds. Tokens
+/// seen more than 255 times barely change their weight, which is already
+/// close to 1. The per-token errors add up over the inverse chi-square, so
+/// scores typically stay within 0.01 of those of the full model and rarely
+/// drift by more than 0.05, which can flip verdicts close to the decision
+/// margin. Document frequencies are dropped, so `idf_weighting` is ignored,
+/// and quantized models cannot be trained further.
+// Width of the log-odds buckets around 0, which span ±5.04
+const LOG_ODDS_STEP: f64 = 0.04;
+
+// The serialized fields are the layout of `QUANTIZED_SNAPSHOT_VERSION`
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct QuantizedModel {
+    #[serde(serialize_with = "serialize_sorted")]
+    pub weights: HashMap<TokenHash, QuantizedWeights, BuildHasherDefault<NoHashHasher<TokenHash>>>,
+    pub spam_learns: u32,
+    pub ham_learns: u32,
+    pub hasher: HashAlgorithm,
+    #[serde(skip)]
+    pub token_hasher: Option<Arc<dyn TokenHasher>>,
+    pub window_size: usize,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuantizedWeights {
+    pub spam_prob: u8,
+    pub hits: u8,
+}
+
+impl BayesModel {
+    pub fn quantize(&self) -> QuantizedModel {
+        QuantizedModel {
+            weights: self
+                .weights
+                .iter()
+                .filter(|(_, weights)| weights.spam.saturating_add(weights.ham) > 0)
+                .map(|(hash, weights)| {
+                    (
+                        *hash,
+                        QuantizedWeights::new(*weights, self.ham_learns, self.spam_learns),
+                    )
+                })
+                .collect(),
+            spam_learns: self.spam_learns,
+            ham_learns: self.ham_learns,
+            hasher: self.hasher.clone(),
+            token_hasher: self.token_hasher.clone(),
+            window_size: self.window_size,
+        }
+    }
+
+    /// Exports the model as a quantized snapshot, see `QuantizedModel`.
+    pub fn to_quantized_snapshot(&self) -> Result<Vec<u8>, SnapshotError> {
+        self.quantize().to_snapshot()
+    }
+}
+
+impl QuantizedModel {
+    pub fn to_snapshot(&self) -> Result<Vec<u8>, SnapshotError> {
+        snapshot::encode(QUANTIZED_SNAPSHOT_MAGIC, QUANTIZED_SNAPSHOT_VERSION, self)
+    }
+
+    pub fn from_snapshot(bytes: &[u8], hasher: HashAlgorithm) -> Result<Self, SnapshotError> {
+        Self::from_snapshot_with_hasher(
+            bytes,
+            hasher
+                .builtin()
+                .ok_or(SnapshotError::UnresolvedHasher(hasher))?,
+        )
+    }
+
+    pub fn from_snapshot_with_hasher(
+        bytes: &[u8],
+        hasher: Arc<dyn TokenHasher>,
+    ) -> Result<Self, SnapshotError> {
+        // Quantized snapshots written before the header have the same layout
+        let (_, payload) =
+            snapshot::decode_header(bytes, QUANTIZED_SNAPSHOT_MAGIC, QUANTIZED_SNAPSHOT_VERSION)?;
+        let mut model: QuantizedModel = snapshot::deserialize(payload)?;
+        let expected = hasher.algorithm();
+        if model.hasher == expected {
+            model.token_hasher = Some(hasher);
+            Ok(model)
+        } else {
+            Err(SnapshotError::HasherMismatch {
+                expected,
+                found: model.hasher,
+            })
+        }
+    }
+
+    pub fn classify_text(
+        &self,
+        classifier: &BayesClassifier,
+        text: &str,
+        suffixes: &PublicSuffix,
+    ) -> Result<Option<f64>, PipelineError> {
+        let mut tokens = OsbTokenizer::with_hasher(
+            BayesTokenizer::new(text, suffixes),
+            self.window_size,
+            self.token_hasher(),
+        )
+        .peekable();
+        if tokens.peek().is_none() {
+            return Err(PipelineError::EmptyInput);
+        }
+
+        Ok(self.classify_token_hashes(classifier, tokens))
+    }
+
+    pub fn classify_token_hashes(
+        &self,
+        classifier: &BayesClassifier,
+        tokens: impl Iterator<Item = OsbToken<TokenHash>>,
+    ) -> Option<f64> {
+        classifier
+            .classify_tokens(
+                tokens.map(|t| {
+                    let weights = self.weights.get(&t.inner).copied().unwrap_or_default();
+                    TokenInput {
+                        // Only the total number of hits is used along a known probability
+                        weights: Weights {
+                            spam: weights.hits as u32,
+                            ham: 0,
+                        },
+                        idx: t.idx,
+                        hash: Some(t.inner),
+                        doc_freq: None,
+                        spam_prob: (weights.hits > 0).then(|| weights.spam_prob()),
+                    }
+                }),
+                self.ham_learns,
+                self.spam_learns,
+            )
+            .map(|result| result.score)
+    }
+}
+
+impl QuantizedWeights {
+    pub fn new(weights: Weights, ham_learns: u32, spam_learns: u32) -> Self {
+        let spam_freq = weights.spam as f64 / f64::max(1.0, spam_learns as f64);
+        let ham_freq = weights.ham as f64 / f64::max(1.0, ham_learns as f64);
+        let spam_prob = if spam_freq + ham_freq > 0.0 {
+            spam_freq / (spam_freq + ham_freq)
+        } else {
+            0.5
+        };
+
+        QuantizedWeights {
+            spam_prob: if spam_prob <= 0.0 {
+                0
+            } else if spam_prob >= 1.0 {
+                u8::MAX
+            } else {
+                (log_odds(spam_prob) / LOG_ODDS_STEP + 128.0)
+                    .round()
+                    .clamp(1.0, 254.0) as u8
+            },
+            hits: weights.spam.saturating_add(weights.ham).min(u8::MAX as u32) as u8,
+        }
+    }
+
+    pub fn spam_prob(&self) -> f64 {
+        match self.spam_prob {
+            0 => 0.0,
+            u8::MAX => 1.0,
+            bucket => 1.0 / (1.0 + (-(bucket as f64 - 128.0) * LOG_ODDS_STEP).exp()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::bayes::{
+        classify::log_odds, hasher::HashAlgorithm, synthetic::SyntheticCorpus, BayesClassifier,
+        BayesModel, TokenHash, Weights,
+    };
+
+    use super::{QuantizedModel, QuantizedWeights};
+
+    #[test]
+    fn quantized_weights() {
+        let weights = |spam, ham| QuantizedWeights::new(Weights { spam, ham }, 100, 100);
+        assert_eq!(weights(10, 0).spam_prob(), 1.0);
+        assert_eq!(weights(0, 10).spam_prob(), 0.0);
+        assert!((log_odds(weights(3, 1).spam_prob()) - 3f64.ln()).abs() <= 0.02);
+        assert_eq!(weights(1, 1000).spam_prob, 1);
+        assert_eq!(weights(1000, 1000).hits, u8::MAX);
+
+        // Saturated counts
+        let saturated = weights(u32::MAX, u32::MAX);
+        assert_eq!(saturated.hits, u8::MAX);
+        let mut model = BayesModel {
+            spam_learns: u32::MAX,
+            ham_learns: u32::MAX,
+            ..Default::default()
+        };
+        model.weights.insert(
+            TokenHash { h1: 1, h2: 1 },
+            Weights {
+                spam: u32::MAX,
+                ham: u32::MAX,
+            },
+        );
+        let quantized = model.quantize();
+        assert_eq!(quantized.weights[&TokenHash { h1: 1, h2: 1 }].hits, u8::MAX);
+        assert!((quantized.weights[&TokenHash { h1: 1, h2: 1 }].spam_prob() - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn quantized_verdicts() {
+        let mut corpus = SyntheticCorpus::new(11);
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            ..Default::default()
+        };
+        let mut model = BayesModel::default();
+        for message in corpus.messages(300) {
+            model.train(message.tokens(), message.is_spam);
+        }
+        model
+            .weights
+            .insert(TokenHash { h1: 1, h2: 1 }, Weights::default());
+
+        let snapshot = model.to_quantized_snapshot().unwrap();
+        assert!(snapshot.len() < model.to_snapshot().unwrap().len());
+        let quantized = QuantizedModel::from_snapshot(&snapshot, model.hasher.clone()).unwrap();
+        assert_eq!(quantized.weights.len(), model.weights.len() - 1);
+        assert!(QuantizedModel::from_snapshot(&snapshot, HashAlgorithm::Xxh3_128).is_err());
+
+        let mut agree = 0;
+        let test = corpus.messages(100);
+        for message in &test {
+            let full = model.classify_token_hashes(&classifier, message.tokens().into_iter());
+            let quantized =
+                quantized.classify_token_hashes(&classifier, message.tokens().into_iter());
+            match (full, quantized) {
+                (Some(full), Some(quantized)) => {
+                    assert!((full - quantized).abs() < 0.05, "{full} vs {quantized}");
+                    agree += usize::from((full > 0.5) == (quantized > 0.5));
+                }
+                (None, None) => agree += 1,
+                // Scores near the decision margin may fall on either side
+                _ => (),
+            }
+        }
+        assert!(agree as f64 / test.len() as f64 > 0.98, "{agree}");
+    }
+}
@@ -0,0 +1,288 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{convert::Infallible, future::Future, sync::Arc, time::Duration};
+
+use parking_lot::Mutex;
+use tokio::{
+    sync::{mpsc, Semaphore},
+    time::{interval, MissedTickBehavior},
+};
+
+use crate::tokenizers::osb::OsbToken;
+
+use super::{BayesModel, TokenHash};
+
+/// Message waiting in a `TrainQueue` to be learned.
+#[derive(Debug, Clone)]
+pub struct TrainSample {
+    pub tokens: Vec<OsbToken<TokenHash>>,
+    pub is_spam: bool,
+}
+
+/// Destination of the batches flushed by a `TrainFlusher`.
+pub trait TrainSink: Sync {
+    type Error;
+
+    fn train_batch(
+        &self,
+        batch: Vec<TrainSample>,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send;
+}
+
+impl TrainSink for Mutex<BayesModel> {
+    type Error = Infallible;
+
+    async fn train_batch(&self, batch: Vec<TrainSample>) -> Result<(), Self::Error> {
+        let mut model = self.lock();
+        for sample in batch {
+            model.train(sample.tokens, sample.is_spam);
+        }
+        Ok(())
+    }
+}
+
+/// Bounded queue of messages to train. A message holds its slot until the
+/// batch it belongs to has been flushed, so `enqueue` waits while `capacity`
+/// messages are pending instead of growing the queue when the store falls
+/// behind.
+#[derive(Debug, Clone)]
+pub struct TrainQueue {
+    sender: mpsc::UnboundedSender<TrainSample>,
+    slots: Arc<Semaphore>,
+    capacity: usize,
+}
+
+// Shortest interval between flushes, as a timer cannot fire continuously
+const MIN_FLUSH_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Receiving end of a `TrainQueue`, flushing a batch once it holds
+/// `batch_size` messages or every `flush_interval`, whichever comes first.
+#[derive(Debug)]
+pub struct TrainFlusher {
+    receiver: mpsc::UnboundedReceiver<TrainSample>,
+    slots: Arc<Semaphore>,
+    batch_size: usize,
+    flush_interval: Duration,
+}
+
+impl TrainQueue {
+    /// Creates a queue holding up to `capacity` messages, at least one, and
+    /// flushing at least every millisecond.
+    pub fn new(
+        capacity: usize,
+        batch_size: usize,
+        flush_interval: Duration,
+    ) -> (TrainQueue, TrainFlusher) {
+        let capacity = capacity.max(1);
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let slots = Arc::new(Semaphore::new(capacity));
+        (
+            TrainQueue {
+                sender,
+                slots: slots.clone(),
+                capacity,
+            },
+            TrainFlusher {
+                receiver,
+                slots,
+                batch_size: batch_size.clamp(1, capacity),
+                flush_interval: flush_interval.max(MIN_FLUSH_INTERVAL),
+            },
+        )
+    }
+
+    /// Adds a message to the queue, waiting for a slot when it is full.
+    /// Returns the message back if the flusher is gone.
+    pub async fn enqueue(&self, sample: TrainSample) -> Result<(), TrainSample> {
+        match self.slots.acquire().await {
+            Ok(permit) => {
+                permit.forget();
+                self.sender.send(sample).map_err(|err| {
+                    self.slots.add_permits(1);
+                    err.0
+                })
+            }
+            Err(_) => Err(sample),
+        }
+    }
+
+    /// Number of messages enqueued that have not been flushed yet.
+    pub fn depth(&self) -> usize {
+        self.capacity.saturating_sub(self.slots.available_permits())
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+impl TrainFlusher {
+    /// Flushes batches to `sink` until every `TrainQueue` handle is dropped,
+    /// flushing any remaining messages before returning. Fails on the first
+    /// batch that the sink rejects, whose messages are discarded.
+    pub async fn run<S: TrainSink>(mut self, sink: &S) -> Result<(), S::Error> {
+        let mut batch = Vec::with_capacity(self.batch_size);
+        let mut ticker = interval(self.flush_interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        // The first tick completes immediately
+        ticker.tick().await;
+
+        loop {
+            tokio::select! {
+                sample = self.receiver.recv() => match sample {
+                    Some(sample) => {
+                        batch.push(sample);
+                        if batch.len() >= self.batch_size {
+                            self.flush(sink, &mut batch).await?;
+                        }
+                    }
+                    None => return self.flush(sink, &mut batch).await,
+                },
+                _ = ticker.tick() => self.flush(sink, &mut batch).await?,
+            }
+        }
+    }
+
+    async fn flush<S: TrainSink>(
+        &self,
+        sink: &S,
+        batch: &mut Vec<TrainSample>,
+    ) -> Result<(), S::Error> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        let len = batch.len();
+        let result = sink.train_batch(std::mem::take(batch)).await;
+        self.slots.add_permits(len);
+        result
+    }
+}
+
+impl Drop for TrainFlusher {
+    fn drop(&mut self) {
+        // Wake up producers waiting for a slot that would never be released
+        self.slots.close();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use parking_lot::Mutex;
+    use tokio::time::{sleep, timeout};
+
+    use crate::{
+        bayes::{BayesModel, TokenHash},
+        tokenizers::osb::OsbToken,
+    };
+
+    use super::{TrainQueue, TrainSample};
+
+    fn sample(h1: u64) -> TrainSample {
+        TrainSample {
+            tokens: vec![OsbToken {
+                inner: TokenHash { h1, h2: 0 },
+                idx: 0,
+            }],
+            is_spam: h1.is_multiple_of(2),
+        }
+    }
+
+    #[tokio::test]
+    async fn train_queue_backpressure() {
+        let model = Mutex::new(BayesModel::default());
+        let (queue, flusher) = TrainQueue::new(4, 2, Duration::from_secs(3600));
+
+        for h1 in 0..4 {
+            queue.enqueue(sample(h1)).await.unwrap();
+        }
+        assert_eq!(queue.depth(), 4);
+
+        // Full until the flusher runs
+        assert!(timeout(Duration::from_millis(50), queue.enqueue(sample(4)))
+            .await
+            .is_err());
+        assert_eq!(queue.depth(), 4);
+
+        let producer = async move {
+            let mut max_depth = 0;
+            for h1 in 0..32 {
+                queue.enqueue(sample(h1)).await.unwrap();
+                max_depth = max_depth.max(queue.depth());
+                assert!(queue.depth() <= queue.capacity());
+            }
+            max_depth
+        };
+        let (max_depth, result) = tokio::join!(producer, flusher.run(&model));
+        assert!(result.is_ok());
+        assert!(max_depth <= 4);
+
+        let model = model.lock();
+        assert_eq!(model.spam_learns + model.ham_learns, 36);
+    }
+
+    #[tokio::test]
+    async fn train_queue_interval_flush() {
+        let model = Mutex::new(BayesModel::default());
+        let (queue, flusher) = TrainQueue::new(100, 100, Duration::from_millis(20));
+
+        let producer = async {
+            queue.enqueue(sample(1)).await.unwrap();
+            sleep(Duration::from_millis(200)).await;
+            assert_eq!(model.lock().ham_learns, 1);
+            assert_eq!(queue.depth(), 0);
+            drop(queue);
+        };
+        let (_, result) = tokio::join!(producer, flusher.run(&model));
+        assert!(result.is_ok());
+
+        // Producers fail once the flusher is gone
+        let (queue, flusher) = TrainQueue::new(1, 1, Duration::from_secs(1));
+        drop(flusher);
+        assert!(queue.enqueue(sample(2)).await.is_err());
+        assert_eq!(queue.depth(), 0);
+    }
+
+    #[tokio::test]
+    async fn train_queue_zero_limits() {
+        let model = Mutex::new(BayesModel::default());
+        let (queue, flusher) = TrainQueue::new(0, 0, Duration::ZERO);
+        assert_eq!(queue.capacity(), 1);
+
+        let producer = async move {
+            for h1 in 0..3 {
+                timeout(Duration::from_secs(5), queue.enqueue(sample(h1)))
+                    .await
+                    .unwrap()
+                    .unwrap();
+            }
+        };
+        let (_, result) = tokio::join!(producer, flusher.run(&model));
+        assert!(result.is_ok());
+        let model = model.lock();
+        assert_eq!(model.spam_learns + model.ham_learns, 3);
+    }
+}
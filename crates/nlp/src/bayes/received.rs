@@ -0,0 +1,170 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{borrow::Cow, net::IpAddr};
+
+use utils::suffixlist::{DomainPart, PublicSuffix};
+
+/// Emits relay tokens from a chain of raw `Received` headers (topmost first):
+/// `relay:<domain>` (or `relay:<ip>`) and optionally `relay_country:<code>` for each hop,
+/// followed by `hops:<n>` for the length of the chain.
+pub struct ReceivedTokenizer<'x> {
+    tokens: std::vec::IntoIter<Cow<'x, str>>,
+}
+
+impl<'x> ReceivedTokenizer<'x> {
+    pub fn new(headers: &[&str], suffixes: &PublicSuffix) -> Self {
+        Self::build(headers, suffixes, None)
+    }
+
+    /// Same as `new`, also emitting the country returned by `resolve` for
+    /// the IP address of each relay.
+    pub fn with_country(
+        headers: &[&str],
+        suffixes: &PublicSuffix,
+        resolve: impl Fn(IpAddr) -> Option<String>,
+    ) -> Self {
+        Self::build(headers, suffixes, Some(&resolve))
+    }
+
+    fn build(
+        headers: &[&str],
+        suffixes: &PublicSuffix,
+        resolve: Option<&dyn Fn(IpAddr) -> Option<String>>,
+    ) -> Self {
+        let mut tokens = Vec::with_capacity(headers.len() * 2 + 1);
+
+        for header in headers {
+            let relay = parse_relay(header);
+            if let Some(host) = relay.host {
+                tokens.push(Cow::from(format!(
+                    "relay:{}",
+                    suffixes.domain_part(&host, DomainPart::Sld).unwrap_or(host)
+                )));
+            } else if let Some(ip) = relay.ip {
+                tokens.push(Cow::from(format!("relay:{ip}")));
+            }
+            if let Some(country) = relay.ip.zip(resolve).and_then(|(ip, resolve)| resolve(ip)) {
+                tokens.push(Cow::from(format!(
+                    "relay_country:{}",
+                    country.to_lowercase()
+                )));
+            }
+        }
+        tokens.push(Cow::from(format!("hops:{}", headers.len())));
+
+        Self {
+            tokens: tokens.into_iter(),
+        }
+    }
+}
+
+impl<'x> Iterator for ReceivedTokenizer<'x> {
+    type Item = Cow<'x, str>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.tokens.next()
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Relay {
+    host: Option<String>,
+    ip: Option<IpAddr>,
+}
+
+fn parse_relay(header: &str) -> Relay {
+    let header = header
+        .trim_start()
+        .strip_prefix("Received:")
+        .unwrap_or(header);
+    let mut relay = Relay::default();
+    let mut words = header.split_whitespace();
+
+    // Only the "from" clause describes the relay that handed over the message
+    while let Some(word) = words.next() {
+        if word.eq_ignore_ascii_case("from") {
+            for word in words.by_ref() {
+                if word.eq_ignore_ascii_case("by") || word.ends_with(';') {
+                    break;
+                } else if let Some(ip) = parse_ip(word) {
+                    relay.ip.get_or_insert(ip);
+                } else if relay.host.is_none() {
+                    let host = word.trim_matches(|c: char| c == '(' || c == ')');
+                    if host.contains('.')
+                        && host
+                            .chars()
+                            .all(|c| c.is_alphanumeric() || c == '.' || c == '-')
+                    {
+                        relay.host = Some(host.to_lowercase());
+                    }
+                }
+            }
+            break;
+        }
+    }
+
+    relay
+}
+
+fn parse_ip(word: &str) -> Option<IpAddr> {
+    let word = word.trim_matches(|c: char| c == '(' || c == ')');
+    let word = word.strip_prefix('[')?.strip_suffix(']')?;
+    word.strip_prefix("IPv6:").unwrap_or(word).parse().ok()
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::IpAddr;
+
+    use utils::suffixlist::PublicSuffix;
+
+    use super::ReceivedTokenizer;
+
+    #[test]
+    fn received_chain() {
+        let suffixes = PublicSuffix::from("com\norg\nuk\nco.uk");
+        let headers = [
+            "from mail.relay.co.uk (mail.relay.co.uk [203.0.113.7]) by mx.example.org (Postfix) with ESMTPS id 4F1; Mon, 1 Jan 2024 10:00:02 +0000",
+            "Received: from unknown (HELO client) ([198.51.100.23])\r\n\tby mail.relay.co.uk with SMTP; Mon, 1 Jan 2024 10:00:01 +0000",
+            "by localhost with LMTP; Mon, 1 Jan 2024 10:00:00 +0000",
+        ];
+
+        assert_eq!(
+            ReceivedTokenizer::new(&headers, &suffixes).collect::<Vec<_>>(),
+            ["relay:relay.co.uk", "relay:198.51.100.23", "hops:3"]
+        );
+        assert_eq!(
+            ReceivedTokenizer::with_country(&headers, &suffixes, |ip: IpAddr| {
+                (ip.to_string() == "203.0.113.7").then(|| "GB".to_string())
+            })
+            .collect::<Vec<_>>(),
+            [
+                "relay:relay.co.uk",
+                "relay_country:gb",
+                "relay:198.51.100.23",
+                "hops:3"
+            ]
+        );
+    }
+}
@@ -0,0 +1,155 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use serde::{Deserialize, Serialize};
+
+use super::{explain::BayesExplanation, BayesResult};
+
+/// Canonical record of a classification, e.g. for audit logs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClassificationRecord {
+    // Seconds since the Unix epoch
+    pub timestamp: u64,
+    pub model_version: u64,
+    pub probability: Option<f64>,
+    pub processed_tokens: u32,
+    pub verdict: RecordVerdict,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub top_tokens: Vec<RecordToken>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordVerdict {
+    Spam,
+    Ham,
+    Undecided,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordToken {
+    // Token hash as 32 hex digits, `h1` first
+    pub hash: String,
+    pub spam_prob: f64,
+}
+
+impl ClassificationRecord {
+    pub fn from_result(result: Option<&BayesResult>, model_version: u64, timestamp: u64) -> Self {
+        ClassificationRecord {
+            timestamp,
+            model_version,
+            probability: result.map(|result| result.score),
+            processed_tokens: result.map_or(0, |result| result.processed_tokens),
+            verdict: match result {
+                Some(result) if result.score > 0.5 => RecordVerdict::Spam,
+                Some(_) => RecordVerdict::Ham,
+                None => RecordVerdict::Undecided,
+            },
+            top_tokens: Vec::new(),
+        }
+    }
+
+    /// Same as `from_result`, including the top tokens of the explanation.
+    pub fn from_explanation(
+        explanation: &BayesExplanation,
+        model_version: u64,
+        timestamp: u64,
+    ) -> Self {
+        ClassificationRecord {
+            top_tokens: explanation
+                .tokens
+                .iter()
+                .map(|token| RecordToken {
+                    hash: format!("{:032x}", u128::from(token.hash)),
+                    spam_prob: token.spam_prob,
+                })
+                .collect(),
+            ..Self::from_result(explanation.result.as_ref(), model_version, timestamp)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        bayes::{BayesClassifier, TokenHash, Weights},
+        tokenizers::osb::OsbToken,
+    };
+
+    use super::{ClassificationRecord, RecordVerdict};
+
+    #[test]
+    fn classification_record() {
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 1,
+            ..Default::default()
+        };
+        let tokens = (1..=3).map(|h1| {
+            (
+                TokenHash { h1, h2: 0xff },
+                OsbToken {
+                    inner: Weights {
+                        spam: 20,
+                        ham: h1 as u32,
+                    },
+                    idx: 0,
+                },
+            )
+        });
+        let explanation = classifier.explain(tokens, 100, 100, 2);
+        let record = ClassificationRecord::from_explanation(&explanation, 42, 1700000000);
+        assert_eq!(record.verdict, RecordVerdict::Spam);
+        assert_eq!(record.processed_tokens, 3);
+
+        let json = serde_json::to_value(&record).unwrap();
+        for field in [
+            "timestamp",
+            "model_version",
+            "probability",
+            "processed_tokens",
+            "verdict",
+            "top_tokens",
+        ] {
+            assert!(json.get(field).is_some(), "missing {field} in {json}");
+        }
+        assert_eq!(json["timestamp"], 1700000000);
+        assert_eq!(json["model_version"], 42);
+        assert_eq!(json["verdict"], "spam");
+        assert_eq!(json["top_tokens"].as_array().unwrap().len(), 2);
+        assert_eq!(
+            json["top_tokens"][0]["hash"],
+            "000000000000000100000000000000ff"
+        );
+        assert_eq!(
+            serde_json::from_value::<ClassificationRecord>(json).unwrap(),
+            record
+        );
+
+        // Classifications without a verdict
+        let json = serde_json::to_value(ClassificationRecord::from_result(None, 42, 0)).unwrap();
+        assert_eq!(json["verdict"], "undecided");
+        assert!(json["probability"].is_null());
+        assert!(json.get("top_tokens").is_none());
+    }
+}
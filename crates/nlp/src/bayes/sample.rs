@@ -0,0 +1,258 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use crate::tokenizers::osb::OsbToken;
+
+use super::{splitmix64, BayesClassifier, BayesModel, TokenHash};
+
+/// Uniform sample of up to `max_tokens` tokens of a stream of any length,
+/// drawn by reservoir sampling so that every token is equally likely to be
+/// kept regardless of its position. The sample only depends on the seed and
+/// the stream, making sampled classifications reproducible.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenSampler {
+    pub max_tokens: usize,
+    pub seed: u64,
+}
+
+impl TokenSampler {
+    pub fn new(max_tokens: usize, seed: u64) -> Self {
+        TokenSampler { max_tokens, seed }
+    }
+
+    /// Returns the sampled items. Streams with up to `max_tokens` items are
+    /// returned whole and in order; otherwise the order is unspecified.
+    pub fn sample<T>(&self, items: impl IntoIterator<Item = T>) -> Vec<T> {
+        let mut reservoir = Vec::with_capacity(self.max_tokens.min(1024));
+        if self.max_tokens == 0 {
+            return reservoir;
+        }
+
+        let mut state = self.seed;
+        for (seen, item) in items.into_iter().enumerate() {
+            if seen < self.max_tokens {
+                reservoir.push(item);
+            } else {
+                let idx = (splitmix64(&mut state) % (seen as u64 + 1)) as usize;
+                if idx < self.max_tokens {
+                    reservoir[idx] = item;
+                }
+            }
+        }
+        reservoir
+    }
+}
+
+impl BayesModel {
+    /// Same as `classify_token_hashes`, classifying only a uniform sample of
+    /// the tokens to bound the cost of huge messages. Sampling reduces the
+    /// evidence available, so scores of long messages are less extreme than
+    /// those of the full message and `min_tokens` applies to the sample.
+    pub fn classify_sampled(
+        &self,
+        classifier: &BayesClassifier,
+        tokens: impl Iterator<Item = OsbToken<TokenHash>>,
+        sampler: &TokenSampler,
+    ) -> Option<f64> {
+        self.classify_token_hashes(classifier, sampler.sample(tokens).into_iter())
+    }
+}
+
+/// Settings of a bootstrap estimate of the uncertainty of a score, see
+/// `classify_bootstrap`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bootstrap {
+    // Number of resamples of the tokens
+    pub resamples: usize,
+    // Share of the resampled scores within the interval, e.g. 0.9
+    pub level: f64,
+    pub seed: u64,
+}
+
+/// Mean of the resampled scores of a message and the interval holding the
+/// central `level` share of them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BootstrapEstimate {
+    pub mean: f64,
+    pub low: f64,
+    pub high: f64,
+    // Resamples that reached a verdict
+    pub resamples: usize,
+}
+
+impl Bootstrap {
+    pub fn new(resamples: usize, level: f64, seed: u64) -> Self {
+        Bootstrap {
+            resamples,
+            level,
+            seed,
+        }
+    }
+}
+
+impl BootstrapEstimate {
+    pub fn width(&self) -> f64 {
+        self.high - self.low
+    }
+}
+
+impl BayesModel {
+    /// Estimates the uncertainty of the score of a message by classifying
+    /// `resamples` samples of its tokens, each drawn with replacement and as
+    /// long as the message. A score resting on a few strong tokens swings
+    /// with the tokens drawn, while one backed by many consistent tokens
+    /// barely moves. Returns None when no resample reaches a verdict.
+    pub fn classify_bootstrap(
+        &self,
+        classifier: &BayesClassifier,
+        tokens: &[OsbToken<TokenHash>],
+        bootstrap: &Bootstrap,
+    ) -> Option<BootstrapEstimate> {
+        let mut state = bootstrap.seed;
+        let mut scores = (0..bootstrap.resamples)
+            .filter_map(|_| {
+                let resample = (0..tokens.len()).map(|_| {
+                    tokens[(splitmix64(&mut state) % tokens.len() as u64) as usize].clone()
+                });
+                self.classify_token_hashes(classifier, resample)
+            })
+            .collect::<Vec<_>>();
+        if scores.is_empty() {
+            return None;
+        }
+
+        scores.sort_unstable_by(f64::total_cmp);
+        let quantile =
+            |q: f64| scores[((q.clamp(0.0, 1.0) * (scores.len() - 1) as f64).round()) as usize];
+        let tail = (1.0 - bootstrap.level) / 2.0;
+        Some(BootstrapEstimate {
+            mean: scores.iter().sum::<f64>() / scores.len() as f64,
+            low: quantile(tail),
+            high: quantile(1.0 - tail),
+            resamples: scores.len(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::borrow::Cow;
+
+    use crate::{
+        bayes::{
+            synthetic::SyntheticCorpus, BayesClassifier, BayesModel, TokenHash, OSB_WINDOW_SIZE,
+        },
+        tokenizers::osb::{OsbToken, OsbTokenizer},
+    };
+
+    use super::{Bootstrap, TokenSampler};
+
+    #[test]
+    fn reservoir_sampling() {
+        let sampler = TokenSampler::new(100, 7);
+        assert_eq!(sampler.sample(0..50), (0..50).collect::<Vec<_>>());
+        assert_eq!(sampler.sample(0..10_000).len(), 100);
+        assert_eq!(sampler.sample(0..10_000), sampler.sample(0..10_000));
+        assert_ne!(
+            sampler.sample(0..10_000),
+            TokenSampler::new(100, 8).sample(0..10_000)
+        );
+        assert!(TokenSampler::new(0, 7).sample(0..10).is_empty());
+
+        // No position bias
+        let mean = |seed| {
+            let sample = TokenSampler::new(1000, seed).sample(0..100_000);
+            sample.iter().sum::<usize>() as f64 / sample.len() as f64
+        };
+        for seed in 0..4 {
+            assert!((mean(seed) - 50_000.0).abs() < 3_000.0, "{}", mean(seed));
+        }
+
+        let mut corpus = SyntheticCorpus::new(3);
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            decision_margin: 0.0,
+            ..Default::default()
+        };
+        let mut model = BayesModel::default();
+        for message in corpus.messages(100) {
+            model.train(message.tokens(), message.is_spam);
+        }
+
+        // Long messages classify the same from a tenth of their tokens
+        let mut corpus = corpus.words_per_message(5000);
+        let sampler = TokenSampler::new(1000, 1);
+        for message in corpus.messages(3) {
+            let tokens = message.tokens();
+            assert!(tokens.len() > 10 * sampler.max_tokens);
+            let full = model
+                .classify_token_hashes(&classifier, tokens.iter().cloned())
+                .unwrap();
+            let sampled = model
+                .classify_sampled(&classifier, tokens.iter().cloned(), &sampler)
+                .unwrap();
+            assert!((full - sampled).abs() < 0.05, "{full} {sampled}");
+        }
+    }
+
+    #[test]
+    fn bootstrap_interval() {
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 1,
+            decision_margin: 0.0,
+            ..Default::default()
+        };
+        let words = |prefix: &str| (0..30).map(|i| format!("{prefix}{i}")).collect::<Vec<_>>();
+        let tokens = |words: &[String]| -> Vec<OsbToken<TokenHash>> {
+            OsbTokenizer::new(words.iter().map(|w| Cow::from(w.as_str())), OSB_WINDOW_SIZE)
+                .collect()
+        };
+        let (spam, ham) = (words("spam"), words("ham"));
+        let mut model = BayesModel::default();
+        for _ in 0..5 {
+            model.train(tokens(&spam), true);
+            model.train(tokens(&ham), false);
+        }
+
+        let bootstrap = Bootstrap::new(200, 0.9, 11);
+        let many = model
+            .classify_bootstrap(&classifier, &tokens(&spam), &bootstrap)
+            .unwrap();
+        let few = model
+            .classify_bootstrap(
+                &classifier,
+                &tokens(&[spam[0].clone(), ham[0].clone(), spam[1].clone()]),
+                &bootstrap,
+            )
+            .unwrap();
+
+        assert!(many.mean > 0.9 && many.low <= many.mean && many.mean <= many.high);
+        assert!(few.width() > many.width() + 0.5, "{few:?} {many:?}");
+        assert_eq!(
+            model.classify_bootstrap(&classifier, &tokens(&spam), &bootstrap),
+            Some(many)
+        );
+        assert_eq!(model.classify_bootstrap(&classifier, &[], &bootstrap), None);
+    }
+}
@@ -0,0 +1,97 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+pub const BAYES_RULE: &str = "BAYES";
+
+/// Blends the Bayes probability with heuristic rule hits. The probability
+/// is mapped linearly to `[-bayes_points, bayes_points]`, where 0.5 scores zero.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CombinedScorer {
+    pub bayes_points: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CombinedScore {
+    pub score: f64,
+    pub breakdown: Vec<(String, f64)>,
+}
+
+impl CombinedScorer {
+    pub fn new(bayes_points: f64) -> Self {
+        Self { bayes_points }
+    }
+
+    pub fn bayes_contribution(&self, probability: f64) -> f64 {
+        (probability.clamp(0.0, 1.0) - 0.5) * 2.0 * self.bayes_points
+    }
+
+    pub fn score<'x>(
+        &self,
+        bayes: Option<f64>,
+        rules: impl IntoIterator<Item = (&'x str, f64)>,
+    ) -> CombinedScore {
+        let mut result = CombinedScore::default();
+
+        if let Some(probability) = bayes {
+            let points = self.bayes_contribution(probability);
+            result.score += points;
+            result.breakdown.push((BAYES_RULE.to_string(), points));
+        }
+        for (name, points) in rules {
+            result.score += points;
+            result.breakdown.push((name.to_string(), points));
+        }
+
+        result
+    }
+}
+
+impl Default for CombinedScorer {
+    fn default() -> Self {
+        Self::new(5.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CombinedScorer, BAYES_RULE};
+
+    #[test]
+    fn combined_score() {
+        let scorer = CombinedScorer::new(4.0);
+        let result = scorer.score(Some(0.75), [("SPF_FAIL", 1.5), ("DKIM_VALID", -0.5)]);
+
+        assert_eq!(result.score, 3.0);
+        assert_eq!(
+            result.breakdown,
+            [
+                (BAYES_RULE.to_string(), 2.0),
+                ("SPF_FAIL".to_string(), 1.5),
+                ("DKIM_VALID".to_string(), -0.5)
+            ]
+        );
+
+        // Inconclusive Bayes verdicts only contribute the rules
+        assert_eq!(scorer.score(None, [("SPF_FAIL", 1.5)]).score, 1.5);
+    }
+}
@@ -0,0 +1,197 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::collections::VecDeque;
+
+use crate::tokenizers::osb::OsbToken;
+
+use super::{BayesClassifier, BayesModel, TokenHash};
+
+/// Runs a candidate model alongside the production one. Verdicts always come
+/// from the primary model, while the classifications where the candidate
+/// disagrees are kept, up to `capacity` of the most recent ones, for review
+/// before the candidate is rolled out.
+#[derive(Debug, Clone)]
+pub struct ShadowClassifier<'x> {
+    primary: &'x BayesModel,
+    primary_classifier: &'x BayesClassifier,
+    candidate: &'x BayesModel,
+    candidate_classifier: &'x BayesClassifier,
+    threshold: f64,
+    capacity: usize,
+    classified: u64,
+    disagreed: u64,
+    disagreements: VecDeque<Disagreement>,
+}
+
+/// Scores given to one message by the primary and candidate models.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Disagreement {
+    // Number of the classification, starting at 0
+    pub sequence: u64,
+    pub primary: Option<f64>,
+    pub candidate: Option<f64>,
+}
+
+impl<'x> ShadowClassifier<'x> {
+    /// Creates a shadow classifier recording the messages whose scores differ
+    /// by more than `threshold`, or that only one of the models has a verdict
+    /// for.
+    pub fn new(
+        primary: &'x BayesModel,
+        primary_classifier: &'x BayesClassifier,
+        candidate: &'x BayesModel,
+        candidate_classifier: &'x BayesClassifier,
+        threshold: f64,
+        capacity: usize,
+    ) -> Self {
+        ShadowClassifier {
+            primary,
+            primary_classifier,
+            candidate,
+            candidate_classifier,
+            threshold,
+            capacity,
+            classified: 0,
+            disagreed: 0,
+            disagreements: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Returns the score of the primary model.
+    pub fn classify(&mut self, tokens: &[OsbToken<TokenHash>]) -> Option<f64> {
+        let primary = self
+            .primary
+            .classify_token_hashes(self.primary_classifier, tokens.iter().cloned());
+        let candidate = self
+            .candidate
+            .classify_token_hashes(self.candidate_classifier, tokens.iter().cloned());
+
+        let disagrees = match (primary, candidate) {
+            (Some(primary), Some(candidate)) => (primary - candidate).abs() > self.threshold,
+            (None, None) => false,
+            _ => true,
+        };
+        if disagrees {
+            self.disagreed += 1;
+            if self.capacity > 0 {
+                if self.disagreements.len() == self.capacity {
+                    self.disagreements.pop_front();
+                }
+                self.disagreements.push_back(Disagreement {
+                    sequence: self.classified,
+                    primary,
+                    candidate,
+                });
+            }
+        }
+        self.classified += 1;
+
+        primary
+    }
+
+    /// The most recent disagreements, oldest first.
+    pub fn disagreements(&self) -> impl Iterator<Item = &Disagreement> {
+        self.disagreements.iter()
+    }
+
+    /// Removes and returns the recorded disagreements, e.g. to log them.
+    pub fn drain_disagreements(&mut self) -> Vec<Disagreement> {
+        self.disagreements.drain(..).collect()
+    }
+
+    /// Fraction of the classified messages the models disagreed on,
+    /// including the disagreements no longer in the buffer.
+    pub fn disagreement_rate(&self) -> f64 {
+        if self.classified > 0 {
+            self.disagreed as f64 / self.classified as f64
+        } else {
+            0.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::borrow::Cow;
+
+    use crate::{
+        bayes::{BayesClassifier, BayesModel, TokenHash, OSB_WINDOW_SIZE},
+        tokenizers::osb::{OsbToken, OsbTokenizer},
+    };
+
+    use super::ShadowClassifier;
+
+    fn tokens(text: &str) -> Vec<OsbToken<TokenHash>> {
+        OsbTokenizer::new(text.split(' ').map(Cow::from), OSB_WINDOW_SIZE).collect()
+    }
+
+    #[test]
+    fn shadow_classifier() {
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 1,
+            ..Default::default()
+        };
+        let mut primary = BayesModel::default();
+        let mut candidate = BayesModel::default();
+        for _ in 0..5 {
+            primary.train(tokens("crypto airdrop bonus"), true);
+            primary.train(tokens("quarterly report"), false);
+            // The candidate has learned that this user wants crypto mail
+            candidate.train(tokens("claim your prize"), true);
+            candidate.train(tokens("crypto airdrop bonus"), false);
+            candidate.train(tokens("quarterly report"), false);
+        }
+
+        let mut shadow =
+            ShadowClassifier::new(&primary, &classifier, &candidate, &classifier, 0.2, 2);
+        let messages = [
+            "crypto airdrop bonus",
+            "quarterly report",
+            "crypto airdrop bonus today",
+            "claim your prize",
+            "crypto airdrop bonus now",
+        ];
+        for message in messages {
+            assert_eq!(
+                shadow.classify(&tokens(message)),
+                primary.classify_token_hashes(&classifier, tokens(message).into_iter())
+            );
+        }
+
+        // Four disagreements, of which only the last two are kept
+        assert_eq!(shadow.disagreement_rate(), 0.8);
+        let disagreements = shadow.drain_disagreements();
+        assert_eq!(
+            disagreements.iter().map(|d| d.sequence).collect::<Vec<_>>(),
+            [3, 4]
+        );
+        // Only the candidate knows the prize message
+        assert_eq!(disagreements[0].primary, None);
+        assert!(disagreements[0].candidate.unwrap() > 0.5);
+        assert!(disagreements[1].primary.unwrap() > 0.5);
+        assert!(disagreements[1].candidate.unwrap() < 0.5);
+        assert_eq!(shadow.disagreements().count(), 0);
+    }
+}
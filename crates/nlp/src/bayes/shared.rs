@@ -0,0 +1,163 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+
+use super::BayesModel;
+
+/// A model that can be replaced while it is being used for classification.
+///
+/// Readers obtain an `Arc` snapshot with `load` and keep using it for the
+/// whole classification, so a swap never affects calls already in flight.
+/// A replaced model is freed once the last in-flight snapshot is dropped.
+#[derive(Debug)]
+pub struct SharedBayesModel {
+    // Swapped together, so that a version always names the model it was
+    // loaded with
+    model: ArcSwap<(u64, Arc<BayesModel>)>,
+}
+
+impl SharedBayesModel {
+    pub fn new(model: BayesModel) -> Self {
+        Self {
+            model: ArcSwap::from_pointee((0, Arc::new(model))),
+        }
+    }
+
+    pub fn load(&self) -> Arc<BayesModel> {
+        self.model.load().1.clone()
+    }
+
+    /// Returns the current model along with its version.
+    pub fn load_versioned(&self) -> (u64, Arc<BayesModel>) {
+        let current = self.model.load();
+        (current.0, current.1.clone())
+    }
+
+    /// Replaces the model, returning the previous one.
+    pub fn swap_model(&self, model: BayesModel) -> Arc<BayesModel> {
+        let model = Arc::new(model);
+        let prev = self.model.rcu(|current| (current.0 + 1, model.clone()));
+        prev.1.clone()
+    }
+
+    /// Number of times the model has been swapped.
+    pub fn version(&self) -> u64 {
+        self.model.load().0
+    }
+}
+
+impl Default for SharedBayesModel {
+    fn default() -> Self {
+        Self::new(BayesModel::default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread,
+    };
+
+    use crate::{
+        bayes::{BayesClassifier, BayesModel, TokenHash, Weights},
+        tokenizers::osb::OsbToken,
+    };
+
+    use super::SharedBayesModel;
+
+    fn model(is_spam: bool) -> BayesModel {
+        let mut model = BayesModel {
+            spam_learns: 100,
+            ham_learns: 100,
+            ..Default::default()
+        };
+        for h1 in 0..20 {
+            model.weights.insert(
+                TokenHash { h1, h2: 0 },
+                if is_spam {
+                    Weights { spam: 9, ham: 1 }
+                } else {
+                    Weights { spam: 1, ham: 9 }
+                },
+            );
+        }
+        model
+    }
+
+    #[test]
+    fn swap_under_load() {
+        let shared = Arc::new(SharedBayesModel::new(model(true)));
+        let done = Arc::new(AtomicBool::new(false));
+        let classifier = Arc::new(BayesClassifier {
+            min_learns: 0,
+            ..Default::default()
+        });
+
+        let readers = (0..4)
+            .map(|_| {
+                let shared = shared.clone();
+                let done = done.clone();
+                let classifier = classifier.clone();
+                thread::spawn(move || {
+                    let mut classified = 0;
+                    while !done.load(Ordering::Relaxed) || classified == 0 {
+                        let (version, model) = shared.load_versioned();
+                        let score = classifier
+                            .classify(
+                                (0..20).map(|h1| OsbToken {
+                                    inner: model.weights[&TokenHash { h1, h2: 0 }],
+                                    idx: 0,
+                                }),
+                                model.ham_learns,
+                                model.spam_learns,
+                            )
+                            .unwrap();
+                        assert!(!(0.1..0.9).contains(&score), "torn model: {score}");
+                        assert_eq!(score > 0.5, version.is_multiple_of(2), "{version}");
+                        classified += 1;
+                    }
+                    classified
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for version in 1..=200 {
+            let prev = shared.swap_model(model(version % 2 == 0));
+            assert_eq!(shared.version(), version);
+            drop(prev);
+        }
+        done.store(true, Ordering::Relaxed);
+
+        for reader in readers {
+            assert!(reader.join().unwrap() > 0);
+        }
+        assert_eq!(Arc::strong_count(&shared.load()), 2);
+    }
+}
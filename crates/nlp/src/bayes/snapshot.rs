@@ -0,0 +1,582 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+    hash::BuildHasherDefault,
+    sync::Arc,
+};
+
+use nohash::NoHashHasher;
+use serde::{de::DeserializeOwned, Deserialize, Serialize, Serializer};
+
+use super::{
+    decay::DecaySchedule,
+    hasher::{HashAlgorithm, TokenHasher},
+    namespace::Namespace,
+    train::MemoryBudget,
+    BayesModel, TokenHash, Weights, OSB_WINDOW_SIZE,
+};
+
+type TokenMap<V> = HashMap<TokenHash, V, BuildHasherDefault<NoHashHasher<TokenHash>>>;
+
+/// Snapshots start with a magic number identifying their kind followed by
+/// the little endian `u16` version of their layout. Snapshots without it
+/// were written before the header was introduced, and hold the original
+/// layout of version 0.
+pub const MODEL_SNAPSHOT_MAGIC: [u8; 4] = *b"BYSM";
+pub const QUANTIZED_SNAPSHOT_MAGIC: [u8; 4] = *b"BYSQ";
+pub const MODEL_SNAPSHOT_VERSION: u16 = 2;
+pub const QUANTIZED_SNAPSHOT_VERSION: u16 = 1;
+
+// Layout of version 0 snapshots, the learn counters and token weights only
+#[derive(Deserialize)]
+struct ModelSnapshotV0 {
+    weights: TokenMap<Weights>,
+    spam_learns: u32,
+    ham_learns: u32,
+}
+
+// Layout of version 1 snapshots, without the learn counters of namespaces
+#[derive(Deserialize)]
+struct ModelSnapshotV1 {
+    weights: TokenMap<Weights>,
+    spam_learns: u32,
+    ham_learns: u32,
+    hasher: HashAlgorithm,
+    doc_freq: TokenMap<u32>,
+    version: u64,
+    learned_messages: u64,
+    class_token_limit: Option<usize>,
+    window_size: usize,
+    memory_budget: Option<MemoryBudget>,
+    decay_schedule: Option<DecaySchedule>,
+}
+
+// Layout of version 2 snapshots. Changing it requires a new version along
+// with a migration from this one in `decode_model`.
+#[derive(Deserialize)]
+struct ModelSnapshotV2 {
+    weights: TokenMap<Weights>,
+    spam_learns: u32,
+    ham_learns: u32,
+    hasher: HashAlgorithm,
+    doc_freq: TokenMap<u32>,
+    version: u64,
+    learned_messages: u64,
+    class_token_limit: Option<usize>,
+    window_size: usize,
+    memory_budget: Option<MemoryBudget>,
+    decay_schedule: Option<DecaySchedule>,
+    namespaces: BTreeMap<Namespace, Weights>,
+}
+
+// Same as `ModelSnapshotV2`, borrowing from the model being written
+#[derive(Serialize)]
+struct ModelSnapshotV2Ref<'x> {
+    #[serde(serialize_with = "serialize_sorted_ref")]
+    weights: &'x TokenMap<Weights>,
+    spam_learns: u32,
+    ham_learns: u32,
+    hasher: &'x HashAlgorithm,
+    #[serde(serialize_with = "serialize_sorted_ref")]
+    doc_freq: &'x TokenMap<u32>,
+    version: u64,
+    learned_messages: u64,
+    class_token_limit: Option<usize>,
+    window_size: usize,
+    memory_budget: Option<MemoryBudget>,
+    decay_schedule: Option<DecaySchedule>,
+    namespaces: &'x BTreeMap<Namespace, Weights>,
+}
+
+impl From<ModelSnapshotV0> for ModelSnapshotV1 {
+    fn from(snapshot: ModelSnapshotV0) -> Self {
+        // The original hasher and window, with no document frequencies
+        ModelSnapshotV1 {
+            weights: snapshot.weights,
+            spam_learns: snapshot.spam_learns,
+            ham_learns: snapshot.ham_learns,
+            hasher: HashAlgorithm::Xxh3Farmhash,
+            doc_freq: TokenMap::default(),
+            version: 0,
+            learned_messages: snapshot.spam_learns as u64 + snapshot.ham_learns as u64,
+            class_token_limit: None,
+            window_size: OSB_WINDOW_SIZE,
+            memory_budget: None,
+            decay_schedule: None,
+        }
+    }
+}
+
+impl From<ModelSnapshotV1> for ModelSnapshotV2 {
+    fn from(snapshot: ModelSnapshotV1) -> Self {
+        // Namespaces kept their counters among the token weights, see
+        // `BayesModel::restore_namespace_learns`
+        ModelSnapshotV2 {
+            weights: snapshot.weights,
+            spam_learns: snapshot.spam_learns,
+            ham_learns: snapshot.ham_learns,
+            hasher: snapshot.hasher,
+            doc_freq: snapshot.doc_freq,
+            version: snapshot.version,
+            learned_messages: snapshot.learned_messages,
+            class_token_limit: snapshot.class_token_limit,
+            window_size: snapshot.window_size,
+            memory_budget: snapshot.memory_budget,
+            decay_schedule: snapshot.decay_schedule,
+            namespaces: BTreeMap::new(),
+        }
+    }
+}
+
+impl From<ModelSnapshotV2> for BayesModel {
+    fn from(snapshot: ModelSnapshotV2) -> Self {
+        BayesModel {
+            weights: snapshot.weights,
+            spam_learns: snapshot.spam_learns,
+            ham_learns: snapshot.ham_learns,
+            hasher: snapshot.hasher,
+            doc_freq: snapshot.doc_freq,
+            version: snapshot.version,
+            learned_messages: snapshot.learned_messages,
+            class_token_limit: snapshot.class_token_limit,
+            window_size: snapshot.window_size,
+            memory_budget: snapshot.memory_budget,
+            decay_schedule: snapshot.decay_schedule,
+            namespaces: snapshot.namespaces,
+            ..Default::default()
+        }
+    }
+}
+
+/// Prepends the header of a snapshot kind to its serialized payload.
+pub(crate) fn encode<T: Serialize>(
+    magic: [u8; 4],
+    version: u16,
+    payload: &T,
+) -> Result<Vec<u8>, SnapshotError> {
+    let mut bytes = Vec::with_capacity(
+        6 + bincode::serialized_size(payload).map_err(SnapshotError::Serialize)? as usize,
+    );
+    bytes.extend_from_slice(&magic);
+    bytes.extend_from_slice(&version.to_le_bytes());
+    bincode::serialize_into(&mut bytes, payload).map_err(SnapshotError::Serialize)?;
+    Ok(bytes)
+}
+
+/// Splits a snapshot into the version of its layout and its payload, which
+/// is a version 0 payload when the header is missing.
+pub(crate) fn decode_header(
+    bytes: &[u8],
+    magic: [u8; 4],
+    latest: u16,
+) -> Result<(u16, &[u8]), SnapshotError> {
+    match bytes {
+        [m0, m1, m2, m3, v0, v1, payload @ ..] if [*m0, *m1, *m2, *m3] == magic => {
+            let version = u16::from_le_bytes([*v0, *v1]);
+            if version <= latest {
+                Ok((version, payload))
+            } else {
+                Err(SnapshotError::UnsupportedVersion(version))
+            }
+        }
+        _ => Ok((0, bytes)),
+    }
+}
+
+pub(crate) fn deserialize<T: DeserializeOwned>(payload: &[u8]) -> Result<T, SnapshotError> {
+    bincode::deserialize(payload).map_err(SnapshotError::Serialize)
+}
+
+/// Returns the layout version of a model snapshot without decoding it.
+pub fn snapshot_version(bytes: &[u8]) -> Result<u16, SnapshotError> {
+    decode_header(bytes, MODEL_SNAPSHOT_MAGIC, MODEL_SNAPSHOT_VERSION).map(|(version, _)| version)
+}
+
+fn decode_model(bytes: &[u8]) -> Result<BayesModel, SnapshotError> {
+    let snapshot = match decode_header(bytes, MODEL_SNAPSHOT_MAGIC, MODEL_SNAPSHOT_VERSION)? {
+        (0, payload) => ModelSnapshotV1::from(deserialize::<ModelSnapshotV0>(payload)?).into(),
+        (1, payload) => deserialize::<ModelSnapshotV1>(payload)?.into(),
+        (_, payload) => deserialize::<ModelSnapshotV2>(payload)?,
+    };
+    Ok(snapshot.into())
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Serialize(bincode::Error),
+    HasherMismatch {
+        expected: HashAlgorithm,
+        found: HashAlgorithm,
+    },
+    // Custom hashers have to be supplied with `from_snapshot_with_hasher`
+    UnresolvedHasher(HashAlgorithm),
+    WindowMismatch {
+        expected: usize,
+        found: usize,
+    },
+    // Written by a newer version with a layout this one cannot read
+    UnsupportedVersion(u16),
+    InvalidWindow(usize),
+}
+
+impl BayesModel {
+    /// Serializes the model with the latest snapshot layout, see
+    /// `MODEL_SNAPSHOT_MAGIC`.
+    pub fn to_snapshot(&self) -> Result<Vec<u8>, SnapshotError> {
+        encode(
+            MODEL_SNAPSHOT_MAGIC,
+            MODEL_SNAPSHOT_VERSION,
+            &ModelSnapshotV2Ref {
+                weights: &self.weights,
+                spam_learns: self.spam_learns,
+                ham_learns: self.ham_learns,
+                hasher: &self.hasher,
+                doc_freq: &self.doc_freq,
+                version: self.version,
+                learned_messages: self.learned_messages,
+                class_token_limit: self.class_token_limit,
+                window_size: self.window_size,
+                memory_budget: self.memory_budget,
+                decay_schedule: self.decay_schedule,
+                namespaces: &self.namespaces,
+            },
+        )
+    }
+
+    /// Restores a model trained with a built-in hasher.
+    pub fn from_snapshot(bytes: &[u8], hasher: HashAlgorithm) -> Result<Self, SnapshotError> {
+        Self::from_snapshot_with_hasher(
+            bytes,
+            hasher
+                .builtin()
+                .ok_or(SnapshotError::UnresolvedHasher(hasher))?,
+        )
+    }
+
+    /// Restores a model trained with any hasher, failing unless the snapshot
+    /// records the identifier of `hasher`.
+    pub fn from_snapshot_with_hasher(
+        bytes: &[u8],
+        hasher: Arc<dyn TokenHasher>,
+    ) -> Result<Self, SnapshotError> {
+        let mut model = decode_model(bytes)?;
+        let expected = hasher.algorithm();
+        if model.hasher == expected {
+            model.token_hasher = Some(hasher);
+            Ok(model)
+        } else {
+            Err(SnapshotError::HasherMismatch {
+                expected,
+                found: model.hasher,
+            })
+        }
+    }
+
+    /// Same as `from_snapshot_with_hasher`, which migrates the snapshots of
+    /// older layouts, also migrating models trained with a different OSB
+    /// window. Tokens do not store their window position, so shrinking the
+    /// window only leaves the weights of the longer distance pairs unused.
+    /// Growing it is refused because the new pairs were never learned, unless
+    /// `force` is set, in which case the model keeps its original window and
+    /// messages must be tokenized with the truncated `window_size` of the model.
+    pub fn import_snapshot(
+        bytes: &[u8],
+        hasher: Arc<dyn TokenHasher>,
+        window_size: usize,
+        force: bool,
+    ) -> Result<Self, SnapshotError> {
+        if !(1..=OSB_WINDOW_SIZE).contains(&window_size) {
+            return Err(SnapshotError::InvalidWindow(window_size));
+        }
+
+        let mut model = Self::from_snapshot_with_hasher(bytes, hasher)?;
+        if !(1..=OSB_WINDOW_SIZE).contains(&model.window_size) {
+            Err(SnapshotError::InvalidWindow(model.window_size))
+        } else if window_size <= model.window_size {
+            model.window_size = window_size;
+            Ok(model)
+        } else if force {
+            Ok(model)
+        } else {
+            Err(SnapshotError::WindowMismatch {
+                expected: window_size,
+                found: model.window_size,
+            })
+        }
+    }
+}
+
+/// Serializes a token map ordered by hash. The iteration order of a map
+/// depends on its capacity and insertion history, so models with the same
+/// counts would otherwise produce different snapshots.
+pub(crate) fn serialize_sorted<V, H, S>(
+    map: &HashMap<TokenHash, V, H>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    V: Serialize,
+    S: Serializer,
+{
+    let mut entries = map.iter().collect::<Vec<_>>();
+    entries.sort_unstable_by_key(|(hash, _)| **hash);
+    serializer.collect_map(entries)
+}
+
+fn serialize_sorted_ref<V, H, S>(
+    map: &&HashMap<TokenHash, V, H>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    V: Serialize,
+    S: Serializer,
+{
+    serialize_sorted(map, serializer)
+}
+
+impl Display for SnapshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotError::Serialize(err) => write!(f, "Failed to serialize model: {err}"),
+            SnapshotError::HasherMismatch { expected, found } => write!(
+                f,
+                "Model was trained with {found:?} token hashes, expected {expected:?}"
+            ),
+            SnapshotError::UnresolvedHasher(hasher) => {
+                write!(f, "No hasher was supplied for {hasher:?} token hashes")
+            }
+            SnapshotError::WindowMismatch { expected, found } => write!(
+                f,
+                "Model was trained with an OSB window of {found}, expected {expected}"
+            ),
+            SnapshotError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported snapshot layout version {version}")
+            }
+            SnapshotError::InvalidWindow(window_size) => write!(
+                f,
+                "Unsupported OSB window of {window_size}, must be between 1 and {OSB_WINDOW_SIZE}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+#[cfg(test)]
+mod test {
+    use utils::suffixlist::PublicSuffix;
+
+    use crate::bayes::{
+        hasher::HashAlgorithm, snapshot::SnapshotError, BayesModel, TokenHash, Weights,
+        OSB_WINDOW_SIZE,
+    };
+
+    #[test]
+    fn snapshot_hasher_mismatch() {
+        let mut model = BayesModel {
+            hasher: HashAlgorithm::Xxh3_128,
+            ..Default::default()
+        };
+        model
+            .weights
+            .insert(TokenHash { h1: 1, h2: 2 }, Weights { spam: 3, ham: 4 });
+        let snapshot = model.to_snapshot().unwrap();
+
+        let restored = BayesModel::from_snapshot(&snapshot, HashAlgorithm::Xxh3_128).unwrap();
+        assert_eq!(restored.weights, model.weights);
+
+        assert!(matches!(
+            BayesModel::from_snapshot(&snapshot, HashAlgorithm::Xxh3Farmhash),
+            Err(SnapshotError::HasherMismatch {
+                expected: HashAlgorithm::Xxh3Farmhash,
+                found: HashAlgorithm::Xxh3_128
+            })
+        ));
+    }
+
+    #[test]
+    fn snapshot_versions() {
+        use std::collections::HashMap;
+
+        use crate::bayes::{
+            decay::DecaySchedule,
+            snapshot::{snapshot_version, MODEL_SNAPSHOT_MAGIC, MODEL_SNAPSHOT_VERSION},
+        };
+
+        let mut model = BayesModel {
+            spam_learns: 3,
+            ham_learns: 4,
+            version: 9,
+            learned_messages: 12,
+            class_token_limit: Some(100),
+            window_size: 3,
+            decay_schedule: Some(DecaySchedule {
+                every: 50,
+                factor: 0.5,
+            }),
+            ..Default::default()
+        };
+        model
+            .weights
+            .insert(TokenHash { h1: 1, h2: 2 }, Weights { spam: 3, ham: 4 });
+        model.doc_freq.insert(TokenHash { h1: 1, h2: 2 }, 5);
+
+        let snapshot = model.to_snapshot().unwrap();
+        assert_eq!(snapshot[..4], MODEL_SNAPSHOT_MAGIC);
+        assert_eq!(snapshot_version(&snapshot).unwrap(), MODEL_SNAPSHOT_VERSION);
+        let restored = BayesModel::from_snapshot(&snapshot, model.hasher.clone()).unwrap();
+        assert_eq!(restored.weights, model.weights);
+        assert_eq!(restored.doc_freq, model.doc_freq);
+        assert_eq!(
+            (
+                restored.version,
+                restored.learned_messages,
+                restored.class_token_limit,
+                restored.window_size,
+                restored.decay_schedule
+            ),
+            (9, 12, Some(100), 3, model.decay_schedule)
+        );
+
+        // Snapshots without a header hold the original layout
+        #[derive(serde::Serialize)]
+        struct Original {
+            weights: HashMap<TokenHash, Weights>,
+            spam_learns: u32,
+            ham_learns: u32,
+        }
+        let original = bincode::serialize(&Original {
+            weights: model.weights.iter().map(|(k, v)| (*k, *v)).collect(),
+            spam_learns: 3,
+            ham_learns: 4,
+        })
+        .unwrap();
+        assert_eq!(snapshot_version(&original).unwrap(), 0);
+        let migrated = BayesModel::from_snapshot(&original, HashAlgorithm::Xxh3Farmhash).unwrap();
+        assert_eq!(migrated.weights, model.weights);
+        assert_eq!((migrated.spam_learns, migrated.ham_learns), (3, 4));
+        assert_eq!(migrated.window_size, OSB_WINDOW_SIZE);
+        assert!(migrated.doc_freq.is_empty());
+
+        // Layouts newer than this version are refused
+        let mut newer = snapshot.clone();
+        newer[4..6].copy_from_slice(&(MODEL_SNAPSHOT_VERSION + 1).to_le_bytes());
+        assert!(matches!(
+            BayesModel::from_snapshot(&newer, HashAlgorithm::Xxh3Farmhash),
+            Err(SnapshotError::UnsupportedVersion(version)) if version == MODEL_SNAPSHOT_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn snapshot_deterministic() {
+        let suffixes = PublicSuffix::default();
+        let corpus = [
+            ("claim your free prize now", true),
+            ("cheap meds shipped overnight", true),
+            ("lunch with the team on friday", false),
+            ("minutes of the quarterly review", false),
+        ];
+        let train = |model: &mut BayesModel| {
+            for (text, is_spam) in corpus {
+                model.train_text(text, is_spam, &suffixes).unwrap();
+            }
+        };
+
+        let mut model = BayesModel::default();
+        train(&mut model);
+
+        // Trained again by another process, with its own hasher seeds
+        const CHILD_OUTPUT: &str = "BAYES_SNAPSHOT_OUTPUT";
+        if let Some(path) = std::env::var_os(CHILD_OUTPUT) {
+            std::fs::write(path, model.to_snapshot().unwrap()).unwrap();
+            return;
+        }
+        let path = std::env::temp_dir().join(format!("bayes-snapshot-{}.bin", std::process::id()));
+        let status = std::process::Command::new(std::env::current_exe().unwrap())
+            .args([
+                "--exact",
+                "bayes::snapshot::test::snapshot_deterministic",
+                "--test-threads=1",
+            ])
+            .env(CHILD_OUTPUT, &path)
+            .stdout(std::process::Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let child = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(child, model.to_snapshot().unwrap());
+
+        // Maps with a different capacity and history iterate in another order
+        let mut other = BayesModel::default();
+        other.weights.reserve(4096);
+        other.doc_freq.reserve(4096);
+        other
+            .weights
+            .insert(TokenHash { h1: 1, h2: 1 }, Weights::default());
+        train(&mut other);
+        other.weights.remove(&TokenHash { h1: 1, h2: 1 });
+        assert_ne!(
+            model.weights.keys().collect::<Vec<_>>(),
+            other.weights.keys().collect::<Vec<_>>()
+        );
+
+        assert_eq!(model.to_snapshot().unwrap(), other.to_snapshot().unwrap());
+        assert_eq!(
+            model.quantize().to_snapshot().unwrap(),
+            other.quantize().to_snapshot().unwrap()
+        );
+    }
+
+    #[test]
+    fn snapshot_window_migration() {
+        let model = BayesModel {
+            window_size: 3,
+            ..Default::default()
+        };
+        let snapshot = model.to_snapshot().unwrap();
+        let import = |window_size, force| {
+            BayesModel::import_snapshot(&snapshot, model.token_hasher(), window_size, force)
+        };
+
+        // Compatible
+        assert_eq!(import(3, false).unwrap().window_size, 3);
+        assert_eq!(import(2, false).unwrap().window_size, 2);
+
+        // Incompatible unless forced
+        assert!(matches!(
+            import(5, false),
+            Err(SnapshotError::WindowMismatch {
+                expected: 5,
+                found: 3
+            })
+        ));
+        assert_eq!(import(5, true).unwrap().window_size, 3);
+        assert!(matches!(
+            import(9, true),
+            Err(SnapshotError::InvalidWindow(9))
+        ));
+    }
+}
@@ -0,0 +1,517 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{convert::Infallible, future::Future};
+
+use crate::tokenizers::osb::OsbToken;
+
+use super::{
+    bloom::TokenBloom,
+    cache::BayesTokenCache,
+    metrics::{LookupMetrics, LookupMetricsReport},
+    namespace::Namespace,
+    BayesClassifier, BayesModel, BayesResult, TokenHash, Weights,
+};
+
+/// Source of token weights used by `BayesClassifier::classify_async`,
+/// allowing models to live outside of memory.
+pub trait TokenStore: Sync {
+    type Error;
+
+    /// Returns the weights of a token, or the default weights if it was never learned.
+    fn get_weights(
+        &self,
+        hash: TokenHash,
+    ) -> impl Future<Output = Result<Weights, Self::Error>> + Send;
+
+    /// Returns the weights of several tokens, in the same order as `hashes`.
+    /// Backends able to fetch them in a single query should override the
+    /// default, which looks up each token with `get_weights`.
+    fn lookup_many(
+        &self,
+        hashes: &[TokenHash],
+    ) -> impl Future<Output = Result<Vec<Weights>, Self::Error>> + Send {
+        async move {
+            let mut weights = Vec::with_capacity(hashes.len());
+            for hash in hashes {
+                weights.push(self.get_weights(*hash).await?);
+            }
+            Ok(weights)
+        }
+    }
+
+    /// Returns the number of ham and spam messages learned.
+    fn learns(&self) -> impl Future<Output = Result<(u32, u32), Self::Error>> + Send;
+
+    /// Returns the number of ham and spam messages learned into a namespace,
+    /// see `NamespacedStore`. Backends without namespaces only hold the
+    /// messages of the default namespace.
+    fn namespace_learns(
+        &self,
+        namespace: Namespace,
+    ) -> impl Future<Output = Result<(u32, u32), Self::Error>> + Send {
+        async move {
+            if namespace.is_default() {
+                self.learns().await
+            } else {
+                Ok((0, 0))
+            }
+        }
+    }
+
+    /// Returns up to `n` of the most frequently seen tokens, used to warm
+    /// caches. Backends unable to rank their tokens return none.
+    fn hottest_tokens(
+        &self,
+        n: usize,
+    ) -> impl Future<Output = Result<Vec<(TokenHash, Weights)>, Self::Error>> + Send {
+        let _ = n;
+        async { Ok(Vec::new()) }
+    }
+}
+
+impl TokenStore for BayesModel {
+    type Error = Infallible;
+
+    async fn get_weights(&self, hash: TokenHash) -> Result<Weights, Self::Error> {
+        Ok(self.weights.get(&hash).copied().unwrap_or_default())
+    }
+
+    async fn learns(&self) -> Result<(u32, u32), Self::Error> {
+        Ok((self.ham_learns, self.spam_learns))
+    }
+
+    async fn namespace_learns(&self, namespace: Namespace) -> Result<(u32, u32), Self::Error> {
+        Ok(BayesModel::namespace_learns(self, namespace))
+    }
+
+    async fn hottest_tokens(&self, n: usize) -> Result<Vec<(TokenHash, Weights)>, Self::Error> {
+        let mut tokens = self
+            .weights
+            .iter()
+            .map(|(hash, weights)| (*hash, *weights))
+            .collect::<Vec<_>>();
+        tokens.sort_unstable_by(|(a_hash, a), (b_hash, b)| {
+            b.spam
+                .saturating_add(b.ham)
+                .cmp(&a.spam.saturating_add(a.ham))
+                .then_with(|| a_hash.cmp(b_hash))
+        });
+        tokens.truncate(n);
+        Ok(tokens)
+    }
+}
+
+/// Attaches to each token its weights in an in-memory model, looked up as
+/// the tokens are consumed, so that hashed tokens can be piped straight into
+/// `BayesClassifier::classify`. Tokens never learned get the default weights.
+pub fn with_weights<I>(tokens: I, model: &BayesModel) -> WithWeights<'_, I::IntoIter>
+where
+    I: IntoIterator<Item = OsbToken<TokenHash>>,
+{
+    WithWeights {
+        tokens: tokens.into_iter(),
+        model,
+    }
+}
+
+pub struct WithWeights<'x, I> {
+    tokens: I,
+    model: &'x BayesModel,
+}
+
+impl<I> Iterator for WithWeights<'_, I>
+where
+    I: Iterator<Item = OsbToken<TokenHash>>,
+{
+    type Item = OsbToken<Weights>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.tokens.next().map(|token| OsbToken {
+            inner: self
+                .model
+                .weights
+                .get(&token.inner)
+                .copied()
+                .unwrap_or_default(),
+            idx: token.idx,
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.tokens.size_hint()
+    }
+}
+
+impl BayesClassifier {
+    /// Classifies a message looking up the weights of its tokens in a store.
+    pub async fn classify_async<S, T>(
+        &self,
+        store: &S,
+        tokens: T,
+    ) -> Result<Option<BayesResult>, S::Error>
+    where
+        S: TokenStore,
+        T: IntoIterator<Item = OsbToken<TokenHash>>,
+    {
+        let (ham_learns, spam_learns) = store.learns().await?;
+        if self.min_learns > 0 && (spam_learns < self.min_learns || ham_learns < self.min_learns) {
+            return Ok(None);
+        }
+
+        let tokens = tokens.into_iter().collect::<Vec<_>>();
+        let hashes = tokens.iter().map(|token| token.inner).collect::<Vec<_>>();
+        let weights = store.lookup_many(&hashes).await?;
+
+        Ok(self.classify_hashed(
+            tokens.into_iter().zip(weights).map(|(token, weights)| {
+                (
+                    token.inner,
+                    OsbToken {
+                        inner: weights,
+                        idx: token.idx,
+                    },
+                )
+            }),
+            ham_learns,
+            spam_learns,
+        ))
+    }
+}
+
+/// Classifier looking up the weights of the tokens of each message in as few
+/// store queries as possible. Tokens missing from the Bloom filter, if any,
+/// get the default weights without a lookup, tokens in the cache are served
+/// from it, and the rest are fetched from the store in a single batch and
+/// cached.
+pub struct AsyncClassifier<'x, S> {
+    classifier: &'x BayesClassifier,
+    store: &'x S,
+    cache: &'x BayesTokenCache,
+    bloom: Option<&'x TokenBloom>,
+    metrics: LookupMetrics,
+}
+
+impl<'x, S: TokenStore> AsyncClassifier<'x, S> {
+    pub fn new(classifier: &'x BayesClassifier, store: &'x S, cache: &'x BayesTokenCache) -> Self {
+        AsyncClassifier {
+            classifier,
+            store,
+            cache,
+            bloom: None,
+            metrics: LookupMetrics::default(),
+        }
+    }
+
+    pub fn with_bloom(mut self, bloom: &'x TokenBloom) -> Self {
+        self.bloom = Some(bloom);
+        self
+    }
+
+    pub async fn classify<T>(&self, tokens: T) -> Result<Option<BayesResult>, S::Error>
+    where
+        T: IntoIterator<Item = OsbToken<TokenHash>>,
+    {
+        let classifier = self.classifier;
+        let (ham_learns, spam_learns) = self.store.learns().await?;
+        if classifier.min_learns > 0
+            && (spam_learns < classifier.min_learns || ham_learns < classifier.min_learns)
+        {
+            return Ok(None);
+        }
+
+        let tokens = tokens.into_iter().collect::<Vec<_>>();
+        let mut weights = Vec::with_capacity(tokens.len());
+        let mut missing = Vec::new();
+        let mut skipped = 0;
+        for (pos, token) in tokens.iter().enumerate() {
+            if self
+                .bloom
+                .is_some_and(|bloom| !bloom.contains(&token.inner))
+            {
+                weights.push(Weights::default());
+                skipped += 1;
+            } else if let Some(cached) = self.cache.get(&token.inner) {
+                weights.push(cached.unwrap_or_default());
+            } else {
+                weights.push(Weights::default());
+                missing.push(pos);
+            }
+        }
+        let hits = tokens.len() - skipped - missing.len();
+        self.metrics
+            .record(hits as u64, missing.len() as u64, skipped as u64);
+
+        if !missing.is_empty() {
+            let hashes = missing
+                .iter()
+                .map(|pos| tokens[*pos].inner)
+                .collect::<Vec<_>>();
+            let fetched = self.store.lookup_many(&hashes).await?;
+            for (pos, fetched) in missing.into_iter().zip(fetched) {
+                self.cache.insert_weights(tokens[pos].inner, fetched);
+                weights[pos] = fetched;
+            }
+        }
+
+        Ok(classifier.classify_hashed(
+            tokens.into_iter().zip(weights).map(|(token, weights)| {
+                (
+                    token.inner,
+                    OsbToken {
+                        inner: weights,
+                        idx: token.idx,
+                    },
+                )
+            }),
+            ham_learns,
+            spam_learns,
+        ))
+    }
+
+    pub fn metrics(&self) -> LookupMetricsReport {
+        self.metrics.report()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        convert::Infallible,
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use crate::{
+        bayes::{
+            bloom::TokenBloom, cache::BayesTokenCache, metrics::LookupMetricsReport,
+            BayesClassifier, BayesModel, TokenHash, Weights,
+        },
+        tokenizers::osb::OsbToken,
+    };
+
+    use super::{with_weights, AsyncClassifier, TokenStore};
+
+    // Counts the queries issued to a model
+    #[derive(Default)]
+    struct CountingStore {
+        model: BayesModel,
+        single: AtomicUsize,
+        batched: AtomicUsize,
+        fetched: AtomicUsize,
+    }
+
+    impl TokenStore for CountingStore {
+        type Error = Infallible;
+
+        async fn get_weights(&self, hash: TokenHash) -> Result<Weights, Self::Error> {
+            self.single.fetch_add(1, Ordering::Relaxed);
+            self.model.get_weights(hash).await
+        }
+
+        async fn lookup_many(&self, hashes: &[TokenHash]) -> Result<Vec<Weights>, Self::Error> {
+            self.batched.fetch_add(1, Ordering::Relaxed);
+            self.fetched.fetch_add(hashes.len(), Ordering::Relaxed);
+            Ok(hashes
+                .iter()
+                .map(|hash| self.model.weights.get(hash).copied().unwrap_or_default())
+                .collect())
+        }
+
+        async fn learns(&self) -> Result<(u32, u32), Self::Error> {
+            self.model.learns().await
+        }
+    }
+
+    fn tokens(hashes: &[u64]) -> Vec<OsbToken<TokenHash>> {
+        hashes
+            .iter()
+            .map(|&h1| OsbToken {
+                inner: TokenHash { h1, h2: 0 },
+                idx: 0,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn hottest_tokens() {
+        let mut model = BayesModel::default();
+        for (h1, spam, ham) in [(1, 3, 0), (2, u32::MAX, 1), (3, 1, u32::MAX), (4, 0, 5)] {
+            model
+                .weights
+                .insert(TokenHash { h1, h2: 0 }, Weights { spam, ham });
+        }
+
+        // Saturated counts tie and are ranked by hash
+        let hottest = model.hottest_tokens(3).await.unwrap();
+        assert_eq!(
+            hottest.iter().map(|(hash, _)| hash.h1).collect::<Vec<_>>(),
+            [2, 3, 4]
+        );
+    }
+
+    #[tokio::test]
+    async fn batched_lookups() {
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 1,
+            min_token_hits: 1,
+            ..Default::default()
+        };
+        let mut store = CountingStore::default();
+        store.model.train(tokens(&[1, 2, 3, 4]), true);
+        store.model.train(tokens(&[5, 6, 7, 8]), false);
+
+        let message = tokens(&[1, 2, 3, 9]);
+        let result = classifier
+            .classify_async(&store, message.clone())
+            .await
+            .unwrap();
+        assert_eq!(store.batched.load(Ordering::Relaxed), 1);
+        assert_eq!(store.single.load(Ordering::Relaxed), 0);
+
+        // The default implementation returns the same weights
+        assert_eq!(
+            result,
+            classifier
+                .classify_async(&store.model, message)
+                .await
+                .unwrap()
+        );
+        assert!(result.unwrap().score > 0.5);
+        assert_eq!(
+            store
+                .model
+                .lookup_many(&[TokenHash { h1: 1, h2: 0 }, TokenHash { h1: 9, h2: 0 }])
+                .await
+                .unwrap(),
+            [Weights { spam: 1, ham: 0 }, Weights::default()]
+        );
+    }
+
+    #[tokio::test]
+    async fn async_classifier() {
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 1,
+            min_token_hits: 1,
+            ..Default::default()
+        };
+        let mut store = CountingStore::default();
+        store.model.train(tokens(&[1, 2, 3, 4]), true);
+        store.model.train(tokens(&[5, 6, 7, 8]), false);
+        let bloom = TokenBloom::from_model(&store.model, 0.001);
+        let cache = BayesTokenCache::new(64, Duration::from_secs(60), Duration::from_secs(60));
+        let lookups = |store: &CountingStore| {
+            (
+                store.batched.load(Ordering::Relaxed),
+                store.fetched.load(Ordering::Relaxed),
+            )
+        };
+
+        // Unknown tokens are skipped and the known ones fetched in one batch
+        let async_classifier = AsyncClassifier::new(&classifier, &store, &cache).with_bloom(&bloom);
+        let message = tokens(&[1, 2, 3, 9, 10]);
+        let result = async_classifier.classify(message.clone()).await.unwrap();
+        assert_eq!(
+            async_classifier.metrics(),
+            LookupMetricsReport {
+                hits: 0,
+                misses: 3,
+                skipped: 2,
+            }
+        );
+        assert_eq!(lookups(&store), (1, 3));
+        assert_eq!(store.single.load(Ordering::Relaxed), 0);
+        assert_eq!(
+            result,
+            classifier
+                .classify_async(&store.model, message)
+                .await
+                .unwrap()
+        );
+        assert!(result.unwrap().score > 0.5);
+
+        // Cached tokens are not fetched again
+        async_classifier
+            .classify(tokens(&[1, 2, 5, 11]))
+            .await
+            .unwrap();
+        assert_eq!(
+            async_classifier.metrics(),
+            LookupMetricsReport {
+                hits: 2,
+                misses: 4,
+                skipped: 3,
+            }
+        );
+        assert_eq!(lookups(&store), (2, 4));
+        async_classifier.classify(tokens(&[1, 5])).await.unwrap();
+        assert_eq!(lookups(&store), (2, 4));
+
+        // Without a Bloom filter unknown tokens are fetched, then cached as such
+        let async_classifier = AsyncClassifier::new(&classifier, &store, &cache);
+        async_classifier.classify(tokens(&[9, 12])).await.unwrap();
+        async_classifier.classify(tokens(&[9, 12])).await.unwrap();
+        assert_eq!(
+            async_classifier.metrics(),
+            LookupMetricsReport {
+                hits: 2,
+                misses: 2,
+                skipped: 0,
+            }
+        );
+        assert_eq!(lookups(&store), (3, 6));
+    }
+
+    #[test]
+    fn lazy_weights() {
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 1,
+            min_token_hits: 1,
+            ..Default::default()
+        };
+        let mut model = BayesModel::default();
+        model.train(tokens(&[1, 2, 3, 4]), true);
+        model.train(tokens(&[3, 5, 6]), false);
+
+        let message = tokens(&[1, 3, 5, 9]);
+        let manual = message
+            .iter()
+            .map(|token| OsbToken {
+                inner: model.weights.get(&token.inner).copied().unwrap_or_default(),
+                idx: token.idx,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            with_weights(message.clone(), &model).collect::<Vec<_>>(),
+            manual
+        );
+        assert_eq!(manual[3].inner, Weights::default());
+        assert_eq!(
+            classifier.classify(with_weights(message, &model), 1, 1),
+            classifier.classify(manual.into_iter(), 1, 1)
+        );
+    }
+}
@@ -0,0 +1,360 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    borrow::Cow,
+    io::{self, BufRead, Read},
+};
+
+use utils::suffixlist::PublicSuffix;
+
+use crate::{language::Language, tokenizers::osb::OsbTokenizer};
+
+use super::{tokenize::BayesTokenizer, BayesClassifier, BayesModel};
+
+/// Default maximum size of the chunks read by a `StreamTokenizer`.
+pub const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Tokenizes text read from a `BufRead` one chunk at a time, in order to
+/// bound the memory used by large messages. Chunks end at a line break, or at
+/// the last whitespace when a line exceeds the chunk size, so words are only
+/// split when longer than a chunk, and never within a UTF-8 sequence. Since
+/// chunks are too short for reliable language detection, the language of the
+/// text must be known in advance.
+///
+/// Iteration stops at the first read error, which is then returned by
+/// `take_error`. Invalid UTF-8 is replaced with U+FFFD.
+pub struct StreamTokenizer<'y, R> {
+    reader: R,
+    suffixes: &'y PublicSuffix,
+    language: Language,
+    max_chunk: usize,
+    pending: Vec<u8>,
+    tokens: std::vec::IntoIter<String>,
+    error: Option<io::Error>,
+    done: bool,
+}
+
+impl<'y, R: BufRead> StreamTokenizer<'y, R> {
+    pub fn new(reader: R, suffixes: &'y PublicSuffix, language: Language) -> Self {
+        Self {
+            reader,
+            suffixes,
+            language,
+            max_chunk: STREAM_CHUNK_SIZE,
+            pending: Vec::new(),
+            tokens: Vec::new().into_iter(),
+            error: None,
+            done: false,
+        }
+    }
+
+    pub fn with_chunk_size(mut self, max_chunk: usize) -> Self {
+        // Large enough to hold any UTF-8 sequence
+        self.max_chunk = max_chunk.max(4);
+        self
+    }
+
+    /// Returns the error that stopped the iteration, if any.
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    // Reads up to the next chunk boundary, returning None at the end of the input
+    fn next_chunk(&mut self) -> io::Result<Option<String>> {
+        loop {
+            let limit = self.max_chunk.saturating_sub(self.pending.len()) as u64;
+            let read = self
+                .reader
+                .by_ref()
+                .take(limit)
+                .read_until(b'\n', &mut self.pending)?;
+
+            let cut = if read == 0 {
+                // End of input
+                if self.pending.is_empty() {
+                    return Ok(None);
+                }
+                self.pending.len()
+            } else if self.pending.ends_with(b"\n") {
+                self.pending.len()
+            } else if self.pending.len() >= self.max_chunk {
+                chunk_boundary(&self.pending)
+            } else {
+                continue;
+            };
+
+            let chunk = self.pending.drain(..cut).collect::<Vec<_>>();
+            return Ok(Some(match String::from_utf8(chunk) {
+                Ok(chunk) => chunk,
+                Err(err) => String::from_utf8_lossy(err.as_bytes()).into_owned(),
+            }));
+        }
+    }
+}
+
+// Returns the end of the last whitespace in a chunk without line breaks, or
+// of its last complete UTF-8 sequence if it has no whitespace
+fn chunk_boundary(bytes: &[u8]) -> usize {
+    if let Some(pos) = bytes.iter().rposition(|ch| ch.is_ascii_whitespace()) {
+        return pos + 1;
+    }
+
+    let Some(start) = bytes.iter().rposition(|ch| (ch & 0xC0) != 0x80) else {
+        return bytes.len();
+    };
+    let len = match bytes[start] {
+        ch if ch < 0x80 => 1,
+        ch if ch >= 0xF0 => 4,
+        ch if ch >= 0xE0 => 3,
+        _ => 2,
+    };
+    if start + len <= bytes.len() || start == 0 {
+        bytes.len()
+    } else {
+        start
+    }
+}
+
+impl<R: BufRead> Iterator for StreamTokenizer<'_, R> {
+    type Item = Cow<'static, str>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(token) = self.tokens.next() {
+                return Some(Cow::Owned(token));
+            } else if self.done {
+                return None;
+            }
+
+            match self.next_chunk() {
+                Ok(Some(chunk)) => {
+                    self.tokens =
+                        BayesTokenizer::with_language(&chunk, self.suffixes, self.language)
+                            .map(Cow::into_owned)
+                            .collect::<Vec<_>>()
+                            .into_iter();
+                }
+                Ok(None) => self.done = true,
+                Err(err) => {
+                    self.error = Some(err);
+                    self.done = true;
+                }
+            }
+        }
+    }
+}
+
+impl BayesModel {
+    /// Trains the model with a text read from `reader`, see `StreamTokenizer`.
+    /// On a read error, the tokens read so far have already been learned.
+    pub fn train_reader(
+        &mut self,
+        reader: impl BufRead,
+        is_spam: bool,
+        suffixes: &PublicSuffix,
+        language: Language,
+    ) -> io::Result<()> {
+        let mut tokenizer = StreamTokenizer::new(reader, suffixes, language);
+        self.train(
+            OsbTokenizer::with_hasher(tokenizer.by_ref(), self.window_size, self.token_hasher()),
+            is_spam,
+        );
+        tokenizer.take_error().map_or(Ok(()), Err)
+    }
+
+    /// Classifies a text read from `reader`, see `StreamTokenizer`. With
+    /// `BayesClassifier::early_exit` set, reading stops as soon as the
+    /// evidence is decisive.
+    pub fn classify_reader(
+        &self,
+        classifier: &BayesClassifier,
+        reader: impl BufRead,
+        suffixes: &PublicSuffix,
+        language: Language,
+    ) -> io::Result<Option<f64>> {
+        let mut tokenizer = StreamTokenizer::new(reader, suffixes, language);
+        let score = self.classify_token_hashes(
+            classifier,
+            OsbTokenizer::with_hasher(tokenizer.by_ref(), self.window_size, self.token_hasher()),
+        );
+        tokenizer.take_error().map_or(Ok(score), Err)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        cell::Cell,
+        io::{self, BufReader, Read},
+    };
+
+    use utils::suffixlist::PublicSuffix;
+
+    use crate::{
+        bayes::{
+            tokenize::BayesTokenizer, BayesClassifier, BayesModel, TokenHash, OSB_WINDOW_SIZE,
+        },
+        language::Language,
+        tokenizers::osb::{OsbToken, OsbTokenizer},
+    };
+
+    use super::{chunk_boundary, StreamTokenizer};
+
+    const BODY: &str = concat!(
+        "Dear customer, your parcel is waiting at the depot since Monday.\n",
+        "Größere Pakete können nicht zugestellt werden, bitte bestätigen Sie\r\n",
+        "the delivery fee of 1.99 EUR today ✅ 📦 or it will be returned\n",
+        "\n",
+        "Visit www.example.com to schedule the delivery at no extra cost",
+    );
+
+    fn osb(
+        tokens: impl Iterator<Item = std::borrow::Cow<'static, str>>,
+    ) -> Vec<OsbToken<TokenHash>> {
+        OsbTokenizer::new(tokens, OSB_WINDOW_SIZE).collect()
+    }
+
+    #[test]
+    fn stream_tokenizer() {
+        let suffixes = PublicSuffix::from("com");
+        let whole = OsbTokenizer::<_, TokenHash>::new(
+            BayesTokenizer::with_language(BODY, &suffixes, Language::English),
+            OSB_WINDOW_SIZE,
+        )
+        .collect::<Vec<_>>();
+        assert!(!whole.is_empty());
+
+        // Tiny reads and chunks splitting lines and multibyte characters
+        for (capacity, max_chunk) in [(1, 4), (3, 16), (5, 23), (64, 1024)] {
+            let reader = BufReader::with_capacity(capacity, BODY.as_bytes());
+            let mut tokenizer = StreamTokenizer::new(reader, &suffixes, Language::English)
+                .with_chunk_size(max_chunk);
+            let stream = osb(tokenizer.by_ref());
+            assert!(tokenizer.take_error().is_none());
+            if max_chunk >= 16 {
+                assert_eq!(stream, whole, "{capacity} {max_chunk}");
+            } else {
+                // Words longer than a chunk are split, but nothing is lost
+                assert!(!stream.is_empty());
+            }
+        }
+
+        let mut model = BayesModel::default();
+        model
+            .train_reader(BODY.as_bytes(), true, &suffixes, Language::English)
+            .unwrap();
+        let mut expected = BayesModel::default();
+        expected.train(whole, true);
+        assert_eq!(model.weights, expected.weights);
+    }
+
+    #[test]
+    fn stream_chunk_boundary() {
+        // Last whitespace
+        assert_eq!(chunk_boundary(b"hello wor"), 6);
+        // Incomplete sequence of 'ö' (C3 B6)
+        assert_eq!(chunk_boundary(b"gr\xC3"), 2);
+        assert_eq!(chunk_boundary("grö".as_bytes()), 4);
+        // Incomplete emoji
+        assert_eq!(chunk_boundary(&"ab📦".as_bytes()[..4]), 2);
+    }
+
+    #[test]
+    fn stream_read_error() {
+        struct Failing(usize);
+        impl Read for Failing {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if self.0 == 0 {
+                    Err(io::Error::other("disconnected"))
+                } else {
+                    self.0 -= 1;
+                    buf[..6].copy_from_slice(b"hello\n");
+                    Ok(6)
+                }
+            }
+        }
+
+        let suffixes = PublicSuffix::default();
+        let mut tokenizer =
+            StreamTokenizer::new(BufReader::new(Failing(2)), &suffixes, Language::English);
+        assert_eq!(tokenizer.by_ref().count(), 2);
+        assert!(tokenizer.take_error().is_some());
+    }
+
+    #[test]
+    fn stream_early_exit() {
+        // Counts the bytes read from a text
+        struct CountingReader<'x> {
+            text: &'x [u8],
+            read: &'x Cell<usize>,
+        }
+
+        impl Read for CountingReader<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let read = self.text.read(buf)?;
+                self.read.set(self.read.get() + read);
+                Ok(read)
+            }
+        }
+
+        let suffixes = PublicSuffix::default();
+        let mut model = BayesModel::default();
+        for _ in 0..10 {
+            model
+                .train_text("claim your free prize money now", true, &suffixes)
+                .unwrap();
+            model
+                .train_text("notes from the weekly planning meeting", false, &suffixes)
+                .unwrap();
+        }
+        let text = "claim your free prize money now\n".repeat(500);
+        let mut classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 11,
+            ..Default::default()
+        };
+        let classify = |classifier: &BayesClassifier| {
+            let read = Cell::new(0);
+            let reader = BufReader::with_capacity(
+                64,
+                CountingReader {
+                    text: text.as_bytes(),
+                    read: &read,
+                },
+            );
+            let score = model
+                .classify_reader(classifier, reader, &suffixes, Language::English)
+                .unwrap()
+                .unwrap();
+            (score, read.get())
+        };
+
+        let (full_score, full_read) = classify(&classifier);
+        assert_eq!(full_read, text.len());
+        classifier.early_exit = Some(20.0);
+        let (score, read) = classify(&classifier);
+        assert!(score > 0.99 && full_score > 0.99, "{score} {full_score}");
+        assert!(read * 20 < full_read, "{read} {full_read}");
+    }
+}
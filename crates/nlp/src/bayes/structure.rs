@@ -0,0 +1,159 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::borrow::Cow;
+
+use mail_parser::{Message, PartType};
+
+/// Shape of the MIME tree of a message, which correlates with spam regardless
+/// of its text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MimeStructure {
+    // Number of non-multipart parts, counting attached messages as one part
+    pub parts: usize,
+    // Nesting level of the deepest multipart, 0 for single part messages
+    pub depth: usize,
+    // Whether the message has an HTML body without a plain text alternative
+    pub only_html: bool,
+}
+
+impl MimeStructure {
+    pub fn new(message: &Message<'_>) -> Self {
+        MimeStructure {
+            parts: message
+                .parts
+                .iter()
+                .filter(|part| !matches!(part.body, PartType::Multipart(_)))
+                .count(),
+            depth: depth(message, 0),
+            only_html: !message.text_body.iter().any(|id| {
+                matches!(
+                    message.part(*id).map(|part| &part.body),
+                    Some(PartType::Text(_))
+                )
+            }) && message.html_body.iter().any(|id| {
+                matches!(
+                    message.part(*id).map(|part| &part.body),
+                    Some(PartType::Html(_))
+                )
+            }),
+        }
+    }
+
+    /// Returns the `parts:<n>`, `mime_depth:<n>` and, when applicable,
+    /// `only_html` tokens of the structure.
+    pub fn tokens(&self) -> Vec<Cow<'static, str>> {
+        let mut tokens = vec![
+            Cow::from(format!("parts:{}", self.parts)),
+            Cow::from(format!("mime_depth:{}", self.depth)),
+        ];
+        if self.only_html {
+            tokens.push(Cow::from("only_html"));
+        }
+        tokens
+    }
+}
+
+fn depth(message: &Message<'_>, id: usize) -> usize {
+    match message.part(id).map(|part| &part.body) {
+        // Guard against malformed messages referencing their own ancestors
+        Some(PartType::Multipart(children)) => {
+            1 + children
+                .iter()
+                .filter(|child| **child > id)
+                .map(|child| depth(message, *child))
+                .max()
+                .unwrap_or(0)
+        }
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use mail_parser::MessageParser;
+    use utils::suffixlist::PublicSuffix;
+
+    use crate::bayes::tokenize::BayesTokenizer;
+
+    use super::MimeStructure;
+
+    const HTML_ONLY: &str = concat!(
+        "From: promo@example.com\r\n",
+        "Subject: Great deals\r\n",
+        "Content-Type: text/html\r\n",
+        "\r\n",
+        "<html><body><p>Buy now</p></body></html>\r\n",
+    );
+
+    const MIXED: &str = concat!(
+        "From: alice@example.com\r\n",
+        "Subject: Report\r\n",
+        "Content-Type: multipart/mixed; boundary=\"outer\"\r\n",
+        "\r\n",
+        "--outer\r\n",
+        "Content-Type: multipart/alternative; boundary=\"inner\"\r\n",
+        "\r\n",
+        "--inner\r\n",
+        "Content-Type: text/plain\r\n",
+        "\r\n",
+        "See the attached report\r\n",
+        "--inner\r\n",
+        "Content-Type: text/html\r\n",
+        "\r\n",
+        "<p>See the attached report</p>\r\n",
+        "--inner--\r\n",
+        "--outer\r\n",
+        "Content-Type: application/pdf\r\n",
+        "Content-Disposition: attachment; filename=\"report.pdf\"\r\n",
+        "\r\n",
+        "JVBERi0xLjQK\r\n",
+        "--outer--\r\n",
+    );
+
+    #[test]
+    fn mime_structure() {
+        let parser = MessageParser::default();
+        let html_only = parser.parse(HTML_ONLY).unwrap();
+        let mixed = parser.parse(MIXED).unwrap();
+
+        assert_eq!(
+            MimeStructure::new(&html_only).tokens(),
+            ["parts:1", "mime_depth:0", "only_html"]
+        );
+        assert_eq!(
+            MimeStructure::new(&mixed).tokens(),
+            ["parts:3", "mime_depth:2"]
+        );
+
+        let suffixes = PublicSuffix::default();
+        let tokens = BayesTokenizer::new("buy now", &suffixes)
+            .tokenize_structure(&html_only)
+            .collect::<Vec<_>>();
+        assert!(
+            tokens.iter().any(|token| token == "only_html"),
+            "{tokens:?}"
+        );
+        assert!(tokens.iter().any(|token| token == "buy"), "{tokens:?}");
+    }
+}
@@ -0,0 +1,291 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::borrow::Cow;
+
+use mail_parser::parsers::MessageStream;
+use utils::suffixlist::PublicSuffix;
+
+use crate::tokenizers::osb::{OsbToken, OsbTokenizer};
+
+use super::{
+    pipeline::PipelineError, tokenize::BayesTokenizer, BayesClassifier, BayesModel, TokenHash,
+};
+
+/// Emits a `subj:<token>` token for each token of a raw `Subject` header
+/// value, after decoding its RFC 2047 encoded words.
+pub struct SubjectTokenizer<'x, 'y> {
+    tokenizer: BayesTokenizer<'x, 'y>,
+}
+
+impl<'x, 'y> SubjectTokenizer<'x, 'y> {
+    pub fn new(subject: &'x str, suffixes: &'y PublicSuffix) -> Self {
+        SubjectTokenizer {
+            tokenizer: BayesTokenizer::new(subject, suffixes),
+        }
+    }
+}
+
+impl<'x, 'y> Iterator for SubjectTokenizer<'x, 'y> {
+    type Item = Cow<'static, str>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.tokenizer
+            .next()
+            .map(|token| Cow::from(format!("subj:{token}")))
+    }
+}
+
+/// Decodes the RFC 2047 encoded words (`=?charset?B|Q?...?=`) of a raw header
+/// value, in any of the charsets supported by the message parser. Malformed
+/// encoded words are kept as they are.
+pub fn decode_header(raw: &str) -> Cow<'_, str> {
+    if !raw.contains("=?") {
+        return Cow::Borrowed(raw);
+    }
+
+    // The parser expects the line ending of the header
+    let line = format!("{raw}\n");
+    MessageStream::new(line.as_bytes())
+        .parse_unstructured()
+        .as_text()
+        .map_or(Cow::Borrowed(raw), |text| Cow::Owned(text.to_string()))
+}
+
+/// Weights of the subject and body scores in the score combined by
+/// `classify_subject_body`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FieldWeights {
+    pub subject: f64,
+    pub body: f64,
+}
+
+impl Default for FieldWeights {
+    fn default() -> Self {
+        FieldWeights {
+            subject: 1.0,
+            body: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FieldScores {
+    pub subject: Option<f64>,
+    pub body: Option<f64>,
+    // Weighted average of the subject and body scores, or the only one of
+    // them available
+    pub score: Option<f64>,
+}
+
+impl BayesModel {
+    /// Learns a message from its raw `Subject` header and its body, the
+    /// subject tokens being tagged with `subj:` so they are learned apart from
+    /// the same words in the body.
+    pub fn train_subject_body(
+        &mut self,
+        subject: &str,
+        body: &str,
+        is_spam: bool,
+        suffixes: &PublicSuffix,
+    ) -> Result<(), PipelineError> {
+        let (subject, body) = self.field_tokens(subject, body, suffixes);
+        if subject.is_empty() && body.is_empty() {
+            return Err(PipelineError::EmptyInput);
+        }
+
+        self.train(subject.into_iter().chain(body), is_spam);
+        Ok(())
+    }
+
+    /// Classifies the subject and the body of a message separately, as
+    /// learned by `train_subject_body`, and combines both scores with the
+    /// given weights. Returns `Err(PipelineError::EmptyInput)` when neither
+    /// contains anything to classify.
+    pub fn classify_subject_body(
+        &self,
+        classifier: &BayesClassifier,
+        subject: &str,
+        body: &str,
+        suffixes: &PublicSuffix,
+        weights: &FieldWeights,
+    ) -> Result<FieldScores, PipelineError> {
+        let (subject, body) = self.field_tokens(subject, body, suffixes);
+        if subject.is_empty() && body.is_empty() {
+            return Err(PipelineError::EmptyInput);
+        }
+
+        let subject = self.classify_token_hashes(classifier, subject.into_iter());
+        let body = self.classify_token_hashes(classifier, body.into_iter());
+        let score = match (subject, body) {
+            (Some(subject), Some(body)) if weights.subject + weights.body > 0.0 => Some(
+                (subject * weights.subject + body * weights.body)
+                    / (weights.subject + weights.body),
+            ),
+            (Some(score), None) | (None, Some(score)) => Some(score),
+            _ => None,
+        };
+
+        Ok(FieldScores {
+            subject,
+            body,
+            score,
+        })
+    }
+
+    fn field_tokens(
+        &self,
+        subject: &str,
+        body: &str,
+        suffixes: &PublicSuffix,
+    ) -> (Vec<OsbToken<TokenHash>>, Vec<OsbToken<TokenHash>>) {
+        let subject = decode_header(subject);
+        (
+            OsbTokenizer::with_hasher(
+                SubjectTokenizer::new(&subject, suffixes),
+                self.window_size,
+                self.token_hasher(),
+            )
+            .collect(),
+            OsbTokenizer::with_hasher(
+                BayesTokenizer::new(body, suffixes),
+                self.window_size,
+                self.token_hasher(),
+            )
+            .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use utils::suffixlist::PublicSuffix;
+
+    use crate::bayes::{
+        pipeline::PipelineError, tokenize::BayesTokenizer, BayesClassifier, BayesModel,
+    };
+
+    use super::{decode_header, FieldWeights, SubjectTokenizer};
+
+    #[test]
+    fn encoded_subjects() {
+        let suffixes = PublicSuffix::default();
+        let tokens = |subject| {
+            let subject = decode_header(subject);
+            SubjectTokenizer::new(&subject, &suffixes).collect::<Vec<_>>()
+        };
+
+        let expected = BayesTokenizer::new("Cheap watches today", &suffixes)
+            .map(|token| format!("subj:{token}"))
+            .collect::<Vec<_>>();
+        assert_eq!(tokens("=?UTF-8?B?Q2hlYXAgd2F0Y2hlcw==?= today"), expected);
+        assert_eq!(tokens("=?utf-8?q?Cheap_watches?= today"), expected);
+
+        // Adjacent encoded words in different charsets are joined
+        assert_eq!(
+            decode_header("=?ISO-8859-1?Q?Rel=F3gios?= =?UTF-8?B?IGJhcmF0b3M=?="),
+            "Relógios baratos"
+        );
+        assert_eq!(decode_header("plain subject"), "plain subject");
+
+        // Malformed encoded words do not prevent tokenization
+        let malformed = decode_header("=?UTF-8?X?garbage?= offer");
+        assert!(malformed.contains("offer"), "{malformed}");
+        assert!(tokens("=?UTF-8?B?not base64 offer")
+            .iter()
+            .any(|token| token == "subj:offer"));
+    }
+
+    #[test]
+    fn subject_body_scores() {
+        let suffixes = PublicSuffix::default();
+        let mut model = BayesModel::default();
+        for _ in 0..5 {
+            model
+                .train_subject_body(
+                    "You won a free prize",
+                    "claim your reward now before it expires",
+                    true,
+                    &suffixes,
+                )
+                .unwrap();
+            model
+                .train_subject_body(
+                    "Minutes of the planning meeting",
+                    "attached are the notes from the planning meeting",
+                    false,
+                    &suffixes,
+                )
+                .unwrap();
+        }
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 1,
+            ..Default::default()
+        };
+        let classify = |weights| {
+            model
+                .classify_subject_body(
+                    &classifier,
+                    "=?UTF-8?Q?You_won_a_free_prize?=",
+                    "attached are the notes from the planning meeting",
+                    &suffixes,
+                    &weights,
+                )
+                .unwrap()
+        };
+
+        let equal = classify(FieldWeights::default());
+        assert!(equal.subject.unwrap() > 0.9, "{equal:?}");
+        assert!(equal.body.unwrap() < 0.1, "{equal:?}");
+        let subject_heavy = classify(FieldWeights {
+            subject: 4.0,
+            body: 1.0,
+        });
+        assert_eq!(
+            (subject_heavy.subject, subject_heavy.body),
+            (equal.subject, equal.body)
+        );
+        assert!(subject_heavy.score.unwrap() > 0.5, "{subject_heavy:?}");
+        assert!(subject_heavy.score > equal.score);
+
+        // The subject words were never learned in a body
+        let body_only = model
+            .classify_subject_body(
+                &classifier,
+                "",
+                "you won a free prize",
+                &suffixes,
+                &FieldWeights::default(),
+            )
+            .unwrap();
+        assert_eq!(
+            (body_only.subject, body_only.body, body_only.score),
+            (None, None, None)
+        );
+        assert_eq!(
+            model.classify_subject_body(&classifier, "", " ", &suffixes, &FieldWeights::default()),
+            Err(PipelineError::EmptyInput)
+        );
+    }
+}
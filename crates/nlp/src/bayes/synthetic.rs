@@ -0,0 +1,198 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+// Test support: deterministic synthetic ham and spam messages
+
+use std::borrow::Cow;
+
+use crate::tokenizers::osb::{OsbToken, OsbTokenizer};
+
+use super::{splitmix64, TokenHash, OSB_WINDOW_SIZE};
+
+/// Generates seeded synthetic messages. Each class has a vocabulary of which
+/// a fraction `overlap` is shared with the other class, and every word of a
+/// message is drawn from its own class vocabulary with probability
+/// `separation` and from the other class vocabulary otherwise, so a
+/// separation of 0.5 makes both classes indistinguishable.
+pub(crate) struct SyntheticCorpus {
+    state: u64,
+    vocabulary: usize,
+    overlap: f64,
+    separation: f64,
+    words_per_message: usize,
+}
+
+pub(crate) struct SyntheticMessage {
+    pub words: Vec<String>,
+    pub is_spam: bool,
+}
+
+impl SyntheticCorpus {
+    pub fn new(seed: u64) -> Self {
+        SyntheticCorpus {
+            state: seed,
+            vocabulary: 200,
+            overlap: 0.5,
+            separation: 0.8,
+            words_per_message: 30,
+        }
+    }
+
+    pub fn vocabulary(mut self, vocabulary: usize) -> Self {
+        self.vocabulary = vocabulary.max(1);
+        self
+    }
+
+    pub fn overlap(mut self, overlap: f64) -> Self {
+        self.overlap = overlap.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn separation(mut self, separation: f64) -> Self {
+        self.separation = separation.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn words_per_message(mut self, words: usize) -> Self {
+        self.words_per_message = words;
+        self
+    }
+
+    pub fn message(&mut self, is_spam: bool) -> SyntheticMessage {
+        let shared = (self.vocabulary as f64 * self.overlap) as usize;
+        let words = (0..self.words_per_message)
+            .map(|_| {
+                let from_spam = (self.next_f64() < self.separation) == is_spam;
+                // Skewed towards the first words, like natural language
+                let idx = (self.next_f64().powi(2) * self.vocabulary as f64) as usize;
+                if idx < shared {
+                    format!("shared{idx}")
+                } else if from_spam {
+                    format!("spam{idx}")
+                } else {
+                    format!("ham{idx}")
+                }
+            })
+            .collect();
+
+        SyntheticMessage { words, is_spam }
+    }
+
+    /// Generates `count` messages of each class, alternating between them.
+    pub fn messages(&mut self, count: usize) -> Vec<SyntheticMessage> {
+        (0..count * 2).map(|i| self.message(i % 2 == 0)).collect()
+    }
+
+    /// Shuffles items in place with the generator, e.g. to reorder tokens.
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            items.swap(i, (self.next_u64() % (i as u64 + 1)) as usize);
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        splitmix64(&mut self.state)
+    }
+
+    /// Returns a uniform draw from [0, 1).
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+impl SyntheticMessage {
+    pub fn tokens(&self) -> Vec<OsbToken<TokenHash>> {
+        OsbTokenizer::new(
+            self.words.iter().map(|word| Cow::Borrowed(word.as_str())),
+            OSB_WINDOW_SIZE,
+        )
+        .collect()
+    }
+
+    pub fn text(&self) -> String {
+        self.words.join(" ")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        bayes::{BayesClassifier, BayesModel},
+        tokenizers::osb::OsbToken,
+    };
+
+    use super::SyntheticCorpus;
+
+    fn accuracy(separation: f64) -> f64 {
+        let mut corpus = SyntheticCorpus::new(42).separation(separation);
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            ..Default::default()
+        };
+        let mut model = BayesModel::default();
+        for message in corpus.messages(100) {
+            model.train(message.tokens(), message.is_spam);
+        }
+
+        let test = corpus.messages(50);
+        let correct = test
+            .iter()
+            .filter(|message| {
+                let score = classifier.classify(
+                    message.tokens().into_iter().map(|token| OsbToken {
+                        inner: model.weights.get(&token.inner).copied().unwrap_or_default(),
+                        idx: token.idx,
+                    }),
+                    model.ham_learns,
+                    model.spam_learns,
+                );
+                // Messages without a verdict count as errors
+                score.is_some_and(|score| (score > 0.5) == message.is_spam)
+            })
+            .count();
+        correct as f64 / test.len() as f64
+    }
+
+    #[test]
+    fn deterministic() {
+        let a = SyntheticCorpus::new(7).messages(5);
+        let b = SyntheticCorpus::new(7).messages(5);
+        let c = SyntheticCorpus::new(8).messages(5);
+        assert!(a.iter().zip(&b).all(|(a, b)| a.text() == b.text()));
+        assert!(a.iter().zip(&c).any(|(a, c)| a.text() != c.text()));
+    }
+
+    #[test]
+    fn separation_accuracy() {
+        let accuracies = [0.5, 0.6, 0.75, 0.95]
+            .into_iter()
+            .map(accuracy)
+            .collect::<Vec<_>>();
+
+        for pair in accuracies.windows(2) {
+            assert!(pair[1] + 0.02 >= pair[0], "{accuracies:?}");
+        }
+        assert!(accuracies[0] < 0.7, "{accuracies:?}");
+        assert!(accuracies[3] > 0.9, "{accuracies:?}");
+    }
+}
@@ -0,0 +1,95 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::borrow::Cow;
+
+const DAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// Time a message was sent, as seen by the recipient, which correlates with
+/// bulk mail sent in bursts at odd hours.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendTime {
+    // Seconds since the Unix epoch
+    pub timestamp: i64,
+    // Offset of the timezone of the recipient from UTC in seconds
+    pub utc_offset: i32,
+}
+
+impl SendTime {
+    pub fn new(timestamp: i64, utc_offset: i32) -> Self {
+        SendTime {
+            timestamp,
+            utc_offset,
+        }
+    }
+
+    /// Returns the `hour:<00-23>` and `dow:<sun-sat>` tokens of the local
+    /// time of the recipient.
+    pub fn tokens(&self) -> Vec<Cow<'static, str>> {
+        let local = self.timestamp + self.utc_offset as i64;
+        let hour = local.rem_euclid(86400) / 3600;
+        // The epoch was a Thursday
+        let day = (local.div_euclid(86400) + 4).rem_euclid(7);
+
+        vec![
+            Cow::from(format!("hour:{hour:02}")),
+            Cow::from(format!("dow:{}", DAYS[day as usize])),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use utils::suffixlist::PublicSuffix;
+
+    use crate::bayes::tokenize::BayesTokenizer;
+
+    use super::SendTime;
+
+    #[test]
+    fn send_time_tokens() {
+        // Saturday 2024-03-16 03:25:00 UTC
+        let timestamp = 1710559500;
+        assert_eq!(SendTime::new(timestamp, 0).tokens(), ["hour:03", "dow:sat"]);
+        // Friday 22:25 in New York
+        assert_eq!(
+            SendTime::new(timestamp, -5 * 3600).tokens(),
+            ["hour:22", "dow:fri"]
+        );
+        // Saturday 12:25 in Tokyo
+        assert_eq!(
+            SendTime::new(timestamp, 9 * 3600).tokens(),
+            ["hour:12", "dow:sat"]
+        );
+        // Before the epoch
+        assert_eq!(SendTime::new(-1, 0).tokens(), ["hour:23", "dow:wed"]);
+
+        let suffixes = PublicSuffix::default();
+        assert_eq!(
+            BayesTokenizer::deterministic("cheap pills", &suffixes)
+                .tokenize_send_time(SendTime::new(timestamp, 0))
+                .collect::<Vec<_>>(),
+            ["hour:03", "dow:sat", "cheap", "pill"]
+        );
+    }
+}
@@ -1,8 +1,10 @@
 use std::borrow::Cow;
 
+use mail_parser::Message;
 use utils::suffixlist::PublicSuffix;
 
 use crate::{
+    bayes::{structure::MimeStructure, temporal::SendTime},
     language::{
         detect::{LanguageDetector, MIN_LANGUAGE_SCORE},
         stemmer::STEMMER_MAP,
@@ -12,15 +14,28 @@ use crate::{
     tokenizers::{
         chinese::JIEBA,
         types::{TokenType, TypesTokenizer},
+        Token,
     },
 };
 
+/// Tokenizer of the texts learned and classified by Bayes models. Hosts are
+/// lowercased, and only with the `idna` feature, which is not a default one,
+/// are their punycode labels decoded as well. Without it, the ASCII and
+/// Unicode forms of a domain produce different tokens.
 pub struct BayesTokenizer<'x, 'y> {
     text: &'x str,
     tokenizer: TypesTokenizer<'x, 'y>,
     stemmer: Stemmer,
     stop_words: Option<&'static phf::Set<&'static str>>,
     tokens: Vec<Cow<'x, str>>,
+    language: Language,
+    email_tokens: bool,
+    raw_emails: bool,
+    bidi_tokens: bool,
+    strip_bidi: bool,
+    max_repeats: usize,
+    split_compounds: bool,
+    peeked: Vec<Token<TokenType<&'x str>>>,
 }
 
 enum Stemmer {
@@ -39,6 +54,16 @@ impl<'x, 'y> BayesTokenizer<'x, 'y> {
             language = Language::English;
         }
 
+        Self::with_language(text, suffixes, language)
+    }
+
+    /// Creates a tokenizer with fixed options that does not depend on language
+    /// detection, producing identical tokens across runs and machines.
+    pub fn deterministic(text: &'x str, suffixes: &'y PublicSuffix) -> Self {
+        Self::with_language(text, suffixes, Language::English)
+    }
+
+    pub fn with_language(text: &'x str, suffixes: &'y PublicSuffix, language: Language) -> Self {
         Self {
             text,
             tokenizer: TypesTokenizer::new(text, suffixes),
@@ -51,8 +76,81 @@ impl<'x, 'y> BayesTokenizer<'x, 'y> {
             },
             stop_words: STOP_WORDS[language as usize],
             tokens: vec![],
+            language,
+            email_tokens: false,
+            raw_emails: true,
+            bidi_tokens: true,
+            strip_bidi: false,
+            max_repeats: 0,
+            split_compounds: false,
+            peeked: vec![],
         }
     }
+
+    /// Emits `email:`, `email_local:` and `email_domain:` tokens for each
+    /// email address found in the text.
+    pub fn tokenize_emails(mut self, tokenize: bool) -> Self {
+        self.email_tokens = tokenize;
+        self
+    }
+
+    /// Whether to also emit the address as found in the text when
+    /// `tokenize_emails` is enabled.
+    pub fn keep_raw_emails(mut self, keep: bool) -> Self {
+        self.raw_emails = keep;
+        self
+    }
+
+    /// Emits a `bidi_override:` token for each Unicode bidirectional control
+    /// character, which are used to disguise file names and text.
+    pub fn tokenize_bidi(mut self, tokenize: bool) -> Self {
+        self.bidi_tokens = tokenize;
+        self
+    }
+
+    /// Joins words split by bidirectional control characters, tokenizing
+    /// the text as if they were not present.
+    pub fn strip_bidi(mut self, strip: bool) -> Self {
+        self.strip_bidi = strip;
+        self
+    }
+
+    /// Shortens runs of the same character in words to at most `max_repeats`
+    /// characters, so that stretched words such as "freeeee" unify with their
+    /// base word. This changes the tokens of any word with longer runs, so
+    /// models must be trained and classified with the same setting. A zero
+    /// `max_repeats`, the default, disables it.
+    pub fn collapse_repeats(mut self, max_repeats: usize) -> Self {
+        self.max_repeats = max_repeats;
+        self
+    }
+
+    /// Also emits the tokens of the parts of camelCase, snake_case and
+    /// kebab-case words after the token of the whole word, such as `free`,
+    /// `money` and `now` for `FreeMoneyNow`. Words joined by `_` or `-` are
+    /// emitted lowercased and unstemmed as a single token. Ignored for words
+    /// joined by `strip_bidi`.
+    pub fn split_compounds(mut self, split: bool) -> Self {
+        self.split_compounds = split;
+        self
+    }
+
+    /// Emits the `parts:`, `mime_depth:` and `only_html` tokens describing
+    /// the MIME structure of the message the text was extracted from, ahead
+    /// of the tokens of the text.
+    pub fn tokenize_structure(mut self, message: &Message<'_>) -> Self {
+        // Pending tokens are popped from the end
+        self.tokens
+            .extend(MimeStructure::new(message).tokens().into_iter().rev());
+        self
+    }
+
+    /// Emits the `hour:` and `dow:` tokens of the time the message was sent,
+    /// ahead of the tokens of the text.
+    pub fn tokenize_send_time(mut self, time: SendTime) -> Self {
+        self.tokens.extend(time.tokens().into_iter().rev());
+        self
+    }
 }
 
 impl<'x, 'y> Iterator for BayesTokenizer<'x, 'y> {
@@ -64,71 +162,77 @@ impl<'x, 'y> Iterator for BayesTokenizer<'x, 'y> {
         }
 
         loop {
-            let token = self.tokenizer.next()?;
+            match self.next_raw()? {
+                RawToken::Word(word) => {
+                    if let Some(word) = self.stem(&word) {
+                        return Some(word);
+                    } else if let Some(token) = self.tokens.pop() {
+                        return Some(token);
+                    }
+                }
+                RawToken::Token(word) => return Some(word),
+            }
+        }
+    }
+}
+
+pub(crate) enum RawToken<'x> {
+    // Alphabetic word that still needs to be lowercased and stemmed
+    Word(Cow<'x, str>),
+    Token(Cow<'x, str>),
+}
+
+impl<'x, 'y> BayesTokenizer<'x, 'y> {
+    pub(crate) fn next_raw(&mut self) -> Option<RawToken<'x>> {
+        loop {
+            let token = self.next_token()?;
 
             let word: Cow<str> = match token.word {
+                TokenType::Alphabetic(word) if self.strip_bidi => {
+                    return Some(self.join_bidi(word, token.to, true));
+                }
+                TokenType::Alphanumeric(word) if self.strip_bidi => {
+                    return Some(self.join_bidi(word, token.to, false));
+                }
+                TokenType::Alphabetic(word) | TokenType::Alphanumeric(word)
+                    if self.split_compounds =>
+                {
+                    let is_alphabetic = matches!(token.word, TokenType::Alphabetic(_));
+                    return Some(self.split_compound(word, token.from, token.to, is_alphabetic));
+                }
                 TokenType::Alphabetic(word) => {
-                    let word = word.to_lowercase();
-                    if self
-                        .stop_words
-                        .map_or(false, |sw| sw.contains(word.as_str()))
-                    {
-                        continue;
-                    }
-                    match &self.stemmer {
-                        Stemmer::IndoEuropean(stemmer) => match stemmer.stem(&word) {
-                            Cow::Borrowed(_) => word.into(),
-                            Cow::Owned(stemmed_word) => stemmed_word.into(),
-                        },
-                        Stemmer::Mandarin => {
-                            let mut result = JIEBA.cut(&word, false).into_iter();
-                            if let Some(stemmed_word) = result.next() {
-                                let stemmed_word = stemmed_word.to_string();
-                                self.tokens = result
-                                    .rev()
-                                    .map(|word| Cow::from(word.to_string()))
-                                    .collect::<Vec<_>>();
-                                stemmed_word.into()
-                            } else {
-                                // This shouldn't happen, but just in case
-                                continue;
-                            }
-                        }
-                        Stemmer::Japanese => {
-                            let mut result = tinysegmenter::tokenize(&word).into_iter();
-                            if let Some(stemmed_word) = result.next() {
-                                self.tokens = result.rev().map(Cow::from).collect::<Vec<_>>();
-                                stemmed_word.into()
-                            } else {
-                                // This shouldn't happen, but just in case
-                                continue;
-                            }
-                        }
-                        Stemmer::None => word.into(),
-                    }
+                    return Some(RawToken::Word(word.into()));
                 }
 
                 TokenType::Url(word) => {
                     if let Some((_, host)) = word.split_once("://") {
-                        host.split_once('/')
-                            .map_or(host, |(h, _)| h)
-                            .to_lowercase()
-                            .into()
+                        canonical_host(host.split_once('/').map_or(host, |(h, _)| h)).into()
                     } else {
                         continue;
                     }
                 }
                 TokenType::IpAddr(word) => word.into(),
-                TokenType::UrlNoScheme(word) => word
-                    .split_once('/')
-                    .map_or(word, |(h, _)| h)
-                    .to_lowercase()
-                    .into(),
+                TokenType::UrlNoScheme(word) => {
+                    canonical_host(word.split_once('/').map_or(word, |(h, _)| h)).into()
+                }
+                TokenType::Email(word) if self.email_tokens => {
+                    if let Some(word) = self.email(word) {
+                        word
+                    } else {
+                        continue;
+                    }
+                }
                 TokenType::Alphanumeric(word)
                 | TokenType::Email(word)
                 | TokenType::UrlNoHost(word) => word.to_lowercase().into(),
                 TokenType::Other(ch) => {
-                    if SYMBOLS.contains(&ch) {
+                    if let Some(name) = bidi_control_name(ch) {
+                        if self.bidi_tokens {
+                            format!("bidi_override:{name}").into()
+                        } else {
+                            continue;
+                        }
+                    } else if SYMBOLS.contains(&ch) {
                         (&self.text[token.from..token.to]).into()
                     } else {
                         continue;
@@ -141,8 +245,260 @@ impl<'x, 'y> Iterator for BayesTokenizer<'x, 'y> {
                 }
             };
 
-            return Some(word);
+            return Some(RawToken::Token(word));
+        }
+    }
+
+    // Returns the first stemmed token of a word, queueing any additional ones,
+    // or None for stop words
+    pub(crate) fn stem(&mut self, word: &str) -> Option<Cow<'x, str>> {
+        let mut word = word.to_lowercase();
+        if self.max_repeats > 0 {
+            word = collapse_repeats(&word, self.max_repeats);
+        }
+        if self.stop_words.is_some_and(|sw| sw.contains(word.as_str())) {
+            return None;
+        }
+        match &self.stemmer {
+            Stemmer::IndoEuropean(stemmer) => match stemmer.stem(&word) {
+                Cow::Borrowed(_) => Some(word.into()),
+                Cow::Owned(stemmed_word) => Some(stemmed_word.into()),
+            },
+            Stemmer::Mandarin => {
+                let mut result = JIEBA.cut(&word, false).into_iter();
+                if let Some(stemmed_word) = result.next() {
+                    let stemmed_word = stemmed_word.to_string();
+                    self.tokens
+                        .extend(result.rev().map(|word| Cow::from(word.to_string())));
+                    Some(stemmed_word.into())
+                } else {
+                    // This shouldn't happen, but just in case
+                    None
+                }
+            }
+            Stemmer::Japanese => {
+                let mut result = tinysegmenter::tokenize(&word).into_iter();
+                if let Some(stemmed_word) = result.next() {
+                    self.tokens.extend(result.rev().map(Cow::from));
+                    Some(stemmed_word.into())
+                } else {
+                    // This shouldn't happen, but just in case
+                    None
+                }
+            }
+            Stemmer::None => Some(word.into()),
+        }
+    }
+
+    // Emits structured tokens for an email address, dropping any quotes and
+    // plus-address tags from the local part
+    fn email(&mut self, address: &str) -> Option<Cow<'x, str>> {
+        let address = address.to_lowercase();
+        let (local, domain) = address.rsplit_once('@')?;
+        let local = local.trim_matches('"');
+        let local = local.split_once('+').map_or(local, |(local, _)| local);
+        let mut tokens = vec![
+            Cow::from(format!("email:{local}@{domain}")),
+            Cow::from(format!("email_local:{local}")),
+            Cow::from(format!("email_domain:{domain}")),
+        ];
+        if self.raw_emails {
+            tokens.insert(0, Cow::from(address));
+        }
+
+        let first = tokens.remove(0);
+        self.tokens.extend(tokens.into_iter().rev());
+        Some(first)
+    }
+
+    fn next_token(&mut self) -> Option<Token<TokenType<&'x str>>> {
+        self.peeked.pop().or_else(|| self.tokenizer.next())
+    }
+
+    // Appends to a word any words that follow it separated only by bidi control
+    // characters, queueing their tokens to be emitted after the joined word
+    fn join_bidi(
+        &mut self,
+        word: &'x str,
+        mut end: usize,
+        mut is_alphabetic: bool,
+    ) -> RawToken<'x> {
+        let mut joined = Cow::from(word);
+        let mut bidi = vec![];
+        let mut after_bidi = false;
+
+        while let Some(token) = self.next_token() {
+            if token.from == end {
+                match token.word {
+                    TokenType::Other(ch) if bidi_control_name(ch).is_some() => {
+                        bidi.push(ch);
+                        after_bidi = true;
+                        end = token.to;
+                        continue;
+                    }
+                    TokenType::Alphabetic(word) | TokenType::Alphanumeric(word) if after_bidi => {
+                        is_alphabetic &= matches!(token.word, TokenType::Alphabetic(_));
+                        joined.to_mut().push_str(word);
+                        after_bidi = false;
+                        end = token.to;
+                        continue;
+                    }
+                    _ => (),
+                }
+            }
+            self.peeked.push(token);
+            break;
+        }
+
+        if self.bidi_tokens {
+            self.tokens.extend(
+                bidi.into_iter()
+                    .rev()
+                    .filter_map(bidi_control_name)
+                    .map(|name| Cow::from(format!("bidi_override:{name}"))),
+            );
+        }
+
+        if is_alphabetic {
+            RawToken::Word(joined)
+        } else {
+            RawToken::Token(joined.to_lowercase().into())
+        }
+    }
+
+    // Joins a word to the words that follow it separated only by '_' or '-',
+    // queueing the tokens of the parts of the compound
+    fn split_compound(
+        &mut self,
+        word: &'x str,
+        from: usize,
+        mut end: usize,
+        mut is_alphabetic: bool,
+    ) -> RawToken<'x> {
+        let mut words = vec![word];
+
+        while let Some(separator) = self.next_token() {
+            if separator.from == end && matches!(separator.word, TokenType::Punctuation('_' | '-'))
+            {
+                if let Some(token) = self.next_token() {
+                    match token.word {
+                        TokenType::Alphabetic(word) | TokenType::Alphanumeric(word)
+                            if token.from == separator.to =>
+                        {
+                            is_alphabetic &= matches!(token.word, TokenType::Alphabetic(_));
+                            words.push(word);
+                            end = token.to;
+                            continue;
+                        }
+                        _ => self.peeked.push(token),
+                    }
+                }
+            }
+            self.peeked.push(separator);
+            break;
+        }
+
+        let parts = words
+            .iter()
+            .flat_map(|word| split_case(word))
+            .collect::<Vec<_>>();
+        if parts.len() > 1 {
+            let tokens = parts
+                .into_iter()
+                .filter_map(|part| {
+                    if part.chars().all(char::is_alphabetic) {
+                        self.stem(part)
+                    } else {
+                        Some(part.to_lowercase().into())
+                    }
+                })
+                .collect::<Vec<_>>();
+            self.tokens.extend(tokens.into_iter().rev());
+        }
+
+        if words.len() > 1 {
+            RawToken::Token(self.text[from..end].to_lowercase().into())
+        } else if is_alphabetic {
+            RawToken::Word(word.into())
+        } else {
+            RawToken::Token(word.to_lowercase().into())
+        }
+    }
+
+    pub(crate) fn language(&self) -> Language {
+        self.language
+    }
+
+    pub(crate) fn max_repeats(&self) -> usize {
+        self.max_repeats
+    }
+
+    pub(crate) fn pop_pending(&mut self) -> Option<Cow<'x, str>> {
+        self.tokens.pop()
+    }
+
+    pub(crate) fn has_pending(&self) -> bool {
+        !self.tokens.is_empty()
+    }
+}
+
+// Splits a word before each uppercase letter that follows a lowercase one or
+// that starts a capitalized word, such as "Server" in "HTTPServer"
+fn split_case(word: &str) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut start = 0;
+    let mut chars = word.char_indices().peekable();
+    let mut prev: Option<char> = None;
+    while let Some((pos, ch)) = chars.next() {
+        if let Some(prev) = prev {
+            if ch.is_uppercase()
+                && (prev.is_lowercase()
+                    || (prev.is_uppercase()
+                        && chars.peek().is_some_and(|(_, next)| next.is_lowercase())))
+            {
+                parts.push(&word[start..pos]);
+                start = pos;
+            }
         }
+        prev = Some(ch);
+    }
+    parts.push(&word[start..]);
+    parts
+}
+
+fn collapse_repeats(word: &str, max_repeats: usize) -> String {
+    let mut result = String::with_capacity(word.len());
+    let mut last = None;
+    let mut run = 0;
+    for ch in word.chars() {
+        if last == Some(ch) {
+            run += 1;
+        } else {
+            last = Some(ch);
+            run = 1;
+        }
+        if run <= max_repeats {
+            result.push(ch);
+        }
+    }
+    result
+}
+
+fn bidi_control_name(ch: char) -> Option<&'static str> {
+    match ch {
+        '\u{061C}' => Some("alm"),
+        '\u{200E}' => Some("lrm"),
+        '\u{200F}' => Some("rlm"),
+        '\u{202A}' => Some("lre"),
+        '\u{202B}' => Some("rle"),
+        '\u{202C}' => Some("pdf"),
+        '\u{202D}' => Some("lro"),
+        '\u{202E}' => Some("rlo"),
+        '\u{2066}' => Some("lri"),
+        '\u{2067}' => Some("rli"),
+        '\u{2068}' => Some("fsi"),
+        '\u{2069}' => Some("pdi"),
+        _ => None,
     }
 }
 
@@ -159,6 +515,34 @@ fn number_to_tag(prefix: &str, num: &str) -> String {
     )
 }
 
+// Lowercases a host and decodes its punycode labels, so that the ASCII and
+// Unicode forms of a domain produce the same token. Hosts that are not valid
+// domain names, including invalid punycode, are only lowercased, as are all
+// hosts without the `idna` feature.
+#[cfg(feature = "idna")]
+fn canonical_host(host: &str) -> String {
+    let (domain, port) = match host.rsplit_once(':') {
+        Some((domain, port)) if port.chars().all(|ch| ch.is_ascii_digit()) => (domain, port),
+        _ => (host, ""),
+    };
+
+    match idna::domain_to_unicode(domain) {
+        (domain, Ok(())) if !domain.is_empty() => {
+            if port.is_empty() {
+                domain
+            } else {
+                format!("{domain}:{port}")
+            }
+        }
+        _ => host.to_lowercase(),
+    }
+}
+
+#[cfg(not(feature = "idna"))]
+fn canonical_host(host: &str) -> String {
+    host.to_lowercase()
+}
+
 pub static SYMBOLS: phf::Set<char> = phf::phf_set! {
     // Currency
     '\u{0024}', '\u{00A2}', '\u{00A3}', '\u{00A4}', '\u{00A5}', '\u{058F}', '\u{060B}', '\u{07FE}',
@@ -1239,4 +1623,165 @@ mod tests {
             assert_eq!(input, expect,);
         }
     }
+
+    #[test]
+    fn bayes_email_tokens() {
+        let suffixes = PublicSuffix::from("com\norg");
+        let text = "write to Jane+News@Example.org or \"john doe\"@example.com";
+
+        assert_eq!(
+            BayesTokenizer::new(text, &suffixes)
+                .tokenize_emails(true)
+                .collect::<Vec<_>>(),
+            [
+                "write",
+                "jane+news@example.org",
+                "email:jane@example.org",
+                "email_local:jane",
+                "email_domain:example.org",
+                "\"john doe\"@example.com",
+                "email:john doe@example.com",
+                "email_local:john doe",
+                "email_domain:example.com",
+            ]
+        );
+        assert_eq!(
+            BayesTokenizer::new("jane+news@example.org", &suffixes)
+                .tokenize_emails(true)
+                .keep_raw_emails(false)
+                .collect::<Vec<_>>(),
+            [
+                "email:jane@example.org",
+                "email_local:jane",
+                "email_domain:example.org",
+            ]
+        );
+    }
+
+    #[test]
+    fn bayes_bidi_tokens() {
+        let suffixes = PublicSuffix::from("com");
+        // Displayed as "invoiceexe.pdf"
+        let text = "open invoice\u{202E}fdp.exe now";
+
+        assert_eq!(
+            BayesTokenizer::deterministic(text, &suffixes).collect::<Vec<_>>(),
+            ["open", "invoic", "bidi_override:rlo", "fdp", "exe"]
+        );
+        assert_eq!(
+            BayesTokenizer::deterministic(text, &suffixes)
+                .strip_bidi(true)
+                .collect::<Vec<_>>(),
+            ["open", "invoicefdp", "bidi_override:rlo", "exe"]
+        );
+        assert_eq!(
+            BayesTokenizer::deterministic("pay\u{2067}\u{202E}9a\u{2069} the bill", &suffixes)
+                .strip_bidi(true)
+                .tokenize_bidi(false)
+                .collect::<Vec<_>>(),
+            ["pay9a", "bill"]
+        );
+    }
+
+    #[test]
+    fn bayes_repeated_chars() {
+        let suffixes = PublicSuffix::from("com");
+        let tokens = |text, max_repeats| {
+            BayesTokenizer::deterministic(text, &suffixes)
+                .collapse_repeats(max_repeats)
+                .collect::<Vec<_>>()
+        };
+
+        let free = tokens("free money", 2);
+        for text in ["freee money", "freeeeeee money", "FREEEE money"] {
+            assert_eq!(tokens(text, 2), free, "{text}");
+        }
+        assert_eq!(tokens("loooove", 2), tokens("loove", 2));
+        assert_eq!(tokens("loooove", 1), tokens("love", 0));
+
+        // Disabled by default
+        assert_ne!(tokens("freeeee money", 0), free);
+    }
+
+    #[test]
+    fn bayes_compound_words() {
+        let suffixes = PublicSuffix::from("com");
+        let tokens = |text| {
+            BayesTokenizer::deterministic(text, &suffixes)
+                .split_compounds(true)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            tokens("Get FreeMoneyNow today"),
+            ["get", "freemoneynow", "free", "money", "today"]
+        );
+        assert_eq!(
+            tokens("free_money_now or free-money"),
+            [
+                "free_money_now",
+                "free",
+                "money",
+                "free-money",
+                "free",
+                "money"
+            ]
+        );
+        assert_eq!(
+            tokens("getHTTPServer x2Go"),
+            ["gethttpserv", "get", "http", "server", "x2go"]
+        );
+        // Separators not followed by a word are kept apart
+        assert_eq!(tokens("money_ now"), ["money"]);
+
+        // Disabled by default
+        assert_eq!(
+            BayesTokenizer::deterministic("Get FreeMoneyNow today", &suffixes).collect::<Vec<_>>(),
+            ["get", "freemoneynow", "today"]
+        );
+    }
+
+    #[cfg(feature = "idna")]
+    #[test]
+    fn bayes_idn_hosts() {
+        let suffixes = PublicSuffix::from("com");
+        let tokens = |text| BayesTokenizer::deterministic(text, &suffixes).collect::<Vec<_>>();
+
+        for text in [
+            "visit http://xn--bcher-kva.com/offer",
+            "visit http://Bücher.com/offer",
+            "visit HTTPS://XN--BCHER-KVA.COM",
+            "visit xn--bcher-kva.com/offer",
+            "visit bücher.com",
+        ] {
+            assert_eq!(tokens(text), ["visit", "bücher.com"], "{text}");
+        }
+        assert_eq!(
+            tokens("visit http://xn--bcher-kva.com:8080/offer"),
+            ["visit", "bücher.com:8080"]
+        );
+
+        // Invalid punycode is kept as found
+        assert_eq!(
+            tokens("visit http://XN--A.com/offer"),
+            ["visit", "xn--a.com"]
+        );
+    }
+
+    #[cfg(not(feature = "idna"))]
+    #[test]
+    fn bayes_idn_hosts() {
+        let suffixes = PublicSuffix::from("com");
+        let tokens = |text| BayesTokenizer::deterministic(text, &suffixes).collect::<Vec<_>>();
+
+        // Hosts are only lowercased
+        assert_eq!(
+            tokens("visit HTTPS://XN--BCHER-KVA.COM"),
+            ["visit", "xn--bcher-kva.com"]
+        );
+        assert_eq!(
+            tokens("visit http://Bücher.com/offer"),
+            ["visit", "bücher.com"]
+        );
+    }
 }
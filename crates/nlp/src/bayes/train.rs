@@ -21,29 +21,312 @@
  * for more details.
 */
 
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    hash::BuildHasherDefault,
+    mem::size_of,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use nohash::NoHashHasher;
+use serde::{Deserialize, Serialize};
+
 use crate::tokenizers::osb::OsbToken;
 
-use super::{BayesModel, TokenHash};
+use super::{analysis::ClassDiversity, BayesModel, TokenHash, Weights};
+
+pub(crate) type TokenSet = HashSet<TokenHash, BuildHasherDefault<NoHashHasher<TokenHash>>>;
+
+// Fraction of the class token limit evicted below it at once, so that a
+// class at its limit is not evicted from on every learn
+const EVICTION_BATCH: usize = 16;
+
+/// Number of distinct tokens of each class and, once `class_token_limit`
+/// evicts tokens, the tokens of each class ordered by their count in it, so
+/// that neither `lacks_diversity` nor evictions scan the whole model. Both
+/// are built on first use and then kept up to date as the model learns.
+#[derive(Debug)]
+pub struct ClassTokenIndex {
+    // Distinct ham and spam tokens, or `UNKNOWN_COUNT`
+    distinct: [AtomicUsize; 2],
+    // Ham and spam tokens
+    classes: Option<[BTreeSet<(u32, TokenHash)>; 2]>,
+}
+
+const UNKNOWN_COUNT: usize = usize::MAX;
+
+/// How a ham message came to be learned. Users explicitly marking a message
+/// as not spam are a stronger signal than messages merely left in the inbox.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HamFeedback {
+    Passive,
+    Explicit,
+}
+
+/// Weights given to ham messages by `train_ham`, see `train_weighted`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeedbackWeights {
+    pub passive: u32,
+    pub explicit: u32,
+}
+
+/// Upper bound on the estimated memory used by the token tables of a model,
+/// see `BayesModel::memory_usage`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryBudget {
+    pub bytes: usize,
+    pub strategy: PruneStrategy,
+}
+
+/// Tokens pruned first when a model exceeds its memory budget.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PruneStrategy {
+    // Tokens seen the fewest times
+    #[default]
+    LowestCount,
+    // Tokens with the spam probability closest to 0.5
+    WeakestProbability,
+}
+
+impl Default for FeedbackWeights {
+    fn default() -> Self {
+        FeedbackWeights {
+            passive: 1,
+            explicit: 3,
+        }
+    }
+}
+
+impl FeedbackWeights {
+    pub fn weight(&self, feedback: HamFeedback) -> u32 {
+        match feedback {
+            HamFeedback::Passive => self.passive,
+            HamFeedback::Explicit => self.explicit,
+        }
+    }
+}
 
 impl BayesModel {
     pub fn train<T>(&mut self, tokens: T, is_spam: bool)
     where
         T: IntoIterator<Item = OsbToken<TokenHash>>,
     {
+        self.train_weighted(tokens, is_spam, 1);
+    }
+
+    /// Same as `train`, learning the message as if it had been trained
+    /// `weight` times, e.g. for hand-picked exemplars that should count more
+    /// than auto-learned messages. The token counts, document frequencies and
+    /// learn counter of the class grow by `weight`, saturating at `u32::MAX`,
+    /// while `learned_messages` counts the message once. A zero weight learns
+    /// nothing. Exemplars are forgotten by untraining them `weight` times.
+    pub fn train_weighted<T>(&mut self, tokens: T, is_spam: bool, weight: u32)
+    where
+        T: IntoIterator<Item = OsbToken<TokenHash>>,
+    {
+        if weight == 0 {
+            return;
+        }
         if is_spam {
-            self.spam_learns += 1;
+            self.spam_learns = self.spam_learns.saturating_add(weight);
         } else {
-            self.ham_learns += 1;
+            self.ham_learns = self.ham_learns.saturating_add(weight);
         }
+        self.version += 1;
+        self.learned_messages += 1;
 
+        let mut seen = TokenSet::default();
         for token in tokens {
             let hs = self.weights.entry(token.inner).or_default();
+            let before = *hs;
+            if is_spam {
+                hs.spam = hs.spam.saturating_add(weight);
+            } else {
+                hs.ham = hs.ham.saturating_add(weight);
+            }
+            self.class_index.update(token.inner, before, *hs);
+            if seen.insert(token.inner) {
+                let df = self.doc_freq.entry(token.inner).or_default();
+                *df = df.saturating_add(weight);
+            }
+        }
+
+        self.scheduled_decay();
+        self.enforce_limits(is_spam, &seen);
+    }
+
+    // Applies the class token limit and memory budget after the tokens of a
+    // message were added to a class
+    fn enforce_limits(&mut self, is_spam: bool, message: &TokenSet) {
+        if let Some(limit) = self.class_token_limit {
+            self.evict_tokens_except(is_spam, limit, message);
+        }
+        if let Some(budget) = self.memory_budget {
+            self.prune_to_budget(budget);
+        }
+    }
+
+    /// Estimated memory used by the token weights and document frequencies,
+    /// in bytes. Only the entries are counted, not the spare capacity of the
+    /// tables.
+    pub fn memory_usage(&self) -> usize {
+        self.weights.len() * size_of::<(TokenHash, Weights)>()
+            + self.doc_freq.len() * size_of::<(TokenHash, u32)>()
+    }
+
+    /// Prunes the lowest value tokens, as ranked by the budget strategy,
+    /// until `memory_usage` fits within the budget. Ties are broken by the
+    /// number of times the token was seen, then by hash.
+    /// The evictions are added to the live metrics and returned. This scans
+    /// the whole model, but only once the budget is exceeded. The tokens of
+    /// every namespace are ranked together, against the learn counters of
+    /// all namespaces.
+    pub fn prune_to_budget(&mut self, budget: MemoryBudget) -> usize {
+        let usage = self.memory_usage();
+        if usage <= budget.bytes {
+            return 0;
+        }
+
+        // Each pruned token frees at least its weights entry
+        let entry_size = size_of::<(TokenHash, Weights)>();
+        let evict = usize::min(
+            (usage - budget.bytes).div_ceil(entry_size),
+            self.weights.len(),
+        );
+        let (spam_learns, ham_learns) = self.namespaces.values().fold(
+            (self.spam_learns, self.ham_learns),
+            |(spam, ham), learns| {
+                (
+                    spam.saturating_add(learns.spam),
+                    ham.saturating_add(learns.ham),
+                )
+            },
+        );
+        let spam_learns = f64::max(1.0, spam_learns as f64);
+        let ham_learns = f64::max(1.0, ham_learns as f64);
+        let value = |weights: &Weights| match budget.strategy {
+            // Ranked by count alone
+            PruneStrategy::LowestCount => 0.0,
+            PruneStrategy::WeakestProbability => {
+                let spam_freq = weights.spam as f64 / spam_learns;
+                let ham_freq = weights.ham as f64 / ham_learns;
+                if spam_freq + ham_freq > 0.0 {
+                    (spam_freq / (spam_freq + ham_freq) - 0.5).abs()
+                } else {
+                    0.0
+                }
+            }
+        };
+        let mut tokens = self
+            .weights
+            .iter()
+            .map(|(hash, weights)| {
+                (
+                    value(weights),
+                    weights.spam.saturating_add(weights.ham),
+                    *hash,
+                )
+            })
+            .collect::<Vec<_>>();
+        if evict < tokens.len() {
+            tokens.select_nth_unstable_by(evict, |a, b| {
+                a.0.total_cmp(&b.0)
+                    .then_with(|| a.1.cmp(&b.1))
+                    .then_with(|| a.2.cmp(&b.2))
+            });
+        }
+        for (_, _, hash) in &tokens[..evict] {
+            if let Some(weights) = self.weights.remove(hash) {
+                self.class_index.update(*hash, weights, Weights::default());
+            }
+            self.doc_freq.remove(hash);
+        }
+
+        self.version += 1;
+        self.metrics.record_evictions(evict as u64);
+        evict
+    }
+
+    /// Learns a ham message with the weight of its feedback, so that explicit
+    /// corrections count more than passively accepted messages. Messages
+    /// are forgotten by untraining them as many times as their weight.
+    pub fn train_ham<T>(&mut self, tokens: T, feedback: HamFeedback, weights: &FeedbackWeights)
+    where
+        T: IntoIterator<Item = OsbToken<TokenHash>>,
+    {
+        self.train_weighted(tokens, false, weights.weight(feedback));
+    }
+
+    /// Evicts the least frequently learned tokens of a class until at most
+    /// `limit` distinct tokens remain in it, so that flooding a class with
+    /// unique tokens cannot grow the model without bound. Ties are broken by
+    /// hash, and tokens left without any weights are removed altogether.
+    /// Once over the limit, a further `limit / 16` tokens are evicted so that
+    /// the following learns do not evict again. Evicting any token bumps the
+    /// model version.
+    pub fn evict_class_tokens(&mut self, is_spam: bool, limit: usize) {
+        self.evict_tokens_except(is_spam, limit, &TokenSet::default());
+    }
+
+    // Evicts the tokens of a message after the other tokens with the same
+    // count, as tokens just learned would otherwise be the first evicted
+    fn evict_tokens_except(&mut self, is_spam: bool, limit: usize, message: &TokenSet) {
+        let weights = &self.weights;
+        let classes = self
+            .class_index
+            .classes
+            .get_or_insert_with(|| ClassTokenIndex::build(weights));
+        let class = &classes[is_spam as usize];
+        if class.len() <= limit {
+            return;
+        }
+
+        let evict = class.len() - (limit - limit / EVICTION_BATCH);
+        let mut tokens = Vec::with_capacity(evict);
+        let mut own = Vec::new();
+        let mut iter = class.iter().copied().peekable();
+        while tokens.len() < evict {
+            let Some((count, hash)) = iter.next() else {
+                break;
+            };
+            if message.contains(&hash) {
+                own.push((count, hash));
+            } else {
+                tokens.push((count, hash));
+            }
+            if iter.peek().is_none_or(|(next, _)| *next != count) {
+                tokens.append(&mut own);
+            }
+        }
+        tokens.truncate(evict);
+
+        for (count, hash) in tokens {
+            let weights = self.weights.get_mut(&hash);
+            let class_count =
+                weights
+                    .as_ref()
+                    .map(|weights| if is_spam { weights.spam } else { weights.ham });
+            if class_count != Some(count) {
+                // The weights were changed without the index, rebuild it
+                self.class_index.clear();
+                return self.evict_tokens_except(is_spam, limit, message);
+            }
+
+            let weights = weights.unwrap();
+            let before = *weights;
             if is_spam {
-                hs.spam += 1;
+                weights.spam = 0;
             } else {
-                hs.ham += 1;
+                weights.ham = 0;
+            }
+            let after = *weights;
+            if after.spam == 0 && after.ham == 0 {
+                self.weights.remove(&hash);
+                self.doc_freq.remove(&hash);
             }
+            self.class_index.update(hash, before, after);
         }
+        self.version += 1;
     }
 
     pub fn untrain<T>(&mut self, tokens: T, is_spam: bool)
@@ -55,14 +338,438 @@ impl BayesModel {
         } else {
             self.ham_learns -= 1;
         }
+        self.version += 1;
 
+        let mut seen = TokenSet::default();
         for token in tokens {
             let hs = self.weights.entry(token.inner).or_default();
+            let before = *hs;
             if is_spam {
                 hs.spam -= 1;
             } else {
                 hs.ham -= 1;
             }
+            self.class_index.update(token.inner, before, *hs);
+            if seen.insert(token.inner) {
+                if let Some(df) = self.doc_freq.get_mut(&token.inner) {
+                    *df = df.saturating_sub(1);
+                }
+            }
+        }
+    }
+
+    /// Moves a message from one class to the other in a single step, e.g. when a
+    /// user corrects a misclassification. Counters are clamped at zero.
+    pub fn relearn<T>(&mut self, tokens: T, from_spam: bool, to_spam: bool)
+    where
+        T: IntoIterator<Item = OsbToken<TokenHash>>,
+    {
+        if from_spam == to_spam {
+            return;
+        }
+        self.metrics.record_correction(from_spam);
+
+        if from_spam {
+            self.spam_learns = self.spam_learns.saturating_sub(1);
+            self.ham_learns = self.ham_learns.saturating_add(1);
+        } else {
+            self.ham_learns = self.ham_learns.saturating_sub(1);
+            self.spam_learns = self.spam_learns.saturating_add(1);
+        }
+        self.version += 1;
+
+        let mut seen = TokenSet::default();
+        for token in tokens {
+            let hs = self.weights.entry(token.inner).or_default();
+            let before = *hs;
+            if from_spam {
+                hs.spam = hs.spam.saturating_sub(1);
+                hs.ham = hs.ham.saturating_add(1);
+            } else {
+                hs.ham = hs.ham.saturating_sub(1);
+                hs.spam = hs.spam.saturating_add(1);
+            }
+            self.class_index.update(token.inner, before, *hs);
+            seen.insert(token.inner);
+        }
+
+        self.enforce_limits(to_spam, &seen);
+    }
+}
+
+impl ClassTokenIndex {
+    fn build(
+        weights: &HashMap<TokenHash, Weights, BuildHasherDefault<NoHashHasher<TokenHash>>>,
+    ) -> [BTreeSet<(u32, TokenHash)>; 2] {
+        let mut classes = [BTreeSet::new(), BTreeSet::new()];
+        for (hash, weights) in weights {
+            for (class, count) in classes.iter_mut().zip([weights.ham, weights.spam]) {
+                if count > 0 {
+                    class.insert((count, *hash));
+                }
+            }
+        }
+        classes
+    }
+
+    /// Returns the number of distinct tokens of each class, counting them
+    /// the first time.
+    pub(crate) fn diversity(
+        &self,
+        weights: &HashMap<TokenHash, Weights, BuildHasherDefault<NoHashHasher<TokenHash>>>,
+    ) -> ClassDiversity {
+        let [ham, spam] = &self.distinct;
+        let (mut ham_tokens, mut spam_tokens) =
+            (ham.load(Ordering::Relaxed), spam.load(Ordering::Relaxed));
+        if ham_tokens == UNKNOWN_COUNT || spam_tokens == UNKNOWN_COUNT {
+            (ham_tokens, spam_tokens) = weights.values().fold((0, 0), |(ham, spam), weights| {
+                (
+                    ham + (weights.ham > 0) as usize,
+                    spam + (weights.spam > 0) as usize,
+                )
+            });
+            ham.store(ham_tokens, Ordering::Relaxed);
+            spam.store(spam_tokens, Ordering::Relaxed);
+        }
+        ClassDiversity {
+            spam_tokens,
+            ham_tokens,
+        }
+    }
+
+    // Records a change of the weights of a token
+    pub(crate) fn update(&mut self, hash: TokenHash, before: Weights, after: Weights) {
+        for (distinct, (before, after)) in self
+            .distinct
+            .iter_mut()
+            .zip([(before.ham, after.ham), (before.spam, after.spam)])
+        {
+            let distinct = distinct.get_mut();
+            if *distinct != UNKNOWN_COUNT {
+                *distinct =
+                    (*distinct + (after > 0) as usize).saturating_sub((before > 0) as usize);
+            }
+        }
+        if let Some(classes) = &mut self.classes {
+            for (class, (before, after)) in classes
+                .iter_mut()
+                .zip([(before.ham, after.ham), (before.spam, after.spam)])
+            {
+                if before != after {
+                    if before > 0 {
+                        class.remove(&(before, hash));
+                    }
+                    if after > 0 {
+                        class.insert((after, hash));
+                    }
+                }
+            }
+        }
+    }
+
+    // Drops the index after the weights were changed wholesale
+    pub(crate) fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+impl Default for ClassTokenIndex {
+    fn default() -> Self {
+        Self {
+            distinct: [
+                AtomicUsize::new(UNKNOWN_COUNT),
+                AtomicUsize::new(UNKNOWN_COUNT),
+            ],
+            classes: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        bayes::{BayesClassifier, BayesModel, TokenHash, Weights},
+        tokenizers::osb::OsbToken,
+    };
+
+    use super::{FeedbackWeights, HamFeedback, MemoryBudget, PruneStrategy};
+
+    fn tokens(hashes: &[u64]) -> Vec<OsbToken<TokenHash>> {
+        hashes
+            .iter()
+            .map(|&h1| OsbToken {
+                inner: TokenHash { h1, h2: 0 },
+                idx: 0,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn relearn_spam_to_ham() {
+        let mut model = BayesModel::default();
+        model.train(tokens(&[1, 2]), true);
+        model.train(tokens(&[1, 3]), false);
+
+        model.relearn(tokens(&[1, 2]), true, false);
+        assert_eq!((model.spam_learns, model.ham_learns), (0, 2));
+        assert_eq!(
+            model.weights[&TokenHash { h1: 1, h2: 0 }],
+            Weights { spam: 0, ham: 2 }
+        );
+        assert_eq!(
+            model.weights[&TokenHash { h1: 2, h2: 0 }],
+            Weights { spam: 0, ham: 1 }
+        );
+
+        // Counters never go below zero
+        model.relearn(tokens(&[2]), true, false);
+        assert_eq!((model.spam_learns, model.ham_learns), (0, 3));
+        assert_eq!(
+            model.weights[&TokenHash { h1: 2, h2: 0 }],
+            Weights { spam: 0, ham: 2 }
+        );
+
+        // Or overflow
+        model.ham_learns = u32::MAX;
+        model.weights.insert(
+            TokenHash { h1: 2, h2: 0 },
+            Weights {
+                spam: 1,
+                ham: u32::MAX,
+            },
+        );
+        model.relearn(tokens(&[2]), true, false);
+        assert_eq!(model.ham_learns, u32::MAX);
+        assert_eq!(
+            model.weights[&TokenHash { h1: 2, h2: 0 }],
+            Weights {
+                spam: 0,
+                ham: u32::MAX
+            }
+        );
+    }
+
+    #[test]
+    fn relearn_limits() {
+        let mut model = BayesModel::default();
+        model.train(tokens(&[1, 2]), true);
+        model.train(tokens(&[1, 2]), true);
+        model.train(tokens(&[1, 2]), false);
+        model.train(tokens(&[3, 4, 5]), false);
+
+        // Corrected tokens count against the limit of their new class
+        model.class_token_limit = Some(2);
+        model.relearn(tokens(&[1, 2]), true, false);
+        assert_eq!(model.weights.len(), 2);
+        for h1 in [1, 2] {
+            assert_eq!(
+                model.weights[&TokenHash { h1, h2: 0 }],
+                Weights { spam: 1, ham: 2 }
+            );
+        }
+
+        // As do they against the memory budget
+        let budget = model.memory_usage();
+        model.memory_budget = Some(MemoryBudget {
+            bytes: budget - 1,
+            strategy: PruneStrategy::LowestCount,
+        });
+        model.relearn(tokens(&[1]), false, true);
+        assert!(model.memory_usage() < budget);
+    }
+
+    #[test]
+    fn class_token_limit() {
+        let mut model = BayesModel {
+            class_token_limit: Some(4),
+            ..Default::default()
+        };
+        model.train(tokens(&[1, 2, 3]), false);
+        for _ in 0..3 {
+            model.train(tokens(&[1, 2]), true);
+        }
+        model.train(tokens(&[3, 4]), true);
+        assert_eq!(model.weights.len(), 4);
+
+        // Flooding spam with unique tokens evicts the rarest spam tokens
+        model.train(tokens(&[10, 11, 12, 13, 14, 15]), true);
+        let spam_tokens = model.weights.values().filter(|w| w.spam > 0).count();
+        assert_eq!(spam_tokens, 4);
+        for h1 in [1, 2] {
+            assert_eq!(model.weights[&TokenHash { h1, h2: 0 }].spam, 3);
+        }
+        // Ham counts of evicted spam tokens are kept
+        assert_eq!(
+            model.weights[&TokenHash { h1: 3, h2: 0 }],
+            Weights { spam: 0, ham: 1 }
+        );
+        assert!(!model.weights.contains_key(&TokenHash { h1: 4, h2: 0 }));
+        assert!(!model.doc_freq.contains_key(&TokenHash { h1: 4, h2: 0 }));
+        assert_eq!(model.weights.len(), 3 + 2);
+    }
+
+    #[test]
+    fn class_token_limit_batches() {
+        let mut model = BayesModel {
+            class_token_limit: Some(3),
+            ..Default::default()
+        };
+        model.train(tokens(&[8, 9]), true);
+        model.train(tokens(&[8]), true);
+
+        // Older tokens are evicted before those of the message just learned
+        // with the same count
+        model.train(tokens(&[1, 2]), true);
+        let mut hashes = model.weights.keys().map(|hash| hash.h1).collect::<Vec<_>>();
+        hashes.sort_unstable();
+        assert_eq!(hashes, [1, 2, 8]);
+
+        // Large limits evict in batches below the limit
+        model.class_token_limit = Some(32);
+        model.train(tokens(&(100..140).collect::<Vec<_>>()), true);
+        assert_eq!(model.weights.len(), 30);
+        model.train(tokens(&[200, 201]), true);
+        assert_eq!(model.weights.len(), 32);
+
+        // Weights changed behind the index are picked up
+        model.weights.clear();
+        model
+            .weights
+            .insert(TokenHash { h1: 7, h2: 0 }, Weights { spam: 1, ham: 0 });
+        let version = model.version;
+        model.evict_class_tokens(true, 0);
+        assert!(model.weights.is_empty());
+        assert_eq!(model.version, version + 1);
+        model.evict_class_tokens(true, 0);
+        assert_eq!(model.version, version + 1);
+    }
+
+    #[test]
+    fn weighted_exemplar() {
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 1,
+            decision_margin: 0.0,
+            ..Default::default()
+        };
+        let trained = |weight| {
+            let mut model = BayesModel::default();
+            for _ in 0..5 {
+                model.train(tokens(&[1, 2, 3]), false);
+                model.train(tokens(&[4, 5, 6]), true);
+            }
+            model.train_weighted(tokens(&[1, 2, 7]), true, weight);
+            model
+        };
+        let score = |model: &BayesModel| {
+            model
+                .classify_token_hashes(&classifier, tokens(&[1, 2, 3]).into_iter())
+                .unwrap()
+        };
+
+        let plain = trained(1);
+        let exemplar = trained(5);
+        assert!(score(&plain) < 0.5, "{}", score(&plain));
+        assert!(score(&exemplar) > score(&plain));
+        assert_eq!((exemplar.spam_learns, exemplar.learned_messages), (10, 11));
+        assert_eq!(
+            exemplar.weights[&TokenHash { h1: 1, h2: 0 }],
+            Weights { spam: 5, ham: 5 }
+        );
+        assert_eq!(exemplar.doc_freq[&TokenHash { h1: 1, h2: 0 }], 10);
+
+        // Untraining an exemplar takes as many steps as its weight
+        let mut model = trained(3);
+        for _ in 0..3 {
+            model.untrain(tokens(&[1, 2, 7]), true);
+        }
+        assert_eq!(model.spam_learns, 5);
+        assert_eq!(
+            model.weights[&TokenHash { h1: 7, h2: 0 }],
+            Weights::default()
+        );
+
+        // Counts saturate instead of overflowing
+        let mut model = trained(u32::MAX);
+        model.train_weighted(tokens(&[1]), true, u32::MAX);
+        assert_eq!(model.spam_learns, u32::MAX);
+        assert_eq!(model.weights[&TokenHash { h1: 1, h2: 0 }].spam, u32::MAX);
+        assert_eq!(trained(0).spam_learns, 5);
+    }
+
+    #[test]
+    fn explicit_ham_feedback() {
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 1,
+            decision_margin: 0.0,
+            ..Default::default()
+        };
+        let trained = |feedback| {
+            let mut model = BayesModel::default();
+            for _ in 0..5 {
+                model.train(tokens(&[1, 2, 3]), true);
+                model.train(tokens(&[4, 5, 6]), false);
+            }
+            model.train_ham(tokens(&[1, 2, 7]), feedback, &FeedbackWeights::default());
+            model
+        };
+        let score = |model: &BayesModel| {
+            model
+                .classify_token_hashes(&classifier, tokens(&[1, 2, 3]).into_iter())
+                .unwrap()
+        };
+
+        let passive = trained(HamFeedback::Passive);
+        let explicit = trained(HamFeedback::Explicit);
+        assert!(score(&explicit) < score(&passive));
+        assert_eq!((passive.ham_learns, explicit.ham_learns), (6, 8));
+        assert_eq!(
+            explicit.weights[&TokenHash { h1: 1, h2: 0 }],
+            Weights { spam: 5, ham: 3 }
+        );
+        assert_eq!(passive.learned_messages, explicit.learned_messages);
+    }
+
+    #[test]
+    fn memory_budget() {
+        for strategy in [
+            PruneStrategy::LowestCount,
+            PruneStrategy::WeakestProbability,
+        ] {
+            let budget = MemoryBudget {
+                bytes: 4096,
+                strategy,
+            };
+            let mut model = BayesModel {
+                memory_budget: Some(budget),
+                ..Default::default()
+            };
+
+            // Every message repeats a few class tokens, and each pair of spam
+            // and ham messages shares five otherwise unseen tokens
+            for i in 0..500 {
+                let is_spam = i % 2 == 0;
+                let mut hashes = if is_spam {
+                    vec![1, 2, 3]
+                } else {
+                    vec![4, 5, 6]
+                };
+                hashes.extend((0..5).map(|n| 1000 + i / 2 * 5 + n));
+                model.train(tokens(&hashes), is_spam);
+                assert!(model.memory_usage() <= budget.bytes);
+            }
+
+            for h1 in 1..=6 {
+                let weights = model.weights[&TokenHash { h1, h2: 0 }];
+                assert_eq!(weights.spam + weights.ham, 250, "{strategy:?}");
+            }
+            let evictions = model.live_metrics().evictions;
+            assert!(
+                evictions as usize >= 250 * 5 + 6 - model.weights.len(),
+                "{evictions}"
+            );
+            assert_eq!(model.prune_to_budget(budget), 0);
         }
     }
 }
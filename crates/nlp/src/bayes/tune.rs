@@ -0,0 +1,497 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use crate::tokenizers::osb::OsbToken;
+
+use super::{BayesClassifier, BayesModel, TokenHash};
+
+/// Metric maximized by `BayesModel::tune_threshold`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ThresholdMetric {
+    #[default]
+    F1,
+    Accuracy,
+}
+
+impl ThresholdMetric {
+    pub fn evaluate(&self, tp: usize, fp: usize, tn: usize, fn_: usize) -> f64 {
+        match self {
+            ThresholdMetric::F1 if tp > 0 => (2 * tp) as f64 / (2 * tp + fp + fn_) as f64,
+            ThresholdMetric::F1 => 0.0,
+            ThresholdMetric::Accuracy if tp + fp + tn + fn_ > 0 => {
+                (tp + tn) as f64 / (tp + fp + tn + fn_) as f64
+            }
+            ThresholdMetric::Accuracy => 0.0,
+        }
+    }
+}
+
+impl BayesModel {
+    /// Returns the spam threshold, to be stored as `spam_threshold`, that
+    /// maximizes `metric` over a labeled validation set. Messages are scored
+    /// without `decision_margin`, and those without a score count as ham.
+    /// Candidates are the midpoints between consecutive distinct scores, and
+    /// ties are broken by the distance to 0.5. Returns 0.5 when no message
+    /// was scored.
+    pub fn tune_threshold<'x, M>(
+        &self,
+        classifier: &BayesClassifier,
+        labeled: M,
+        metric: ThresholdMetric,
+    ) -> f64
+    where
+        M: IntoIterator<Item = (&'x [OsbToken<TokenHash>], bool)>,
+    {
+        let classifier = BayesClassifier {
+            decision_margin: 0.0,
+            ..classifier.clone()
+        };
+        let mut scores = Vec::new();
+        let (mut total_spam, mut total_ham) = (0, 0);
+        for (tokens, is_spam) in labeled {
+            if is_spam {
+                total_spam += 1;
+            } else {
+                total_ham += 1;
+            }
+            if let Some(score) = self.classify_token_hashes(&classifier, tokens.iter().cloned()) {
+                scores.push((score, is_spam));
+            }
+        }
+        if scores.is_empty() {
+            return 0.5;
+        }
+        scores.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+
+        // Messages scoring at least the threshold are spam, starting with all
+        // the scored messages and dropping the lowest scores one at a time
+        let mut tp = scores.iter().filter(|(_, is_spam)| *is_spam).count();
+        let mut fp = scores.len() - tp;
+        let mut best: (f64, f64) = (f64::MIN, 0.5);
+        for pos in 0..=scores.len() {
+            if pos == 0 || pos == scores.len() || scores[pos - 1].0 < scores[pos].0 {
+                let threshold = match pos {
+                    0 => scores[0].0,
+                    _ if pos == scores.len() => (scores[pos - 1].0 + 1.0) / 2.0,
+                    _ => (scores[pos - 1].0 + scores[pos].0) / 2.0,
+                };
+                let value = metric.evaluate(tp, fp, total_ham - fp, total_spam - tp);
+                if value > best.0
+                    || (value == best.0 && (threshold - 0.5).abs() < (best.1 - 0.5).abs())
+                {
+                    best = (value, threshold);
+                }
+            }
+            if let Some((_, is_spam)) = scores.get(pos) {
+                if *is_spam {
+                    tp -= 1;
+                } else {
+                    fp -= 1;
+                }
+            }
+        }
+
+        best.1
+    }
+
+    /// Sweeps `min_token_hits` over the candidate values, classifying a
+    /// labeled validation set with each and reporting its accuracy and F1.
+    /// Messages are spam when they score at least `spam_threshold`, and
+    /// those without a score count as ham. The recommended value maximizes
+    /// `metric`, with ties going to the lowest value.
+    pub fn tune_min_token_hits<'x, M>(
+        &self,
+        classifier: &BayesClassifier,
+        labeled: M,
+        candidates: impl IntoIterator<Item = u32>,
+        metric: ThresholdMetric,
+    ) -> MinTokenHitsSweep
+    where
+        M: IntoIterator<Item = (&'x [OsbToken<TokenHash>], bool)>,
+    {
+        let labeled = labeled.into_iter().collect::<Vec<_>>();
+        let mut candidates = candidates.into_iter().collect::<Vec<_>>();
+        candidates.sort_unstable();
+        candidates.dedup();
+
+        let mut sweep = MinTokenHitsSweep {
+            results: Vec::with_capacity(candidates.len()),
+            recommended: classifier.min_token_hits,
+        };
+        let mut best = f64::MIN;
+        for min_token_hits in candidates {
+            let classifier = BayesClassifier {
+                min_token_hits,
+                ..classifier.clone()
+            };
+            let (mut tp, mut fp, mut tn, mut fn_, mut scored) = (0, 0, 0, 0, 0);
+            for (tokens, is_spam) in &labeled {
+                let score = self.classify_token_hashes(&classifier, tokens.iter().cloned());
+                scored += usize::from(score.is_some());
+                match (score.is_some_and(|s| classifier.is_spam(s)), *is_spam) {
+                    (true, true) => tp += 1,
+                    (true, false) => fp += 1,
+                    (false, false) => tn += 1,
+                    (false, true) => fn_ += 1,
+                }
+            }
+
+            let result = MinTokenHitsResult {
+                min_token_hits,
+                accuracy: ThresholdMetric::Accuracy.evaluate(tp, fp, tn, fn_),
+                f1: ThresholdMetric::F1.evaluate(tp, fp, tn, fn_),
+                scored,
+            };
+            let value = match metric {
+                ThresholdMetric::F1 => result.f1,
+                ThresholdMetric::Accuracy => result.accuracy,
+            };
+            if value > best {
+                best = value;
+                sweep.recommended = min_token_hits;
+            }
+            sweep.results.push(result);
+        }
+
+        sweep
+    }
+}
+
+impl BayesModel {
+    /// Returns the reliability diagram of the raw scores, computed without
+    /// `decision_margin`, over a labeled set split into `bins` equal-width
+    /// score bins. Messages without a score are only counted as unscored.
+    pub fn calibration_curve<'x, M>(
+        &self,
+        classifier: &BayesClassifier,
+        labeled: M,
+        bins: usize,
+    ) -> CalibrationCurve
+    where
+        M: IntoIterator<Item = (&'x [OsbToken<TokenHash>], bool)>,
+    {
+        let classifier = BayesClassifier {
+            decision_margin: 0.0,
+            ..classifier.clone()
+        };
+        let mut unscored = 0;
+        let scores = labeled
+            .into_iter()
+            .filter_map(|(tokens, is_spam)| {
+                let score = self.classify_token_hashes(&classifier, tokens.iter().cloned());
+                unscored += usize::from(score.is_none());
+                score.map(|score| (score, is_spam))
+            })
+            .collect::<Vec<_>>();
+
+        CalibrationCurve {
+            unscored,
+            ..CalibrationCurve::from_scores(scores, bins)
+        }
+    }
+}
+
+/// Predicted spam probability against the observed fraction of spam, for
+/// plotting how well the scores are calibrated.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalibrationCurve {
+    pub bins: Vec<CalibrationBin>,
+    pub unscored: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CalibrationBin {
+    // Scores from `low`, included, to `high`, excluded except for the last bin
+    pub low: f64,
+    pub high: f64,
+    pub count: usize,
+    // Mean score and fraction of spam of the messages in the bin, or the
+    // middle of the bin and 0.0 when it is empty
+    pub predicted: f64,
+    pub observed: f64,
+}
+
+impl CalibrationCurve {
+    /// Bins `(score, is_spam)` pairs into `bins` equal-width score bins, one
+    /// at least. Non-finite scores are skipped.
+    pub fn from_scores(scores: impl IntoIterator<Item = (f64, bool)>, bins: usize) -> Self {
+        let bins = bins.max(1);
+        let mut sums = vec![(0usize, 0.0, 0usize); bins];
+        for (score, is_spam) in scores {
+            if score.is_finite() {
+                let bin = ((score.clamp(0.0, 1.0) * bins as f64) as usize).min(bins - 1);
+                sums[bin].0 += 1;
+                sums[bin].1 += score;
+                sums[bin].2 += usize::from(is_spam);
+            }
+        }
+
+        CalibrationCurve {
+            bins: sums
+                .into_iter()
+                .enumerate()
+                .map(|(bin, (count, score_sum, spam))| {
+                    let low = bin as f64 / bins as f64;
+                    let high = (bin + 1) as f64 / bins as f64;
+                    CalibrationBin {
+                        low,
+                        high,
+                        count,
+                        predicted: if count > 0 {
+                            score_sum / count as f64
+                        } else {
+                            (low + high) / 2.0
+                        },
+                        observed: if count > 0 {
+                            spam as f64 / count as f64
+                        } else {
+                            0.0
+                        },
+                    }
+                })
+                .collect(),
+            unscored: 0,
+        }
+    }
+
+    /// Expected calibration error: the mean distance between the predicted
+    /// and observed spam fractions of the bins, weighted by their counts.
+    pub fn expected_error(&self) -> f64 {
+        let total = self.bins.iter().map(|bin| bin.count).sum::<usize>();
+        if total == 0 {
+            return 0.0;
+        }
+        self.bins
+            .iter()
+            .map(|bin| bin.count as f64 * (bin.predicted - bin.observed).abs())
+            .sum::<f64>()
+            / total as f64
+    }
+}
+
+/// Outcome of `BayesModel::tune_min_token_hits`, with the results sorted by
+/// `min_token_hits`. The recommendation is the current `min_token_hits` of
+/// the classifier when no candidate was given.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MinTokenHitsSweep {
+    pub results: Vec<MinTokenHitsResult>,
+    pub recommended: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MinTokenHitsResult {
+    pub min_token_hits: u32,
+    pub accuracy: f64,
+    pub f1: f64,
+    // Validation messages that received a score
+    pub scored: usize,
+}
+
+#[cfg(test)]
+mod test {
+    use crate::bayes::{synthetic::SyntheticCorpus, BayesClassifier, BayesModel};
+
+    use super::{CalibrationCurve, ThresholdMetric};
+
+    #[test]
+    fn tune_threshold() {
+        let mut corpus = SyntheticCorpus::new(11).separation(0.65);
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            decision_margin: 0.0,
+            ..Default::default()
+        };
+        // Trained on a spam-heavy mix, which biases the scores towards spam
+        let mut model = BayesModel::default();
+        for (i, message) in corpus.messages(150).into_iter().enumerate() {
+            if message.is_spam || i % 6 == 1 {
+                model.train(message.tokens(), message.is_spam);
+            }
+        }
+
+        let validation = corpus
+            .messages(100)
+            .into_iter()
+            .map(|message| (message.tokens(), message.is_spam))
+            .collect::<Vec<_>>();
+        let test = corpus
+            .messages(100)
+            .into_iter()
+            .map(|message| (message.tokens(), message.is_spam))
+            .collect::<Vec<_>>();
+        let f1 = |threshold: f64| {
+            let (mut tp, mut fp, mut tn, mut fn_) = (0, 0, 0, 0);
+            for (tokens, is_spam) in &test {
+                let score = model
+                    .classify_token_hashes(&classifier, tokens.iter().cloned())
+                    .unwrap_or(0.0);
+                match (score >= threshold, *is_spam) {
+                    (true, true) => tp += 1,
+                    (true, false) => fp += 1,
+                    (false, false) => tn += 1,
+                    (false, true) => fn_ += 1,
+                }
+            }
+            ThresholdMetric::F1.evaluate(tp, fp, tn, fn_)
+        };
+
+        let threshold = model.tune_threshold(
+            &classifier,
+            validation
+                .iter()
+                .map(|(tokens, is_spam)| (tokens.as_slice(), *is_spam)),
+            ThresholdMetric::F1,
+        );
+        assert!(threshold > 0.5, "{threshold}");
+        assert!(f1(threshold) > f1(0.5), "{} <= {}", f1(threshold), f1(0.5));
+        let tuned = BayesClassifier {
+            spam_threshold: threshold,
+            ..Default::default()
+        };
+        assert!(tuned.is_spam(threshold) && !tuned.is_spam(0.5));
+
+        // Without any scored message the threshold is left at 0.5
+        assert_eq!(
+            BayesModel::default().tune_threshold(
+                &classifier,
+                validation
+                    .iter()
+                    .map(|(tokens, is_spam)| (tokens.as_slice(), *is_spam)),
+                ThresholdMetric::Accuracy,
+            ),
+            0.5
+        );
+    }
+
+    #[test]
+    fn tune_min_token_hits() {
+        let mut corpus = SyntheticCorpus::new(5)
+            .vocabulary(1000)
+            .separation(0.8)
+            .words_per_message(20);
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            min_tokens: 1,
+            decision_margin: 0.0,
+            ..Default::default()
+        };
+        let mut model = BayesModel::default();
+        // Some of the training messages are mislabeled, which makes the rarely
+        // seen tokens unreliable
+        for (i, message) in corpus.messages(200).into_iter().enumerate() {
+            model.train(message.tokens(), message.is_spam ^ (i % 5 == 0));
+        }
+        let validation = corpus
+            .messages(100)
+            .into_iter()
+            .map(|message| (message.tokens(), message.is_spam))
+            .collect::<Vec<_>>();
+
+        let sweep = model.tune_min_token_hits(
+            &classifier,
+            validation
+                .iter()
+                .map(|(tokens, is_spam)| (tokens.as_slice(), *is_spam)),
+            [200, 1, 2, 3, 5, 8, 13, 21, 34, 55, 89, 1],
+            ThresholdMetric::F1,
+        );
+        let hits = sweep
+            .results
+            .iter()
+            .map(|result| result.min_token_hits)
+            .collect::<Vec<_>>();
+        assert_eq!(hits, [1, 2, 3, 5, 8, 13, 21, 34, 55, 89, 200]);
+
+        let f1 = |min_token_hits| {
+            sweep
+                .results
+                .iter()
+                .find(|result| result.min_token_hits == min_token_hits)
+                .unwrap()
+                .f1
+        };
+        let recommended = f1(sweep.recommended);
+        assert!(recommended > f1(1), "{sweep:?}");
+        assert!(recommended > f1(200), "{sweep:?}");
+        // Fewer messages have enough frequent tokens to be scored
+        assert!(sweep
+            .results
+            .windows(2)
+            .all(|pair| pair[0].scored >= pair[1].scored));
+    }
+
+    #[test]
+    fn calibration_curve() {
+        // Messages are spam with exactly the probability of their score
+        let mut corpus = SyntheticCorpus::new(3);
+        let scores = (0..20000)
+            .map(|i| {
+                let score = (i % 1000) as f64 / 1000.0 + 0.0005;
+                (score, corpus.next_f64() < score)
+            })
+            .collect::<Vec<_>>();
+
+        let curve = CalibrationCurve::from_scores(scores.iter().copied(), 10);
+        assert_eq!(curve.bins.len(), 10);
+        for bin in &curve.bins {
+            assert_eq!(bin.count, 2000);
+            assert!((bin.predicted - bin.observed).abs() < 0.05, "{bin:?}");
+            assert!(bin.low <= bin.predicted && bin.predicted < bin.high);
+        }
+        assert!(curve.expected_error() < 0.02, "{}", curve.expected_error());
+
+        // Scores always leaning towards the wrong class are far off the diagonal
+        let inverted = CalibrationCurve::from_scores(
+            scores.iter().map(|(score, _)| (*score, *score < 0.5)),
+            10,
+        );
+        assert!(inverted.expected_error() > 0.7);
+
+        // Model scores are binned along the unscored messages
+        let mut model = BayesModel::default();
+        for message in SyntheticCorpus::new(9).messages(50) {
+            model.train(message.tokens(), message.is_spam);
+        }
+        let classifier = BayesClassifier {
+            min_learns: 0,
+            ..Default::default()
+        };
+        let labeled = SyntheticCorpus::new(10)
+            .messages(50)
+            .into_iter()
+            .map(|message| (message.tokens(), message.is_spam))
+            .collect::<Vec<_>>();
+        let curve = model.calibration_curve(
+            &classifier,
+            labeled
+                .iter()
+                .map(|(tokens, is_spam)| (tokens.as_slice(), *is_spam)),
+            5,
+        );
+        assert_eq!(
+            curve.bins.iter().map(|bin| bin.count).sum::<usize>() + curve.unscored,
+            100
+        );
+        assert!(curve.bins[4].observed > curve.bins[0].observed, "{curve:?}");
+    }
+}
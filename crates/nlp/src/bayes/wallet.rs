@@ -0,0 +1,243 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::borrow::Cow;
+
+use sha2::{Digest, Sha256};
+use tiny_keccak::{Hasher, Keccak};
+
+/// Emits a `wallet:<currency>:<address>` token for each cryptocurrency wallet
+/// address found in the text. Bitcoin addresses are validated by their
+/// base58check or bech32 checksum, and Ethereum addresses by their EIP-55
+/// checksum when written in mixed case.
+pub struct WalletTokenizer<'x> {
+    text: &'x str,
+    pos: usize,
+}
+
+const BASE58_ALPHABET: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+const BECH32_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const BECH32_CONST: u32 = 1;
+const BECH32M_CONST: u32 = 0x2bc830a3;
+
+impl<'x> WalletTokenizer<'x> {
+    pub fn new(text: &'x str) -> Self {
+        Self { text, pos: 0 }
+    }
+}
+
+impl<'x> Iterator for WalletTokenizer<'x> {
+    type Item = Cow<'x, str>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let bytes = self.text.as_bytes();
+
+        while self.pos < bytes.len() {
+            if !bytes[self.pos].is_ascii_alphanumeric() {
+                self.pos += 1;
+                continue;
+            }
+
+            // Addresses are whole runs of ASCII alphanumerics
+            let start = self.pos;
+            while self.pos < bytes.len() && bytes[self.pos].is_ascii_alphanumeric() {
+                self.pos += 1;
+            }
+            if self.pos < bytes.len() && !bytes[self.pos].is_ascii() {
+                continue;
+            }
+            if start > 0 && !bytes[start - 1].is_ascii() {
+                continue;
+            }
+
+            let word = &self.text[start..self.pos];
+            if let Some(token) = parse_eth(word)
+                .or_else(|| parse_bech32(word))
+                .or_else(|| parse_base58(word))
+            {
+                return Some(token.into());
+            }
+        }
+
+        None
+    }
+}
+
+fn parse_eth(word: &str) -> Option<String> {
+    let hex = word.strip_prefix("0x")?;
+    if hex.len() != 40 || !hex.bytes().all(|ch| ch.is_ascii_hexdigit()) {
+        return None;
+    }
+    let address = hex.to_ascii_lowercase();
+
+    // Mixed case addresses carry an EIP-55 checksum
+    if hex.bytes().any(|ch| ch.is_ascii_lowercase())
+        && hex.bytes().any(|ch| ch.is_ascii_uppercase())
+    {
+        let mut hash = [0u8; 32];
+        let mut keccak = Keccak::v256();
+        keccak.update(address.as_bytes());
+        keccak.finalize(&mut hash);
+
+        for (pos, ch) in hex.bytes().enumerate() {
+            let nibble = (hash[pos / 2] >> (if pos % 2 == 0 { 4 } else { 0 })) & 0x0f;
+            if ch.is_ascii_alphabetic() && ch.is_ascii_uppercase() != (nibble >= 8) {
+                return None;
+            }
+        }
+    }
+
+    Some(format!("wallet:eth:0x{address}"))
+}
+
+fn parse_bech32(word: &str) -> Option<String> {
+    if !(14..=74).contains(&word.len())
+        || (word.bytes().any(|ch| ch.is_ascii_lowercase())
+            && word.bytes().any(|ch| ch.is_ascii_uppercase()))
+    {
+        return None;
+    }
+    let address = word.to_ascii_lowercase();
+    let data = address.strip_prefix("bc1")?;
+
+    let mut values = Vec::with_capacity(data.len());
+    for ch in data.bytes() {
+        values.push(BECH32_CHARSET.iter().position(|&c| c == ch)? as u8);
+    }
+    if values.len() < 7 || values[0] > 16 {
+        return None;
+    }
+
+    // Segwit version 0 uses bech32, later versions bech32m
+    let expected = if values[0] == 0 {
+        BECH32_CONST
+    } else {
+        BECH32M_CONST
+    };
+    let hrp = b"bc";
+    let checksum = bech32_polymod(
+        hrp.iter()
+            .map(|ch| ch >> 5)
+            .chain([0])
+            .chain(hrp.iter().map(|ch| ch & 0x1f))
+            .chain(values.iter().copied()),
+    );
+
+    (checksum == expected).then(|| format!("wallet:btc:{address}"))
+}
+
+fn bech32_polymod(values: impl Iterator<Item = u8>) -> u32 {
+    const GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+    let mut checksum = 1u32;
+    for value in values {
+        let top = checksum >> 25;
+        checksum = ((checksum & 0x1ffffff) << 5) ^ value as u32;
+        for (bit, generator) in GENERATOR.iter().enumerate() {
+            if (top >> bit) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+    checksum
+}
+
+fn parse_base58(word: &str) -> Option<String> {
+    if !(26..=35).contains(&word.len()) || !matches!(word.as_bytes()[0], b'1' | b'3') {
+        return None;
+    }
+
+    // Version byte, 20 byte hash and 4 byte checksum
+    let mut decoded = [0u8; 25];
+    for ch in word.bytes() {
+        let mut carry = BASE58_ALPHABET.iter().position(|&c| c == ch)? as u32;
+        for byte in decoded.iter_mut().rev() {
+            carry += *byte as u32 * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        if carry != 0 {
+            return None;
+        }
+    }
+
+    // Each leading zero byte is encoded as a leading '1'
+    if word.bytes().take_while(|&ch| ch == b'1').count()
+        != decoded.iter().take_while(|&&byte| byte == 0).count()
+        || !matches!(decoded[0], 0x00 | 0x05)
+    {
+        return None;
+    }
+
+    let hash = Sha256::digest(Sha256::digest(&decoded[..21]));
+    (hash[..4] == decoded[21..]).then(|| format!("wallet:btc:{word}"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::WalletTokenizer;
+
+    #[test]
+    fn wallet_addresses() {
+        for (text, token) in [
+            (
+                "send to 1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa now",
+                "wallet:btc:1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa",
+            ),
+            (
+                "send to 3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy now",
+                "wallet:btc:3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy",
+            ),
+            (
+                "send to BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4.",
+                "wallet:btc:bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4",
+            ),
+            (
+                "send to 0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed now",
+                "wallet:eth:0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed",
+            ),
+            (
+                "send to 0xfb6916095ca1df60bb79ce92ce3ea74c37c5d359 now",
+                "wallet:eth:0xfb6916095ca1df60bb79ce92ce3ea74c37c5d359",
+            ),
+        ] {
+            assert_eq!(
+                WalletTokenizer::new(text).collect::<Vec<_>>(),
+                [token],
+                "{text}"
+            );
+        }
+
+        for text in [
+            // Bad checksums
+            "send to 1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNb now",
+            "send to bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t5 now",
+            "send to 0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeD now",
+            // Wrong shapes
+            "send to 0x5aaeb6053f3e94c9b9a09f33669435e7ef1bea now",
+            "order 1234567890123456789012345678 shipped",
+            "send to Bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4 now",
+        ] {
+            assert_eq!(WalletTokenizer::new(text).next(), None, "{text}");
+        }
+    }
+}
@@ -1,8 +1,11 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
 pub mod bayes;
+#[cfg(feature = "std")]
 pub mod language;
 pub mod tokenizers;
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod test {
     use std::fs;
 
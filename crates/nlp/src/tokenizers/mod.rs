@@ -21,13 +21,19 @@
  * for more details.
 */
 
+#[cfg(feature = "std")]
 pub mod chinese;
+#[cfg(feature = "std")]
 pub mod japanese;
 pub mod osb;
+#[cfg(feature = "std")]
 pub mod space;
+#[cfg(feature = "std")]
 pub mod types;
+#[cfg(feature = "std")]
 pub mod word;
 
+#[cfg(feature = "std")]
 use std::borrow::Cow;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -37,6 +43,7 @@ pub struct Token<T> {
     pub to: usize,
 }
 
+#[cfg(feature = "std")]
 pub trait InnerToken<'x>: Sized {
     fn new_alphabetic(value: impl Into<Cow<'x, str>>) -> Self;
     fn unwrap_alphabetic(self) -> Cow<'x, str>;
@@ -44,6 +51,7 @@ pub trait InnerToken<'x>: Sized {
     fn is_alphabetic_8bit(&self) -> bool;
 }
 
+#[cfg(feature = "std")]
 impl<'x> InnerToken<'x> for Cow<'x, str> {
     fn new_alphabetic(value: impl Into<Cow<'x, str>>) -> Self {
         value.into()
@@ -21,8 +21,12 @@
  * for more details.
 */
 
+#[cfg(feature = "std")]
 use std::{borrow::Cow, iter::Peekable};
 
+#[cfg(feature = "std")]
+use serde::{Deserialize, Deserializer, Serialize};
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct OsbToken<T> {
     pub inner: T,
@@ -35,6 +39,7 @@ pub enum Gram<'x> {
     Bi { t1: &'x str, t2: &'x str },
 }
 
+#[cfg(feature = "std")]
 pub struct OsbTokenizer<'x, I, R>
 where
     I: Iterator<Item = Cow<'x, str>>,
@@ -45,26 +50,36 @@ where
     window_size: usize,
     window_pos: usize,
     window_idx: usize,
-    phantom: std::marker::PhantomData<R>,
+    convert: Box<dyn Fn(Gram<'_>) -> R + Send + Sync>,
 }
 
+#[cfg(feature = "std")]
 impl<'x, I, R> OsbTokenizer<'x, I, R>
 where
     I: Iterator<Item = Cow<'x, str>>,
     R: for<'y> From<Gram<'y>> + 'static,
 {
     pub fn new(iter: I, window_size: usize) -> Self {
+        Self::with_converter(iter, window_size, |gram| R::from(gram))
+    }
+
+    pub fn with_converter(
+        iter: I,
+        window_size: usize,
+        convert: impl Fn(Gram<'_>) -> R + Send + Sync + 'static,
+    ) -> Self {
         Self {
             iter: iter.peekable(),
             buf: vec![None; window_size],
             window_pos: 0,
             window_idx: 0,
             window_size,
-            phantom: std::marker::PhantomData,
+            convert: Box::new(convert),
         }
     }
 }
 
+#[cfg(feature = "std")]
 impl<'x, I, R> Iterator for OsbTokenizer<'x, I, R>
 where
     I: Iterator<Item = Cow<'x, str>>,
@@ -80,7 +95,7 @@ where
 
         let t1 = self.buf[self.window_pos % self.window_size].as_deref()?;
         let token = OsbToken {
-            inner: R::from(if self.window_idx != 0 {
+            inner: (self.convert)(if self.window_idx != 0 {
                 Gram::Bi {
                     t1,
                     t2: self.buf[end_pos].as_deref()?,
@@ -106,7 +121,91 @@ where
     }
 }
 
-#[cfg(test)]
+/// OSB window carried between chunks of a text that arrive separately, such
+/// as the headers and then the body of a message, so that bigrams spanning
+/// chunks are formed as if the text had been tokenized whole. The state holds
+/// the last `window_size - 1` words of the chunks seen so far (4 words with
+/// the default window), whose bigrams are not complete until more words
+/// arrive, and can be serialized to resume tokenization later.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenizerState {
+    words: Vec<String>,
+    #[serde(deserialize_with = "deserialize_window_size")]
+    window_size: usize,
+}
+
+// Clamps the window of a serialized state like `TokenizerState::new` does
+#[cfg(feature = "std")]
+fn deserialize_window_size<'de, D: Deserializer<'de>>(deserializer: D) -> Result<usize, D::Error> {
+    usize::deserialize(deserializer).map(|window_size| window_size.max(1))
+}
+
+#[cfg(feature = "std")]
+impl TokenizerState {
+    pub fn new(window_size: usize) -> Self {
+        TokenizerState {
+            words: Vec::with_capacity(window_size),
+            window_size: window_size.max(1),
+        }
+    }
+
+    /// Returns the tokens of the words whose window is complete after adding
+    /// the words of a chunk, which is the same sequence `OsbTokenizer` would
+    /// produce for them. Call `finish` after the last chunk.
+    pub fn tokenize_chunk<'x, R>(
+        &mut self,
+        words: impl IntoIterator<Item = Cow<'x, str>>,
+        convert: impl Fn(Gram<'_>) -> R,
+    ) -> Vec<OsbToken<R>> {
+        self.words
+            .extend(words.into_iter().map(|word| word.into_owned()));
+        let complete = (self.words.len() + 1).saturating_sub(self.window_size);
+        let tokens = self.tokens(complete, &convert);
+        self.words.drain(..complete);
+        tokens
+    }
+
+    /// Returns the tokens of the words left in the window.
+    pub fn finish<R>(mut self, convert: impl Fn(Gram<'_>) -> R) -> Vec<OsbToken<R>> {
+        let tokens = self.tokens(self.words.len(), &convert);
+        self.words.clear();
+        tokens
+    }
+
+    /// Number of words held in the state, at most `window_size - 1`.
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    // Tokens of the first `count` words paired with the words that follow them
+    fn tokens<R>(&self, count: usize, convert: &impl Fn(Gram<'_>) -> R) -> Vec<OsbToken<R>> {
+        let mut tokens = Vec::with_capacity(count * self.window_size);
+        for (pos, t1) in self.words[..count].iter().enumerate() {
+            tokens.push(OsbToken {
+                inner: convert(Gram::Uni { t1 }),
+                idx: 0,
+            });
+            for (idx, t2) in self.words[pos + 1..]
+                .iter()
+                .take(self.window_size - 1)
+                .enumerate()
+            {
+                tokens.push(OsbToken {
+                    inner: convert(Gram::Bi { t1, t2 }),
+                    idx: idx + 1,
+                });
+            }
+        }
+        tokens
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
 mod test {
     use std::borrow::Cow;
 
@@ -355,4 +454,67 @@ mod test {
             ]
         );
     }
+
+    #[test]
+    fn resumable_tokenization() {
+        let whole = |text: &str, window_size| {
+            super::OsbTokenizer::new(text.split_ascii_whitespace().map(Cow::from), window_size)
+                .collect::<Vec<OsbToken<String>>>()
+        };
+        fn convert(gram: Gram<'_>) -> String {
+            gram.into()
+        }
+        let chunked = |chunks: &[&str], window_size| {
+            let mut state = super::TokenizerState::new(window_size);
+            let mut tokens = Vec::new();
+            for chunk in chunks {
+                tokens.extend(
+                    state.tokenize_chunk(chunk.split_ascii_whitespace().map(Cow::from), convert),
+                );
+                assert!(state.len() < window_size);
+
+                // The state can be saved between chunks
+                state = serde_json::from_str(&serde_json::to_string(&state).unwrap()).unwrap();
+            }
+            tokens.extend(state.finish(convert));
+            tokens
+        };
+
+        for window_size in [1, 2, 5] {
+            assert_eq!(
+                chunked(&["a b", "c d"], window_size),
+                whole("a b c d", window_size),
+                "window {window_size}"
+            );
+        }
+        assert!(chunked(&["a b", "c d"], 2).contains(&OsbToken {
+            inner: "b c".to_string(),
+            idx: 1
+        }));
+        // A state saved without a window tokenizes as a window of one word
+        let mut state: super::TokenizerState =
+            serde_json::from_str("{\"words\": [\"a\"], \"window_size\": 0}").unwrap();
+        assert_eq!(
+            state.tokenize_chunk(["b"].map(Cow::from), convert),
+            whole("a b", 1)
+        );
+        assert!(state.finish(convert).is_empty());
+
+        assert_eq!(
+            chunked(
+                &[
+                    "The quick brown",
+                    "",
+                    "fox jumps over the lazy",
+                    "dog and",
+                    "the lazy cat"
+                ],
+                5
+            ),
+            whole(
+                "The quick brown fox jumps over the lazy dog and the lazy cat",
+                5
+            )
+        );
+    }
 }
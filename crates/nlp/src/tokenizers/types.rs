@@ -62,6 +62,8 @@ pub enum TokenType<T> {
 
 impl Copy for Token<TokenType<&'_ str>> {}
 
+const MAX_QUOTED_LOCAL_TOKENS: usize = 32;
+
 impl<'x, 'y> Iterator for TypesTokenizer<'x, 'y> {
     type Item = Token<TokenType<&'x str>>;
 
@@ -102,6 +104,16 @@ impl<'x, 'y> Iterator for TypesTokenizer<'x, 'y> {
             }
         }
 
+        // Try parsing email with a quoted local part
+        if self.tokenize_emails && token.word == TokenType::Punctuation('"') {
+            if let Some(email) = self.try_parse_quoted_email() {
+                self.peek_advance();
+                return Some(email);
+            } else {
+                self.peek_rewind();
+            }
+        }
+
         // Try parsing URL without scheme
         if self.tokenize_urls_without_scheme
             && token.word.is_domain_atom(true)
@@ -541,6 +553,36 @@ impl<'x, 'y> TypesTokenizer<'x, 'y> {
         .into()
     }
 
+    fn try_parse_quoted_email(&mut self) -> Option<Token<TokenType<&'x str>>> {
+        self.peek_rewind();
+        let start_token = self.peek()?;
+
+        // Find closing quote, followed by '@'
+        for _ in 0..MAX_QUOTED_LOCAL_TOKENS {
+            match self.peek()?.word {
+                TokenType::Punctuation('"') => {
+                    if self.peek()?.word != TokenType::Punctuation('@') {
+                        return None;
+                    }
+                    let (_, end_pos, _) = self.try_parse_hostname()?;
+
+                    return Token {
+                        word: TokenType::Email(&self.text[start_token.from..end_pos]),
+                        from: start_token.from,
+                        to: end_pos,
+                    }
+                    .into();
+                }
+                TokenType::Punctuation('\\' | '@') => {
+                    return None;
+                }
+                _ => {}
+            }
+        }
+
+        None
+    }
+
     fn try_parse_hostname(&mut self) -> Option<(usize, usize, bool)> {
         let mut last_ch = u8::MAX;
         let mut has_int = false;
@@ -2642,6 +2684,21 @@ mod test {
                     TokenType::Punctuation('"'),
                 ],
             ),
+            (
+                "\"john doe\"@example.com",
+                vec![TokenType::Email("\"john doe\"@example.com")],
+            ),
+            (
+                "\"john\" @example.com",
+                vec![
+                    TokenType::Punctuation('"'),
+                    TokenType::Alphabetic("john"),
+                    TokenType::Punctuation('"'),
+                    TokenType::Space,
+                    TokenType::Punctuation('@'),
+                    TokenType::UrlNoScheme("example.com"),
+                ],
+            ),
             (
                 ",a@example.com,",
                 vec![
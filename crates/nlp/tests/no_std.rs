@@ -0,0 +1,57 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+// Exercises the core classification path without the standard library, run with
+// `cargo test -p nlp --no-default-features --test no_std`
+#![no_std]
+
+use nlp::{
+    bayes::{BayesClassifier, Weights},
+    tokenizers::osb::OsbToken,
+};
+
+fn classify(weights: Weights, thread_scores: &[f64]) -> Option<f64> {
+    let classifier = BayesClassifier {
+        min_learns: 0,
+        ..BayesClassifier::new()
+    };
+    let tokens = core::iter::repeat_n(
+        OsbToken {
+            inner: weights,
+            idx: 0,
+        },
+        12,
+    );
+
+    classifier.classify_in_thread(tokens, 100, 100, thread_scores)
+}
+
+#[test]
+fn classify_no_std() {
+    let spam = classify(Weights { spam: 9, ham: 1 }, &[]).unwrap();
+    let ham = classify(Weights { spam: 1, ham: 9 }, &[]).unwrap();
+    assert!(spam > 0.5 && ham < 0.5);
+
+    let smoothed = classify(Weights { spam: 9, ham: 1 }, &[0.0, 0.0]).unwrap();
+    assert!(smoothed < spam);
+}